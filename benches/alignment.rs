@@ -0,0 +1,62 @@
+// Output row alignment at 4K, behind the default of output-alignment: every row
+// starting on a 64 byte boundary, as in the frames of our pool, against every row
+// starting one byte past it.
+//
+//     cargo bench --bench alignment
+//     cargo bench --bench alignment -- 'opencv/RGBA'
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use gstrsbayer::convert::{Converter, Pattern};
+
+mod common;
+
+use common::{BACKENDS, backend_name, bayer_frame, default_stride};
+
+const WIDTH: usize = 3840;
+const HEIGHT: usize = 2160;
+const ALIGNMENT: usize = 64;
+
+// `len` bytes starting `offset` bytes past an `ALIGNMENT` boundary, within the
+// returned allocation.
+fn offset_buffer(len: usize, offset: usize) -> (Vec<u8>, usize) {
+    let data = vec![0u8; len + 2 * ALIGNMENT];
+    let start = data.as_ptr().align_offset(ALIGNMENT) + offset;
+    (data, start)
+}
+
+fn alignment(c: &mut Criterion) {
+    gst::init().unwrap();
+
+    let input = bayer_frame(WIDTH, HEIGHT);
+    let mut group = c.benchmark_group("4K");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    for &backend in BACKENDS {
+        for format in Converter::OUTPUT_FORMATS {
+            // 4K rows of every format are a multiple of the alignment already, so the
+            // offset of the first row is that of all of them.
+            let out_stride = default_stride(format, WIDTH, HEIGHT).next_multiple_of(ALIGNMENT);
+
+            for (name, offset) in [("aligned", 0), ("unaligned", 1)] {
+                let mut converter = Converter::new(backend, Pattern::Rggb, WIDTH, HEIGHT, format)
+                    .expect("supported conversion");
+                let (mut data, start) = offset_buffer(out_stride * HEIGHT, offset);
+                let output = &mut data[start..][..out_stride * HEIGHT];
+
+                let id = format!("{}/{}/{}", backend_name(backend), format.to_str(), name);
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        converter
+                            .convert(&input, WIDTH, output, out_stride)
+                            .unwrap()
+                    })
+                });
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, alignment);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+// Helpers shared by the benchmarks.
+#![allow(dead_code)]
+
+use gstrsbayer::convert::Backend;
+
+pub const BACKENDS: &[Backend] = &[
+    #[cfg(feature = "opencv")]
+    Backend::OpenCv,
+    #[cfg(feature = "rust-demosaic")]
+    Backend::Rust,
+];
+
+// Deterministic noise, so every run converts the same frame and no path can take a
+// shortcut on flat input.
+pub fn bayer_frame(width: usize, height: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..width * height)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+pub fn backend_name(backend: Backend) -> &'static str {
+    match backend {
+        #[cfg(feature = "opencv")]
+        Backend::OpenCv => "opencv",
+        #[cfg(feature = "rust-demosaic")]
+        Backend::Rust => "rust",
+    }
+}
+
+// Stride of packed `width` pixel rows of `format`, as the caps describe them.
+pub fn default_stride(format: gst_video::VideoFormat, width: usize, height: usize) -> usize {
+    gst_video::VideoInfo::builder(format, width as u32, height as u32)
+        .build()
+        .unwrap()
+        .stride()[0] as usize
+}
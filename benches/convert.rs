@@ -0,0 +1,57 @@
+// Throughput of the conversion core, driven from plain slices without a pipeline.
+//
+// Every backend built in is measured for all patterns and output formats at 720p,
+// 1080p and 4K. Criterion reports the time per frame, whose inverse is the frame rate,
+// and the throughput in bytes of bayer input per second.
+//
+//     cargo bench --features rust-demosaic
+//     cargo bench --features rust-demosaic -- '1080p/rust/rggb/RGBA'
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use gstrsbayer::convert::{Converter, Pattern};
+
+mod common;
+
+use common::{BACKENDS, backend_name, bayer_frame, default_stride};
+
+const RESOLUTIONS: [(&str, usize, usize); 3] = [
+    ("720p", 1280, 720),
+    ("1080p", 1920, 1080),
+    ("4K", 3840, 2160),
+];
+
+fn convert(c: &mut Criterion) {
+    gst::init().unwrap();
+
+    for (resolution, width, height) in RESOLUTIONS {
+        let input = bayer_frame(width, height);
+
+        let mut group = c.benchmark_group(resolution);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        for &backend in BACKENDS {
+            for pattern in Pattern::ALL {
+                for format in Converter::OUTPUT_FORMATS {
+                    let mut converter = Converter::new(backend, pattern, width, height, format)
+                        .expect("supported conversion");
+                    let out_stride = default_stride(format, width, height);
+                    let mut output = vec![0u8; out_stride * height];
+
+                    let id = format!("{}/{}/{}", backend_name(backend), pattern, format.to_str());
+                    group.bench_function(id, |b| {
+                        b.iter(|| {
+                            converter
+                                .convert(&input, width, &mut output, out_stride)
+                                .unwrap()
+                        })
+                    });
+                }
+            }
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+// Fixed cost of every frame at machine vision sizes, where it adds up at 1000+ fps: a
+// converter set up once per caps, as the element keeps it, against one set up again
+// for every frame, which is what the element did before.
+//
+//     cargo bench --bench per_frame
+//     cargo bench --bench per_frame -- 'VGA/opencv/RGB'
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use gstrsbayer::convert::{Converter, Pattern};
+
+mod common;
+
+use common::{BACKENDS, backend_name, bayer_frame, default_stride};
+
+const RESOLUTIONS: [(&str, usize, usize); 3] =
+    [("64x48", 64, 48), ("QVGA", 320, 240), ("VGA", 640, 480)];
+
+fn per_frame(c: &mut Criterion) {
+    gst::init().unwrap();
+
+    for (resolution, width, height) in RESOLUTIONS {
+        let input = bayer_frame(width, height);
+
+        let mut group = c.benchmark_group(resolution);
+        group.throughput(Throughput::Elements(1));
+
+        for &backend in BACKENDS {
+            for format in Converter::OUTPUT_FORMATS {
+                let out_stride = default_stride(format, width, height);
+                let mut output = vec![0u8; out_stride * height];
+                let new_converter = || {
+                    Converter::new(backend, Pattern::Rggb, width, height, format)
+                        .expect("supported conversion")
+                };
+
+                let id = |setup: &str| {
+                    format!("{}/{}/{}", backend_name(backend), format.to_str(), setup)
+                };
+                let mut converter = new_converter();
+                group.bench_function(id("cached"), |b| {
+                    b.iter(|| {
+                        converter
+                            .convert(&input, width, &mut output, out_stride)
+                            .unwrap()
+                    })
+                });
+                group.bench_function(id("rebuilt"), |b| {
+                    b.iter(|| {
+                        new_converter()
+                            .convert(&input, width, &mut output, out_stride)
+                            .unwrap()
+                    })
+                });
+            }
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, per_frame);
+criterion_main!(benches);
@@ -0,0 +1,206 @@
+// Converts the bayer frames of a V4L2 camera, or of rsbayertestsrc with --synthetic,
+// and shows them or saves them as PNG files, printing the caps the converter
+// negotiated. The plugin is registered from the example itself, nothing needs to be
+// installed:
+//
+//     cargo run --example camera -- --device /dev/video0 --pattern grbg --width 1920 --height 1080
+//     cargo run --example camera -- --synthetic --sink png --num-buffers 10
+//
+// Cameras only stream bayer formats they support, `v4l2-ctl --list-formats-ext` lists
+// them with their sizes and frame rates. The format of the caps is the 2x2 pattern
+// read row by row, e.g. "BA81" is bggr and "GRBG" is grbg.
+
+use gst::prelude::*;
+
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: camera [OPTIONS]
+
+Options:
+  --device PATH        V4L2 device to capture from [default: /dev/video0]
+  --synthetic          Capture from rsbayertestsrc instead of a device
+  --pattern PATTERN    Bayer pattern of the frames: rggb, bggr, gbrg, grbg [default: rggb]
+  --width N            Frame width [default: 640]
+  --height N           Frame height [default: 480]
+  --framerate N        Frames per second [default: 30]
+  --sink SINK          auto to show the frames, png to save them [default: auto]
+  --location PATTERN   File names of the saved frames [default: frame-%05d.png]
+  --num-buffers N      Stop after N frames [default: unlimited]
+  --help               Print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sink {
+    Auto,
+    Png,
+}
+
+#[derive(Debug)]
+struct Options {
+    device: String,
+    synthetic: bool,
+    pattern: gstrsbayer::convert::Pattern,
+    width: u32,
+    height: u32,
+    framerate: u32,
+    sink: Sink,
+    location: String,
+    num_buffers: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            device: "/dev/video0".into(),
+            synthetic: false,
+            pattern: gstrsbayer::convert::Pattern::Rggb,
+            width: 640,
+            height: 480,
+            framerate: 30,
+            sink: Sink::Auto,
+            location: "frame-%05d.png".into(),
+            num_buffers: None,
+        }
+    }
+}
+
+// Parses the options, None when the help was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    fn value<T: std::str::FromStr>(
+        args: &mut impl Iterator<Item = String>,
+        option: &str,
+    ) -> Result<T, String> {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{option} needs a value"))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value {value:?} for {option}"))
+    }
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--device" => options.device = value(&mut args, &arg)?,
+            "--synthetic" => options.synthetic = true,
+            "--pattern" => options.pattern = value(&mut args, &arg)?,
+            "--width" => options.width = value(&mut args, &arg)?,
+            "--height" => options.height = value(&mut args, &arg)?,
+            "--framerate" => options.framerate = value(&mut args, &arg)?,
+            "--sink" => {
+                options.sink = match value::<String>(&mut args, &arg)?.as_str() {
+                    "auto" => Sink::Auto,
+                    "png" => Sink::Png,
+                    other => return Err(format!("unknown sink {other:?}")),
+                }
+            }
+            "--location" => options.location = value(&mut args, &arg)?,
+            "--num-buffers" => options.num_buffers = Some(value(&mut args, &arg)?),
+            "--help" | "-h" => return Ok(None),
+            other => return Err(format!("unknown option {other:?}")),
+        }
+    }
+
+    Ok(Some(options))
+}
+
+fn pipeline_description(options: &Options) -> String {
+    let num_buffers = options
+        .num_buffers
+        .map_or(String::new(), |n| format!(" num-buffers={n}"));
+    let source = if options.synthetic {
+        format!("rsbayertestsrc is-live=true pattern=color-bars{num_buffers}")
+    } else {
+        format!("v4l2src device={}{num_buffers}", options.device)
+    };
+    let sink = match options.sink {
+        Sink::Auto => "autovideosink".to_string(),
+        Sink::Png => format!("pngenc ! multifilesink location={}", options.location),
+    };
+
+    format!(
+        "{source} ! video/x-bayer,format={},width={},height={},framerate={}/1 \
+         ! rsbayer2rgb name=convert ! videoconvert ! {sink}",
+        options.pattern, options.width, options.height, options.framerate,
+    )
+}
+
+// Prints the caps of both converter pads whenever they are (re)negotiated.
+fn print_caps(pipeline: &gst::Pipeline) {
+    let convert = pipeline.by_name("convert").expect("converter is named");
+    for pad in convert.pads() {
+        pad.connect_notify(Some("caps"), |pad, _| {
+            if let Some(caps) = pad.current_caps() {
+                println!("rsbayer2rgb {} caps: {caps}", pad.name());
+            }
+        });
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    gst::init().map_err(|err| format!("Failed to initialize GStreamer: {err}"))?;
+    gstrsbayer::plugin_register_static()
+        .map_err(|err| format!("Failed to register the plugin: {err}"))?;
+
+    let description = pipeline_description(options);
+    println!("Pipeline: {description}");
+    let pipeline = gst::parse::launch(&description)
+        .map_err(|err| format!("Failed to build the pipeline: {err}"))?
+        .downcast::<gst::Pipeline>()
+        .expect("a description with several elements is parsed into a pipeline");
+    print_caps(&pipeline);
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|_| "Failed to start the pipeline".to_string())?;
+
+    let bus = pipeline.bus().expect("pipelines have a bus");
+    let mut res = Ok(());
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Eos(..) => break,
+            MessageView::Error(err) => {
+                let source = err
+                    .src()
+                    .map_or_else(|| "pipeline".to_string(), |src| src.path_string().into());
+                res = Err(match err.debug() {
+                    Some(debug) => format!("Error from {source}: {}\n{debug}", err.error()),
+                    None => format!("Error from {source}: {}", err.error()),
+                });
+                break;
+            }
+            MessageView::Warning(warning) => {
+                eprintln!("Warning: {}", warning.error());
+            }
+            _ => (),
+        }
+    }
+
+    let _ = pipeline.set_state(gst::State::Null);
+
+    res
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Automatic white balance computed from the raw channel means, applied through the
+// same raw gains as the manual red/green/blue-gain properties, the automatic balance
+// of the two greens, and the gains of a color temperature.
+
+use super::cfa::CfaColor;
+use super::raw::{Gains, MAX_GAIN};
+
+// Fraction of the distance to the measured gains covered every frame, so a sudden
+// change in the scene fades in over a few frames instead of flickering.
+const GRAY_WORLD_DAMPING: f64 = 0.5;
+// The same for the green balance. The sensitivity difference of the greens doesn't
+// change with the scene, so it can settle slowly and ride out noisy frames.
+const GREEN_BALANCE_DAMPING: f64 = 0.25;
+
+/// Range of the green balance, manual or measured.
+pub const GREEN_BALANCE_RANGE: (f64, f64) = (0.5, 2.0);
+
+/// Range of color temperatures in kelvin the gains are computed for.
+pub const TEMPERATURE_RANGE: (u32, u32) = (2500, 10000);
+// Change of the red and blue gains at the ends of the tint range.
+const TINT_RANGE: f64 = 0.25;
+
+/// Gray world: assumes the scene averages to gray, so red and blue are scaled until
+/// their means match the green one. `means` are indexed by `CfaColor as usize` and
+/// `applied` are the gains used for the previous frame. A channel without signal keeps
+/// its gain.
+pub fn gray_world(means: [f64; 4], applied: Gains) -> Gains {
+    let green = (means[CfaColor::GreenRed as usize] + means[CfaColor::GreenBlue as usize]) / 2.0;
+    let target = |mean: f64, gain: f64| {
+        if mean < 1.0 || green < 1.0 {
+            return gain;
+        }
+        let measured = (green / mean).clamp(1.0 / MAX_GAIN, MAX_GAIN);
+        gain + GRAY_WORLD_DAMPING * (measured - gain)
+    };
+
+    Gains {
+        red: target(means[CfaColor::Red as usize], applied.red),
+        green: 1.0,
+        blue: target(means[CfaColor::Blue as usize], applied.blue),
+        green_balance: applied.green_balance,
+    }
+}
+
+/// Gains that make a gray card with the channel `means` neutral, indexed by
+/// `CfaColor as usize`, green at unity and the green balance of `applied` kept. None
+/// unless every color has signal.
+pub fn gray_card(means: [f64; 4], applied: Gains) -> Option<Gains> {
+    if means.iter().any(|&mean| mean < 1.0) {
+        return None;
+    }
+    let green = (means[CfaColor::GreenRed as usize] + means[CfaColor::GreenBlue as usize]) / 2.0;
+    let gain = |mean: f64| (green / mean).clamp(1.0 / MAX_GAIN, MAX_GAIN);
+
+    Some(Gains {
+        red: gain(means[CfaColor::Red as usize]),
+        green: 1.0,
+        blue: gain(means[CfaColor::Blue as usize]),
+        green_balance: applied.green_balance,
+    })
+}
+
+/// Moves the gain of the blue row greens towards matching their mean to that of the
+/// red row greens. `means` are indexed by `CfaColor as usize` and `applied` is the
+/// green balance of the previous frame, kept without signal.
+pub fn green_balance(means: [f64; 4], applied: f64) -> f64 {
+    let (gr, gb) = (
+        means[CfaColor::GreenRed as usize],
+        means[CfaColor::GreenBlue as usize],
+    );
+    if gr < 1.0 || gb < 1.0 {
+        return applied;
+    }
+    let measured = (gr / gb).clamp(GREEN_BALANCE_RANGE.0, GREEN_BALANCE_RANGE.1);
+    applied + GREEN_BALANCE_DAMPING * (measured - applied)
+}
+
+/// Gains that make the light of a black body at `temperature` kelvin neutral, green at
+/// unity, taking the raw channels for sRGB primaries as nothing tells the element
+/// about the sensor's. The chromaticity comes from the cubic approximation of the
+/// Planckian locus of Kim et al., which holds from 1667 K to 25000 K. `tint` from -1.0
+/// to 1.0 scales red and blue against green, moving a green cast towards magenta.
+pub fn temperature_gains(temperature: f64, tint: f64) -> Gains {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x * x + 3.75112997 * x - 0.37001483
+    };
+
+    // XYZ of the light at unit luminance, then linear sRGB.
+    let (big_x, big_z) = (x / y, (1.0 - x - y) / y);
+    let red = 3.2406 * big_x - 1.5372 - 0.4986 * big_z;
+    let green = -0.9689 * big_x + 1.8758 + 0.0415 * big_z;
+    let blue = 0.0557 * big_x - 0.2040 + 1.0570 * big_z;
+    let tint = 1.0 + TINT_RANGE * tint.clamp(-1.0, 1.0);
+
+    Gains {
+        red: (tint * green / red).clamp(1.0 / MAX_GAIN, MAX_GAIN),
+        green: 1.0,
+        blue: (tint * green / blue).clamp(1.0 / MAX_GAIN, MAX_GAIN),
+        green_balance: 1.0,
+    }
+}
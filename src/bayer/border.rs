@@ -0,0 +1,88 @@
+// Border handling of the demosaic.
+//
+// Interpolating the outermost pixels of a frame lacks the neighbors on one side, so
+// most demosaics, OpenCV's among them, get the colors of a border about two pixels
+// wide visibly wrong. Replicate pads the raw frame by that border before demosaic and
+// keeps the original window of the result, crop demosaics the frame itself and drops
+// the border from the output.
+//
+// Padding repeats the nearest sample of the same CFA color rather than the nearest
+// sample, so the padded frame keeps the pattern of the frame: the two columns left of
+// the frame are copies of its first two columns, the two right of it of its last two,
+// and the same for the rows.
+
+use super::frame::{Error, fits};
+use super::raw::Sample;
+
+/// Samples added at every edge by padding, and pixels dropped by cropping. Even, so
+/// the CFA phase stays the same.
+pub const BORDER: usize = 2;
+
+/// Pads a `width` x `height` frame of bayer samples by [`BORDER`] samples on every
+/// side into `output`, a frame of `width + 2 * BORDER` samples per row without
+/// padding. The stride is in samples.
+pub fn pad<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    output: &mut Vec<S>,
+) -> Result<(), Error> {
+    if width < 2 || height < 2 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, in_stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let padded_width = width + 2 * BORDER;
+    output.clear();
+    output.reserve(padded_width * (height + 2 * BORDER));
+    for y in 0..height + 2 * BORDER {
+        let row = &input[source(y, height) * in_stride..][..width];
+        output.extend((0..padded_width).map(|x| row[source(x, width)]));
+    }
+
+    Ok(())
+}
+
+/// Copies `width` x `height` pixels of `pixel_stride` samples from [`BORDER`] pixels
+/// in from the top-left corner of `input` to `output`. Strides are in samples.
+pub fn crop<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pixel_stride: usize,
+    output: &mut [S],
+    out_stride: usize,
+) -> Result<(), Error> {
+    let row_len = width * pixel_stride;
+    let offset = BORDER * in_stride + BORDER * pixel_stride;
+    if !fits(
+        input.len().saturating_sub(offset),
+        height,
+        row_len,
+        in_stride,
+    ) || !fits(output.len(), height, row_len, out_stride)
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        output[y * out_stride..][..row_len]
+            .copy_from_slice(&input[offset + y * in_stride..][..row_len]);
+    }
+
+    Ok(())
+}
+
+// Position within a frame of `size` samples of the one padded position `padded` is
+// copied from, of the same parity.
+fn source(padded: usize, size: usize) -> usize {
+    match padded.checked_sub(BORDER) {
+        None => padded % 2,
+        Some(pos) if pos < size => pos,
+        Some(pos) => pos - 2 * ((pos - size) / 2 + 1),
+    }
+}
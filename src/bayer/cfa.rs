@@ -0,0 +1,117 @@
+// Color filter array layouts shared by the conversion backends.
+
+use std::fmt;
+use std::str::FromStr;
+
+use gst::glib;
+
+/// Arrangement of the 2x2 color filter tile, named after its top-left quad read row by
+/// row as in the `video/x-bayer` caps format field.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayerPattern")]
+pub enum Pattern {
+    #[default]
+    #[enum_value(name = "Red, green / green, blue", nick = "rggb")]
+    Rggb = 0,
+    #[enum_value(name = "Blue, green / green, red", nick = "bggr")]
+    Bggr = 1,
+    #[enum_value(name = "Green, blue / red, green", nick = "gbrg")]
+    Gbrg = 2,
+    #[enum_value(name = "Green, red / blue, green", nick = "grbg")]
+    Grbg = 3,
+}
+
+/// Color of a single CFA site. Greens are told apart by the color sharing their row
+/// since sensors often treat them differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CfaColor {
+    Red,
+    GreenRed,
+    GreenBlue,
+    Blue,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 4] = [Pattern::Rggb, Pattern::Bggr, Pattern::Gbrg, Pattern::Grbg];
+
+    pub fn from_caps_format(format: &str) -> Option<Self> {
+        match format {
+            "rggb" => Some(Pattern::Rggb),
+            "bggr" => Some(Pattern::Bggr),
+            "gbrg" => Some(Pattern::Gbrg),
+            "grbg" => Some(Pattern::Grbg),
+            _ => None,
+        }
+    }
+
+    pub fn to_caps_format(self) -> &'static str {
+        match self {
+            Pattern::Rggb => "rggb",
+            Pattern::Bggr => "bggr",
+            Pattern::Gbrg => "gbrg",
+            Pattern::Grbg => "grbg",
+        }
+    }
+
+    /// The top-left 2x2 tile, indexed `[y][x]`.
+    pub fn tile(self) -> [[CfaColor; 2]; 2] {
+        use CfaColor::*;
+
+        match self {
+            Pattern::Rggb => [[Red, GreenRed], [GreenBlue, Blue]],
+            Pattern::Bggr => [[Blue, GreenBlue], [GreenRed, Red]],
+            Pattern::Gbrg => [[GreenBlue, Blue], [Red, GreenRed]],
+            Pattern::Grbg => [[GreenRed, Red], [Blue, GreenBlue]],
+        }
+    }
+
+    pub fn color_at(self, x: usize, y: usize) -> CfaColor {
+        self.tile()[y & 1][x & 1]
+    }
+
+    /// Pattern of the part of a frame starting at column `x` and row `y`.
+    pub fn offset(self, x: usize, y: usize) -> Pattern {
+        let tile = [
+            [self.color_at(x, y), self.color_at(x + 1, y)],
+            [self.color_at(x, y + 1), self.color_at(x + 1, y + 1)],
+        ];
+        Pattern::ALL
+            .into_iter()
+            .find(|pattern| pattern.tile() == tile)
+            .expect("shifted bayer tiles are bayer tiles")
+    }
+}
+
+impl CfaColor {
+    pub const ALL: [CfaColor; 4] = [
+        CfaColor::Red,
+        CfaColor::GreenRed,
+        CfaColor::GreenBlue,
+        CfaColor::Blue,
+    ];
+
+    /// Name used for the color in messages and structures.
+    pub fn nick(self) -> &'static str {
+        match self {
+            CfaColor::Red => "red",
+            CfaColor::GreenRed => "green-red",
+            CfaColor::GreenBlue => "green-blue",
+            CfaColor::Blue => "blue",
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_caps_format())
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pattern::from_caps_format(s).ok_or_else(|| format!("unknown bayer pattern {s:?}"))
+    }
+}
@@ -0,0 +1,92 @@
+// False color suppression after demosaic.
+//
+// Detail near the sampling limit of the mosaic, like fabric or test charts, demosaics
+// into colored aliasing: the luma is right but the chroma flips from pixel to pixel.
+// Every pixel is split into BT.601 luma and the red and blue color differences, the
+// differences go through a 3x3 median and the pixel is put together again from the
+// untouched luma. A median drops the isolated chroma spikes of aliasing while keeping
+// the chroma edges of real colored objects in place.
+
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+
+// BT.601 luma weights of red, green and blue, in thousandths.
+const LUMA: [i64; 3] = [299, 587, 114];
+
+/// Moves the chroma of every pixel of a `width` x `height` output frame of `layout`
+/// towards the median of its 3x3 neighborhood, all the way with a `strength` of 1.
+/// Luma is kept up to rounding and clipping. `scratch` holds the color differences
+/// between frames. The stride is in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn suppress<S: Sample>(
+    output: &mut [S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    strength: f64,
+    scratch: &mut Vec<i64>,
+) -> Result<(), Error> {
+    if !fits(output.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    // Luma, red and blue differences of every pixel, the luma in thousandths.
+    scratch.resize(3 * width * height, 0);
+    let (luma, chroma) = scratch.split_at_mut(width * height);
+    let (cr, cb) = chroma.split_at_mut(width * height);
+    for y in 0..height {
+        let row = &output[y * stride..][..width * layout.pixel_stride];
+        for (x, pixel) in row.chunks_exact(layout.pixel_stride).enumerate() {
+            let rgb = [layout.red, layout.green, layout.blue].map(|i| pixel[i].to_u64() as i64);
+            let y1000 = LUMA[0] * rgb[0] + LUMA[1] * rgb[1] + LUMA[2] * rgb[2];
+            let i = y * width + x;
+            luma[i] = y1000;
+            cr[i] = 1000 * rgb[0] - y1000;
+            cb[i] = 1000 * rgb[2] - y1000;
+        }
+    }
+
+    let strength = (strength.clamp(0.0, 1.0) * 1024.0).round() as i64;
+    let max = S::MAX as i64;
+    for y in 0..height {
+        let row = &mut output[y * stride..][..width * layout.pixel_stride];
+        for (x, pixel) in row.chunks_exact_mut(layout.pixel_stride).enumerate() {
+            let i = y * width + x;
+            let towards_median = |plane: &[i64]| {
+                let value = plane[i];
+                value + (median(plane, width, height, x, y) - value) * strength / 1024
+            };
+            let (red_diff, blue_diff) = (towards_median(cr), towards_median(cb));
+
+            let y1000 = luma[i];
+            let red = (y1000 + red_diff).clamp(0, 1000 * max);
+            let blue = (y1000 + blue_diff).clamp(0, 1000 * max);
+            let green = (1000 * y1000 - LUMA[0] * red - LUMA[2] * blue).clamp(0, 587_000 * max);
+            let round = |value: i64, scale: i64| S::from_u64(((value + scale / 2) / scale) as u64);
+            pixel[layout.red] = round(red, 1000);
+            pixel[layout.green] = round(green, LUMA[1] * 1000);
+            pixel[layout.blue] = round(blue, 1000);
+        }
+    }
+
+    Ok(())
+}
+
+// Median of the 3x3 neighborhood of `x`, `y` in a `width` x `height` plane, edges
+// repeated.
+fn median(plane: &[i64], width: usize, height: usize, x: usize, y: usize) -> i64 {
+    let mut values = [0; 9];
+    let rows = [y.saturating_sub(1), y, (y + 1).min(height - 1)];
+    let cols = [x.saturating_sub(1), x, (x + 1).min(width - 1)];
+    for (value, (row, col)) in values.iter_mut().zip(
+        rows.iter()
+            .flat_map(|&row| cols.iter().map(move |&col| (row, col))),
+    ) {
+        *value = plane[row * width + col];
+    }
+    *values.select_nth_unstable(4).1
+}
@@ -0,0 +1,1584 @@
+// Conversion of single frames held in plain slices. The element drives this from
+// `transform()`, and it can equally be driven without any pipeline, e.g. from the
+// benchmarks. The enums parse from their property nicks and the output formats from
+// their caps names with `FromStr`, for tools taking them on the command line.
+
+pub use super::awb::temperature_gains;
+pub use super::cfa::{CfaColor, Pattern};
+#[cfg(feature = "opencv")]
+pub use super::cv::Options as OpenCvOptions;
+pub use super::defects::{AutoDefects, DefectList, DefectMap};
+pub use super::fpn::Fpn;
+pub use super::frame::OutputLayout;
+pub use super::raw::Gains;
+pub use super::tone::AutoLevels;
+pub use super::{
+    AwbMode, Backend, BorderMode, CfaLayout, DemosaicAlgorithm, FieldMode, GrGbBalance,
+    HighlightMode, InputPattern, Method, QuadMode, ToneMapping,
+};
+
+use super::border::{self, BORDER};
+use super::chroma;
+#[cfg(feature = "opencv")]
+use super::cv;
+use super::decompand;
+use super::defects;
+use super::demosaic;
+#[cfg(feature = "opencv")]
+use super::filter;
+use super::imp::CAT;
+use super::orient;
+use super::precision;
+use super::raw::{self, Sample};
+use super::superpixel;
+use super::tone;
+use super::zebra;
+
+// Fractions of the samples below the black and the white point of
+// ToneMapping::PercentileAuto.
+const AUTO_PERCENTILES: (f64, f64) = (0.005, 0.995);
+// How far the points move towards those of every new frame, so a passing highlight
+// doesn't make the exposure pump.
+const AUTO_DAMPING: f64 = 0.25;
+
+/// Demosaics 8-bit bayer frames of one size and pattern into one output format,
+/// keeping the scratch memory and device state the backend needs between frames.
+pub struct Converter {
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+    format: gst_video::VideoFormat,
+    inner: Inner,
+    algorithm: DemosaicAlgorithm,
+    // Border handling of full resolution conversions, the backend state for the padded
+    // frame of BorderMode::Replicate and the frames around the demosaic.
+    border_mode: BorderMode,
+    padded_inner: Option<Inner>,
+    border_scratch: BorderScratch<u8>,
+    wide_border_scratch: BorderScratch<u16>,
+    black_level: [u16; 4],
+    gains: Gains,
+    exposure_gain: f64,
+    highlight_mode: HighlightMode,
+    // Master dark of the frame size and the input with it subtracted.
+    dark_frame: Option<Vec<u8>>,
+    dark_scratch: Vec<u8>,
+    // The dark frame promoted to 16 bits, made by the first frame of deep input.
+    wide_dark: Vec<u16>,
+    defects: Option<DefectMap>,
+    auto_defects: Option<AutoDefects>,
+    detector: defects::Detector,
+    // Sites the detector corrected in the last frame.
+    corrected_defects: usize,
+    // Lens shading gain of every sample, and the same folded with the white balance and
+    // exposure gains for as long as they don't change.
+    shading: Option<Vec<f32>>,
+    shading_gains: Option<(Gains, Vec<u32>)>,
+    // Fixed pattern noise offsets and the same in the levels of the samples corrected.
+    fpn: Option<Fpn>,
+    fpn_offsets: raw::Offsets,
+    // Corrected input, only used when there is anything to correct.
+    raw_scratch: Vec<u8>,
+    gamma: f64,
+    tone_curve: Option<Vec<u16>>,
+    brightness: f64,
+    contrast: f64,
+    saturation: f64,
+    // Tone mapping of the output, None while it would be the identity.
+    tone: Option<tone::Lut<u8>>,
+    // Strength of the chroma median after demosaic, and its color differences.
+    false_color_suppression: f64,
+    chroma_scratch: Vec<i64>,
+    // 16-bit level of every input code, which makes the conversion run at 16 bits.
+    decompanding: Option<Vec<u16>>,
+    // Stages from the raw corrections to the saturation on samples promoted to 16 bits,
+    // and whether their result is dithered to the output.
+    high_precision: bool,
+    dither: bool,
+    // Tone mapping of 16-bit samples, only kept while in high precision.
+    wide_tone: Option<tone::Lut<u16>>,
+    // Reduction of the demosaiced 16-bit samples to the output range, its black and
+    // white points and the lookup table, None while it is the identity.
+    tone_mapping: ToneMapping,
+    black_point: f64,
+    white_point: f64,
+    reduction: Option<tone::Lut<u16>>,
+    // Points measured with ToneMapping::PercentileAuto, damped over the frames.
+    auto_points: Option<(f64, f64)>,
+    // Stretch of the output and its levels, damped over the frames.
+    auto_levels: Option<AutoLevels>,
+    levels: Option<(f64, f64)>,
+    // Promoted input, the same corrected and the 16-bit output before quantization.
+    wide_input: Vec<u16>,
+    wide_raw: Vec<u16>,
+    wide_output: Vec<u16>,
+    // Raw level above which the output gets zebra stripes, as a fraction of full scale.
+    zebra: Option<f64>,
+    // Set for Method::Superpixel, which bypasses the backend.
+    superpixel: Option<OutputLayout>,
+    // Flip or rotation of the output, and the frame before it while there is one.
+    orientation: orient::Transform,
+    unoriented: Vec<u8>,
+    #[cfg(feature = "opencv")]
+    opencv_options: OpenCvOptions,
+    #[cfg(feature = "opencv")]
+    filters: filter::Filters,
+}
+
+enum Inner {
+    #[cfg(feature = "opencv")]
+    OpenCv(cv::Converter),
+    #[cfg(feature = "rust-demosaic")]
+    Rust {
+        layout: OutputLayout,
+        pattern: Pattern,
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Inner {
+    fn new(
+        backend: Backend,
+        pattern: Pattern,
+        width: usize,
+        height: usize,
+        format: gst_video::VideoFormat,
+    ) -> Result<Self, String> {
+        Ok(match backend {
+            #[cfg(feature = "opencv")]
+            Backend::OpenCv => Inner::OpenCv(cv::Converter::new(pattern, width, height, format)?),
+            #[cfg(feature = "rust-demosaic")]
+            Backend::Rust => Inner::Rust {
+                layout: OutputLayout::for_format(format)
+                    .ok_or_else(|| format!("Unsupported output format {format:?}"))?,
+                pattern,
+                width,
+                height,
+            },
+        })
+    }
+
+    // Demosaics a frame of the size and pattern the backend state was made for.
+    fn demosaic(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+        #[cfg(feature = "opencv")] options: &OpenCvOptions,
+    ) -> Result<(), gst::FlowError> {
+        match *self {
+            #[cfg(feature = "opencv")]
+            Inner::OpenCv(ref mut converter) => {
+                converter.convert(input, in_stride, output, out_stride, options)
+            }
+            #[cfg(feature = "rust-demosaic")]
+            Inner::Rust {
+                layout,
+                pattern,
+                width,
+                height,
+            } => demosaic::bilinear(
+                input, in_stride, width, height, pattern, output, out_stride, layout,
+            )
+            .map_err(|err| {
+                gst::error!(CAT, "Demosaic failed: {}", err);
+                gst::FlowError::Error
+            }),
+        }
+    }
+
+    fn set_algorithm(&mut self, algorithm: DemosaicAlgorithm) {
+        match self {
+            #[cfg(feature = "opencv")]
+            Inner::OpenCv(converter) => converter.set_algorithm(algorithm),
+            #[cfg(feature = "rust-demosaic")]
+            Inner::Rust { .. } => {
+                if algorithm != DemosaicAlgorithm::Bilinear {
+                    gst::warning!(
+                        CAT,
+                        "{:?} demosaic needs the OpenCV backend, using bilinear",
+                        algorithm
+                    );
+                }
+            }
+        }
+    }
+}
+
+// The padded bayer frame and the demosaiced frame a border mode goes through.
+struct BorderScratch<S> {
+    padded: Vec<S>,
+    demosaiced: Vec<S>,
+}
+
+impl<S> Default for BorderScratch<S> {
+    fn default() -> Self {
+        BorderScratch {
+            padded: Vec::new(),
+            demosaiced: Vec::new(),
+        }
+    }
+}
+
+impl Converter {
+    /// Output formats the converter supports, in order of preference.
+    pub const OUTPUT_FORMATS: [gst_video::VideoFormat; 3] = [
+        gst_video::VideoFormat::Rgba,
+        gst_video::VideoFormat::Rgb,
+        gst_video::VideoFormat::Bgr,
+    ];
+
+    pub fn new(
+        backend: Backend,
+        pattern: Pattern,
+        width: usize,
+        height: usize,
+        format: gst_video::VideoFormat,
+    ) -> Result<Self, String> {
+        let inner = Inner::new(backend, pattern, width, height, format)?;
+
+        Ok(Converter {
+            pattern,
+            width,
+            height,
+            format,
+            inner,
+            algorithm: DemosaicAlgorithm::default(),
+            border_mode: BorderMode::default(),
+            padded_inner: None,
+            border_scratch: BorderScratch::default(),
+            wide_border_scratch: BorderScratch::default(),
+            black_level: [0; 4],
+            gains: Gains::default(),
+            exposure_gain: 1.0,
+            highlight_mode: HighlightMode::default(),
+            dark_frame: None,
+            dark_scratch: Vec::new(),
+            wide_dark: Vec::new(),
+            defects: None,
+            auto_defects: None,
+            detector: defects::Detector::default(),
+            corrected_defects: 0,
+            shading: None,
+            shading_gains: None,
+            fpn: None,
+            fpn_offsets: raw::Offsets::default(),
+            raw_scratch: Vec::new(),
+            gamma: 1.0,
+            tone_curve: None,
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            tone: None,
+            false_color_suppression: 0.0,
+            chroma_scratch: Vec::new(),
+            decompanding: None,
+            high_precision: false,
+            dither: false,
+            wide_tone: None,
+            tone_mapping: ToneMapping::default(),
+            black_point: 0.0,
+            white_point: 1.0,
+            reduction: None,
+            auto_points: None,
+            auto_levels: None,
+            levels: None,
+            wide_input: Vec::new(),
+            wide_raw: Vec::new(),
+            wide_output: Vec::new(),
+            zebra: None,
+            superpixel: None,
+            orientation: orient::Transform::default(),
+            unoriented: Vec::new(),
+            #[cfg(feature = "opencv")]
+            opencv_options: OpenCvOptions::default(),
+            #[cfg(feature = "opencv")]
+            filters: filter::Filters::default(),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.inner {
+            #[cfg(feature = "opencv")]
+            Inner::OpenCv(_) => Backend::OpenCv,
+            #[cfg(feature = "rust-demosaic")]
+            Inner::Rust { .. } => Backend::Rust,
+        }
+    }
+
+    /// Switches to `backend` for the following frames, keeping size, pattern and format.
+    pub fn set_backend(&mut self, backend: Backend) -> Result<(), String> {
+        if backend == self.backend() {
+            return Ok(());
+        }
+
+        let mut converter =
+            Converter::new(backend, self.pattern, self.width, self.height, self.format)?;
+        converter.superpixel = self.superpixel;
+        converter.orientation = self.orientation;
+        converter.black_level = self.black_level;
+        converter.gains = self.gains;
+        converter.exposure_gain = self.exposure_gain;
+        converter.highlight_mode = self.highlight_mode;
+        converter.dark_frame = self.dark_frame.take();
+        converter.defects = self.defects.take();
+        converter.auto_defects = self.auto_defects;
+        converter.detector = std::mem::take(&mut self.detector);
+        converter.shading = self.shading.take();
+        converter.fpn = self.fpn.take();
+        converter.gamma = self.gamma;
+        converter.tone_curve = self.tone_curve.take();
+        converter.brightness = self.brightness;
+        converter.contrast = self.contrast;
+        converter.saturation = self.saturation;
+        converter.tone = self.tone.take();
+        converter.false_color_suppression = self.false_color_suppression;
+        converter.decompanding = self.decompanding.take();
+        converter.high_precision = self.high_precision;
+        converter.dither = self.dither;
+        converter.wide_tone = self.wide_tone.take();
+        converter.tone_mapping = self.tone_mapping;
+        converter.black_point = self.black_point;
+        converter.white_point = self.white_point;
+        converter.reduction = self.reduction.take();
+        converter.auto_points = self.auto_points;
+        converter.auto_levels = self.auto_levels;
+        converter.levels = self.levels;
+        converter.zebra = self.zebra;
+        #[cfg(feature = "opencv")]
+        {
+            converter.opencv_options = self.opencv_options.clone();
+        }
+        converter.set_algorithm(self.algorithm);
+        converter.set_border_mode(self.border_mode)?;
+        *self = converter;
+
+        Ok(())
+    }
+
+    /// A converter of a `width` x `height` window of the frame, whose CFA pattern is
+    /// `pattern`, with the backend, method, direction, algorithm and border mode of
+    /// this one. The corrections and tone settings start out at their defaults.
+    pub fn for_window(
+        &self,
+        pattern: Pattern,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, String> {
+        let mut converter = Converter::new(self.backend(), pattern, width, height, self.format)?;
+        converter.superpixel = self.superpixel;
+        converter.orientation = self.orientation;
+        converter.set_algorithm(self.algorithm);
+        converter.set_border_mode(self.border_mode)?;
+
+        Ok(converter)
+    }
+
+    pub fn method(&self) -> Method {
+        match self.superpixel {
+            Some(_) => Method::Superpixel,
+            None => Method::Full,
+        }
+    }
+
+    /// With [`Method::Superpixel`] the output frame has half the input width and height.
+    pub fn set_method(&mut self, method: Method) -> Result<(), String> {
+        self.superpixel = match method {
+            Method::Full => None,
+            Method::Superpixel => Some(
+                OutputLayout::for_format(self.format)
+                    .ok_or_else(|| format!("Unsupported output format {:?}", self.format))?,
+            ),
+        };
+
+        Ok(())
+    }
+
+    /// Flips or rotates the output. Rotations by 90 degrees swap the output width and
+    /// height. Automatic and custom directions aren't supported and leave the output
+    /// as it is.
+    pub fn set_direction(&mut self, direction: gst_video::VideoOrientationMethod) {
+        self.orientation = orient::Transform::for_direction(direction);
+    }
+
+    pub fn algorithm(&self) -> DemosaicAlgorithm {
+        self.algorithm
+    }
+
+    /// Selects the interpolation used by full resolution conversions. Backends without
+    /// `algorithm` log a warning and keep converting bilinearly.
+    pub fn set_algorithm(&mut self, algorithm: DemosaicAlgorithm) {
+        if algorithm == self.algorithm {
+            return;
+        }
+        self.algorithm = algorithm;
+
+        self.inner.set_algorithm(algorithm);
+        if let Some(ref mut inner) = self.padded_inner {
+            inner.set_algorithm(algorithm);
+        }
+    }
+
+    pub fn border_mode(&self) -> BorderMode {
+        self.border_mode
+    }
+
+    /// How full resolution conversions treat the border of the frame. With
+    /// [`BorderMode::Crop`] the output frame is `2 * BORDER` pixels narrower and lower
+    /// than the input.
+    pub fn set_border_mode(&mut self, mode: BorderMode) -> Result<(), String> {
+        if mode == self.border_mode {
+            return Ok(());
+        }
+
+        self.padded_inner = match mode {
+            BorderMode::Replicate => {
+                let (width, height) = (self.width + 2 * BORDER, self.height + 2 * BORDER);
+                let mut inner =
+                    Inner::new(self.backend(), self.pattern, width, height, self.format)?;
+                inner.set_algorithm(self.algorithm);
+                Some(inner)
+            }
+            BorderMode::None | BorderMode::Crop => None,
+        };
+        self.border_mode = mode;
+
+        Ok(())
+    }
+
+    /// Pixels dropped from every edge of the output by [`BorderMode::Crop`].
+    pub fn cropped_border(&self) -> usize {
+        match (self.border_mode, self.superpixel) {
+            (BorderMode::Crop, None) => BORDER,
+            _ => 0,
+        }
+    }
+
+    /// Dark frame subtracted from the input before any other correction, a frame of
+    /// bayer samples without padding. None disables dark frame subtraction.
+    pub fn set_dark_frame(&mut self, dark_frame: Option<Vec<u8>>) {
+        self.dark_frame = dark_frame;
+        self.wide_dark.clear();
+    }
+
+    /// Defective sites patched after the black level, shading and white balance
+    /// corrections. None disables defective pixel correction.
+    pub fn set_defects(&mut self, defects: Option<DefectMap>) {
+        self.defects = defects;
+    }
+
+    /// Detection and correction of defective pixels on the frames themselves, after
+    /// those of the defect list. None disables it.
+    pub fn set_auto_defects(&mut self, auto_defects: Option<AutoDefects>) {
+        if auto_defects.is_none() {
+            self.detector.reset();
+            self.corrected_defects = 0;
+        }
+        self.auto_defects = auto_defects;
+    }
+
+    /// Number of defective pixels detected and corrected in the last frame.
+    pub fn corrected_defects(&self) -> usize {
+        self.corrected_defects
+    }
+
+    /// Black level subtracted from the bayer samples of each color before the gains
+    /// are applied, indexed by `CfaColor as usize`: red, red row green, blue row green
+    /// and blue.
+    pub fn set_black_level(&mut self, black_level: [u16; 4]) {
+        self.black_level = black_level;
+    }
+
+    /// White balance gains applied to the bayer samples before demosaic.
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+    }
+
+    /// Lens shading gain of every sample of the input frame, row major, multiplied in
+    /// together with the white balance gains. None disables shading correction.
+    pub fn set_shading(&mut self, shading: Option<Vec<f32>>) {
+        self.shading = shading;
+        self.shading_gains = None;
+    }
+
+    /// Column and row offsets of the input frame added to the black level, in input
+    /// sample levels. None disables fixed pattern noise correction.
+    pub fn set_fpn(&mut self, fpn: Option<Fpn>) {
+        self.fpn = fpn;
+    }
+
+    /// Digital gain applied to every bayer sample along with the white balance gains.
+    pub fn set_exposure_gain(&mut self, gain: f64) {
+        self.exposure_gain = gain;
+    }
+
+    /// Handling of the highlights the gains clip in some colors only.
+    pub fn set_highlight_mode(&mut self, mode: HighlightMode) {
+        self.highlight_mode = mode;
+    }
+
+    /// Gamma encoding of the output, 1.0 leaves it linear. Unused while a tone curve
+    /// is set.
+    pub fn set_gamma(&mut self, gamma: f64) {
+        if gamma == self.gamma {
+            return;
+        }
+
+        self.gamma = gamma;
+        self.update_tone();
+    }
+
+    /// Tone curve applied to the output instead of the gamma, mapping `curve.len()`
+    /// input levels to as many output levels. Curves with fewer than two points are
+    /// ignored.
+    pub fn set_tone_curve(&mut self, curve: Option<&[u16]>) {
+        if curve == self.tone_curve.as_deref() {
+            return;
+        }
+
+        self.tone_curve = curve.map(<[u16]>::to_vec);
+        self.update_tone();
+    }
+
+    /// Brightness offset, 0.0 for none, and contrast around mid-gray, 1.0 for none,
+    /// applied after the gamma or tone curve.
+    pub fn set_brightness_contrast(&mut self, brightness: f64, contrast: f64) {
+        if (brightness, contrast) == (self.brightness, self.contrast) {
+            return;
+        }
+
+        self.brightness = brightness;
+        self.contrast = contrast;
+        self.update_tone();
+    }
+
+    /// Color saturation of the output, 0.0 gives grayscale and 1.0 leaves it as is.
+    pub fn set_saturation(&mut self, saturation: f64) {
+        self.saturation = saturation;
+    }
+
+    /// Promotes the samples to 16 bits for every stage from the raw corrections to the
+    /// saturation and quantizes them to the output at the end, rounding or, with
+    /// `dither`, with an ordered dither. The demosaic is then always bilinear and the
+    /// OpenCV filters run on the quantized output, as both only take 8-bit frames.
+    pub fn set_high_precision(&mut self, high_precision: bool, dither: bool) {
+        self.dither = dither;
+        if high_precision == self.high_precision {
+            return;
+        }
+
+        self.high_precision = high_precision;
+        self.update_tone();
+    }
+
+    /// Expands companded input through `lut`, the 16-bit linear level of every 8-bit
+    /// code, before any other correction but the dark frame. While set every stage
+    /// runs at 16 bits like with [`Converter::set_high_precision`], and the black level
+    /// goes through the lookup table as well. None takes the input as linear.
+    pub fn set_decompanding(&mut self, lut: Option<&[u16]>) {
+        if lut == self.decompanding.as_deref() {
+            return;
+        }
+
+        self.decompanding = lut.map(<[u16]>::to_vec);
+        self.update_tone();
+    }
+
+    // Whether the stages run on 16-bit samples.
+    fn wide(&self) -> bool {
+        self.high_precision || self.decompanding.is_some()
+    }
+
+    /// Reduction of the 16-bit samples of high-precision processing to the output
+    /// range right after demosaic. `black_point` and `white_point` are fractions of
+    /// full scale, with [`ToneMapping::PercentileAuto`] they replace the measured
+    /// points unless they are 0.0 and 1.0.
+    pub fn set_tone_mapping(&mut self, mapping: ToneMapping, black_point: f64, white_point: f64) {
+        if (mapping, black_point, white_point)
+            == (self.tone_mapping, self.black_point, self.white_point)
+        {
+            return;
+        }
+
+        self.tone_mapping = mapping;
+        self.black_point = black_point;
+        self.white_point = white_point;
+        self.reduction = match mapping {
+            ToneMapping::Linear if (black_point, white_point) == (0.0, 1.0) => None,
+            // Made anew for every frame.
+            ToneMapping::PercentileAuto => None,
+            _ => Some(tone::Lut::reduction(mapping, black_point, white_point)),
+        };
+    }
+
+    // Black and white points of ToneMapping::PercentileAuto for a demosaiced frame,
+    // damped towards those of the frame.
+    fn auto_points(
+        &mut self,
+        frame: &[u16],
+        stride: usize,
+        layout: OutputLayout,
+    ) -> Result<(f64, f64), gst::FlowError> {
+        let (width, height) = self.unoriented_size();
+        let (black, white) = tone::percentiles(
+            frame,
+            stride,
+            width,
+            height,
+            layout,
+            AUTO_PERCENTILES.0,
+            AUTO_PERCENTILES.1,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "Measuring the percentiles failed: {}", err);
+            gst::FlowError::Error
+        })?;
+        let (black, white) = match self.auto_points {
+            Some((last_black, last_white)) => (
+                last_black + (black - last_black) * AUTO_DAMPING,
+                last_white + (white - last_white) * AUTO_DAMPING,
+            ),
+            None => (black, white),
+        };
+        self.auto_points = Some((black, white));
+
+        // Points set explicitly win over the measured ones.
+        let black = if self.black_point != 0.0 {
+            self.black_point
+        } else {
+            black
+        };
+        let white = if self.white_point != 1.0 {
+            self.white_point
+        } else {
+            white
+        };
+        Ok((black, white))
+    }
+
+    /// Stretches the output linearly between percentiles of its luma measured on every
+    /// frame, before gamma and the other tone stages. None disables it.
+    pub fn set_auto_levels(&mut self, auto_levels: Option<AutoLevels>) {
+        if auto_levels.is_none() {
+            self.levels = None;
+        }
+        self.auto_levels = auto_levels;
+    }
+
+    // The stretch of auto-levels, on the demosaiced frame.
+    fn stretch_levels<S: Sample>(
+        &mut self,
+        frame: &mut [S],
+        stride: usize,
+        width: usize,
+        height: usize,
+        layout: OutputLayout,
+    ) -> Result<(), gst::FlowError> {
+        let Some(auto_levels) = self.auto_levels else {
+            return Ok(());
+        };
+
+        let (black, white) = match self.levels {
+            Some(levels) if auto_levels.lock => levels,
+            last => {
+                let measured = tone::luma_percentiles(
+                    frame,
+                    stride,
+                    width,
+                    height,
+                    layout,
+                    auto_levels.low,
+                    auto_levels.high,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, "Measuring the levels failed: {}", err);
+                    gst::FlowError::Error
+                })?;
+                *self.levels.insert(auto_levels.damp(last, measured))
+            }
+        };
+        let lut = tone::Lut::reduction(ToneMapping::Linear, black, white);
+        tone::apply(&lut, frame, stride, width, height, layout).map_err(|err| {
+            gst::error!(CAT, "Stretching the levels failed: {}", err);
+            gst::FlowError::Error
+        })
+    }
+
+    fn update_tone(&mut self) {
+        self.tone = self.tone_lut();
+        self.wide_tone = match self.wide() {
+            true => self.tone_lut(),
+            false => None,
+        };
+    }
+
+    fn tone_lut<S: Sample>(&self) -> Option<tone::Lut<S>> {
+        let lut = match self.tone_curve {
+            Some(ref curve) if curve.len() >= 2 => Some(tone::Lut::from_curve(curve)),
+            _ => (self.gamma != 1.0).then(|| tone::Lut::gamma(self.gamma)),
+        };
+
+        if self.brightness != 0.0 || self.contrast != 1.0 {
+            Some(
+                lut.unwrap_or_else(tone::Lut::identity)
+                    .adjust(self.brightness, self.contrast),
+            )
+        } else {
+            lut
+        }
+    }
+
+    /// Pulls the chroma of every demosaiced pixel towards the median of its neighbors
+    /// to suppress colored aliasing, from 0.0, off, to 1.0, all the way.
+    pub fn set_false_color_suppression(&mut self, strength: f64) {
+        self.false_color_suppression = strength;
+    }
+
+    /// Draws diagonal stripes over the output pixels whose raw samples exceed
+    /// `threshold`, a fraction of full scale, to show clipped highlights. None
+    /// disables the overlay.
+    pub fn set_zebra(&mut self, threshold: Option<f64>) {
+        self.zebra = threshold;
+    }
+
+    /// Drops the frames kept for temporal filtering and the damped black and white
+    /// points and levels, e.g. after a seek. Locked levels are kept.
+    pub fn reset_history(&mut self) {
+        self.auto_points = None;
+        if !self.auto_levels.is_some_and(|auto_levels| auto_levels.lock) {
+            self.levels = None;
+        }
+        #[cfg(feature = "opencv")]
+        self.filters.reset_history();
+    }
+
+    /// Threading and offload options used by the OpenCV backend.
+    #[cfg(feature = "opencv")]
+    pub fn set_opencv_options(&mut self, options: OpenCvOptions) {
+        self.opencv_options = options;
+    }
+
+    /// Converts one frame. `input` holds `height` rows of `width` bayer samples
+    /// `in_stride` bytes apart, `output` the rows of the output format `out_stride`
+    /// bytes apart.
+    pub fn convert(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        if self.orientation.is_identity() {
+            return self.render(input, in_stride, output, out_stride);
+        }
+
+        let layout = OutputLayout::for_format(self.format).ok_or(gst::FlowError::NotNegotiated)?;
+        let (width, height) = self.unoriented_size();
+        let stride = width * layout.pixel_stride;
+        let mut unoriented = std::mem::take(&mut self.unoriented);
+        unoriented.resize(stride * height, 0);
+        let res = self
+            .render(input, in_stride, &mut unoriented, stride)
+            .and_then(|()| {
+                orient::apply(
+                    &unoriented,
+                    stride,
+                    width,
+                    height,
+                    layout.pixel_stride,
+                    self.orientation,
+                    output,
+                    out_stride,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, "Flip or rotation failed: {}", err);
+                    gst::FlowError::Error
+                })
+            });
+        self.unoriented = unoriented;
+
+        res
+    }
+
+    // Output size before any flip or rotation.
+    fn unoriented_size(&self) -> (usize, usize) {
+        let border = 2 * self.cropped_border();
+        match self.superpixel {
+            Some(_) => (self.width / 2, self.height / 2),
+            None => (
+                self.width.saturating_sub(border),
+                self.height.saturating_sub(border),
+            ),
+        }
+    }
+
+    // Every stage but the flip or rotation.
+    fn render(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        let layout = OutputLayout::for_format(self.format).ok_or(gst::FlowError::NotNegotiated)?;
+        let (width, height) = self.unoriented_size();
+        match self.wide() {
+            true => self.render_wide(input, in_stride, output, out_stride, layout)?,
+            false => {
+                self.convert_linear(input, in_stride, output, out_stride)?;
+                self.suppress_false_color(output, out_stride, width, height, layout)?;
+            }
+        }
+
+        #[cfg(feature = "opencv")]
+        self.filters
+            .apply(
+                output,
+                out_stride,
+                width,
+                height,
+                layout.pixel_stride,
+                &self.opencv_options,
+            )
+            .map_err(|err| {
+                gst::error!(CAT, "{}", err);
+                gst::FlowError::Error
+            })?;
+
+        if !self.wide() {
+            self.stretch_levels(output, out_stride, width, height, layout)?;
+            tone_map(
+                self.tone.as_ref(),
+                self.saturation,
+                output,
+                out_stride,
+                width,
+                height,
+                layout,
+            )?;
+        }
+        self.draw_zebra(input, in_stride, output, out_stride, layout)
+    }
+
+    // The stages up to the saturation on 16-bit samples, then the quantization into
+    // `output`.
+    fn render_wide(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+        layout: OutputLayout,
+    ) -> Result<(), gst::FlowError> {
+        let (width, height) = self.unoriented_size();
+        let stride = width * layout.pixel_stride;
+        let black_level = match self.decompanding {
+            Some(ref lut) => self
+                .black_level
+                .map(|black| lut[(black as usize).min(lut.len() - 1)]),
+            None => precision::promote_black_level(self.black_level),
+        };
+        // Offsets are small, so they are taken through the curve at the black level.
+        let fpn_scale = match self.decompanding {
+            Some(ref lut) => {
+                let black = self
+                    .black_level
+                    .iter()
+                    .map(|&black| black as usize)
+                    .sum::<usize>()
+                    / 4;
+                let code = black.min(lut.len().saturating_sub(2));
+                lut.get(code + 1)
+                    .map_or(0.0, |&level| level as f32 - lut[code] as f32)
+            }
+            None => precision::PROMOTE as f32,
+        };
+
+        let mut wide_input = std::mem::take(&mut self.wide_input);
+        let mut wide_output = std::mem::take(&mut self.wide_output);
+        wide_output.resize(stride * height, 0);
+        let res = self
+            .promote(input, in_stride, &mut wide_input)
+            .and_then(|()| {
+                self.process_wide(
+                    &wide_input,
+                    self.width,
+                    black_level,
+                    fpn_scale,
+                    &mut wide_output,
+                    stride,
+                    layout,
+                )
+            })
+            .and_then(|()| {
+                precision::quantize(
+                    &wide_output,
+                    stride,
+                    width,
+                    height,
+                    layout.pixel_stride,
+                    output,
+                    out_stride,
+                    self.dither,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, "Quantization failed: {}", err);
+                    gst::FlowError::Error
+                })
+            });
+        self.wide_input = wide_input;
+        self.wide_output = wide_output;
+
+        res
+    }
+
+    /// Converts one frame of 16-bit bayer samples, deeper input scaled to the full
+    /// range as by `unpack::widen()`, into 16-bit samples of the output layout, e.g.
+    /// for a P010 frame written by `yuv::write_p010()`. Strides are in samples. Every
+    /// stage of [`Converter::set_high_precision`] runs on them, with the black level
+    /// and dark frame promoted from 8 bits, then the zebra and the flip or rotation.
+    /// The input is taken as linear, decompanding only covers 8-bit codes, and the
+    /// OpenCV filters, which only take 8-bit frames, are skipped.
+    pub fn convert_wide(
+        &mut self,
+        input: &[u16],
+        in_stride: usize,
+        output: &mut [u16],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        let layout = OutputLayout::for_format(self.format).ok_or(gst::FlowError::NotNegotiated)?;
+        let (width, height) = self.unoriented_size();
+        let stride = width * layout.pixel_stride;
+        let black_level = precision::promote_black_level(self.black_level);
+        let fpn_scale = precision::PROMOTE as f32;
+
+        let mut wide_input = std::mem::take(&mut self.wide_input);
+        let mut wide_output = std::mem::take(&mut self.wide_output);
+        let res = self
+            .subtract_wide_dark(input, in_stride, &mut wide_input)
+            .and_then(|subtracted| {
+                let (input, in_stride) = match subtracted {
+                    true => (&wide_input[..], self.width),
+                    false => (input, in_stride),
+                };
+                if self.orientation.is_identity() {
+                    self.process_wide(
+                        input,
+                        in_stride,
+                        black_level,
+                        fpn_scale,
+                        output,
+                        out_stride,
+                        layout,
+                    )?;
+                    return self.draw_zebra(input, in_stride, output, out_stride, layout);
+                }
+
+                wide_output.resize(stride * height, 0);
+                self.process_wide(
+                    input,
+                    in_stride,
+                    black_level,
+                    fpn_scale,
+                    &mut wide_output,
+                    stride,
+                    layout,
+                )?;
+                self.draw_zebra(input, in_stride, &mut wide_output, stride, layout)?;
+                orient::apply(
+                    &wide_output,
+                    stride,
+                    width,
+                    height,
+                    layout.pixel_stride,
+                    self.orientation,
+                    output,
+                    out_stride,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, "Flip or rotation failed: {}", err);
+                    gst::FlowError::Error
+                })
+            });
+        self.wide_input = wide_input;
+        self.wide_output = wide_output;
+
+        res
+    }
+
+    // Subtracts the dark frame, promoted to 16 bits, from `input` into `output`.
+    // Returns whether there was one to subtract.
+    fn subtract_wide_dark(
+        &mut self,
+        input: &[u16],
+        in_stride: usize,
+        output: &mut Vec<u16>,
+    ) -> Result<bool, gst::FlowError> {
+        let Some(ref dark_frame) = self.dark_frame else {
+            return Ok(false);
+        };
+
+        if self.wide_dark.len() != dark_frame.len() {
+            precision::promote(
+                dark_frame,
+                self.width,
+                self.width,
+                self.height,
+                &mut self.wide_dark,
+            )
+            .map_err(|err| {
+                gst::error!(CAT, "Promotion to 16 bits failed: {}", err);
+                gst::FlowError::Error
+            })?;
+        }
+        output.resize(self.width * self.height, 0);
+        raw::subtract(
+            input,
+            in_stride,
+            self.width,
+            self.height,
+            &self.wide_dark,
+            output,
+            self.width,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "Dark frame subtraction failed: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        Ok(true)
+    }
+
+    // The stages from the raw corrections to the saturation on 16-bit samples, from
+    // bayer `input` into `output`, a frame of the unoriented size. Strides are in
+    // samples.
+    #[allow(clippy::too_many_arguments)]
+    fn process_wide(
+        &mut self,
+        input: &[u16],
+        in_stride: usize,
+        black_level: [u16; 4],
+        fpn_scale: f32,
+        output: &mut [u16],
+        out_stride: usize,
+        layout: OutputLayout,
+    ) -> Result<(), gst::FlowError> {
+        let (width, height) = self.unoriented_size();
+        let gains = self.gains.scale(self.exposure_gain);
+
+        let mut wide_raw = std::mem::take(&mut self.wide_raw);
+        wide_raw.resize(self.width * self.height, 0);
+        let res = self
+            .correct_raw(
+                input,
+                in_stride,
+                black_level,
+                fpn_scale,
+                gains,
+                &mut wide_raw,
+            )
+            .and_then(|()| match self.superpixel {
+                Some(_) => superpixel::convert(
+                    &wide_raw,
+                    self.width,
+                    self.width,
+                    self.height,
+                    self.pattern,
+                    output,
+                    out_stride,
+                    layout,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, "Demosaic failed: {}", err);
+                    gst::FlowError::Error
+                }),
+                None => {
+                    let pattern = self.pattern;
+                    demosaic_bordered(
+                        self.border_mode,
+                        &wide_raw,
+                        self.width,
+                        self.width,
+                        self.height,
+                        layout.pixel_stride,
+                        &mut self.wide_border_scratch,
+                        output,
+                        out_stride,
+                        |input, in_stride, width, height, output, out_stride| {
+                            demosaic::bilinear_scalar(
+                                input, in_stride, width, height, pattern, output, out_stride,
+                                layout,
+                            )
+                            .map_err(|err| {
+                                gst::error!(CAT, "Demosaic failed: {}", err);
+                                gst::FlowError::Error
+                            })
+                        },
+                    )
+                }
+            })
+            .and_then(|()| self.suppress_false_color(output, out_stride, width, height, layout))
+            .and_then(|()| {
+                if self.tone_mapping != ToneMapping::PercentileAuto {
+                    return Ok(());
+                }
+                let (black, white) = self.auto_points(output, out_stride, layout)?;
+                self.reduction = Some(tone::Lut::reduction(
+                    ToneMapping::PercentileAuto,
+                    black,
+                    white,
+                ));
+                Ok(())
+            })
+            .and_then(|()| {
+                let Some(ref lut) = self.reduction else {
+                    return Ok(());
+                };
+                tone::apply(lut, output, out_stride, width, height, layout).map_err(|err| {
+                    gst::error!(CAT, "Tone mapping to the output range failed: {}", err);
+                    gst::FlowError::Error
+                })
+            })
+            .and_then(|()| self.stretch_levels(output, out_stride, width, height, layout))
+            .and_then(|()| {
+                tone_map(
+                    self.wide_tone.as_ref(),
+                    self.saturation,
+                    output,
+                    out_stride,
+                    width,
+                    height,
+                    layout,
+                )
+            });
+        self.wide_raw = wide_raw;
+
+        res
+    }
+
+    // The zebra over the unoriented output, if enabled, from the samples of `input`.
+    fn draw_zebra<S: Sample>(
+        &self,
+        input: &[S],
+        in_stride: usize,
+        output: &mut [S],
+        out_stride: usize,
+        layout: OutputLayout,
+    ) -> Result<(), gst::FlowError> {
+        let Some(threshold) = self.zebra else {
+            return Ok(());
+        };
+
+        let (width, height) = self.unoriented_size();
+        let scale = if self.superpixel.is_some() { 2 } else { 1 };
+        let border = self.cropped_border();
+        zebra::draw(
+            input.get(border * in_stride + border..).unwrap_or_default(),
+            in_stride,
+            scale,
+            (threshold * S::MAX as f64).round() as u64,
+            output,
+            out_stride,
+            width,
+            height,
+            layout,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "Zebra overlay failed: {}", err);
+            gst::FlowError::Error
+        })
+    }
+
+    // The chroma median on the linear demosaiced samples, if enabled.
+    fn suppress_false_color<S: Sample>(
+        &mut self,
+        output: &mut [S],
+        stride: usize,
+        width: usize,
+        height: usize,
+        layout: OutputLayout,
+    ) -> Result<(), gst::FlowError> {
+        if self.false_color_suppression <= 0.0 {
+            return Ok(());
+        }
+
+        chroma::suppress(
+            output,
+            stride,
+            width,
+            height,
+            layout,
+            self.false_color_suppression,
+            &mut self.chroma_scratch,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "False color suppression failed: {}", err);
+            gst::FlowError::Error
+        })
+    }
+
+    // Subtracts the dark frame, which is exact on the 8-bit samples, and promotes the
+    // result to 16 bits, or expands it with decompanding.
+    fn promote(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut Vec<u16>,
+    ) -> Result<(), gst::FlowError> {
+        let promote = |input: &[u8], in_stride: usize, output: &mut Vec<u16>| {
+            match self.decompanding {
+                Some(ref lut) => {
+                    decompand::expand(input, in_stride, self.width, self.height, lut, output)
+                }
+                None => precision::promote(input, in_stride, self.width, self.height, output),
+            }
+            .map_err(|err| {
+                gst::error!(CAT, "Promotion to 16 bits failed: {}", err);
+                gst::FlowError::Error
+            })
+        };
+        let Some(ref dark_frame) = self.dark_frame else {
+            return promote(input, in_stride, output);
+        };
+
+        let mut dark_scratch = std::mem::take(&mut self.dark_scratch);
+        dark_scratch.resize(self.width * self.height, 0);
+        let res = raw::subtract(
+            input,
+            in_stride,
+            self.width,
+            self.height,
+            dark_frame,
+            &mut dark_scratch,
+            self.width,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "Dark frame subtraction failed: {}", err);
+            gst::FlowError::Error
+        })
+        .and_then(|()| promote(&dark_scratch, self.width, output));
+        self.dark_scratch = dark_scratch;
+
+        res
+    }
+
+    // Dark frame, black level, shading, white balance, defective pixels and demosaic,
+    // the stages on linear samples.
+    fn convert_linear(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        let Some(ref dark_frame) = self.dark_frame else {
+            return self.correct(input, in_stride, output, out_stride);
+        };
+
+        let mut dark_scratch = std::mem::take(&mut self.dark_scratch);
+        dark_scratch.resize(self.width * self.height, 0);
+        let res = raw::subtract(
+            input,
+            in_stride,
+            self.width,
+            self.height,
+            dark_frame,
+            &mut dark_scratch,
+            self.width,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, "Dark frame subtraction failed: {}", err);
+            gst::FlowError::Error
+        })
+        .and_then(|()| self.correct(&dark_scratch, self.width, output, out_stride));
+        self.dark_scratch = dark_scratch;
+
+        res
+    }
+
+    fn correct(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        let gains = self.gains.scale(self.exposure_gain);
+        if self.black_level == [0; 4]
+            && gains.is_unity()
+            && self.shading.is_none()
+            && self.fpn.is_none()
+            && self.defects.is_none()
+            && self.auto_defects.is_none()
+            && self.highlight_mode == HighlightMode::Clip
+        {
+            return self.demosaic(input, in_stride, output, out_stride);
+        }
+
+        let mut raw_scratch = std::mem::take(&mut self.raw_scratch);
+        raw_scratch.resize(self.width * self.height, 0);
+        let res = self
+            .correct_raw(
+                input,
+                in_stride,
+                self.black_level,
+                1.0,
+                gains,
+                &mut raw_scratch,
+            )
+            .and_then(|()| self.demosaic(&raw_scratch, self.width, output, out_stride));
+        self.raw_scratch = raw_scratch;
+
+        res
+    }
+
+    // Black level, fixed pattern noise, shading, white balance, defective pixels and
+    // highlights, from `input` into `output`, a frame without padding. `fpn_scale`
+    // takes the offsets from input levels to those of the samples.
+    fn correct_raw<S: Sample>(
+        &mut self,
+        input: &[S],
+        in_stride: usize,
+        black_level: [u16; 4],
+        fpn_scale: f32,
+        gains: Gains,
+        output: &mut [S],
+    ) -> Result<(), gst::FlowError> {
+        if let Some(ref fpn) = self.fpn {
+            fpn.levels(fpn_scale, &mut self.fpn_offsets);
+        }
+        let fpn = self.fpn.as_ref().map(|_| &self.fpn_offsets);
+        match self.shading {
+            Some(ref shading) => {
+                let folded = match self.shading_gains {
+                    Some((folded_for, ref folded)) if folded_for == gains => folded,
+                    _ => {
+                        let folded = raw::shading_gains(
+                            shading,
+                            self.width,
+                            self.height,
+                            self.pattern,
+                            gains,
+                        );
+                        &self.shading_gains.insert((gains, folded)).1
+                    }
+                };
+                raw::correct_shaded(
+                    input,
+                    in_stride,
+                    self.width,
+                    self.height,
+                    self.pattern,
+                    black_level,
+                    fpn,
+                    folded,
+                    output,
+                    self.width,
+                )
+            }
+            None => raw::correct(
+                input,
+                in_stride,
+                self.width,
+                self.height,
+                self.pattern,
+                black_level,
+                fpn,
+                gains,
+                output,
+                self.width,
+            ),
+        }
+        .map_err(|err| {
+            gst::error!(CAT, "Raw correction failed: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        if let Some(ref defects) = self.defects {
+            defects.apply(output);
+        }
+        if let Some(auto_defects) = self.auto_defects {
+            self.corrected_defects =
+                self.detector
+                    .correct(output, self.width, self.height, auto_defects);
+        }
+        // After the defects, so a hot pixel doesn't count as a clipped highlight. The
+        // sensor saturates at the top of the decompanding curve if there is one.
+        if self.highlight_mode != HighlightMode::Clip {
+            let white = match self.decompanding {
+                Some(ref lut) => lut.last().map_or(S::MAX, |&white| white as u64),
+                None => S::MAX,
+            };
+            raw::highlights(
+                output,
+                self.width,
+                self.height,
+                self.pattern,
+                black_level,
+                gains,
+                white,
+                self.highlight_mode,
+            )
+            .map_err(|err| {
+                gst::error!(CAT, "Highlight handling failed: {}", err);
+                gst::FlowError::Error
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn demosaic(
+        &mut self,
+        input: &[u8],
+        in_stride: usize,
+        output: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(), gst::FlowError> {
+        if let Some(layout) = self.superpixel {
+            return superpixel::convert(
+                input,
+                in_stride,
+                self.width,
+                self.height,
+                self.pattern,
+                output,
+                out_stride,
+                layout,
+            )
+            .map_err(|err| {
+                gst::error!(CAT, "Superpixel conversion failed: {}", err);
+                gst::FlowError::Error
+            });
+        }
+
+        let layout = OutputLayout::for_format(self.format).ok_or(gst::FlowError::NotNegotiated)?;
+        #[cfg(feature = "opencv")]
+        let options = &self.opencv_options;
+        // Only set with BorderMode::Replicate, which demosaics the padded frame.
+        let inner = match self.padded_inner {
+            Some(ref mut inner) => inner,
+            None => &mut self.inner,
+        };
+        demosaic_bordered(
+            self.border_mode,
+            input,
+            in_stride,
+            self.width,
+            self.height,
+            layout.pixel_stride,
+            &mut self.border_scratch,
+            output,
+            out_stride,
+            |input, in_stride, _, _, output, out_stride| {
+                inner.demosaic(
+                    input,
+                    in_stride,
+                    output,
+                    out_stride,
+                    #[cfg(feature = "opencv")]
+                    options,
+                )
+            },
+        )
+    }
+}
+
+// Demosaics a `width` x `height` frame of `input` through `demosaic`, called with a
+// bayer frame, its stride and size and the output frame and its stride, handling the
+// border by `mode`. The output is `2 * BORDER` pixels narrower and lower with
+// BorderMode::Crop. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+fn demosaic_bordered<S: Sample, F>(
+    mode: BorderMode,
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pixel_stride: usize,
+    scratch: &mut BorderScratch<S>,
+    output: &mut [S],
+    out_stride: usize,
+    mut demosaic: F,
+) -> Result<(), gst::FlowError>
+where
+    F: FnMut(&[S], usize, usize, usize, &mut [S], usize) -> Result<(), gst::FlowError>,
+{
+    let (out_width, out_height) = match mode {
+        BorderMode::None => return demosaic(input, in_stride, width, height, output, out_stride),
+        BorderMode::Replicate => (width, height),
+        BorderMode::Crop => (
+            width.saturating_sub(2 * BORDER),
+            height.saturating_sub(2 * BORDER),
+        ),
+    };
+    let (input, in_stride, width, height) = match mode {
+        BorderMode::Replicate => {
+            border::pad(input, in_stride, width, height, &mut scratch.padded).map_err(|err| {
+                gst::error!(CAT, "Padding the border failed: {}", err);
+                gst::FlowError::Error
+            })?;
+            let padded_width = width + 2 * BORDER;
+            (
+                &scratch.padded[..],
+                padded_width,
+                padded_width,
+                height + 2 * BORDER,
+            )
+        }
+        _ => (input, in_stride, width, height),
+    };
+
+    let stride = width * pixel_stride;
+    scratch.demosaiced.resize(stride * height, S::from_u64(0));
+    demosaic(
+        input,
+        in_stride,
+        width,
+        height,
+        &mut scratch.demosaiced,
+        stride,
+    )?;
+    border::crop(
+        &scratch.demosaiced,
+        stride,
+        out_width,
+        out_height,
+        pixel_stride,
+        output,
+        out_stride,
+    )
+    .map_err(|err| {
+        gst::error!(CAT, "Cropping the border failed: {}", err);
+        gst::FlowError::Error
+    })
+}
+
+// Gamma or tone curve, brightness and contrast, then saturation, on a frame of `S`
+// samples. The stride is in samples.
+fn tone_map<S: Sample>(
+    lut: Option<&tone::Lut<S>>,
+    saturation: f64,
+    frame: &mut [S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+) -> Result<(), gst::FlowError> {
+    if let Some(lut) = lut {
+        tone::apply(lut, frame, stride, width, height, layout).map_err(|err| {
+            gst::error!(CAT, "Tone mapping failed: {}", err);
+            gst::FlowError::Error
+        })?;
+    }
+    if saturation != 1.0 {
+        tone::saturate(frame, stride, width, height, layout, saturation).map_err(|err| {
+            gst::error!(CAT, "Saturation adjustment failed: {}", err);
+            gst::FlowError::Error
+        })?;
+    }
+
+    Ok(())
+}
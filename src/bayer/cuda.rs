@@ -0,0 +1,123 @@
+// CUDA conversion path, only built with the `cuda` cargo feature.
+//
+// Frames are staged through page-locked host memory so the uploads and downloads can
+// run as DMA transfers on the element's own stream instead of pageable copies.
+
+use opencv::core::{self, GpuMat, HostMem, HostMem_AllocType, Stream};
+use opencv::cudaimgproc;
+use opencv::prelude::*;
+
+use super::imp::CAT;
+
+// Initialized lazily the first time use-cuda is seen enabled, and never retried for
+// the same caps once it failed.
+pub enum Cuda {
+    Untried,
+    Unavailable,
+    Ready(CudaFrames),
+}
+
+pub struct CudaFrames {
+    stream: Stream,
+    input_staging: HostMem,
+    output_staging: HostMem,
+    input: GpuMat,
+    demosaiced: GpuMat,
+    output: GpuMat,
+}
+
+impl Cuda {
+    pub fn frames(
+        &mut self,
+        device: i32,
+        rows: usize,
+        cols: usize,
+        output_type: i32,
+    ) -> Option<&mut CudaFrames> {
+        if let Cuda::Untried = self {
+            *self = match CudaFrames::new(device, rows, cols, output_type) {
+                Ok(frames) => Cuda::Ready(frames),
+                Err(err) => {
+                    gst::warning!(CAT, "CUDA unavailable, converting on the CPU: {}", err);
+                    Cuda::Unavailable
+                }
+            };
+        }
+
+        match self {
+            Cuda::Ready(frames) => Some(frames),
+            _ => None,
+        }
+    }
+}
+
+impl CudaFrames {
+    fn new(device: i32, rows: usize, cols: usize, output_type: i32) -> Result<Self, String> {
+        let count = core::get_cuda_enabled_device_count().map_err(|err| err.to_string())?;
+        if device >= count {
+            return Err(format!("CUDA device {device} not present, {count} found"));
+        }
+        core::set_device(device).map_err(|err| err.to_string())?;
+
+        let (rows, cols) = (rows as i32, cols as i32);
+        let frames = (|| -> opencv::Result<Self> {
+            Ok(CudaFrames {
+                stream: Stream::default()?,
+                input_staging: HostMem::new(
+                    rows,
+                    cols,
+                    core::CV_8UC1,
+                    HostMem_AllocType::PAGE_LOCKED,
+                )?,
+                output_staging: HostMem::new(
+                    rows,
+                    cols,
+                    output_type,
+                    HostMem_AllocType::PAGE_LOCKED,
+                )?,
+                input: GpuMat::new_def()?,
+                demosaiced: GpuMat::new_def()?,
+                output: GpuMat::new_def()?,
+            })
+        })()
+        .map_err(|err| err.to_string())?;
+
+        gst::info!(CAT, "Using CUDA device {}", device);
+
+        Ok(frames)
+    }
+
+    // Demosaics with `code`, followed by `expand_code` for two-pass conversions.
+    pub fn convert(
+        &mut self,
+        input: &impl MatTraitConst,
+        output: &mut impl core::ToOutputArray,
+        code: i32,
+        expand_code: Option<i32>,
+    ) -> opencv::Result<()> {
+        let mut staging_in = self.input_staging.create_mat_header()?;
+        input.copy_to(&mut staging_in)?;
+        self.input.upload_async(&staging_in, &mut self.stream)?;
+
+        cudaimgproc::demosaicing(&self.input, &mut self.demosaiced, code, -1, &mut self.stream)?;
+        let result = match expand_code {
+            Some(expand_code) => {
+                cudaimgproc::cvt_color(
+                    &self.demosaiced,
+                    &mut self.output,
+                    expand_code,
+                    0,
+                    &mut self.stream,
+                )?;
+                &self.output
+            }
+            None => &self.demosaiced,
+        };
+
+        let mut staging_out = self.output_staging.create_mat_header()?;
+        result.download_async(&mut staging_out, &mut self.stream)?;
+        self.stream.wait_for_completion()?;
+
+        staging_out.copy_to(output)
+    }
+}
@@ -0,0 +1,820 @@
+// OpenCV conversion backend: plain cvtColor on the CPU, optionally split into strips
+// over several threads, or offloaded to OpenCL or CUDA.
+
+use gst::glib;
+use gst::prelude::*;
+use opencv::prelude::*;
+
+use super::{Denoise, DemosaicAlgorithm, OverlayCorner, ScaleMethod};
+use super::cfa::Pattern;
+#[cfg(feature = "cuda")]
+use super::cuda;
+use super::imp::CAT;
+use super::mat;
+
+const DEFAULT_N_THREADS: u32 = 0;
+const DEFAULT_OPENCV_THREADS: i32 = -1;
+const DEFAULT_USE_OPENCL: bool = false;
+const DEFAULT_DENOISE_STRENGTH: f64 = 1.0;
+const DEFAULT_TEMPORAL_DENOISE: f64 = 0.0;
+const DEFAULT_SHARPEN_AMOUNT: f64 = 0.0;
+const DEFAULT_SHARPEN_RADIUS: f64 = 1.0;
+const DEFAULT_SHOW_DEBUG_OVERLAY: bool = false;
+const DEFAULT_OUTPUT_WIDTH: u32 = 0;
+const DEFAULT_OUTPUT_HEIGHT: u32 = 0;
+#[cfg(feature = "cuda")]
+const DEFAULT_USE_CUDA: bool = false;
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: i32 = 0;
+
+// OpenCV's thread count is process-global. The first element to start with
+// opencv-threads set applies it, later instances only warn if they disagree.
+static OPENCV_THREADS: std::sync::OnceLock<i32> = std::sync::OnceLock::new();
+
+// Properties specific to this backend, part of the element settings.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub n_threads: u32,
+    pub opencv_threads: i32,
+    pub use_opencl: bool,
+    pub denoise: Denoise,
+    pub denoise_strength: f64,
+    pub temporal_denoise: f64,
+    pub sharpen_amount: f64,
+    pub sharpen_radius: f64,
+    pub show_debug_overlay: bool,
+    pub overlay_corner: OverlayCorner,
+    pub output_width: u32,
+    pub output_height: u32,
+    pub scale_method: ScaleMethod,
+    #[cfg(feature = "cuda")]
+    pub use_cuda: bool,
+    #[cfg(feature = "cuda")]
+    pub device: i32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            n_threads: DEFAULT_N_THREADS,
+            opencv_threads: DEFAULT_OPENCV_THREADS,
+            use_opencl: DEFAULT_USE_OPENCL,
+            denoise: Denoise::default(),
+            denoise_strength: DEFAULT_DENOISE_STRENGTH,
+            temporal_denoise: DEFAULT_TEMPORAL_DENOISE,
+            sharpen_amount: DEFAULT_SHARPEN_AMOUNT,
+            sharpen_radius: DEFAULT_SHARPEN_RADIUS,
+            show_debug_overlay: DEFAULT_SHOW_DEBUG_OVERLAY,
+            overlay_corner: OverlayCorner::default(),
+            output_width: DEFAULT_OUTPUT_WIDTH,
+            output_height: DEFAULT_OUTPUT_HEIGHT,
+            scale_method: ScaleMethod::default(),
+            #[cfg(feature = "cuda")]
+            use_cuda: DEFAULT_USE_CUDA,
+            #[cfg(feature = "cuda")]
+            device: DEFAULT_DEVICE,
+        }
+    }
+}
+
+pub fn properties() -> Vec<glib::ParamSpec> {
+    #[allow(unused_mut)]
+    let mut properties = vec![
+        glib::ParamSpecUInt::builder("n-threads")
+            .nick("Number of Threads")
+            .blurb("Convert horizontal strips of the frame on this many threads (0 = off)")
+            .maximum(256)
+            .default_value(DEFAULT_N_THREADS)
+            .mutable_playing()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:opencv-threads:
+         *
+         * Size of OpenCV's internal thread pool, applied when the element
+         * starts. This setting is process-global: it affects every OpenCV user
+         * in the process, only the first element to start applies it, and it
+         * is left in place when the element stops.
+         */
+        glib::ParamSpecInt::builder("opencv-threads")
+            .nick("OpenCV Threads")
+            .blurb("Process-wide OpenCV thread count set on start (-1 = leave untouched, 0 = no threading)")
+            .minimum(-1)
+            .maximum(1024)
+            .default_value(DEFAULT_OPENCV_THREADS)
+            .mutable_ready()
+            .build(),
+        glib::ParamSpecBoolean::builder("use-opencl")
+            .nick("Use OpenCL")
+            .blurb("Convert on an OpenCL device when available, falling back to the CPU")
+            .default_value(DEFAULT_USE_OPENCL)
+            .mutable_playing()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:denoise:
+         *
+         * Spatial noise reduction on the demosaiced frame, before gamma and the
+         * other tone adjustments, with any backend. `gaussian` is cheap but
+         * softens edges. `bilateral` keeps edges but is expensive, easily
+         * costing more than the demosaic itself at higher strengths; the cost
+         * shows up in #GstRsBayer2Rgb:avg-conversion-time like the rest of the
+         * conversion.
+         */
+        glib::ParamSpecEnum::builder_with_default("denoise", Denoise::default())
+            .nick("Denoise")
+            .blurb("Spatial noise reduction of the demosaiced frame")
+            .mutable_playing()
+            .build(),
+        glib::ParamSpecDouble::builder("denoise-strength")
+            .nick("Denoise Strength")
+            .blurb("Spatial sigma of the denoise filter in pixels")
+            .minimum(0.1)
+            .maximum(10.0)
+            .default_value(DEFAULT_DENOISE_STRENGTH)
+            .mutable_playing()
+            .controllable()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:temporal-denoise:
+         *
+         * Weight of the previous result when blending it with every new frame,
+         * a running average that removes noise from static scenes but smears
+         * motion. The history is dropped on caps changes, flushes and
+         * discontinuities so seeks and scene cuts don't ghost. Costs one extra
+         * output frame of memory while enabled.
+         */
+        glib::ParamSpecDouble::builder("temporal-denoise")
+            .nick("Temporal Denoise")
+            .blurb("Weight of the previous frame in the temporal filter (0 = off)")
+            .minimum(0.0)
+            .maximum(0.99)
+            .default_value(DEFAULT_TEMPORAL_DENOISE)
+            .mutable_playing()
+            .controllable()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:sharpen-amount:
+         *
+         * Strength of an unsharp mask applied after denoising, adding this
+         * much of the difference between the frame and a blurred copy of it.
+         * Around 0.5 counters the softness of bilinear demosaicing, 0 skips the
+         * pass entirely.
+         */
+        glib::ParamSpecDouble::builder("sharpen-amount")
+            .nick("Sharpen Amount")
+            .blurb("Strength of the unsharp mask (0 = off)")
+            .minimum(0.0)
+            .maximum(10.0)
+            .default_value(DEFAULT_SHARPEN_AMOUNT)
+            .mutable_playing()
+            .controllable()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:sharpen-radius:
+         *
+         * Sigma of the gaussian blur of the unsharp mask. Larger radii enhance
+         * coarser detail.
+         */
+        glib::ParamSpecDouble::builder("sharpen-radius")
+            .nick("Sharpen Radius")
+            .blurb("Sigma of the unsharp mask blur in pixels")
+            .minimum(0.1)
+            .maximum(10.0)
+            .default_value(DEFAULT_SHARPEN_RADIUS)
+            .mutable_playing()
+            .controllable()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:show-debug-overlay:
+         *
+         * Burn a block of text into the output with the negotiated pattern and
+         * input size, the framerate and the conversion time of every frame, for
+         * recordings made while investigating issues in the field. It is drawn
+         * last, so the focus metric and the stats don't see it, but previews do.
+         */
+        glib::ParamSpecBoolean::builder("show-debug-overlay")
+            .nick("Show Debug Overlay")
+            .blurb("Draw pattern, size, framerate and conversion time into the output")
+            .default_value(DEFAULT_SHOW_DEBUG_OVERLAY)
+            .mutable_playing()
+            .build(),
+        glib::ParamSpecEnum::builder_with_default("overlay-corner", OverlayCorner::default())
+            .nick("Overlay Corner")
+            .blurb("Corner of the output show-debug-overlay draws into")
+            .mutable_playing()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:output-width:
+         *
+         * Scale the output to this width after demosaicing, 0 keeps the width of
+         * the conversion. The frame is converted at its full size and resized with
+         * #GstRsBayer2Rgb:scale-method, stretched rather than letterboxed if the
+         * aspect ratio changes. The width is that of the final output, after any
+         * rotation by #GstRsBayer2Rgb:video-direction. Changing it renegotiates
+         * the caps, and scaled output is never negotiated in GL memory.
+         */
+        glib::ParamSpecUInt::builder("output-width")
+            .nick("Output Width")
+            .blurb("Scale the output to this width (0 = converted width)")
+            .maximum(i32::MAX as u32)
+            .default_value(DEFAULT_OUTPUT_WIDTH)
+            .mutable_playing()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:output-height:
+         *
+         * Scale the output to this height after demosaicing, 0 keeps the height of
+         * the conversion, like #GstRsBayer2Rgb:output-width.
+         */
+        glib::ParamSpecUInt::builder("output-height")
+            .nick("Output Height")
+            .blurb("Scale the output to this height (0 = converted height)")
+            .maximum(i32::MAX as u32)
+            .default_value(DEFAULT_OUTPUT_HEIGHT)
+            .mutable_playing()
+            .build(),
+        /**
+         * GstRsBayer2Rgb:scale-method:
+         *
+         * Interpolation of output-width and output-height. `area` averages the
+         * pixels every output pixel covers, which avoids aliasing when scaling down
+         * but blurs like `linear` when scaling up, where `cubic` and `lanczos` are
+         * sharper.
+         */
+        glib::ParamSpecEnum::builder_with_default("scale-method", ScaleMethod::default())
+            .nick("Scale Method")
+            .blurb("Interpolation scaling the output to output-width and output-height")
+            .mutable_playing()
+            .build(),
+    ];
+
+    #[cfg(feature = "cuda")]
+    properties.extend([
+        glib::ParamSpecBoolean::builder("use-cuda")
+            .nick("Use CUDA")
+            .blurb("Demosaic on a CUDA device when available, falling back to the CPU")
+            .default_value(DEFAULT_USE_CUDA)
+            .mutable_playing()
+            .build(),
+        glib::ParamSpecInt::builder("device")
+            .nick("Device")
+            .blurb("Index of the CUDA device used when use-cuda is enabled")
+            .minimum(0)
+            .default_value(DEFAULT_DEVICE)
+            .mutable_ready()
+            .build(),
+    ]);
+
+    properties
+}
+
+impl Options {
+    // Returns false if `name` isn't one of this backend's properties.
+    pub fn set_property(&mut self, name: &str, value: &glib::Value) -> bool {
+        match name {
+            "n-threads" => {
+                let n_threads = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    "Changing n-threads from {} to {}",
+                    self.n_threads,
+                    n_threads
+                );
+                self.n_threads = n_threads;
+            }
+            "opencv-threads" => {
+                self.opencv_threads = value.get().expect("type checked upstream");
+            }
+            "use-opencl" => {
+                self.use_opencl = value.get().expect("type checked upstream");
+            }
+            "denoise" => {
+                self.denoise = value.get().expect("type checked upstream");
+            }
+            "denoise-strength" => {
+                self.denoise_strength = value.get().expect("type checked upstream");
+            }
+            "temporal-denoise" => {
+                self.temporal_denoise = value.get().expect("type checked upstream");
+            }
+            "sharpen-amount" => {
+                self.sharpen_amount = value.get().expect("type checked upstream");
+            }
+            "sharpen-radius" => {
+                self.sharpen_radius = value.get().expect("type checked upstream");
+            }
+            "show-debug-overlay" => {
+                self.show_debug_overlay = value.get().expect("type checked upstream");
+            }
+            "overlay-corner" => {
+                self.overlay_corner = value.get().expect("type checked upstream");
+            }
+            "output-width" => {
+                self.output_width = value.get().expect("type checked upstream");
+            }
+            "output-height" => {
+                self.output_height = value.get().expect("type checked upstream");
+            }
+            "scale-method" => {
+                self.scale_method = value.get().expect("type checked upstream");
+            }
+            #[cfg(feature = "cuda")]
+            "use-cuda" => {
+                self.use_cuda = value.get().expect("type checked upstream");
+            }
+            #[cfg(feature = "cuda")]
+            "device" => {
+                self.device = value.get().expect("type checked upstream");
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn property(&self, name: &str) -> Option<glib::Value> {
+        match name {
+            "n-threads" => Some(self.n_threads.to_value()),
+            "opencv-threads" => Some(self.opencv_threads.to_value()),
+            "use-opencl" => Some(self.use_opencl.to_value()),
+            "denoise" => Some(self.denoise.to_value()),
+            "denoise-strength" => Some(self.denoise_strength.to_value()),
+            "temporal-denoise" => Some(self.temporal_denoise.to_value()),
+            "sharpen-amount" => Some(self.sharpen_amount.to_value()),
+            "sharpen-radius" => Some(self.sharpen_radius.to_value()),
+            "show-debug-overlay" => Some(self.show_debug_overlay.to_value()),
+            "overlay-corner" => Some(self.overlay_corner.to_value()),
+            "output-width" => Some(self.output_width.to_value()),
+            "output-height" => Some(self.output_height.to_value()),
+            "scale-method" => Some(self.scale_method.to_value()),
+            #[cfg(feature = "cuda")]
+            "use-cuda" => Some(self.use_cuda.to_value()),
+            #[cfg(feature = "cuda")]
+            "device" => Some(self.device.to_value()),
+            _ => None,
+        }
+    }
+}
+
+pub fn apply_opencv_threads(n_threads: i32) {
+    let applied = *OPENCV_THREADS.get_or_init(|| {
+        gst::warning!(
+            CAT,
+            "Setting OpenCV thread count to {} for the whole process",
+            n_threads
+        );
+        if let Err(err) = opencv::core::set_num_threads(n_threads) {
+            gst::warning!(CAT, "Failed to set OpenCV threads: {}", err);
+        }
+        n_threads
+    });
+
+    if applied != n_threads {
+        gst::warning!(
+            CAT,
+            "OpenCV thread count already set to {} by another element, ignoring {}",
+            applied,
+            n_threads
+        );
+    }
+}
+
+// OpenCV names Bayer codes after the second row of the tile, so its BayerBG is what
+// the caps call rggb. Every algorithm only demosaics into 3-channel frames.
+fn demosaic_code(
+    pattern: Pattern,
+    format: gst_video::VideoFormat,
+    algorithm: DemosaicAlgorithm,
+) -> i32 {
+    use opencv::imgproc::*;
+
+    let bgr = format == gst_video::VideoFormat::Bgr;
+    match (algorithm, pattern, bgr) {
+        (DemosaicAlgorithm::Bilinear, Pattern::Rggb, false) => COLOR_BayerBG2RGB,
+        (DemosaicAlgorithm::Bilinear, Pattern::Rggb, true) => COLOR_BayerBG2BGR,
+        (DemosaicAlgorithm::Bilinear, Pattern::Bggr, false) => COLOR_BayerRG2RGB,
+        (DemosaicAlgorithm::Bilinear, Pattern::Bggr, true) => COLOR_BayerRG2BGR,
+        (DemosaicAlgorithm::Bilinear, Pattern::Gbrg, false) => COLOR_BayerGR2RGB,
+        (DemosaicAlgorithm::Bilinear, Pattern::Gbrg, true) => COLOR_BayerGR2BGR,
+        (DemosaicAlgorithm::Bilinear, Pattern::Grbg, false) => COLOR_BayerGB2RGB,
+        (DemosaicAlgorithm::Bilinear, Pattern::Grbg, true) => COLOR_BayerGB2BGR,
+        (DemosaicAlgorithm::Vng, Pattern::Rggb, false) => COLOR_BayerBG2RGB_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Rggb, true) => COLOR_BayerBG2BGR_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Bggr, false) => COLOR_BayerRG2RGB_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Bggr, true) => COLOR_BayerRG2BGR_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Gbrg, false) => COLOR_BayerGR2RGB_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Gbrg, true) => COLOR_BayerGR2BGR_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Grbg, false) => COLOR_BayerGB2RGB_VNG,
+        (DemosaicAlgorithm::Vng, Pattern::Grbg, true) => COLOR_BayerGB2BGR_VNG,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Rggb, false) => COLOR_BayerBG2RGB_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Rggb, true) => COLOR_BayerBG2BGR_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Bggr, false) => COLOR_BayerRG2RGB_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Bggr, true) => COLOR_BayerRG2BGR_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Gbrg, false) => COLOR_BayerGR2RGB_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Gbrg, true) => COLOR_BayerGR2BGR_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Grbg, false) => COLOR_BayerGB2RGB_EA,
+        (DemosaicAlgorithm::EdgeAware, Pattern::Grbg, true) => COLOR_BayerGB2BGR_EA,
+    }
+}
+
+// How the negotiated output format is produced from the bayer input. Derived once per
+// caps so conversions don't redo it for every buffer.
+#[derive(Debug, Clone, Copy)]
+enum Conversion {
+    // One pass, bayer straight into the output plane.
+    Direct { code: i32, output_type: i32 },
+    // Two passes, bayer to RGB into the intermediate frame, then `expand_code` into
+    // the output plane. Slower, but works for formats OpenCV can't demosaic into.
+    Intermediate {
+        code: i32,
+        expand_code: i32,
+        output_type: i32,
+    },
+}
+
+impl Conversion {
+    fn new(
+        pattern: Pattern,
+        format: gst_video::VideoFormat,
+        algorithm: DemosaicAlgorithm,
+    ) -> Option<Self> {
+        match format {
+            gst_video::VideoFormat::Bgr | gst_video::VideoFormat::Rgb => {
+                Some(Conversion::Direct {
+                    code: demosaic_code(pattern, format, algorithm),
+                    output_type: opencv::core::CV_8UC3,
+                })
+            }
+            gst_video::VideoFormat::Rgba => Some(Conversion::Intermediate {
+                code: demosaic_code(pattern, format, algorithm),
+                expand_code: opencv::imgproc::COLOR_RGB2RGBA,
+                output_type: opencv::core::CV_8UC4,
+            }),
+            _ => None,
+        }
+    }
+
+    // Code of the demosaic pass, the first pass of two-pass conversions.
+    fn code(&self) -> i32 {
+        match *self {
+            Conversion::Direct { code, .. } | Conversion::Intermediate { code, .. } => code,
+        }
+    }
+
+    fn output_type(&self) -> i32 {
+        match *self {
+            Conversion::Direct { output_type, .. }
+            | Conversion::Intermediate { output_type, .. } => output_type,
+        }
+    }
+}
+
+// OpenCL is initialized lazily the first time use-opencl is seen enabled, and never
+// retried for the same caps once it failed.
+enum OpenCl {
+    Untried,
+    Unavailable,
+    Ready(OpenClFrames),
+}
+
+// Device-side frames kept across buffers to avoid per-frame allocations.
+#[derive(Default)]
+struct OpenClFrames {
+    input: opencv::core::UMat,
+    demosaiced: opencv::core::UMat,
+    output: opencv::core::UMat,
+}
+
+impl OpenCl {
+    fn frames(&mut self) -> Option<&mut OpenClFrames> {
+        if let OpenCl::Untried = self {
+            *self = match init_opencl() {
+                Ok(()) => OpenCl::Ready(OpenClFrames::default()),
+                Err(err) => {
+                    gst::warning!(CAT, "OpenCL unavailable, converting on the CPU: {}", err);
+                    OpenCl::Unavailable
+                }
+            };
+        }
+
+        match self {
+            OpenCl::Ready(frames) => Some(frames),
+            _ => None,
+        }
+    }
+}
+
+fn init_opencl() -> Result<(), String> {
+    if !opencv::core::have_opencl().map_err(|err| err.to_string())? {
+        return Err("OpenCV was built without OpenCL or no runtime was found".into());
+    }
+    opencv::core::set_use_opencl(true).map_err(|err| err.to_string())?;
+    if !opencv::core::use_opencl().map_err(|err| err.to_string())? {
+        return Err("no usable OpenCL device".into());
+    }
+
+    let device = opencv::core::Device::get_default()
+        .and_then(|device| device.name())
+        .unwrap_or_else(|_| "unknown".into());
+    gst::info!(CAT, "Using OpenCL device {}", device);
+
+    Ok(())
+}
+
+// Per-caps OpenCV state, including scratch frames reused across buffers.
+pub struct Converter {
+    pattern: Pattern,
+    format: gst_video::VideoFormat,
+    width: usize,
+    height: usize,
+    algorithm: DemosaicAlgorithm,
+    conversion: Conversion,
+    // Scratch RGB frame for two-pass conversions, allocated once per caps.
+    intermediate_rgb: Option<opencv::core::Mat>,
+    // Per-strip scratch frames for n-threads, grown on demand.
+    strip_scratch: Vec<opencv::core::Mat>,
+    opencl: OpenCl,
+    #[cfg(feature = "cuda")]
+    cuda: cuda::Cuda,
+    #[cfg(feature = "cuda")]
+    cuda_algorithm_warned: bool,
+}
+
+impl Converter {
+    pub fn new(
+        pattern: Pattern,
+        width: usize,
+        height: usize,
+        format: gst_video::VideoFormat,
+    ) -> Result<Self, String> {
+        let algorithm = DemosaicAlgorithm::default();
+        let conversion = Conversion::new(pattern, format, algorithm)
+            .ok_or_else(|| format!("Unsupported output format {format:?}"))?;
+
+        let intermediate_rgb = match conversion {
+            Conversion::Intermediate { .. } => Some(
+                mat::zeroed(height, width, opencv::core::CV_8UC3)
+                    .map_err(|err| format!("Failed to allocate: {err}"))?,
+            ),
+            Conversion::Direct { .. } => None,
+        };
+
+        Ok(Converter {
+            pattern,
+            format,
+            width,
+            height,
+            algorithm,
+            conversion,
+            intermediate_rgb,
+            strip_scratch: Vec::new(),
+            opencl: OpenCl::Untried,
+            #[cfg(feature = "cuda")]
+            cuda: cuda::Cuda::Untried,
+            #[cfg(feature = "cuda")]
+            cuda_algorithm_warned: false,
+        })
+    }
+
+    // Only the demosaic code changes, so the scratch frames stay valid.
+    pub fn set_algorithm(&mut self, algorithm: DemosaicAlgorithm) {
+        if algorithm == self.algorithm {
+            return;
+        }
+
+        if let Some(conversion) = Conversion::new(self.pattern, self.format, algorithm) {
+            self.algorithm = algorithm;
+            self.conversion = conversion;
+        }
+    }
+
+    pub fn convert(
+        &mut self,
+        in_data: &[u8],
+        in_stride: usize,
+        out_data: &mut [u8],
+        out_stride: usize,
+        options: &Options,
+    ) -> Result<(), gst::FlowError> {
+        let n_strips = (options.n_threads as usize).min(self.height / STRIP_MIN_ROWS);
+        if n_strips > 1 {
+            return self.convert_strips(in_data, in_stride, out_data, out_stride, n_strips);
+        }
+
+        let input_mat = mat::wrap(
+            in_data,
+            self.height,
+            self.width,
+            opencv::core::CV_8UC1, //bayer will always be this
+            in_stride,
+        )
+        .map_err(|err| wrap_error("input", err))?;
+
+        let mut output_mat = mat::wrap_mut(
+            out_data,
+            self.height,
+            self.width,
+            self.conversion.output_type(),
+            out_stride,
+        )
+        .map_err(|err| wrap_error("output", err))?;
+
+        // cv::cuda::demosaicing() has no VNG or edge-aware variant.
+        #[cfg(feature = "cuda")]
+        if options.use_cuda && self.algorithm != DemosaicAlgorithm::Bilinear {
+            if !self.cuda_algorithm_warned {
+                gst::warning!(
+                    CAT,
+                    "{:?} demosaic is not available with CUDA, converting on the CPU",
+                    self.algorithm
+                );
+                self.cuda_algorithm_warned = true;
+            }
+        } else if options.use_cuda {
+            let conversion = self.conversion;
+            let expand_code = match conversion {
+                Conversion::Direct { .. } => None,
+                Conversion::Intermediate { expand_code, .. } => Some(expand_code),
+            };
+            if let Some(frames) = self.cuda.frames(
+                options.device,
+                self.height,
+                self.width,
+                conversion.output_type(),
+            ) {
+                return frames
+                    .convert(&input_mat, &mut output_mat, conversion.code(), expand_code)
+                    .map_err(|err| {
+                        gst::error!(CAT, "CUDA conversion failed: {}", err);
+                        gst::FlowError::Error
+                    });
+            }
+        }
+
+        if options.use_opencl {
+            let conversion = self.conversion;
+            if let Some(frames) = self.opencl.frames() {
+                return opencl_convert(&input_mat, &mut output_mat, conversion, frames);
+            }
+        }
+
+        match self.conversion {
+            Conversion::Direct { code, .. } => {
+                opencv::imgproc::cvt_color_def(&input_mat, &mut output_mat, code)
+                    .map_err(|_| gst::FlowError::Error)
+            }
+            Conversion::Intermediate {
+                code, expand_code, ..
+            } => {
+                let intermediate_rgb = self
+                    .intermediate_rgb
+                    .as_mut()
+                    .ok_or(gst::FlowError::NotNegotiated)?;
+                opencv::imgproc::cvt_color_def(&input_mat, intermediate_rgb, code)
+                    .map_err(|_| gst::FlowError::Error)?;
+                opencv::imgproc::cvt_color_def(&*intermediate_rgb, &mut output_mat, expand_code)
+                    .map_err(|_| gst::FlowError::Error)
+            }
+        }
+    }
+
+    // Splits the frame into `n_strips` horizontal strips converted on scoped threads.
+    // Each thread writes only its own rows of the output plane, so the result is
+    // identical to a single conversion of the whole frame.
+    fn convert_strips(
+        &mut self,
+        in_data: &[u8],
+        in_stride: usize,
+        out_data: &mut [u8],
+        out_stride: usize,
+        n_strips: usize,
+    ) -> Result<(), gst::FlowError> {
+        let width = self.width;
+        let height = self.height;
+        // Round up to even so every strip starts on an even row.
+        let strip_rows = height.div_ceil(n_strips).next_multiple_of(2);
+        let conversion = self.conversion;
+
+        self.strip_scratch
+            .resize_with(n_strips, opencv::core::Mat::default);
+
+        std::thread::scope(|scope| {
+            let handles = out_data
+                .chunks_mut(strip_rows * out_stride)
+                .zip(self.strip_scratch.iter_mut())
+                .enumerate()
+                .map(|(i, (out_strip, scratch))| {
+                    let start = i * strip_rows;
+                    let rows = start.min(height)..(start + strip_rows).min(height);
+                    scope.spawn(move || {
+                        convert_strip(
+                            in_data, in_stride, width, height, rows, conversion, scratch,
+                            out_strip, out_stride,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles.into_iter().try_for_each(|handle| {
+                handle.join().unwrap_or(Err(gst::FlowError::Error))
+            })
+        })
+    }
+}
+
+// Uploads the input, runs the conversion on the OpenCL device and downloads the result
+// straight into the output plane.
+fn opencl_convert(
+    input_mat: &impl MatTraitConst,
+    output_mat: &mut impl opencv::core::ToOutputArray,
+    conversion: Conversion,
+    frames: &mut OpenClFrames,
+) -> Result<(), gst::FlowError> {
+    input_mat
+        .copy_to(&mut frames.input)
+        .map_err(|_| gst::FlowError::Error)?;
+
+    match conversion {
+        Conversion::Direct { code, .. } => {
+            opencv::imgproc::cvt_color_def(&frames.input, &mut frames.output, code)
+        }
+        Conversion::Intermediate {
+            code, expand_code, ..
+        } => opencv::imgproc::cvt_color_def(&frames.input, &mut frames.demosaiced, code)
+            .and_then(|_| {
+                opencv::imgproc::cvt_color_def(
+                    &frames.demosaiced,
+                    &mut frames.output,
+                    expand_code,
+                )
+            }),
+    }
+    .map_err(|_| gst::FlowError::Error)?;
+
+    frames
+        .output
+        .copy_to(output_mat)
+        .map_err(|_| gst::FlowError::Error)
+}
+
+// Demosaicing a row reads its neighbours, so every strip converts this many extra input
+// rows on either side and discards them. Being even, it also keeps each strip starting
+// on the same CFA phase as the full frame.
+const STRIP_OVERLAP: usize = 2;
+// Strips smaller than this cost more in thread handoff than they save.
+const STRIP_MIN_ROWS: usize = 64;
+
+#[allow(clippy::too_many_arguments)]
+fn convert_strip(
+    in_data: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    rows: std::ops::Range<usize>,
+    conversion: Conversion,
+    scratch: &mut opencv::core::Mat,
+    out_strip: &mut [u8],
+    out_stride: usize,
+) -> Result<(), gst::FlowError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let first = rows.start.saturating_sub(STRIP_OVERLAP);
+    let last = (rows.end + STRIP_OVERLAP).min(height);
+    let input_mat = mat::wrap(
+        in_data.get(first * in_stride..).unwrap_or_default(),
+        last - first,
+        width,
+        opencv::core::CV_8UC1,
+        in_stride,
+    )
+    .map_err(|err| wrap_error("input strip", err))?;
+
+    opencv::imgproc::cvt_color_def(&input_mat, scratch, conversion.code())
+        .map_err(|_| gst::FlowError::Error)?;
+    let valid = scratch
+        .row_bounds((rows.start - first) as i32, (rows.end - first) as i32)
+        .map_err(|_| gst::FlowError::Error)?;
+
+    let mut output_mat = mat::wrap_mut(
+        out_strip,
+        rows.len(),
+        width,
+        conversion.output_type(),
+        out_stride,
+    )
+    .map_err(|err| wrap_error("output strip", err))?;
+
+    match conversion {
+        Conversion::Direct { .. } => valid.copy_to(&mut output_mat),
+        Conversion::Intermediate { expand_code, .. } => {
+            opencv::imgproc::cvt_color_def(&valid, &mut output_mat, expand_code)
+        }
+    }
+    .map_err(|_| gst::FlowError::Error)
+}
+
+fn wrap_error(what: &str, err: mat::MatError) -> gst::FlowError {
+    gst::error!(CAT, "Failed to wrap {} buffer: {}", what, err);
+    gst::FlowError::Error
+}
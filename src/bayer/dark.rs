@@ -0,0 +1,76 @@
+// Dark frame subtraction. A master dark, the average of exposures taken with the lens
+// capped at the exposure time and temperature of the captures, holds the fixed pattern
+// of the sensor: hot pixels, amplifier glow and the black level. It is subtracted from
+// every frame before anything else.
+
+use std::path::Path;
+
+use super::frame::Rect;
+use super::npy::{Array, Data};
+
+/// A master dark with the size of the input frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DarkFrame {
+    width: usize,
+    height: usize,
+    samples: Samples,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Samples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl DarkFrame {
+    /// Loads a dark frame from a `.npy` file holding a `(height, width)` array of `u1`
+    /// or `u2` bayer samples.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let array = Array::load(path)?;
+        let [height, width] = array.shape[..] else {
+            return Err(format!(
+                "{}: dark frame needs a (height, width) shape, not {:?}",
+                path.display(),
+                array.shape
+            ));
+        };
+        let samples = match array.data {
+            Data::U8(samples) => Samples::U8(samples),
+            Data::U16(samples) => Samples::U16(samples),
+            _ => {
+                return Err(format!(
+                    "{}: dark frame needs 8 or 16-bit samples",
+                    path.display()
+                ));
+            }
+        };
+
+        Ok(DarkFrame {
+            width,
+            height,
+            samples,
+        })
+    }
+
+    /// The `window` of the dark frame for `width` x `height` 8-bit input frames, row
+    /// major. Fails unless the dark frame has the input size and depth.
+    pub fn window_u8(&self, width: usize, height: usize, window: Rect) -> Result<Vec<u8>, String> {
+        if (self.width, self.height) != (width, height) {
+            return Err(format!(
+                "{}x{} dark frame doesn't match the {}x{} input",
+                self.width, self.height, width, height
+            ));
+        }
+        let Samples::U8(ref samples) = self.samples else {
+            return Err("16-bit dark frame doesn't match the 8-bit input".to_string());
+        };
+
+        Ok(samples
+            .chunks_exact(self.width)
+            .skip(window.y)
+            .take(window.height)
+            .flat_map(|row| &row[window.x..][..window.width])
+            .copied()
+            .collect())
+    }
+}
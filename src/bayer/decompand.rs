@@ -0,0 +1,135 @@
+// Decompanding of companded raw input for the decompanding property.
+//
+// HDR sensors, many automotive and MIPI ones among them, compress 20 or 24 bits of
+// linear signal into far fewer with a piecewise-linear curve, steep in the shadows and
+// flatter and flatter towards the highlights. Demosaicing those codes as they are gets
+// every tone wrong, so the curve is inverted first, on the raw samples before the
+// black level, by a lookup table from every 8-bit code to a 16-bit linear level. The
+// rest of the conversion then runs at 16 bits like with high-precision.
+//
+// The curve is given by knee points, pairs of an input code and the linear level it
+// expands to, joined by straight lines. Codes before the first knee take its level and
+// codes past the last one the last level.
+
+use gst::glib;
+
+use super::frame::{Error, fits};
+
+/// Highest input code and output level of a knee point.
+pub const MAX_CODE: u32 = u8::MAX as u32;
+pub const MAX_LEVEL: u32 = u16::MAX as u32;
+
+/// Named curves, for property values holding just the name.
+pub const PRESETS: [(&str, &[(u32, u32)]); 1] = [("a-law", &A_LAW)];
+
+// Eight segments of 32 codes, the first two as steep as 12-bit linear data and every
+// following one twice as steep as the one before, the segmented law of G.711 A-law.
+// Expands to 12 bits, scaled to 16.
+const A_LAW: [(u32, u32); 9] = [
+    (0, 0),
+    (32, 512),
+    (64, 1024),
+    (96, 2048),
+    (128, 4096),
+    (160, 8192),
+    (192, 16384),
+    (224, 32768),
+    (255, 64512),
+];
+
+/// Knee points of a decompanding property value: an array of `<code, level>` pairs, or
+/// of a preset name alone. The codes have to rise and the levels must not fall, so the
+/// curve can be inverted by the sensor's compander.
+pub fn knees(array: &gst::Array) -> Result<Vec<(u32, u32)>, String> {
+    let name = match array.as_slice() {
+        [name] => name.get::<&str>().ok(),
+        _ => None,
+    };
+    if let Some(name) = name {
+        return PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, knees)| knees.to_vec())
+            .ok_or_else(|| format!("unknown preset {name:?}"));
+    }
+
+    let knees = array
+        .iter()
+        .map(knee)
+        .collect::<Option<Vec<_>>>()
+        .ok_or("knee points have to be pairs of integers")?;
+    if knees.len() < 2 {
+        return Err("needs at least two knee points".to_string());
+    }
+    if let Some(&(code, level)) = knees
+        .iter()
+        .find(|&&(code, level)| code > MAX_CODE || level > MAX_LEVEL)
+    {
+        return Err(format!(
+            "knee point <{code}, {level}> is past code {MAX_CODE} or level {MAX_LEVEL}"
+        ));
+    }
+    if let Some(pair) = knees
+        .windows(2)
+        .find(|pair| pair[1].0 <= pair[0].0 || pair[1].1 < pair[0].1)
+    {
+        return Err(format!(
+            "curve isn't monotonic from <{}, {}> to <{}, {}>",
+            pair[0].0, pair[0].1, pair[1].0, pair[1].1
+        ));
+    }
+
+    Ok(knees)
+}
+
+fn knee(value: &glib::SendValue) -> Option<(u32, u32)> {
+    let pair = value.get::<gst::Array>().ok()?;
+    let [code, level] = pair.as_slice() else {
+        return None;
+    };
+    let integer = |value: &glib::SendValue| u32::try_from(value.get::<i32>().ok()?).ok();
+    Some((integer(code)?, integer(level)?))
+}
+
+/// The 16-bit level of every 8-bit code along the curve through validated `knees`.
+pub fn lut(knees: &[(u32, u32)]) -> Vec<u16> {
+    let (first, last) = (knees[0], knees[knees.len() - 1]);
+    (0..=MAX_CODE)
+        .map(|code| {
+            let level = if code <= first.0 {
+                first.1
+            } else if code >= last.0 {
+                last.1
+            } else {
+                // Along the segment to the first knee past the code.
+                let next = knees.partition_point(|&(knee, _)| knee <= code);
+                let ((start, from), (end, to)) = (knees[next - 1], knees[next]);
+                from + ((to - from) * (code - start) + (end - start) / 2) / (end - start)
+            };
+            level as u16
+        })
+        .collect()
+}
+
+/// Expands `width` x `height` 8-bit samples, rows `in_stride` bytes apart, through
+/// `lut` into `output`, rows tightly packed.
+pub fn expand(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    lut: &[u16],
+    output: &mut Vec<u16>,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width, in_stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    output.clear();
+    for y in 0..height {
+        let row = &input[y * in_stride..][..width];
+        output.extend(row.iter().map(|&code| lut[code as usize]));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+// Defective pixel correction. Stuck and dead sites from the sensor's defect list are
+// replaced with the median of their same color neighbors before demosaic, where they
+// would otherwise smear into colored crosses.
+//
+// The neighbors are the eight samples two sites away horizontally, vertically and
+// diagonally, which share the defect's color in every bayer pattern. Neighbors outside
+// the frame or on the defect list themselves are left out.
+//
+// Without a defect list, outliers can be detected on the frames themselves: samples
+// beyond all four of their horizontal and vertical same color neighbors, and further
+// than a threshold from the median of them. Detection costs a pass over the frame, so
+// it may run on every Nth frame only, with the sites found then corrected in between.
+
+use std::path::Path;
+
+use super::frame::Rect;
+use super::raw::Sample;
+
+/// Positions of the defective sites of a sensor, in input pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefectList {
+    sites: Vec<(usize, usize)>,
+}
+
+impl DefectList {
+    /// Loads a defect list from a text file with one `x,y` site per line. Fields may
+    /// be separated by commas or whitespace, an optional third field giving the
+    /// defect type is ignored, lines starting with `#` are comments and the first
+    /// line may be a header.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        DefectList::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sites = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty());
+            let site = fields
+                .next()
+                .zip(fields.next())
+                .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+            match site {
+                Some(site) => sites.push(site),
+                None if i == 0 => (),
+                None => return Err(format!("line {}: expected x,y, got '{}'", i + 1, line)),
+            }
+        }
+
+        sites.sort_unstable();
+        sites.dedup();
+        Ok(DefectList { sites })
+    }
+
+    /// The sites within `window` of `width` x `height` input frames, for frames of
+    /// the window's size without padding. Fails if a site is outside the input.
+    pub fn for_window(
+        &self,
+        width: usize,
+        height: usize,
+        window: Rect,
+    ) -> Result<DefectMap, String> {
+        if let Some(&(x, y)) = self.sites.iter().find(|&&(x, y)| x >= width || y >= height) {
+            return Err(format!(
+                "defect at {x},{y} is outside the {width}x{height} input"
+            ));
+        }
+
+        let inside = |x: usize, y: usize| {
+            (window.x..window.x + window.width).contains(&x)
+                && (window.y..window.y + window.height).contains(&y)
+        };
+        let is_defect = |x: usize, y: usize| self.sites.binary_search(&(x, y)).is_ok();
+        let index = |x: usize, y: usize| (y - window.y) * window.width + (x - window.x);
+
+        let sites = self
+            .sites
+            .iter()
+            .filter(|&&(x, y)| inside(x, y))
+            .map(|&(x, y)| {
+                let neighbors = NEIGHBORS
+                    .iter()
+                    .filter_map(|&(dx, dy)| {
+                        let x = x.checked_add_signed(dx)?;
+                        let y = y.checked_add_signed(dy)?;
+                        (inside(x, y) && !is_defect(x, y)).then(|| index(x, y))
+                    })
+                    .collect();
+                Site {
+                    index: index(x, y),
+                    neighbors,
+                }
+            })
+            .collect();
+
+        Ok(DefectMap { sites })
+    }
+}
+
+// Same color neighbors of a site.
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-2, -2),
+    (0, -2),
+    (2, -2),
+    (-2, 0),
+    (2, 0),
+    (-2, 2),
+    (0, 2),
+    (2, 2),
+];
+
+/// Defective sites of a frame with the samples to replace each of them with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefectMap {
+    sites: Vec<Site>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Site {
+    index: usize,
+    neighbors: Vec<usize>,
+}
+
+impl DefectMap {
+    /// Replaces every defective sample of `frame` with the median of its neighbors.
+    /// Sites without a usable neighbor are left as they are.
+    pub fn apply<S: Sample>(&self, frame: &mut [S]) {
+        let mut values = Vec::with_capacity(NEIGHBORS.len());
+        for site in &self.sites {
+            values.clear();
+            values.extend(
+                site.neighbors
+                    .iter()
+                    .filter_map(|&i| frame.get(i).map(|sample| sample.to_u64())),
+            );
+            if values.is_empty() || site.index >= frame.len() {
+                continue;
+            }
+
+            let mid = values.len() / 2;
+            frame[site.index] = S::from_u64(*values.select_nth_unstable(mid).1);
+        }
+    }
+}
+
+/// Settings of the on the fly detection of defective pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoDefects {
+    /// Distance from the median of the neighbors a defect exceeds, as a fraction of
+    /// the full sample range.
+    pub threshold: f64,
+    /// Frames from one detection to the next.
+    pub interval: u32,
+}
+
+/// Detects and corrects outliers, keeping the sites found across frames.
+#[derive(Debug, Default)]
+pub struct Detector {
+    suspects: Vec<usize>,
+    // Frames since the last detection, None before the first one.
+    frames: Option<u32>,
+}
+
+impl Detector {
+    /// Corrects the defects of a `width` x `height` frame without padding, detecting
+    /// them anew if `settings.interval` frames have passed since the last detection.
+    /// Returns the number of corrected sites.
+    pub fn correct<S: Sample>(
+        &mut self,
+        frame: &mut [S],
+        width: usize,
+        height: usize,
+        settings: AutoDefects,
+    ) -> usize {
+        if width < 5 || height < 5 || frame.len() < width * height {
+            return 0;
+        }
+
+        let frames = self
+            .frames
+            .map_or(u32::MAX, |frames| frames.saturating_add(1));
+        if frames >= settings.interval.max(1) {
+            let threshold = (settings.threshold * S::MAX as f64).round() as u64;
+            self.detect(frame, width, height, threshold);
+            self.frames = Some(0);
+        } else {
+            self.frames = Some(frames);
+        }
+
+        // Replaced after detecting every site, so a defect never feeds another's median.
+        let medians: Vec<u64> = self
+            .suspects
+            .iter()
+            .map(|&i| median_of_4(neighbors(frame, width, i)).0)
+            .collect();
+        for (&i, median) in self.suspects.iter().zip(medians) {
+            frame[i] = S::from_u64(median);
+        }
+
+        self.suspects.len()
+    }
+
+    /// Forgets the sites found so far, the next frame is searched again.
+    pub fn reset(&mut self) {
+        self.suspects.clear();
+        self.frames = None;
+    }
+
+    fn detect<S: Sample>(&mut self, frame: &[S], width: usize, height: usize, threshold: u64) {
+        self.suspects.clear();
+        for y in 2..height - 2 {
+            for x in 2..width - 2 {
+                let i = y * width + x;
+                let value = frame[i].to_u64();
+                let (median, min, max) = median_of_4(neighbors(frame, width, i));
+                if (value > max && value - median > threshold)
+                    || (value < min && median - value > threshold)
+                {
+                    self.suspects.push(i);
+                }
+            }
+        }
+    }
+}
+
+fn neighbors<S: Sample>(frame: &[S], width: usize, i: usize) -> [u64; 4] {
+    [
+        frame[i - 2 * width],
+        frame[i - 2],
+        frame[i + 2],
+        frame[i + 2 * width],
+    ]
+    .map(S::to_u64)
+}
+
+// Median of four values, the mean of the middle two, with their minimum and maximum.
+fn median_of_4(values: [u64; 4]) -> (u64, u64, u64) {
+    let min = values.into_iter().min().unwrap();
+    let max = values.into_iter().max().unwrap();
+    let sum: u64 = values.into_iter().sum();
+
+    ((sum - min - max) / 2, min, max)
+}
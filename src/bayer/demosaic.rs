@@ -0,0 +1,349 @@
+// Built-in bilinear demosaic, used when the element is built without OpenCV or when
+// the `backend` property selects it.
+//
+// Every output pixel takes its own CFA sample for its color and averages the nearest
+// two or four samples of the other colors. Rows and columns beyond the frame are
+// mirrored around the edge pixel, which keeps the CFA phase intact. Interior pixels
+// match OpenCV's bilinear `cvtColor()` within +-1 from rounding; the outermost row and
+// column can differ more because OpenCV copies their neighbours instead of mirroring.
+//
+// The scalar code is generic over the sample type, so high-precision mode demosaics
+// its 16-bit frames with it too, whichever backends are built.
+
+use super::cfa::{CfaColor, Pattern};
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+
+#[cfg(feature = "rust-demosaic")]
+mod simd;
+
+// Mirrors `i` around the frame edges, so -1 maps to 1 and `n` to `n - 2`.
+#[inline]
+fn mirror(i: isize, n: usize) -> usize {
+    if i < 0 {
+        (-i) as usize
+    } else if i as usize >= n {
+        2 * (n - 1) - i as usize
+    } else {
+        i as usize
+    }
+}
+
+#[inline]
+fn avg2<S: Sample>(a: S, b: S) -> S {
+    S::from_u64((a.to_u64() + b.to_u64()).div_ceil(2))
+}
+
+#[inline]
+fn avg4<S: Sample>(a: S, b: S, c: S, d: S) -> S {
+    S::from_u64((a.to_u64() + b.to_u64() + c.to_u64() + d.to_u64() + 2) / 4)
+}
+
+// Interpolates the pixel at `x` of the row `cur`, whose CFA site has `color`. `up` and
+// `down` are the mirrored neighbouring rows.
+#[inline]
+fn interpolate<S: Sample>(up: &[S], cur: &[S], down: &[S], x: usize, color: CfaColor) -> [S; 3] {
+    let width = cur.len();
+    let l = mirror(x as isize - 1, width);
+    let r = mirror(x as isize + 1, width);
+
+    match color {
+        CfaColor::Red => [
+            cur[x],
+            avg4(up[x], down[x], cur[l], cur[r]),
+            avg4(up[l], up[r], down[l], down[r]),
+        ],
+        CfaColor::Blue => [
+            avg4(up[l], up[r], down[l], down[r]),
+            avg4(up[x], down[x], cur[l], cur[r]),
+            cur[x],
+        ],
+        CfaColor::GreenRed => [avg2(cur[l], cur[r]), cur[x], avg2(up[x], down[x])],
+        CfaColor::GreenBlue => [avg2(up[x], down[x]), cur[x], avg2(cur[l], cur[r])],
+    }
+}
+
+#[inline]
+fn store<S: Sample>(pixel: &mut [S], [red, green, blue]: [S; 3], layout: OutputLayout) {
+    pixel[layout.red] = red;
+    pixel[layout.green] = green;
+    pixel[layout.blue] = blue;
+    if let Some(alpha) = layout.alpha {
+        pixel[alpha] = S::from_u64(S::MAX);
+    }
+}
+
+// Demosaics pixels `range` of one row. `tile_row` holds the CFA colors of even and odd
+// columns.
+fn row_scalar<S: Sample>(
+    up: &[S],
+    cur: &[S],
+    down: &[S],
+    tile_row: [CfaColor; 2],
+    range: std::ops::Range<usize>,
+    out: &mut [S],
+    layout: OutputLayout,
+) {
+    for x in range {
+        let pixel = &mut out[x * layout.pixel_stride..(x + 1) * layout.pixel_stride];
+        store(
+            pixel,
+            interpolate(up, cur, down, x, tile_row[x & 1]),
+            layout,
+        );
+    }
+}
+
+/// Demosaics an 8-bit `width` x `height` frame into `output` using `layout`.
+///
+/// Uses the vectorized row kernel the CPU supports, which produces output identical to
+/// [`bilinear_scalar`].
+#[cfg(feature = "rust-demosaic")]
+#[allow(clippy::too_many_arguments)]
+pub fn bilinear(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    output: &mut [u8],
+    out_stride: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    bilinear_with(
+        simd::Kernel::detect(),
+        input,
+        in_stride,
+        width,
+        height,
+        pattern,
+        output,
+        out_stride,
+        layout,
+    )
+}
+
+// Like bilinear(), with `kernel` for the rows, or the scalar code without.
+#[cfg(feature = "rust-demosaic")]
+#[allow(clippy::too_many_arguments)]
+fn bilinear_with(
+    kernel: Option<simd::Kernel>,
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    output: &mut [u8],
+    out_stride: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    match kernel {
+        Some(kernel) => demosaic(
+            input,
+            in_stride,
+            width,
+            height,
+            pattern,
+            output,
+            out_stride,
+            layout,
+            |Rows {
+                 up,
+                 cur,
+                 down,
+                 tile_row,
+                 out,
+             }| {
+                let done = kernel.row(up, cur, down, tile_row, out, layout);
+                row_scalar(up, cur, down, tile_row, 0..1, out, layout);
+                row_scalar(up, cur, down, tile_row, done..width, out, layout);
+            },
+        ),
+        None => bilinear_scalar(
+            input, in_stride, width, height, pattern, output, out_stride, layout,
+        ),
+    }
+}
+
+/// Scalar reference implementation of [`bilinear`], which also demosaics 16-bit
+/// frames. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn bilinear_scalar<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    output: &mut [S],
+    out_stride: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    demosaic(
+        input,
+        in_stride,
+        width,
+        height,
+        pattern,
+        output,
+        out_stride,
+        layout,
+        |Rows {
+             up,
+             cur,
+             down,
+             tile_row,
+             out,
+         }| { row_scalar(up, cur, down, tile_row, 0..width, out, layout) },
+    )
+}
+
+// The rows needed to demosaic one output row.
+struct Rows<'a, S> {
+    up: &'a [S],
+    cur: &'a [S],
+    down: &'a [S],
+    tile_row: [CfaColor; 2],
+    out: &'a mut [S],
+}
+
+#[allow(clippy::too_many_arguments)]
+fn demosaic<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    output: &mut [S],
+    out_stride: usize,
+    layout: OutputLayout,
+    mut row: impl FnMut(Rows<'_, S>),
+) -> Result<(), Error> {
+    if width < 2 || height < 2 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, in_stride)
+        || !fits(
+            output.len(),
+            height,
+            width * layout.pixel_stride,
+            out_stride,
+        )
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let line = |y: usize| &input[y * in_stride..y * in_stride + width];
+    for y in 0..height {
+        row(Rows {
+            up: line(mirror(y as isize - 1, height)),
+            cur: line(y),
+            down: line(mirror(y as isize + 1, height)),
+            tile_row: pattern.tile()[y & 1],
+            out: &mut output[y * out_stride..y * out_stride + width * layout.pixel_stride],
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "rust-demosaic"))]
+mod tests {
+    use super::simd::Kernel;
+    use super::*;
+
+    const LAYOUTS: [gst_video::VideoFormat; 3] = [
+        gst_video::VideoFormat::Rgb,
+        gst_video::VideoFormat::Bgr,
+        gst_video::VideoFormat::Rgba,
+    ];
+
+    // xorshift64*, enough to fill the frames with noise.
+    fn random_samples(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state >> 12;
+                state ^= state << 25;
+                state ^= state >> 27;
+                (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        // Odd widths, widths with no room for a whole vector and widths leaving every
+        // tail length for the scalar code, at 2 rows and more.
+        let widths = (2..=70).chain([127, 128, 129]);
+        // None is the dispatch of bilinear(), whichever kernel it detects.
+        let mut kernels = vec![None];
+        kernels.extend(
+            Kernel::ALL
+                .iter()
+                .copied()
+                .filter(|kernel| kernel.is_supported())
+                .map(Some),
+        );
+
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+        for width in widths {
+            for height in [2, 3, 5] {
+                // Padded rows, so a kernel reading past the row would pick up noise.
+                let in_stride = width + 7;
+                seed += 1;
+                let input = random_samples(seed, in_stride * height);
+                for pattern in Pattern::ALL {
+                    for format in LAYOUTS {
+                        let layout = OutputLayout::for_format(format).unwrap();
+                        let out_stride = width * layout.pixel_stride + 5;
+                        let mut expected = vec![0; out_stride * height];
+                        bilinear_scalar(
+                            &input,
+                            in_stride,
+                            width,
+                            height,
+                            pattern,
+                            &mut expected,
+                            out_stride,
+                            layout,
+                        )
+                        .unwrap();
+
+                        for &kernel in &kernels {
+                            let mut output = vec![0; out_stride * height];
+                            match kernel {
+                                Some(kernel) => bilinear_with(
+                                    Some(kernel),
+                                    &input,
+                                    in_stride,
+                                    width,
+                                    height,
+                                    pattern,
+                                    &mut output,
+                                    out_stride,
+                                    layout,
+                                ),
+                                None => bilinear(
+                                    &input,
+                                    in_stride,
+                                    width,
+                                    height,
+                                    pattern,
+                                    &mut output,
+                                    out_stride,
+                                    layout,
+                                ),
+                            }
+                            .unwrap();
+                            assert!(
+                                output == expected,
+                                "{kernel:?} differs from the scalar code at {width}x{height} \
+                                 {pattern:?} {format:?}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,507 @@
+// Vectorized row kernels for the bilinear demosaic.
+//
+// Every kernel computes the same rounded averages as the scalar code, so the output is
+// bit-identical. A row is processed in chunks of as many pixels as a vector holds,
+// starting at column 1 so that all loads of the left and right neighbours stay inside
+// the row. The caller finishes the first column and the tail with the scalar code.
+//
+// The generic `row()` is instantiated once per instruction set inside a function with
+// the matching `#[target_feature]`, and everything it calls is `#[inline(always)]` so
+// the vector operations are compiled for that instruction set.
+
+use super::{CfaColor, OutputLayout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+impl Kernel {
+    /// Every kernel of the target architecture, widest first.
+    pub const ALL: &[Kernel] = &[
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2,
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2,
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon,
+    ];
+
+    /// The widest kernel the running CPU supports, if any.
+    pub fn detect() -> Option<Self> {
+        Kernel::ALL
+            .iter()
+            .copied()
+            .find(|kernel| kernel.is_supported())
+    }
+
+    /// Whether the running CPU supports the kernel.
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => std::arch::is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => std::arch::is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+        }
+    }
+
+    /// Demosaics the row `cur` from column 1 on, returning the first column it didn't
+    /// write. `up`, `down` and `cur` must have the same length and `out` must hold that
+    /// many pixels of `layout`.
+    pub fn row(
+        self,
+        up: &[u8],
+        cur: &[u8],
+        down: &[u8],
+        tile_row: [CfaColor; 2],
+        out: &mut [u8],
+        layout: OutputLayout,
+    ) -> usize {
+        assert!(up.len() == cur.len() && down.len() == cur.len());
+        assert!(out.len() >= cur.len() * layout.pixel_stride);
+
+        // SAFETY: `detect()` only returns kernels the CPU supports and the lengths were
+        // checked above.
+        unsafe {
+            match self {
+                #[cfg(target_arch = "x86_64")]
+                Kernel::Sse2 => x86::row_sse2(up, cur, down, tile_row, out, layout),
+                #[cfg(target_arch = "x86_64")]
+                Kernel::Avx2 => x86::row_avx2(up, cur, down, tile_row, out, layout),
+                #[cfg(target_arch = "aarch64")]
+                Kernel::Neon => arm::row_neon(up, cur, down, tile_row, out, layout),
+            }
+        }
+    }
+}
+
+// The operations the row kernel needs from a vector of u8 lanes.
+trait Vector: Copy {
+    const LANES: usize;
+
+    /// Loads `LANES` bytes from `src`.
+    unsafe fn load(src: *const u8) -> Self;
+    unsafe fn splat(value: u8) -> Self;
+    /// `(a + b + 1) / 2` per lane.
+    unsafe fn avg2(a: Self, b: Self) -> Self;
+    /// `(a + b + c + d + 2) / 4` per lane.
+    unsafe fn avg4(a: Self, b: Self, c: Self, d: Self) -> Self;
+    /// Even lanes from `even`, odd lanes from `odd`.
+    unsafe fn blend(even: Self, odd: Self) -> Self;
+    /// Interleaves three channels into `3 * LANES` bytes at `dst`.
+    unsafe fn store3(channels: [Self; 3], dst: *mut u8);
+    /// Interleaves four channels into `4 * LANES` bytes at `dst`.
+    unsafe fn store4(channels: [Self; 4], dst: *mut u8);
+}
+
+#[inline(always)]
+unsafe fn row<V: Vector>(
+    up: &[u8],
+    cur: &[u8],
+    down: &[u8],
+    tile_row: [CfaColor; 2],
+    out: &mut [u8],
+    layout: OutputLayout,
+) -> usize {
+    let width = cur.len();
+    let alpha = unsafe { V::splat(u8::MAX) };
+
+    let mut x = 1;
+    while x + V::LANES < width {
+        // SAFETY: `x - 1 >= 0` and `x + 1 + LANES <= width`, so every load is inside
+        // the rows, and `(x + LANES) * pixel_stride` bytes fit in `out`.
+        unsafe {
+            let c = V::load(cur.as_ptr().add(x));
+            let l = V::load(cur.as_ptr().add(x - 1));
+            let r = V::load(cur.as_ptr().add(x + 1));
+            let u = V::load(up.as_ptr().add(x));
+            let ul = V::load(up.as_ptr().add(x - 1));
+            let ur = V::load(up.as_ptr().add(x + 1));
+            let d = V::load(down.as_ptr().add(x));
+            let dl = V::load(down.as_ptr().add(x - 1));
+            let dr = V::load(down.as_ptr().add(x + 1));
+
+            let horizontal = V::avg2(l, r);
+            let vertical = V::avg2(u, d);
+            let cross = V::avg4(u, d, l, r);
+            let diagonal = V::avg4(ul, ur, dl, dr);
+
+            let sources = [horizontal, vertical, cross, diagonal, c];
+            // Chunks start at odd columns, so even lanes hold odd columns.
+            let (even, odd) = (select(tile_row[1], sources), select(tile_row[0], sources));
+            let red = V::blend(even[0], odd[0]);
+            let green = V::blend(even[1], odd[1]);
+            let blue = V::blend(even[2], odd[2]);
+
+            let dst = out.as_mut_ptr().add(x * layout.pixel_stride);
+            match layout.alpha {
+                Some(a) => {
+                    let mut channels = [alpha; 4];
+                    channels[layout.red] = red;
+                    channels[layout.green] = green;
+                    channels[layout.blue] = blue;
+                    channels[a] = alpha;
+                    V::store4(channels, dst);
+                }
+                None => {
+                    let mut channels = [c; 3];
+                    channels[layout.red] = red;
+                    channels[layout.green] = green;
+                    channels[layout.blue] = blue;
+                    V::store3(channels, dst);
+                }
+            }
+        }
+
+        x += V::LANES;
+    }
+
+    x
+}
+
+// Picks the red, green and blue sources for a CFA site of `color` out of the
+// horizontal, vertical, cross and diagonal averages and the center sample.
+#[inline(always)]
+fn select<V: Vector>(
+    color: CfaColor,
+    [horizontal, vertical, cross, diagonal, center]: [V; 5],
+) -> [V; 3] {
+    match color {
+        CfaColor::Red => [center, cross, diagonal],
+        CfaColor::Blue => [diagonal, cross, center],
+        CfaColor::GreenRed => [horizontal, center, vertical],
+        CfaColor::GreenBlue => [vertical, center, horizontal],
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{CfaColor, OutputLayout, Vector};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn row_sse2(
+        up: &[u8],
+        cur: &[u8],
+        down: &[u8],
+        tile_row: [CfaColor; 2],
+        out: &mut [u8],
+        layout: OutputLayout,
+    ) -> usize {
+        unsafe { super::row::<Sse2>(up, cur, down, tile_row, out, layout) }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn row_avx2(
+        up: &[u8],
+        cur: &[u8],
+        down: &[u8],
+        tile_row: [CfaColor; 2],
+        out: &mut [u8],
+        layout: OutputLayout,
+    ) -> usize {
+        unsafe { super::row::<Avx2>(up, cur, down, tile_row, out, layout) }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Sse2(__m128i);
+
+    impl Vector for Sse2 {
+        const LANES: usize = 16;
+
+        #[inline(always)]
+        unsafe fn load(src: *const u8) -> Self {
+            unsafe { Sse2(_mm_loadu_si128(src.cast())) }
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: u8) -> Self {
+            unsafe { Sse2(_mm_set1_epi8(value as i8)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg2(a: Self, b: Self) -> Self {
+            unsafe { Sse2(_mm_avg_epu8(a.0, b.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg4(a: Self, b: Self, c: Self, d: Self) -> Self {
+            unsafe { Sse2(avg4_128(a.0, b.0, c.0, d.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn blend(even: Self, odd: Self) -> Self {
+            unsafe { Sse2(blend_128(even.0, odd.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn store3(channels: [Self; 3], dst: *mut u8) {
+            // SSE2 has no byte shuffle, interleave through the stack.
+            let mut planes = [[0u8; 16]; 3];
+            for (plane, channel) in planes.iter_mut().zip(channels) {
+                unsafe { _mm_storeu_si128(plane.as_mut_ptr().cast(), channel.0) };
+            }
+            for i in 0..16 {
+                for (k, plane) in planes.iter().enumerate() {
+                    unsafe { *dst.add(3 * i + k) = plane[i] };
+                }
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn store4(channels: [Self; 4], dst: *mut u8) {
+            unsafe { store4_128(channels.map(|c| c.0), dst) }
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Avx2(__m256i);
+
+    impl Avx2 {
+        #[inline(always)]
+        unsafe fn halves(self) -> [__m128i; 2] {
+            unsafe {
+                [
+                    _mm256_castsi256_si128(self.0),
+                    _mm256_extracti128_si256::<1>(self.0),
+                ]
+            }
+        }
+    }
+
+    impl Vector for Avx2 {
+        const LANES: usize = 32;
+
+        #[inline(always)]
+        unsafe fn load(src: *const u8) -> Self {
+            unsafe { Avx2(_mm256_loadu_si256(src.cast())) }
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: u8) -> Self {
+            unsafe { Avx2(_mm256_set1_epi8(value as i8)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg2(a: Self, b: Self) -> Self {
+            unsafe { Avx2(_mm256_avg_epu8(a.0, b.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg4(a: Self, b: Self, c: Self, d: Self) -> Self {
+            // Unpacking and packing both work within 128-bit lanes, so the byte order
+            // survives the round trip through 16 bits.
+            unsafe {
+                let zero = _mm256_setzero_si256();
+                let two = _mm256_set1_epi16(2);
+
+                let lo = _mm256_add_epi16(
+                    _mm256_add_epi16(
+                        _mm256_unpacklo_epi8(a.0, zero),
+                        _mm256_unpacklo_epi8(b.0, zero),
+                    ),
+                    _mm256_add_epi16(
+                        _mm256_unpacklo_epi8(c.0, zero),
+                        _mm256_unpacklo_epi8(d.0, zero),
+                    ),
+                );
+                let hi = _mm256_add_epi16(
+                    _mm256_add_epi16(
+                        _mm256_unpackhi_epi8(a.0, zero),
+                        _mm256_unpackhi_epi8(b.0, zero),
+                    ),
+                    _mm256_add_epi16(
+                        _mm256_unpackhi_epi8(c.0, zero),
+                        _mm256_unpackhi_epi8(d.0, zero),
+                    ),
+                );
+
+                Avx2(_mm256_packus_epi16(
+                    _mm256_srli_epi16::<2>(_mm256_add_epi16(lo, two)),
+                    _mm256_srli_epi16::<2>(_mm256_add_epi16(hi, two)),
+                ))
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn blend(even: Self, odd: Self) -> Self {
+            unsafe {
+                let mask = _mm256_set1_epi16(0x00ff);
+                Avx2(_mm256_or_si256(
+                    _mm256_and_si256(mask, even.0),
+                    _mm256_andnot_si256(mask, odd.0),
+                ))
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn store3(channels: [Self; 3], dst: *mut u8) {
+            let [c0, c1, c2] = channels.map(|c| unsafe { c.halves() });
+            unsafe {
+                store3_ssse3([c0[0], c1[0], c2[0]], dst);
+                store3_ssse3([c0[1], c1[1], c2[1]], dst.add(48));
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn store4(channels: [Self; 4], dst: *mut u8) {
+            let [c0, c1, c2, c3] = channels.map(|c| unsafe { c.halves() });
+            unsafe {
+                store4_128([c0[0], c1[0], c2[0], c3[0]], dst);
+                store4_128([c0[1], c1[1], c2[1], c3[1]], dst.add(64));
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn avg4_128(a: __m128i, b: __m128i, c: __m128i, d: __m128i) -> __m128i {
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let two = _mm_set1_epi16(2);
+
+            let lo = _mm_add_epi16(
+                _mm_add_epi16(_mm_unpacklo_epi8(a, zero), _mm_unpacklo_epi8(b, zero)),
+                _mm_add_epi16(_mm_unpacklo_epi8(c, zero), _mm_unpacklo_epi8(d, zero)),
+            );
+            let hi = _mm_add_epi16(
+                _mm_add_epi16(_mm_unpackhi_epi8(a, zero), _mm_unpackhi_epi8(b, zero)),
+                _mm_add_epi16(_mm_unpackhi_epi8(c, zero), _mm_unpackhi_epi8(d, zero)),
+            );
+
+            _mm_packus_epi16(
+                _mm_srli_epi16::<2>(_mm_add_epi16(lo, two)),
+                _mm_srli_epi16::<2>(_mm_add_epi16(hi, two)),
+            )
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn blend_128(even: __m128i, odd: __m128i) -> __m128i {
+        unsafe {
+            let mask = _mm_set1_epi16(0x00ff);
+            _mm_or_si128(_mm_and_si128(mask, even), _mm_andnot_si128(mask, odd))
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn store4_128([c0, c1, c2, c3]: [__m128i; 4], dst: *mut u8) {
+        unsafe {
+            let c01_lo = _mm_unpacklo_epi8(c0, c1);
+            let c01_hi = _mm_unpackhi_epi8(c0, c1);
+            let c23_lo = _mm_unpacklo_epi8(c2, c3);
+            let c23_hi = _mm_unpackhi_epi8(c2, c3);
+
+            _mm_storeu_si128(dst.cast(), _mm_unpacklo_epi16(c01_lo, c23_lo));
+            _mm_storeu_si128(dst.add(16).cast(), _mm_unpackhi_epi16(c01_lo, c23_lo));
+            _mm_storeu_si128(dst.add(32).cast(), _mm_unpacklo_epi16(c01_hi, c23_hi));
+            _mm_storeu_si128(dst.add(48).cast(), _mm_unpackhi_epi16(c01_hi, c23_hi));
+        }
+    }
+
+    // Byte `j` of the 48 interleaved output bytes is channel `j % 3` of pixel `j / 3`.
+    // `STORE3_SHUFFLES[v][k]` gathers channel `k`'s bytes of output vector `v`, with
+    // 0x80 zeroing the bytes that belong to the other channels.
+    const STORE3_SHUFFLES: [[[u8; 16]; 3]; 3] = {
+        let mut masks = [[[0x80; 16]; 3]; 3];
+        let mut j = 0;
+        while j < 48 {
+            masks[j / 16][j % 3][j % 16] = (j / 3) as u8;
+            j += 1;
+        }
+        masks
+    };
+
+    #[inline(always)]
+    unsafe fn store3_ssse3(channels: [__m128i; 3], dst: *mut u8) {
+        unsafe {
+            for (v, masks) in STORE3_SHUFFLES.iter().enumerate() {
+                let mut bytes = _mm_setzero_si128();
+                for (channel, mask) in channels.iter().zip(masks) {
+                    let mask = _mm_loadu_si128(mask.as_ptr().cast());
+                    bytes = _mm_or_si128(bytes, _mm_shuffle_epi8(*channel, mask));
+                }
+                _mm_storeu_si128(dst.add(16 * v).cast(), bytes);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use super::{CfaColor, OutputLayout, Vector};
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn row_neon(
+        up: &[u8],
+        cur: &[u8],
+        down: &[u8],
+        tile_row: [CfaColor; 2],
+        out: &mut [u8],
+        layout: OutputLayout,
+    ) -> usize {
+        unsafe { super::row::<Neon>(up, cur, down, tile_row, out, layout) }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Neon(uint8x16_t);
+
+    impl Vector for Neon {
+        const LANES: usize = 16;
+
+        #[inline(always)]
+        unsafe fn load(src: *const u8) -> Self {
+            unsafe { Neon(vld1q_u8(src)) }
+        }
+
+        #[inline(always)]
+        unsafe fn splat(value: u8) -> Self {
+            unsafe { Neon(vdupq_n_u8(value)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg2(a: Self, b: Self) -> Self {
+            unsafe { Neon(vrhaddq_u8(a.0, b.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn avg4(a: Self, b: Self, c: Self, d: Self) -> Self {
+            unsafe {
+                let lo = vaddq_u16(
+                    vaddl_u8(vget_low_u8(a.0), vget_low_u8(b.0)),
+                    vaddl_u8(vget_low_u8(c.0), vget_low_u8(d.0)),
+                );
+                let hi = vaddq_u16(vaddl_high_u8(a.0, b.0), vaddl_high_u8(c.0, d.0));
+                // Rounding shift: (sum + 2) >> 2.
+                Neon(vcombine_u8(vrshrn_n_u16::<2>(lo), vrshrn_n_u16::<2>(hi)))
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn blend(even: Self, odd: Self) -> Self {
+            unsafe {
+                let mask = vreinterpretq_u8_u16(vdupq_n_u16(0x00ff));
+                Neon(vbslq_u8(mask, even.0, odd.0))
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn store3(channels: [Self; 3], dst: *mut u8) {
+            let [c0, c1, c2] = channels;
+            unsafe { vst3q_u8(dst, uint8x16x3_t(c0.0, c1.0, c2.0)) }
+        }
+
+        #[inline(always)]
+        unsafe fn store4(channels: [Self; 4], dst: *mut u8) {
+            let [c0, c1, c2, c3] = channels;
+            unsafe { vst4q_u8(dst, uint8x16x4_t(c0.0, c1.0, c2.0, c3.0)) }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Bayer pattern detection for pattern=auto-detect.
+//
+// Both greens of a 2x2 quad sample the same channel a pixel apart, so they differ far
+// less than red and blue do across the other diagonal, whatever the scene looks like
+// unless it is gray. That gives away which diagonal is green. Red and blue can't be
+// told apart that reliably: the statistics of a frame with the two swapped are just
+// as plausible. The warmer channel is taken for red, which holds for most scenes
+// under daylight and artificial light; scenes dominated by blue fool it.
+//
+// A handful of quads spread over the frame is enough, so only every `step`th quad in
+// both directions is looked at and the sums are accumulated over a few frames.
+
+use super::cfa::Pattern;
+
+// Frames measured before the result is locked in.
+pub const FRAMES: u32 = 3;
+// Quads sampled along each axis of a frame, at most.
+const SAMPLES: usize = 64;
+// Relative difference between the diagonals below which the green one is a guess.
+const MIN_CONFIDENCE: f64 = 0.1;
+
+#[derive(Debug, Default)]
+pub struct Detector {
+    frames: u32,
+    // Absolute differences between the samples on the main (top left to bottom right)
+    // and the anti diagonal of the sampled quads.
+    main: u64,
+    anti: u64,
+    // Sums of the samples at each position of the quads, row by row.
+    sums: [u64; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub pattern: Pattern,
+    /// How much closer the greens are than red and blue, 0 if not at all.
+    pub confidence: f64,
+}
+
+impl Detection {
+    pub fn is_ambiguous(&self) -> bool {
+        self.confidence < MIN_CONFIDENCE
+    }
+}
+
+impl Detector {
+    /// Measures one frame of `height` rows of `width` samples `stride` bytes apart.
+    pub fn add(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), String> {
+        if width < 2 || height < 2 {
+            return Err(format!("{width}x{height} frame has no 2x2 quad"));
+        }
+        if data.len() < (height - 1) * stride + width {
+            return Err(format!(
+                "{} bytes are too few for a {}x{} frame with stride {}",
+                data.len(),
+                width,
+                height,
+                stride
+            ));
+        }
+
+        let (quads_x, quads_y) = (width / 2, height / 2);
+        let step_x = quads_x.div_ceil(SAMPLES);
+        let step_y = quads_y.div_ceil(SAMPLES);
+        for qy in (0..quads_y).step_by(step_y) {
+            let top = &data[2 * qy * stride..];
+            let bottom = &data[(2 * qy + 1) * stride..];
+            for qx in (0..quads_x).step_by(step_x) {
+                let x = 2 * qx;
+                let quad = [top[x], top[x + 1], bottom[x], bottom[x + 1]];
+                self.main += quad[0].abs_diff(quad[3]) as u64;
+                self.anti += quad[1].abs_diff(quad[2]) as u64;
+                for (sum, sample) in self.sums.iter_mut().zip(quad) {
+                    *sum += sample as u64;
+                }
+            }
+        }
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Frames measured so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The most likely pattern from the frames measured so far.
+    pub fn detect(&self) -> Detection {
+        let [top_left, top_right, bottom_left, bottom_right] = self.sums;
+        let pattern = if self.anti <= self.main {
+            // Greens on the anti diagonal, red and blue on the main one.
+            match top_left >= bottom_right {
+                true => Pattern::Rggb,
+                false => Pattern::Bggr,
+            }
+        } else {
+            match top_right >= bottom_left {
+                true => Pattern::Grbg,
+                false => Pattern::Gbrg,
+            }
+        };
+
+        let (greens, others) = (self.main.min(self.anti), self.main.max(self.anti));
+        let confidence = match others {
+            0 => 0.0,
+            _ => 1.0 - greens as f64 / others as f64,
+        };
+
+        Detection {
+            pattern,
+            confidence,
+        }
+    }
+}
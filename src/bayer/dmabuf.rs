@@ -0,0 +1,151 @@
+// DMABuf output, `memory:DMABuf`, for zero-copy encoding.
+//
+// Output buffers are allocated from a Linux DMA heap and wrapped by the dmabuf
+// allocator of libgstallocators, so they are written through a CPU mapping like
+// system memory and handed to a hardware encoder without a copy. The dmabuf allocator
+// brackets every mapping with DMA_BUF_IOCTL_SYNC, which makes the writes visible to
+// devices once transform() drops its mapping of the output, before the buffer is
+// pushed.
+//
+// Contiguous heaps are tried first, as encoders without an IOMMU, like the Hantro VPU
+// of the i.MX8, can only import physically contiguous buffers. Without any heap the
+// feature isn't offered in the caps and negotiation falls back to system memory.
+//
+// Whether a pipeline gets DMABuf output shows in the negotiated caps and in the log,
+// which names the heap, for example with an encoder importing RGBA dmabufs:
+//
+//     GST_DEBUG=rsbayer2rgb:4 gst-launch-1.0 -v rsbayertestsrc ! rsbayer2rgb \
+//         ! 'video/x-raw(memory:DMABuf),format=RGBA' \
+//         ! v4l2h264enc output-io-mode=dmabuf-import ! h264parse \
+//         ! matroskamux ! filesink location=out.mkv
+
+use gst::glib;
+use gst::subclass::prelude::*;
+use gst_allocators::prelude::*;
+
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::LazyLock;
+
+use super::imp::CAT;
+
+pub const CAPS_FEATURE: &str = "memory:DMABuf";
+
+const HEAPS: [&str; 3] = ["linux,cma", "reserved", "system"];
+
+// _IOWR('H', 0x0, struct dma_heap_allocation_data) of linux/dma-heap.h.
+const DMA_HEAP_IOCTL_ALLOC: libc::Ioctl = 0xc018_4800_u32 as libc::Ioctl;
+
+#[repr(C)]
+struct AllocationData {
+    len: u64,
+    fd: u32,
+    fd_flags: u32,
+    heap_flags: u64,
+}
+
+static HEAP: LazyLock<Option<File>> = LazyLock::new(|| {
+    HEAPS.iter().find_map(|name| {
+        let path = format!("/dev/dma_heap/{name}");
+        match File::open(&path) {
+            Ok(heap) => {
+                gst::info!(CAT, "Allocating DMABuf output from {}", path);
+                Some(heap)
+            }
+            Err(err) => {
+                gst::debug!(CAT, "No DMA heap {}: {}", path, err);
+                None
+            }
+        }
+    })
+});
+
+/// Whether the platform has a DMA heap to allocate DMABuf output from.
+pub fn available() -> bool {
+    HEAP.is_some()
+}
+
+pub fn is_dmabuf_caps(caps: &gst::CapsRef) -> bool {
+    caps.features(0)
+        .is_some_and(|features| features.contains(CAPS_FEATURE))
+}
+
+// A new buffer of `len` bytes from `heap`.
+fn allocate(heap: &File, len: usize) -> std::io::Result<OwnedFd> {
+    let mut data = AllocationData {
+        len: len as u64,
+        fd: 0,
+        fd_flags: (libc::O_RDWR | libc::O_CLOEXEC) as u32,
+        heap_flags: 0,
+    };
+    // SAFETY: the request and the struct are the ones of linux/dma-heap.h, the kernel
+    // only writes the fd field.
+    let res = unsafe { libc::ioctl(heap.as_raw_fd(), DMA_HEAP_IOCTL_ALLOC, &mut data) };
+    if res < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: the fd was just created for us and nothing else owns it.
+    Ok(unsafe { OwnedFd::from_raw_fd(data.fd as i32) })
+}
+
+glib::wrapper! {
+    /// Allocator of dmabuf memory from the DMA heap, for the output buffer pool.
+    pub struct HeapAllocator(ObjectSubclass<imp::HeapAllocator>)
+        @extends gst::Allocator, gst::Object;
+}
+
+impl Default for HeapAllocator {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+mod imp {
+    use super::*;
+
+    pub struct HeapAllocator {
+        dmabuf: gst_allocators::DmaBufAllocator,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for HeapAllocator {
+        const NAME: &'static str = "GstRsBayer2RgbHeapAllocator";
+        type Type = super::HeapAllocator;
+        type ParentType = gst::Allocator;
+
+        fn new() -> Self {
+            Self {
+                dmabuf: gst_allocators::DmaBufAllocator::new(),
+            }
+        }
+    }
+
+    impl ObjectImpl for HeapAllocator {}
+
+    impl GstObjectImpl for HeapAllocator {}
+
+    impl AllocatorImpl for HeapAllocator {
+        // The memory belongs to the dmabuf allocator, which maps it and closes the fd
+        // when it is freed.
+        fn alloc(
+            &self,
+            size: usize,
+            params: Option<&gst::AllocationParams>,
+        ) -> Result<gst::Memory, glib::BoolError> {
+            let heap = HEAP
+                .as_ref()
+                .ok_or_else(|| glib::bool_error!("No DMA heap"))?;
+            // Heap buffers are page aligned, which covers any alignment asked for.
+            let (prefix, padding) =
+                params.map_or((0, 0), |params| (params.prefix(), params.padding()));
+            let fd = allocate(heap, prefix + size + padding)
+                .map_err(|err| glib::bool_error!("DMA heap allocation failed: {}", err))?;
+
+            // SAFETY: the fd is a dmabuf of the full size.
+            let mut memory = unsafe { self.dmabuf.alloc_dmabuf(fd, prefix + size + padding)? };
+            memory.get_mut().unwrap().resize(prefix..prefix + size);
+            Ok(memory)
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Debug frame dumps for dump-location and dump-interval.
+//
+// The streaming thread only copies the rows of the frames to dump into tightly packed
+// vectors and hands them to a writer thread over a short bounded channel. Encoding and
+// writing happen on that thread, so a slow disk never stalls the pipeline: if the
+// writer falls behind, further frames are skipped until there is room again. Failing
+// to write a file only logs a warning.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use opencv::core::Vector;
+use opencv::imgcodecs;
+
+use super::frame::OutputLayout;
+use super::imp::CAT;
+use super::mat;
+
+// Frames waiting to be written before new ones are skipped.
+const QUEUE_SIZE: usize = 4;
+
+/// Pixels of one dumped image, rows tightly packed.
+pub enum Image {
+    /// Converted output pixels in the byte order of `layout`.
+    Output {
+        data: Vec<u8>,
+        width: usize,
+        height: usize,
+        layout: OutputLayout,
+    },
+    /// Raw 8-bit CFA samples, written as 16-bit gray.
+    Raw {
+        data: Vec<u8>,
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Image {
+    /// Copies `width` x `height` output pixels out of `data` with rows `stride` bytes
+    /// apart.
+    pub fn output(
+        data: &[u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        layout: OutputLayout,
+    ) -> Option<Self> {
+        Some(Image::Output {
+            data: pack(data, stride, width * layout.pixel_stride, height)?,
+            width,
+            height,
+            layout,
+        })
+    }
+
+    /// Copies `width` x `height` raw samples out of `data` with rows `stride` bytes
+    /// apart.
+    pub fn raw(data: &[u8], stride: usize, width: usize, height: usize) -> Option<Self> {
+        Some(Image::Raw {
+            data: pack(data, stride, width, height)?,
+            width,
+            height,
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Image::Output { .. } => "png",
+            Image::Raw { .. } => "pgm",
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<(), String> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| format!("{} is not valid UTF-8", path.display()))?;
+
+        let (data, width, height, typ) = match self {
+            Image::Output {
+                data,
+                width,
+                height,
+                layout,
+            } => {
+                // imwrite() expects BGR or BGRA.
+                let (bgr, typ) = to_bgr(data, *layout);
+                (bgr, *width, *height, typ)
+            }
+            Image::Raw {
+                data,
+                width,
+                height,
+            } => {
+                let samples = data
+                    .iter()
+                    .flat_map(|&sample| (sample as u16 * 257).to_ne_bytes())
+                    .collect();
+                (samples, *width, *height, opencv::core::CV_16UC1)
+            }
+        };
+
+        let stride = width * mat::elem_size(typ);
+        let image = mat::wrap(&data, height, width, typ, stride).map_err(|err| err.to_string())?;
+        match imgcodecs::imwrite(path, &*image, &Vector::new()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("no encoder wrote the file".to_string()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+// Rows of `row_bytes` bytes out of `data`, without the padding between them.
+fn pack(data: &[u8], stride: usize, row_bytes: usize, height: usize) -> Option<Vec<u8>> {
+    let mut packed = Vec::with_capacity(row_bytes * height);
+    for y in 0..height {
+        let start = y * stride;
+        packed.extend_from_slice(data.get(start..start + row_bytes)?);
+    }
+    Some(packed)
+}
+
+fn to_bgr(data: &[u8], layout: OutputLayout) -> (Vec<u8>, i32) {
+    let channels = match layout.alpha {
+        Some(_) => 4,
+        None => 3,
+    };
+    let mut bgr = Vec::with_capacity(data.len() / layout.pixel_stride * channels);
+    for pixel in data.chunks_exact(layout.pixel_stride) {
+        bgr.extend_from_slice(&[pixel[layout.blue], pixel[layout.green], pixel[layout.red]]);
+        if let Some(alpha) = layout.alpha {
+            bgr.push(pixel[alpha]);
+        }
+    }
+
+    let typ = match channels {
+        4 => opencv::core::CV_8UC4,
+        _ => opencv::core::CV_8UC3,
+    };
+    (bgr, typ)
+}
+
+/// File name of a dumped image: frame index and PTS in nanoseconds, so files sort by
+/// frame and can be matched against buffer timestamps in a log.
+pub fn file_name(index: u64, pts: Option<gst::ClockTime>, image: &Image) -> String {
+    let pts = match pts {
+        Some(pts) => pts.nseconds().to_string(),
+        None => "none".to_string(),
+    };
+    let kind = match image {
+        Image::Output { .. } => "",
+        Image::Raw { .. } => "-raw",
+    };
+    format!("frame-{index:06}-{pts}{kind}.{}", image.extension())
+}
+
+/// The directory of dump-location, with `{name}` replaced by the element name.
+pub fn directory(location: &str, element_name: &str) -> PathBuf {
+    PathBuf::from(location.replace("{name}", element_name))
+}
+
+pub struct Writer {
+    sender: Option<mpsc::SyncSender<(PathBuf, Image)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn new(name: String) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<(PathBuf, Image)>(QUEUE_SIZE);
+
+        let thread = std::thread::Builder::new().name(name).spawn(move || {
+            for (path, image) in receiver {
+                if let Some(dir) = path.parent() {
+                    if let Err(err) = std::fs::create_dir_all(dir) {
+                        gst::warning!(CAT, "Failed to create {}: {}", dir.display(), err);
+                        continue;
+                    }
+                }
+                match image.write(&path) {
+                    Ok(()) => gst::debug!(CAT, "Wrote {}", path.display()),
+                    Err(err) => {
+                        gst::warning!(CAT, "Failed to write {}: {}", path.display(), err)
+                    }
+                }
+            }
+        })?;
+
+        Ok(Writer {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Hands `image` to the writer thread, false if it was skipped because the
+    /// writer is behind.
+    pub fn write(&self, path: PathBuf, image: Image) -> bool {
+        let Some(sender) = &self.sender else {
+            return false;
+        };
+        sender.try_send((path, image)).is_ok()
+    }
+}
+
+impl Drop for Writer {
+    // Writes out what is queued and joins the thread.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
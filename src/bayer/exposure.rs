@@ -0,0 +1,186 @@
+// RsBayerExposureMeta, attached to bayer buffers by sources that know the exposure
+// every frame was captured with, such as a libcamera or v4l2 source reading the sensor
+// controls. With exposure-compensation, rsbayer2rgb scales every frame to the
+// brightness of reference-exposure, so output doesn't flicker while an exposure loop
+// changes the exposure time or the analog gain from frame to frame.
+//
+// Like RsBayerTimingMeta, the meta holds plain values only, and it describes the
+// capture rather than the layout of the buffer, so every transform copies it.
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+use gst::glib;
+use gst::glib::translate::*;
+use gst::prelude::*;
+
+/// Exposure a bayer frame was captured with.
+///
+/// The meta API is registered as `RsBayerExposureMetaAPI`, without tags. Sources
+/// written in C attach it by looking up the `RsBayerExposureMeta` info with
+/// `gst_meta_get_info()`, passing a pointer to a struct of the exposure time in
+/// nanoseconds, a `guint64`, and the analog gain, a `gdouble`, as the init params.
+#[repr(transparent)]
+pub struct RsBayerExposureMeta(imp::RsBayerExposureMeta);
+
+unsafe impl Send for RsBayerExposureMeta {}
+unsafe impl Sync for RsBayerExposureMeta {}
+
+impl RsBayerExposureMeta {
+    /// Adds an exposure meta to `buffer`, `analog_gain` being linear, 1.0 for none.
+    pub fn add(
+        buffer: &mut gst::BufferRef,
+        exposure_time: gst::ClockTime,
+        analog_gain: f64,
+    ) -> gst::MetaRefMut<'_, Self, gst::meta::Standalone> {
+        unsafe {
+            let mut params = imp::Params {
+                exposure_time: exposure_time.nseconds(),
+                analog_gain,
+            };
+            let meta = gst_sys::gst_buffer_add_meta(
+                buffer.as_mut_ptr(),
+                imp::meta_get_info(),
+                &mut params as *mut imp::Params as glib::ffi::gpointer,
+            ) as *mut imp::RsBayerExposureMeta;
+
+            Self::from_mut_ptr(buffer, meta)
+        }
+    }
+
+    pub fn exposure_time(&self) -> gst::ClockTime {
+        gst::ClockTime::from_nseconds(self.0.exposure_time)
+    }
+
+    pub fn analog_gain(&self) -> f64 {
+        self.0.analog_gain
+    }
+
+    /// Exposure time times analog gain, in nanoseconds at unity gain: how much light
+    /// the samples were scaled by, up to the constant of the sensor.
+    pub fn total_exposure(&self) -> f64 {
+        self.0.exposure_time as f64 * self.0.analog_gain
+    }
+}
+
+unsafe impl MetaAPI for RsBayerExposureMeta {
+    type GstType = imp::RsBayerExposureMeta;
+
+    fn meta_api() -> glib::Type {
+        imp::meta_api_get_type()
+    }
+}
+
+impl fmt::Debug for RsBayerExposureMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RsBayerExposureMeta")
+            .field("exposure_time", &self.exposure_time())
+            .field("analog_gain", &self.analog_gain())
+            .finish()
+    }
+}
+
+/// Registers `RsBayerExposureMetaAPI` and the `RsBayerExposureMeta` info at plugin
+/// load. C sources attach the meta through `gst_meta_get_info()`, which would find
+/// nothing until the Rust side had added one itself.
+pub fn register() {
+    imp::meta_api_get_type();
+    imp::meta_get_info();
+}
+
+mod imp {
+    use super::*;
+
+    // The init params, the struct C sources pass too.
+    #[repr(C)]
+    pub(super) struct Params {
+        pub exposure_time: u64,
+        pub analog_gain: f64,
+    }
+
+    #[repr(C)]
+    pub struct RsBayerExposureMeta {
+        parent: gst_sys::GstMeta,
+        pub(super) exposure_time: u64,
+        pub(super) analog_gain: f64,
+    }
+
+    pub(super) fn meta_api_get_type() -> glib::Type {
+        static TYPE: std::sync::OnceLock<glib::Type> = std::sync::OnceLock::new();
+
+        *TYPE.get_or_init(|| unsafe {
+            let t = from_glib(gst_sys::gst_meta_api_type_register(
+                c"RsBayerExposureMetaAPI".as_ptr(),
+                [ptr::null::<std::os::raw::c_char>()].as_ptr() as *mut *const _,
+            ));
+            assert_ne!(t, glib::Type::INVALID);
+            t
+        })
+    }
+
+    unsafe extern "C" fn meta_init(
+        meta: *mut gst_sys::GstMeta,
+        params: glib::ffi::gpointer,
+        _buffer: *mut gst_sys::GstBuffer,
+    ) -> glib::ffi::gboolean {
+        unsafe {
+            assert!(!params.is_null());
+            let meta = &mut *(meta as *mut RsBayerExposureMeta);
+            let params = &*(params as *const Params);
+
+            ptr::write(&mut meta.exposure_time, params.exposure_time);
+            ptr::write(&mut meta.analog_gain, params.analog_gain);
+        }
+
+        true.into_glib()
+    }
+
+    unsafe extern "C" fn meta_free(_meta: *mut gst_sys::GstMeta, _buffer: *mut gst_sys::GstBuffer) {
+        // Nothing but plain values in the meta.
+    }
+
+    unsafe extern "C" fn meta_transform(
+        dest: *mut gst_sys::GstBuffer,
+        meta: *mut gst_sys::GstMeta,
+        _buffer: *mut gst_sys::GstBuffer,
+        _type_: glib::ffi::GQuark,
+        _data: glib::ffi::gpointer,
+    ) -> glib::ffi::gboolean {
+        unsafe {
+            let meta = &*(meta as *const RsBayerExposureMeta);
+            super::RsBayerExposureMeta::add(
+                gst::BufferRef::from_mut_ptr(dest),
+                gst::ClockTime::from_nseconds(meta.exposure_time),
+                meta.analog_gain,
+            );
+        }
+
+        true.into_glib()
+    }
+
+    pub(super) fn meta_get_info() -> *const gst_sys::GstMetaInfo {
+        struct MetaInfo(ptr::NonNull<gst_sys::GstMetaInfo>);
+        unsafe impl Send for MetaInfo {}
+        unsafe impl Sync for MetaInfo {}
+
+        static META_INFO: std::sync::OnceLock<MetaInfo> = std::sync::OnceLock::new();
+
+        META_INFO
+            .get_or_init(|| unsafe {
+                MetaInfo(
+                    ptr::NonNull::new(gst_sys::gst_meta_register(
+                        meta_api_get_type().into_glib(),
+                        c"RsBayerExposureMeta".as_ptr(),
+                        mem::size_of::<RsBayerExposureMeta>(),
+                        Some(meta_init),
+                        Some(meta_free),
+                        Some(meta_transform),
+                    ) as *mut gst_sys::GstMetaInfo)
+                    .expect("Failed to register RsBayerExposureMeta"),
+                )
+            })
+            .0
+            .as_ptr()
+    }
+}
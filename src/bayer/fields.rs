@@ -0,0 +1,51 @@
+// Interlaced bayer input with interlace-mode=interleaved.
+//
+// Every buffer holds two fields, line by line: the top field in the even rows and the
+// bottom field in the odd rows. Each field on its own is a bayer mosaic. The top one
+// has the pattern of the caps, the bottom one starts a row lower and so has the
+// pattern shifted by one row. Converted as they are, neighboring rows of the frame
+// alternate between the two phases and demosaic pairs samples of the wrong colors.
+//
+// `weave()` puts the fields together into a progressive mosaic with the pattern of the
+// caps: in every other pair of rows the two fields swap places, which restores the row
+// phase at the cost of moving those rows by one. Demosaicing the fields separately
+// instead is left to two converters that see every other row of the frame.
+
+use super::raw::Sample;
+
+/// Weaves the `height` rows of `width` samples in `input`, `stride` samples apart,
+/// into `output` as a progressive mosaic of tightly packed rows.
+pub fn weave<S: Sample>(
+    input: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    output: &mut Vec<S>,
+) -> Result<(), String> {
+    if stride < width {
+        return Err(format!("stride {stride} is smaller than the width {width}"));
+    }
+    let required = match height {
+        0 => 0,
+        _ => (height - 1) * stride + width,
+    };
+    if input.len() < required {
+        return Err(format!(
+            "{} samples are too few for {}x{} samples with stride {}",
+            input.len(),
+            width,
+            height,
+            stride
+        ));
+    }
+
+    output.resize(width * height, S::from_u64(0));
+    for (y, row) in output.chunks_exact_mut(width).enumerate() {
+        // Rows 2i and 2i + 1 swap for odd i, the last row of an odd height stays.
+        let swap = (y / 2) % 2 == 1 && (y ^ 1) < height;
+        let src = if swap { y ^ 1 } else { y };
+        row.copy_from_slice(&input[src * stride..src * stride + width]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+// Spatial filters on the demosaiced frame, run with OpenCV whatever the backend.
+//
+// They work in place on the output plane, with scratch frames kept across buffers.
+// OpenCV's bilateral filter only takes one or three channels, so four channel output
+// takes a detour through an RGB copy. The temporal filter runs after the spatial one
+// and blends every frame with the previous result, sharpening comes last so it
+// doesn't bring back the noise just removed.
+
+use opencv::boxed_ref::BoxedRefMut;
+use opencv::core::Mat;
+use opencv::prelude::*;
+
+use super::Denoise;
+use super::cv::Options;
+use super::mat;
+
+#[derive(Default)]
+pub struct Filters {
+    rgb: Mat,
+    filtered: Mat,
+    // Previous filtered frame while temporal denoise is on.
+    history: Option<Mat>,
+}
+
+impl Filters {
+    /// Filters a `width` x `height` frame of 3 or 4 channel pixels, rows `stride` bytes
+    /// apart, according to `options`.
+    pub fn apply(
+        &mut self,
+        frame: &mut [u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        options: &Options,
+    ) -> Result<(), String> {
+        if options.temporal_denoise == 0.0 {
+            self.history = None;
+        }
+        if options.denoise == Denoise::None
+            && options.temporal_denoise == 0.0
+            && options.sharpen_amount == 0.0
+        {
+            return Ok(());
+        }
+
+        let typ = match channels {
+            3 => opencv::core::CV_8UC3,
+            4 => opencv::core::CV_8UC4,
+            _ => return Err(format!("Unsupported number of channels {channels}")),
+        };
+        let mut frame =
+            mat::wrap_mut(frame, height, width, typ, stride).map_err(|err| err.to_string())?;
+
+        self.denoise(&mut frame, channels, options)
+            .map_err(|err| format!("Denoise failed: {err}"))?;
+        if options.temporal_denoise > 0.0 {
+            self.blend_history(&mut frame, options.temporal_denoise)
+                .map_err(|err| format!("Temporal denoise failed: {err}"))?;
+        }
+        if options.sharpen_amount > 0.0 {
+            self.sharpen(&mut frame, options.sharpen_amount, options.sharpen_radius)
+                .map_err(|err| format!("Sharpening failed: {err}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the previous frame, so the next one isn't blended with it.
+    pub fn reset_history(&mut self) {
+        self.history = None;
+    }
+
+    // `out = (1 - weight) * frame + weight * previous`, and remembers `out`.
+    fn blend_history(
+        &mut self,
+        frame: &mut BoxedRefMut<'_, Mat>,
+        weight: f64,
+    ) -> opencv::Result<()> {
+        match self.history {
+            Some(ref mut history)
+                if history.size()? == frame.size()? && history.typ() == frame.typ() =>
+            {
+                opencv::core::add_weighted_def(
+                    &*frame,
+                    1.0 - weight,
+                    &*history,
+                    weight,
+                    0.0,
+                    &mut self.filtered,
+                )?;
+                self.filtered.copy_to(frame)?;
+                frame.copy_to(history)
+            }
+            _ => {
+                self.history = Some(frame.try_clone()?);
+                Ok(())
+            }
+        }
+    }
+
+    fn denoise(
+        &mut self,
+        frame: &mut BoxedRefMut<'_, Mat>,
+        channels: usize,
+        options: &Options,
+    ) -> opencv::Result<()> {
+        let sigma = options.denoise_strength;
+
+        match options.denoise {
+            Denoise::None => return Ok(()),
+            Denoise::Gaussian => opencv::imgproc::gaussian_blur_def(
+                &*frame,
+                &mut self.filtered,
+                opencv::core::Size::new(0, 0),
+                sigma,
+            )?,
+            // Colors within ten times the spatial sigma of each other are smoothed.
+            Denoise::Bilateral if channels == 4 => {
+                opencv::imgproc::cvt_color_def(
+                    &*frame,
+                    &mut self.rgb,
+                    opencv::imgproc::COLOR_RGBA2RGB,
+                )?;
+                opencv::imgproc::bilateral_filter_def(
+                    &self.rgb,
+                    &mut self.filtered,
+                    -1,
+                    sigma * 10.0,
+                    sigma,
+                )?;
+                return opencv::imgproc::cvt_color_def(
+                    &self.filtered,
+                    frame,
+                    opencv::imgproc::COLOR_RGB2RGBA,
+                );
+            }
+            Denoise::Bilateral => opencv::imgproc::bilateral_filter_def(
+                &*frame,
+                &mut self.filtered,
+                -1,
+                sigma * 10.0,
+                sigma,
+            )?,
+        }
+
+        self.filtered.copy_to(frame)
+    }
+
+    // Unsharp mask, `out = frame + amount * (frame - blurred)`. OpenCV saturates the
+    // result to the 8-bit range, and the constant alpha channel of four channel frames
+    // comes out unchanged.
+    fn sharpen(
+        &mut self,
+        frame: &mut BoxedRefMut<'_, Mat>,
+        amount: f64,
+        radius: f64,
+    ) -> opencv::Result<()> {
+        opencv::imgproc::gaussian_blur_def(
+            &*frame,
+            &mut self.rgb,
+            opencv::core::Size::new(0, 0),
+            radius,
+        )?;
+        opencv::core::add_weighted_def(
+            &*frame,
+            1.0 + amount,
+            &self.rgb,
+            -amount,
+            0.0,
+            &mut self.filtered,
+        )?;
+        self.filtered.copy_to(frame)
+    }
+}
@@ -0,0 +1,64 @@
+// Sharpness of the output for autofocus loops: the variance of the Laplacian of its
+// luma. In-focus frames have strong local contrast, so the Laplacian swings widely
+// and its variance is high. Luma is normalized to the sample range, which makes the
+// figure independent of output format and depth.
+
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+use super::tone::LUMA_WEIGHTS;
+
+/// Variance of the Laplacian of the luma of a `width` x `height` frame of `layout`
+/// pixels, taking only every `step`th pixel in each direction. 0.0 for frames with
+/// fewer than 3x3 pixels taken. The stride is in samples.
+pub fn variance_of_laplacian<S: Sample>(
+    frame: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    step: usize,
+) -> Result<f64, Error> {
+    if !fits(frame.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let step = step.max(1);
+    let scale = 1.0 / (S::MAX as f64 * 256.0);
+    let luma: Vec<Vec<f64>> = (0..height)
+        .step_by(step)
+        .map(|y| {
+            let row = &frame[y * stride..][..width * layout.pixel_stride];
+            row.chunks_exact(layout.pixel_stride)
+                .step_by(step)
+                .map(|pixel| {
+                    let weighted: i64 = [layout.red, layout.green, layout.blue]
+                        .into_iter()
+                        .zip(LUMA_WEIGHTS)
+                        .map(|(channel, weight)| pixel[channel].to_u64() as i64 * weight)
+                        .sum();
+                    weighted as f64 * scale
+                })
+                .collect()
+        })
+        .collect();
+
+    let (rows, cols) = (luma.len(), luma.first().map_or(0, Vec::len));
+    if rows < 3 || cols < 3 {
+        return Ok(0.0);
+    }
+
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..rows - 1 {
+        for x in 1..cols - 1 {
+            let laplacian = luma[y - 1][x] + luma[y + 1][x] + luma[y][x - 1] + luma[y][x + 1]
+                - 4.0 * luma[y][x];
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
+
+    let n = ((rows - 2) * (cols - 2)) as f64;
+    let mean = sum / n;
+    Ok((sum_sq / n - mean * mean).max(0.0))
+}
@@ -0,0 +1,234 @@
+// Fixed pattern noise correction for fpn-correction.
+//
+// Sensors with a column parallel readout add an offset of its own to every column,
+// which shows as vertical stripes in the shadows, and sometimes one of their own to
+// every row. The offsets are added to the black level the raw corrections subtract,
+// so they cost nothing beyond the black level pass itself. Both CFA colors of a
+// column or row get their own offset, as the two colors of a column go through
+// different amplifiers on many sensors.
+//
+// The offsets come from a calibration file, or are estimated on the optical black
+// margins of every frame: the optical black rows at the top cover every column, so
+// they give the column offsets, and the optical black columns on the left cover
+// every row, so they give the row offsets. Estimates are damped over the frames, so
+// the few optical black samples of a column don't turn their noise into flicker.
+
+use std::path::Path;
+
+use super::cfa::Pattern;
+use super::frame::{Error, Rect, fits};
+use super::npy::{Array, Data};
+use super::raw::{self, Sample};
+
+// How far the offsets move towards those of every new frame.
+const DAMPING: f32 = 0.1;
+
+/// Offset of every column and row of a frame in input sample levels, positive when
+/// the column or row reads above the black level. `columns[y % 2][x]` is the offset of
+/// column `x` in the rows of `y`'s parity, `rows[x % 2][y]` that of row `y` in the
+/// columns of `x`'s parity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fpn {
+    pub columns: [Vec<f32>; 2],
+    pub rows: [Vec<f32>; 2],
+}
+
+impl Fpn {
+    fn zero(width: usize, height: usize) -> Self {
+        Fpn {
+            columns: [vec![0.0; width], vec![0.0; width]],
+            rows: [vec![0.0; height], vec![0.0; height]],
+        }
+    }
+
+    /// The offsets of `window` of the frame, which must lie within it.
+    pub fn window(&self, window: Rect) -> Fpn {
+        Fpn {
+            columns: std::array::from_fn(|parity| {
+                self.columns[(parity + window.y) % 2][window.x..][..window.width].to_vec()
+            }),
+            rows: std::array::from_fn(|parity| {
+                self.rows[(parity + window.x) % 2][window.y..][..window.height].to_vec()
+            }),
+        }
+    }
+
+    /// The offsets scaled by `scale`, for samples of another depth than the input's,
+    /// and rounded into `offsets`.
+    pub fn levels(&self, scale: f32, offsets: &mut raw::Offsets) {
+        let round = |from: &[f32], to: &mut Vec<i32>| {
+            to.clear();
+            to.extend(from.iter().map(|offset| (offset * scale).round() as i32));
+        };
+        for (from, to) in self.columns.iter().zip(&mut offsets.columns) {
+            round(from, to);
+        }
+        for (from, to) in self.rows.iter().zip(&mut offsets.rows) {
+            round(from, to);
+        }
+    }
+}
+
+/// Offsets loaded from a calibration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FpnFile {
+    // Two rows of offsets, one for each parity, of the columns and then the rows.
+    offsets: [Vec<f32>; 2],
+}
+
+impl FpnFile {
+    /// Loads offsets from a `.npy` file holding a `(2, width)` or `(2, width + height)`
+    /// array of `f4` or `f8` offsets in input sample levels: the column offsets of the
+    /// even and of the odd rows, followed by the row offsets of the even and of the odd
+    /// columns if there are any.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let array = Array::load(path)?;
+        let [2, len] = array.shape[..] else {
+            return Err(format!(
+                "{}: fixed pattern noise offsets need a (2, n) shape, not {:?}",
+                path.display(),
+                array.shape
+            ));
+        };
+        let offsets: Vec<f32> = match array.data {
+            Data::F32(offsets) => offsets,
+            Data::F64(offsets) => offsets.into_iter().map(|offset| offset as f32).collect(),
+            _ => {
+                return Err(format!(
+                    "{}: fixed pattern noise offsets need float32 or float64 elements",
+                    path.display()
+                ));
+            }
+        };
+        let (even, odd) = offsets.split_at(len);
+
+        Ok(FpnFile {
+            offsets: [even.to_vec(), odd.to_vec()],
+        })
+    }
+
+    /// The offsets of the `window` of `width` x `height` input frames. Fails unless
+    /// the file has offsets for every column, and for every row if it has any.
+    pub fn for_window(&self, width: usize, height: usize, window: Rect) -> Result<Fpn, String> {
+        let len = self.offsets[0].len();
+        let mut fpn = Fpn::zero(width, height);
+        if len == width || len == width + height {
+            for (parity, offsets) in self.offsets.iter().enumerate() {
+                let (columns, rows) = offsets.split_at(width);
+                fpn.columns[parity].copy_from_slice(columns);
+                if !rows.is_empty() {
+                    fpn.rows[parity].copy_from_slice(rows);
+                }
+            }
+        } else {
+            return Err(format!(
+                "{len} offsets don't match the {width}x{height} input, needs {width} or {}",
+                width + height
+            ));
+        }
+
+        Ok(fpn.window(window))
+    }
+}
+
+/// Estimates the offsets on the optical black margins of every frame.
+#[derive(Debug, Default)]
+pub struct Estimator {
+    fpn: Option<Fpn>,
+}
+
+impl Estimator {
+    /// Measures the offsets of a `width` x `height` frame with `rows` optical black
+    /// rows at the top and `cols` optical black columns on the left, relative to the
+    /// `black_level` of every color, and damps them with those of the previous frames.
+    /// Columns are left without offsets when there are no optical black rows and rows
+    /// when there are no optical black columns.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update<S: Sample>(
+        &mut self,
+        input: &[S],
+        stride: usize,
+        width: usize,
+        height: usize,
+        pattern: Pattern,
+        rows: usize,
+        cols: usize,
+        black_level: [u16; 4],
+    ) -> Result<&Fpn, Error> {
+        if rows >= height || cols >= width {
+            return Err(Error::FrameTooSmall);
+        }
+        if !fits(input.len(), height, width, stride) {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let difference = |x: usize, y: usize| {
+            let black = black_level[pattern.color_at(x, y) as usize];
+            input[y * stride + x].to_u64() as f32 - black as f32
+        };
+        let mut fpn = Fpn::zero(width, height);
+        if rows > 0 {
+            let mut sums = [vec![0.0; width], vec![0.0; width]];
+            let mut counts = [0; 2];
+            for y in 0..rows {
+                for (x, sum) in sums[y % 2].iter_mut().enumerate() {
+                    *sum += difference(x, y);
+                }
+                counts[y % 2] += 1;
+            }
+            fpn.columns = means(sums, counts);
+        }
+        // Less the offsets of their columns.
+        if cols > 0 {
+            let mut sums = [vec![0.0; height], vec![0.0; height]];
+            let mut counts = [0; 2];
+            for x in 0..cols {
+                for (y, sum) in sums[x % 2].iter_mut().enumerate() {
+                    *sum += difference(x, y) - fpn.columns[y % 2][x];
+                }
+                counts[x % 2] += 1;
+            }
+            fpn.rows = means(sums, counts);
+        }
+
+        let fpn = match self.fpn.take() {
+            Some(mut damped)
+                if damped.columns[0].len() == width && damped.rows[0].len() == height =>
+            {
+                let offsets = fpn.columns.iter().chain(&fpn.rows);
+                for (damped, offsets) in damped
+                    .columns
+                    .iter_mut()
+                    .chain(&mut damped.rows)
+                    .zip(offsets)
+                {
+                    for (damped, offset) in damped.iter_mut().zip(offsets) {
+                        *damped += (offset - *damped) * DAMPING;
+                    }
+                }
+                damped
+            }
+            // The first frame, or one of another size.
+            _ => fpn,
+        };
+
+        Ok(self.fpn.insert(fpn))
+    }
+}
+
+// Means of the sums over `counts` lines of each parity. A single optical black row or
+// column only has the even parity, which the odd one gets too.
+fn means(mut sums: [Vec<f32>; 2], counts: [u32; 2]) -> [Vec<f32>; 2] {
+    for (sums, count) in sums.iter_mut().zip(counts) {
+        if count > 0 {
+            for sum in sums.iter_mut() {
+                *sum /= count as f32;
+            }
+        }
+    }
+    if counts[1] == 0 {
+        sums[1] = sums[0].clone();
+    }
+
+    sums
+}
@@ -0,0 +1,121 @@
+// Frame geometry shared by the code paths that work on plain slices.
+
+/// Byte positions of the color channels within one output pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLayout {
+    pub red: usize,
+    pub green: usize,
+    pub blue: usize,
+    pub alpha: Option<usize>,
+    pub pixel_stride: usize,
+}
+
+impl OutputLayout {
+    pub fn for_format(format: gst_video::VideoFormat) -> Option<Self> {
+        match format {
+            gst_video::VideoFormat::Rgb => Some(OutputLayout {
+                red: 0,
+                green: 1,
+                blue: 2,
+                alpha: None,
+                pixel_stride: 3,
+            }),
+            gst_video::VideoFormat::Bgr => Some(OutputLayout {
+                red: 2,
+                green: 1,
+                blue: 0,
+                alpha: None,
+                pixel_stride: 3,
+            }),
+            gst_video::VideoFormat::Rgba => Some(OutputLayout {
+                red: 0,
+                green: 1,
+                blue: 2,
+                alpha: Some(3),
+                pixel_stride: 4,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A rectangle in frame pixel coordinates. A zero width or height extends to the
+/// right or bottom edge of the frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The part of the rectangle within a `width` x `height` frame, grown to whole 2x2
+    /// quads so the frame's CFA pattern applies to it unchanged. `None` if nothing of
+    /// it is left.
+    pub fn clamp_to_cfa(self, width: usize, height: usize) -> Option<Rect> {
+        let extent = |start: usize, len: usize, size: usize| {
+            let end = match len {
+                0 => size,
+                len => start.saturating_add(len).min(size),
+            };
+            let start = start & !1;
+            let end = (end.div_ceil(2) * 2).min(size & !1);
+            (end > start).then_some((start, end - start))
+        };
+
+        let (x, width) = extent(self.x, self.width, width)?;
+        let (y, height) = extent(self.y, self.height, height)?;
+        Some(Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Whether this is the whole frame.
+    pub fn is_full_frame(&self) -> bool {
+        *self == Rect::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The frame is smaller than the 2x2 CFA tile.
+    FrameTooSmall,
+    /// A slice can't hold the frame at the given stride.
+    BufferTooSmall,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FrameTooSmall => f.write_str("frame smaller than 2x2"),
+            Error::BufferTooSmall => f.write_str("buffer too small for frame"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Whether `rows` rows of `row_bytes` bytes make a frame that can be allocated in one
+/// piece, at most `isize::MAX` bytes, and wrapped in an OpenCV Mat, whose rows and row
+/// steps are `i32`.
+pub fn addressable(rows: usize, row_bytes: usize) -> bool {
+    rows <= i32::MAX as usize
+        && row_bytes <= i32::MAX as usize
+        && rows
+            .checked_mul(row_bytes)
+            .is_some_and(|size| size <= isize::MAX as usize)
+}
+
+/// Whether `rows` rows of `row_bytes` bytes, `stride` bytes apart, fit in `len` bytes.
+pub fn fits(len: usize, rows: usize, row_bytes: usize, stride: usize) -> bool {
+    stride >= row_bytes
+        && rows.checked_sub(1).is_none_or(|last| {
+            last.checked_mul(stride)
+                .and_then(|n| n.checked_add(row_bytes))
+                .is_some_and(|required| required <= len)
+        })
+}
@@ -0,0 +1,171 @@
+// GL context sharing for `memory:GLMemory` output.
+//
+// The element never issues GL commands itself. Output buffers come from a GL buffer
+// pool whose memory is backed by pixel buffer objects, so mapping them for writing
+// hands out CPU memory and the texture upload happens when the sink maps them for GL.
+// All that is needed here is the display and context negotiation every GL element
+// does, following GstGLBaseFilter.
+
+use gst::glib;
+use gst::glib::translate::{FromGlibPtrFull, IntoGlib, ToGlibPtr};
+use gst::prelude::*;
+
+use std::sync::Mutex;
+
+use super::imp::CAT;
+
+/// Caps structures with the GL memory feature carry the texture target as well.
+pub const TEXTURE_TARGET: &str = "2D";
+
+#[derive(Default, Clone)]
+struct Objects {
+    display: Option<gst_gl::GLDisplay>,
+    // The application's context, which ours has to share resources with.
+    other_context: Option<gst_gl::GLContext>,
+    context: Option<gst_gl::GLContext>,
+}
+
+// The lock is never held across the libgstgl helpers: they post need-context messages
+// and set contexts on the element, which lands in `set_context()` again.
+#[derive(Default)]
+pub struct Context {
+    objects: Mutex<Objects>,
+}
+
+impl Context {
+    pub fn set_context(&self, element: &gst::Element, context: &gst::Context) {
+        let (display, other_context) = gst_gl::functions::gl_handle_set_context(element, context);
+
+        let mut objects = self.objects.lock().unwrap();
+        if display.is_some() {
+            objects.display = display;
+        }
+        if other_context.is_some() {
+            objects.other_context = other_context;
+        }
+    }
+
+    pub fn handle_query(&self, element: &gst::Element, query: &mut gst::query::Context) -> bool {
+        let objects = self.objects.lock().unwrap().clone();
+        gst_gl::functions::gl_handle_context_query(
+            element,
+            query,
+            objects.display.as_ref(),
+            objects.context.as_ref(),
+            objects.other_context.as_ref(),
+        )
+    }
+
+    /// Looks for a GL display shared by the application or a neighbouring element,
+    /// creating one if none is shared but GL is usable. Returns false when GL output
+    /// can't be offered.
+    pub fn ensure_display(&self, element: &gst::Element) -> bool {
+        let Objects {
+            mut display,
+            mut other_context,
+            ..
+        } = self.objects.lock().unwrap().clone();
+        if display.is_some() {
+            return true;
+        }
+
+        if !ensure_element_data(element, &mut display, &mut other_context) {
+            return false;
+        }
+
+        let mut objects = self.objects.lock().unwrap();
+        objects.display = display;
+        objects.other_context = other_context;
+        true
+    }
+
+    /// The context output buffers are allocated in: downstream's if it shares one, ours
+    /// otherwise.
+    pub fn ensure_context(&self, element: &gst::Element) -> Option<gst_gl::GLContext> {
+        if !self.ensure_display(element) {
+            return None;
+        }
+
+        let Objects {
+            display,
+            other_context,
+            mut context,
+        } = self.objects.lock().unwrap().clone();
+        if context.is_some() {
+            return context;
+        }
+
+        query_local_gl_context(element, gst::PadDirection::Src, &mut context);
+
+        if context.is_none() {
+            let display = display?;
+            let display = display.object_lock();
+            match gst_gl::GLDisplay::create_context(&display, other_context.as_ref()) {
+                Ok(new_context) => {
+                    if let Err(err) = gst_gl::GLDisplay::add_context(&display, &new_context) {
+                        gst::warning!(CAT, obj = element, "Failed to add GL context: {}", err);
+                    }
+                    context = Some(new_context);
+                }
+                Err(err) => {
+                    gst::warning!(CAT, obj = element, "Failed to create GL context: {}", err);
+                }
+            }
+        }
+
+        self.objects.lock().unwrap().context = context.clone();
+        context
+    }
+
+    /// Forgets the output context, keeping the display and the application's context.
+    pub fn reset(&self) {
+        self.objects.lock().unwrap().context = None;
+    }
+}
+
+// The libgstgl helpers without bindings. Both take the objects in and out, replacing
+// them with what they find.
+fn ensure_element_data(
+    element: &gst::Element,
+    display: &mut Option<gst_gl::GLDisplay>,
+    other_context: &mut Option<gst_gl::GLContext>,
+) -> bool {
+    unsafe {
+        let mut display_ptr = display.take().to_glib_full();
+        let mut context_ptr = other_context.take().to_glib_full();
+        let found = gst_gl::ffi::gst_gl_ensure_element_data(
+            element.to_glib_none().0,
+            &mut display_ptr,
+            &mut context_ptr,
+        );
+        *display = Option::from_glib_full(display_ptr);
+        *other_context = Option::from_glib_full(context_ptr);
+        found != glib::ffi::GFALSE
+    }
+}
+
+fn query_local_gl_context(
+    element: &gst::Element,
+    direction: gst::PadDirection,
+    context: &mut Option<gst_gl::GLContext>,
+) {
+    unsafe {
+        let mut context_ptr = context.take().to_glib_full();
+        gst_gl::ffi::gst_gl_query_local_gl_context(
+            element.to_glib_none().0,
+            direction.into_glib(),
+            &mut context_ptr,
+        );
+        *context = Option::from_glib_full(context_ptr);
+    }
+}
+
+pub fn is_gl_caps(caps: &gst::CapsRef) -> bool {
+    caps.features(0)
+        .is_some_and(|features| features.contains(gst_gl::CAPS_FEATURE_MEMORY_GL_MEMORY))
+}
+
+/// GL buffer pool allocating in `context`.
+pub fn buffer_pool(context: &gst_gl::GLContext) -> gst::BufferPool {
+    gst_gl::GLBufferPool::new(context).upcast()
+}
@@ -1,17 +1,61 @@
 use gst::glib;
-use gst::glib::ffi::GFALSE;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
+use gst_base::prelude::*;
 use gst_base::subclass::prelude::*;
-use gst_base_sys as ffi;
-use gst_base_sys::GstBaseTransformClass;
 use gst_video::VideoFrameExt;
-use gst_video::VideoFrameRef;
-use opencv::prelude::*;
-use opencv::{Result, highgui, imgproc, videoio};
+use gst_video::prelude::VideoBufferPoolConfig;
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+use super::worker::{Queued, Worker};
+use super::awb;
+use super::border;
+use super::{
+    AwbMode, Backend, BorderMode, CfaLayout, DemosaicAlgorithm, FieldMode, FpnCorrection,
+    GrGbBalance, HighlightMode, InputPattern, Leaky, Method, QuadMode, QualityLevel, ToneMapping,
+};
+use super::cfa::{CfaColor, Pattern};
+use super::convert::{AutoDefects, AutoLevels, Converter, Gains};
+use super::dark;
+use super::decompand;
+use super::defects;
+use super::detect;
+use super::exposure::RsBayerExposureMeta;
+use super::fields;
+use super::focus;
+use super::fpn;
+use super::frame::{self, OutputLayout, Rect};
+use super::info::{self, BayerFrame, BayerInfo, Depth};
+use super::meta::RsBayerTimingMeta;
+use super::orient;
+#[cfg(feature = "opencv")]
+use super::overlay;
+use super::preset;
+use super::preview::PreviewPad;
+use super::quad;
+use super::quality;
+use super::raw;
+#[cfg(feature = "opencv")]
+use super::resize;
+use super::shading;
+use super::stack;
+use super::stats;
+use super::tuning;
+use super::unpack;
+use super::yuv;
+#[cfg(feature = "opencv")]
+use super::Denoise;
+#[cfg(feature = "opencv")]
+use super::cv;
+#[cfg(feature = "dmabuf")]
+use super::dmabuf;
+#[cfg(feature = "dump")]
+use super::dump;
+#[cfg(feature = "gl")]
+use super::gl;
+
+pub(super) static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
     gst::DebugCategory::new(
         "rsbayer2rgb",
         gst::DebugColorFlags::empty(),
@@ -19,104 +63,4639 @@ static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
     )
 });
 
+const DEFAULT_TIMING_REPORT_INTERVAL: u32 = 5;
+const DEFAULT_STATS_INTERVAL: u32 = 0;
+const DEFAULT_EMIT_SIGNALS: bool = false;
+const DEFAULT_ENABLE_LAST_SAMPLE: bool = false;
+const DEFAULT_MIN_BUFFERS: u32 = 0;
+const DEFAULT_MAX_BUFFERS: u32 = 0;
+const DEFAULT_OUTPUT_ALIGNMENT: u32 = 32;
+const DEFAULT_MAX_QUEUE_BUFFERS: u32 = 0;
+const DEFAULT_GAMMA: f64 = 1.0;
+// Sizes accepted for tone-lut, for 8-bit and for high depth processing.
+const TONE_LUT_SIZES: [usize; 2] = [256, 1024];
+const DEFAULT_EXPOSURE_GAIN: f64 = 1.0;
+const DEFAULT_EXPOSURE_COMPENSATION: bool = false;
+// 10 ms at unity analog gain.
+const DEFAULT_REFERENCE_EXPOSURE: u64 = 10_000_000;
+const DEFAULT_PROCESSING_DEADLINE: u64 = 0;
+const DEFAULT_AUTO_ORIENT: bool = false;
+const DEFAULT_GR_GB_RATIO: f64 = 1.0;
+const DEFAULT_COLOR_TEMPERATURE: u32 = 0;
+const DEFAULT_TINT: f64 = 0.0;
+const DEFAULT_SKIP: u32 = 0;
+const DEFAULT_POST_EMBEDDED_DATA: bool = false;
+const DEFAULT_OB_ROWS: u32 = 0;
+const DEFAULT_OB_COLS: u32 = 0;
+const DEFAULT_OB_CROP: bool = false;
+const DEFAULT_PHASE: u32 = 0;
+const DEFAULT_BRIGHTNESS: f64 = 0.0;
+const DEFAULT_CONTRAST: f64 = 1.0;
+const DEFAULT_SATURATION: f64 = 1.0;
+const DEFAULT_FALSE_COLOR_SUPPRESSION: f64 = 0.0;
+const DEFAULT_HIGH_PRECISION: bool = false;
+const DEFAULT_DITHER: bool = false;
+const DEFAULT_PROCESS_ROI_FILL_COLOR: u32 = 0xff000000;
+const DEFAULT_PROCESS_ROI_KEEP_PREVIOUS: bool = false;
+const DEFAULT_WATCH_TUNING_FILE: bool = false;
+const DEFAULT_STACK_FRAMES: u32 = 1;
+const MAX_STACK_FRAMES: u32 = 1024;
+const DEFAULT_BLACK_POINT: f64 = 0.0;
+const DEFAULT_WHITE_POINT: f64 = 1.0;
+const DEFAULT_AUTO_LEVELS: bool = false;
+const DEFAULT_AUTO_LEVELS_LOW: f64 = 0.005;
+const DEFAULT_AUTO_LEVELS_HIGH: f64 = 0.995;
+const DEFAULT_AUTO_LEVELS_FRAMES: u32 = 4;
+const DEFAULT_LEVELS_LOCK: bool = false;
+const DEFAULT_SHOW_ZEBRA: bool = false;
+const DEFAULT_ZEBRA_THRESHOLD: f64 = 0.98;
+const DEFAULT_POST_STATS: bool = false;
+const DEFAULT_STATS_SUBSAMPLING: u32 = 2;
+const DEFAULT_POST_FOCUS_METRIC: bool = false;
+const DEFAULT_ATTACH_TIMING_META: bool = false;
+const DEFAULT_AUTO_DEFECT_CORRECTION: bool = false;
+const DEFAULT_DEFECT_THRESHOLD: f64 = 0.2;
+const DEFAULT_DEFECT_DETECTION_INTERVAL: u32 = 1;
+#[cfg(feature = "dump")]
+const DEFAULT_DUMP_INTERVAL: u32 = 0;
+#[cfg(feature = "dump")]
+const DEFAULT_DUMP_RAW: bool = false;
+
+// Property values live apart from the streaming state so that setting or reading a
+// property never waits for a conversion in progress. `transform()` takes a copy at the
+// start of every buffer, which makes changes atomic per frame: a property set while a
+// frame is being converted applies from the next buffer on. Properties that change the
+// output size (method, video-direction, auto-orient, border-mode=crop, the crop-*
+// properties and the output-width/-height of the OpenCV backend) additionally send a
+// reconfigure event upstream and only take effect once the new caps reach `set_caps()`.
+//
+// Numeric processing properties are registered as CONTROLLABLE; control sources bound
+// to them are sampled at the top of `transform()` before the copy is taken.
+#[derive(Debug, Clone)]
+struct Settings {
+    timing_report_interval: u32,
+    stats_interval: u32,
+    emit_signals: bool,
+    enable_last_sample: bool,
+    min_buffers: u32,
+    max_buffers: u32,
+    output_alignment: u32,
+    // Names of the meta APIs copied to the output besides the default ones.
+    extra_meta_apis: Vec<String>,
+    method: Method,
+    video_direction: gst_video::VideoOrientationMethod,
+    auto_orient: bool,
+    // Direction of the last image-orientation tag, for auto-orient.
+    tag_direction: gst_video::VideoOrientationMethod,
+    demosaic_algorithm: DemosaicAlgorithm,
+    processing_deadline: u64,
+    border_mode: BorderMode,
+    field_mode: FieldMode,
+    gains: Gains,
+    // Kelvin, 0 when the gains don't come from a color temperature.
+    color_temperature: u32,
+    tint: f64,
+    awb_mode: AwbMode,
+    gr_gb_balance: GrGbBalance,
+    gr_gb_ratio: f64,
+    exposure_gain: f64,
+    highlight_mode: HighlightMode,
+    exposure_compensation: bool,
+    reference_exposure: u64,
+    // Lines and columns at the edges of the input that aren't image at all.
+    skip_lines_top: u32,
+    skip_lines_bottom: u32,
+    skip_cols_left: u32,
+    skip_cols_right: u32,
+    post_embedded_data: bool,
+    // The decompanding curve as set and the lookup table it makes.
+    decompanding: Option<(gst::Array, std::sync::Arc<[u16]>)>,
+    ob_rows: u32,
+    ob_cols: u32,
+    ob_crop: bool,
+    crop: Rect,
+    pattern: InputPattern,
+    cfa_layout: CfaLayout,
+    quad_mode: QuadMode,
+    // Columns and rows the CFA pattern of the caps is shifted by, 0 or 1.
+    x_phase: u32,
+    y_phase: u32,
+    lsc_file: Option<String>,
+    flat_field_file: Option<String>,
+    dark_frame_file: Option<String>,
+    defect_list_file: Option<String>,
+    fpn_correction: FpnCorrection,
+    fpn_file: Option<String>,
+    auto_defect_correction: bool,
+    defect_threshold: f64,
+    defect_detection_interval: u32,
+    // Loaded from dark_frame_file whenever it is set.
+    dark_frame: Option<std::sync::Arc<dark::DarkFrame>>,
+    gamma: f64,
+    tone_lut: Option<std::sync::Arc<[u16]>>,
+    brightness: f64,
+    contrast: f64,
+    saturation: f64,
+    false_color_suppression: f64,
+    high_precision: bool,
+    dither: bool,
+    tone_mapping: ToneMapping,
+    black_point: f64,
+    white_point: f64,
+    auto_levels: bool,
+    auto_levels_low: f64,
+    auto_levels_high: f64,
+    auto_levels_frames: u32,
+    levels_lock: bool,
+    show_zebra: bool,
+    zebra_threshold: f64,
+    post_stats: bool,
+    stats_subsampling: u32,
+    post_focus_metric: bool,
+    attach_timing_meta: bool,
+    stats_roi: Rect,
+    process_roi: Rect,
+    // ARGB color of the output outside the process ROI.
+    process_roi_fill_color: u32,
+    process_roi_keep_previous: bool,
+    stack_frames: u32,
+    max_queue_buffers: u32,
+    leaky: Leaky,
+    backend: Backend,
+    // Name of the last preset loaded.
+    preset: Option<String>,
+    tuning_file: Option<String>,
+    watch_tuning_file: bool,
+    #[cfg(feature = "dump")]
+    dump_location: Option<String>,
+    #[cfg(feature = "dump")]
+    dump_interval: u32,
+    #[cfg(feature = "dump")]
+    dump_raw: bool,
+    #[cfg(feature = "opencv")]
+    opencv: cv::Options,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timing_report_interval: DEFAULT_TIMING_REPORT_INTERVAL,
+            stats_interval: DEFAULT_STATS_INTERVAL,
+            emit_signals: DEFAULT_EMIT_SIGNALS,
+            enable_last_sample: DEFAULT_ENABLE_LAST_SAMPLE,
+            min_buffers: DEFAULT_MIN_BUFFERS,
+            max_buffers: DEFAULT_MAX_BUFFERS,
+            output_alignment: DEFAULT_OUTPUT_ALIGNMENT,
+            extra_meta_apis: Vec::new(),
+            method: Method::default(),
+            video_direction: gst_video::VideoOrientationMethod::Identity,
+            auto_orient: DEFAULT_AUTO_ORIENT,
+            tag_direction: gst_video::VideoOrientationMethod::Identity,
+            demosaic_algorithm: DemosaicAlgorithm::default(),
+            processing_deadline: DEFAULT_PROCESSING_DEADLINE,
+            border_mode: BorderMode::default(),
+            field_mode: FieldMode::default(),
+            gains: Gains::default(),
+            color_temperature: DEFAULT_COLOR_TEMPERATURE,
+            tint: DEFAULT_TINT,
+            awb_mode: AwbMode::default(),
+            gr_gb_balance: GrGbBalance::default(),
+            gr_gb_ratio: DEFAULT_GR_GB_RATIO,
+            exposure_gain: DEFAULT_EXPOSURE_GAIN,
+            highlight_mode: HighlightMode::default(),
+            exposure_compensation: DEFAULT_EXPOSURE_COMPENSATION,
+            reference_exposure: DEFAULT_REFERENCE_EXPOSURE,
+            skip_lines_top: DEFAULT_SKIP,
+            skip_lines_bottom: DEFAULT_SKIP,
+            skip_cols_left: DEFAULT_SKIP,
+            skip_cols_right: DEFAULT_SKIP,
+            post_embedded_data: DEFAULT_POST_EMBEDDED_DATA,
+            decompanding: None,
+            ob_rows: DEFAULT_OB_ROWS,
+            ob_cols: DEFAULT_OB_COLS,
+            ob_crop: DEFAULT_OB_CROP,
+            crop: Rect::default(),
+            pattern: InputPattern::default(),
+            cfa_layout: CfaLayout::default(),
+            quad_mode: QuadMode::default(),
+            x_phase: DEFAULT_PHASE,
+            y_phase: DEFAULT_PHASE,
+            lsc_file: None,
+            flat_field_file: None,
+            dark_frame_file: None,
+            defect_list_file: None,
+            fpn_correction: FpnCorrection::default(),
+            fpn_file: None,
+            auto_defect_correction: DEFAULT_AUTO_DEFECT_CORRECTION,
+            defect_threshold: DEFAULT_DEFECT_THRESHOLD,
+            defect_detection_interval: DEFAULT_DEFECT_DETECTION_INTERVAL,
+            dark_frame: None,
+            gamma: DEFAULT_GAMMA,
+            tone_lut: None,
+            brightness: DEFAULT_BRIGHTNESS,
+            contrast: DEFAULT_CONTRAST,
+            saturation: DEFAULT_SATURATION,
+            false_color_suppression: DEFAULT_FALSE_COLOR_SUPPRESSION,
+            high_precision: DEFAULT_HIGH_PRECISION,
+            dither: DEFAULT_DITHER,
+            tone_mapping: ToneMapping::default(),
+            black_point: DEFAULT_BLACK_POINT,
+            white_point: DEFAULT_WHITE_POINT,
+            auto_levels: DEFAULT_AUTO_LEVELS,
+            auto_levels_low: DEFAULT_AUTO_LEVELS_LOW,
+            auto_levels_high: DEFAULT_AUTO_LEVELS_HIGH,
+            auto_levels_frames: DEFAULT_AUTO_LEVELS_FRAMES,
+            levels_lock: DEFAULT_LEVELS_LOCK,
+            show_zebra: DEFAULT_SHOW_ZEBRA,
+            zebra_threshold: DEFAULT_ZEBRA_THRESHOLD,
+            post_stats: DEFAULT_POST_STATS,
+            stats_subsampling: DEFAULT_STATS_SUBSAMPLING,
+            post_focus_metric: DEFAULT_POST_FOCUS_METRIC,
+            attach_timing_meta: DEFAULT_ATTACH_TIMING_META,
+            stats_roi: Rect::default(),
+            process_roi: Rect::default(),
+            process_roi_fill_color: DEFAULT_PROCESS_ROI_FILL_COLOR,
+            process_roi_keep_previous: DEFAULT_PROCESS_ROI_KEEP_PREVIOUS,
+            stack_frames: DEFAULT_STACK_FRAMES,
+            max_queue_buffers: DEFAULT_MAX_QUEUE_BUFFERS,
+            leaky: Leaky::default(),
+            backend: Backend::default(),
+            preset: None,
+            tuning_file: None,
+            watch_tuning_file: DEFAULT_WATCH_TUNING_FILE,
+            #[cfg(feature = "dump")]
+            dump_location: None,
+            #[cfg(feature = "dump")]
+            dump_interval: DEFAULT_DUMP_INTERVAL,
+            #[cfg(feature = "dump")]
+            dump_raw: DEFAULT_DUMP_RAW,
+            #[cfg(feature = "opencv")]
+            opencv: cv::Options::default(),
+        }
+    }
+}
+
+impl Settings {
+    // The direction the output is turned in. The image-orientation tag decides with
+    // video-direction=auto, and with auto-orient unless video-direction turns it.
+    fn direction(&self) -> gst_video::VideoOrientationMethod {
+        use gst_video::VideoOrientationMethod as Direction;
+
+        match self.video_direction {
+            Direction::Auto => self.tag_direction,
+            Direction::Identity if self.auto_orient => self.tag_direction,
+            direction => direction,
+        }
+    }
+
+    // Whether red/green/blue-gain were set, which overrides color-temperature.
+    fn explicit_gains(&self) -> bool {
+        (self.gains.red, self.gains.green, self.gains.blue) != (1.0, 1.0, 1.0)
+    }
+
+    // The gains of awb-mode=manual, from color-temperature unless explicit gains are
+    // set.
+    fn manual_gains(&self) -> Gains {
+        if self.color_temperature == 0 || self.explicit_gains() {
+            return self.gains;
+        }
+        awb::temperature_gains(self.color_temperature as f64, self.tint)
+    }
+}
+
+// Counters readable from any thread while streaming. Only the streaming thread writes
+// them, so plain loads and stores are enough.
+#[derive(Default)]
+struct Stats {
+    frames_processed: AtomicU64,
+    frames_dropped: AtomicU64,
+    // Exponentially weighted average conversion time in microseconds, as f64 bits.
+    avg_conversion_time: AtomicU64,
+}
+
+// Weight of the newest sample in the average conversion time.
+const AVG_CONVERSION_TIME_WEIGHT: f64 = 0.1;
+
+impl Stats {
+    fn reset(&self) {
+        self.frames_processed.store(0, Ordering::Relaxed);
+        self.frames_dropped.store(0, Ordering::Relaxed);
+        self.avg_conversion_time.store(0f64.to_bits(), Ordering::Relaxed);
+    }
+
+    fn frame_processed(&self, elapsed: std::time::Duration) {
+        let micros = elapsed.as_secs_f64() * 1_000_000.0;
+        let processed = self.frames_processed.fetch_add(1, Ordering::Relaxed);
+        let avg = if processed == 0 {
+            micros
+        } else {
+            let prev = f64::from_bits(self.avg_conversion_time.load(Ordering::Relaxed));
+            prev + AVG_CONVERSION_TIME_WEIGHT * (micros - prev)
+        };
+        self.avg_conversion_time.store(avg.to_bits(), Ordering::Relaxed);
+    }
+
+    fn frame_dropped(&self) {
+        self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn avg_conversion_time(&self) -> f64 {
+        f64::from_bits(self.avg_conversion_time.load(Ordering::Relaxed))
+    }
+}
+
 #[derive(Default)]
 pub struct RsBayer2Rgb {
+    settings: std::sync::Mutex<Settings>,
+    // Only touched by the streaming thread, and by the conversion thread while
+    // max-queue-buffers is non-zero.
     state: std::sync::Mutex<Option<State>>,
+    negotiated: std::sync::Mutex<Option<std::sync::Arc<Negotiated>>>,
+    stats: Stats,
+    // Only updated by the streaming thread, after every frame.
+    quality: std::sync::Mutex<quality::QualityControl>,
+    last_sample: std::sync::Mutex<Option<gst::Sample>>,
+    // Gains used for the last frame, manual or from AWB.
+    applied_gains: std::sync::Mutex<Gains>,
+    // Window of the input calibrate-wb asked to measure on the next frame.
+    wb_calibration: std::sync::Mutex<Option<Rect>>,
+    calibration: std::sync::Mutex<Calibration>,
+    // Pattern locked in by pattern=auto-detect, kept across caps changes until the
+    // element stops.
+    detected: std::sync::Mutex<Option<Pattern>>,
+    // Raw frames of the stack being added up while stack-frames is above 1.
+    stack: std::sync::Mutex<Option<stack::Stack>>,
+    // Held while a preset or a tuning file loads, so the settings of a frame are all
+    // from before or all from after it.
+    tuning_lock: std::sync::Mutex<()>,
+    // Modification time of the tuning file when it was last loaded.
+    tuning_modified: std::sync::Mutex<Option<std::time::SystemTime>>,
+    // Conversion thread while max-queue-buffers is non-zero.
+    worker: std::sync::Mutex<Option<std::sync::Arc<Worker>>>,
+    // Requested preview_%u pads.
+    previews: std::sync::Mutex<Vec<PreviewPad>>,
+    // Started with the first frame dumped.
+    #[cfg(feature = "dump")]
+    dump_writer: std::sync::Mutex<Option<dump::Writer>>,
+    #[cfg(feature = "gl")]
+    gl: gl::Context,
 }
 
 struct State {
     in_info: InputInfo,
     out_info: gst_video::VideoInfo,
-    intermediate_rgb: Option<opencv::core::Mat>,
+    // Part of the input the converter sees, all of it unless ob-crop or a crop is set.
+    active: Rect,
+    // Flip or rotation the converter applies.
+    orientation: orient::Transform,
+    converter: Converter,
+    // Converter of the bottom field, the other one converting the top field, while
+    // interlaced fields are converted separately.
+    field_converter: Option<Converter>,
+    // How interlaced input is converted, None for progressive input.
+    fields: Option<FieldMode>,
+    // Progressive mosaic woven from the fields of the current frame.
+    woven: Vec<u8>,
+    // Bayer mosaic made from the quad bayer samples of the current frame.
+    quad_samples: Vec<u8>,
+    // 8-bit mosaic narrowed from deeper input.
+    narrowed: Vec<u8>,
+    // The same steps on 16 bits for P010 output.
+    wide: WideFrames,
+    // Measures the first frames while auto-detecting the pattern, None once it is
+    // locked in.
+    detector: Option<detect::Detector>,
+    // Dark frame the converter subtracts.
+    dark_frame: Option<std::sync::Arc<dark::DarkFrame>>,
+    // Measured on the optical black margins of the last frame.
+    black_level: Option<[u16; 4]>,
+    // Fixed pattern noise of fpn-correction=optical-black.
+    fpn: fpn::Estimator,
+    // Converts the process ROI alone, None while it is the whole frame.
+    process_roi: Option<ProcessRoi>,
+    // Output of the last frame for process-roi-keep-previous.
+    background: Vec<u8>,
+    timing: FrameTiming,
+    stats_timing: FrameTiming,
+    // Text of show-debug-overlay, kept across frames.
+    #[cfg(feature = "opencv")]
+    overlay: overlay::Overlay,
+    // Converts at the full size before scaling to output-width and output-height,
+    // None if the output isn't scaled.
+    #[cfg(feature = "opencv")]
+    scaler: Option<resize::Scaler>,
+}
+
+// Deep input widened to 16 bits, the bayer mosaic made of it like `quad_samples` and
+// `woven`, and the 16-bit RGB converted from it, written out as P010.
+#[derive(Default)]
+struct WideFrames {
+    widened: Vec<u16>,
+    quad_samples: Vec<u16>,
+    woven: Vec<u16>,
+    rgb: Vec<u16>,
+}
+
+// The 16-bit mosaic of a frame, rows `stride` samples apart, and the frame to convert
+// it into, while converting to P010.
+struct WideInput<'a> {
+    data: &'a [u16],
+    stride: usize,
+    rgb: &'a mut Vec<u16>,
+}
+
+// What threads other than the streaming thread need to know of the conversion, to add
+// metas and emit handoff. Published whenever it changes, so they never wait for the
+// conversion of a frame to finish.
+#[derive(Clone, PartialEq)]
+struct Negotiated {
+    in_info: InputInfo,
+    out_info: gst_video::VideoInfo,
+    active: Rect,
+    orientation: orient::Transform,
+    // Input pixels per output pixel in each direction and the border cropped by
+    // border-mode=crop.
+    scale: usize,
+    border: usize,
+    // Size of the frames before output-width and output-height scale them.
+    converted: (usize, usize),
+}
+
+impl Negotiated {
+    fn of(state: &State) -> Self {
+        Negotiated {
+            in_info: state.in_info,
+            out_info: state.out_info.clone(),
+            active: state.active,
+            orientation: state.orientation,
+            scale: match state.converter.method() {
+                Method::Full => 1,
+                Method::Superpixel => 2,
+            },
+            border: state.converter.cropped_border(),
+            converted: state.converted_size(),
+        }
+    }
+}
+
+impl State {
+    // Size of the converted frames, which is that of the output unless it is scaled.
+    fn converted_size(&self) -> (usize, usize) {
+        #[cfg(feature = "opencv")]
+        if let Some(scaler) = &self.scaler {
+            return (scaler.info.width() as usize, scaler.info.height() as usize);
+        }
+        (
+            self.out_info.width() as usize,
+            self.out_info.height() as usize,
+        )
+    }
+}
+
+// Conversion of the process ROI set by the process-roi properties.
+struct ProcessRoi {
+    // The process-roi properties it was set up for.
+    roi: Rect,
+    // Window of the active input converted and its converter, None if the ROI is
+    // outside of it and the whole frame is converted.
+    converter: Option<(Rect, Converter)>,
+}
+
+// Conversion times accumulated between two DEBUG reports or stats messages.
+struct FrameTiming {
+    since: std::time::Instant,
+    frames: u64,
+    total: std::time::Duration,
+    min: std::time::Duration,
+    max: std::time::Duration,
+}
+
+impl FrameTiming {
+    fn new() -> Self {
+        FrameTiming {
+            since: std::time::Instant::now(),
+            frames: 0,
+            total: std::time::Duration::ZERO,
+            min: std::time::Duration::MAX,
+            max: std::time::Duration::ZERO,
+        }
+    }
+
+    fn add(&mut self, elapsed: std::time::Duration) {
+        self.frames += 1;
+        self.total += elapsed;
+        self.min = self.min.min(elapsed);
+        self.max = self.max.max(elapsed);
+    }
+}
+
+// Calibration data loaded from the file properties when starting.
+#[derive(Default)]
+struct Calibration {
+    gain_map: Option<shading::GainMap>,
+    flat_field: Option<shading::FlatField>,
+    defects: Option<defects::DefectList>,
+    fpn: Option<fpn::FpnFile>,
+}
+
+impl Calibration {
+    fn load(settings: &Settings) -> Result<Self, gst::ErrorMessage> {
+        // The dark frame is loaded as soon as the property is set.
+        if let (Some(path), None) = (&settings.dark_frame_file, &settings.dark_frame) {
+            return Err(gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to load dark frame {}", path]
+            ));
+        }
+
+        match settings.fpn_correction {
+            FpnCorrection::File if settings.fpn_file.is_none() => {
+                return Err(gst::error_msg!(
+                    gst::LibraryError::Settings,
+                    ["fpn-correction=file needs an fpn-file"]
+                ));
+            }
+            FpnCorrection::OpticalBlack if settings.ob_rows == 0 && settings.ob_cols == 0 => {
+                return Err(gst::error_msg!(
+                    gst::LibraryError::Settings,
+                    ["fpn-correction=optical-black needs ob-rows or ob-cols"]
+                ));
+            }
+            _ => (),
+        }
+
+        fn load_file<T>(
+            path: &Option<String>,
+            what: &str,
+            load: impl FnOnce(&std::path::Path) -> Result<T, String>,
+        ) -> Result<Option<T>, gst::ErrorMessage> {
+            path.as_deref()
+                .map(|path| {
+                    load(std::path::Path::new(path)).map_err(|err| {
+                        gst::error_msg!(
+                            gst::ResourceError::OpenRead,
+                            ["Failed to load {}: {}", what, err]
+                        )
+                    })
+                })
+                .transpose()
+        }
+
+        Ok(Calibration {
+            gain_map: load_file(&settings.lsc_file, "lens shading map", shading::GainMap::load)?,
+            flat_field: load_file(
+                &settings.flat_field_file,
+                "flat-field",
+                shading::FlatField::load,
+            )?,
+            defects: load_file(
+                &settings.defect_list_file,
+                "defect list",
+                defects::DefectList::load,
+            )?,
+            fpn: match settings.fpn_correction {
+                FpnCorrection::File => load_file(
+                    &settings.fpn_file,
+                    "fixed pattern noise offsets",
+                    fpn::FpnFile::load,
+                )?,
+                _ => None,
+            },
+        })
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.gain_map.is_some()
+            || self.flat_field.is_some()
+            || self.defects.is_some()
+            || self.fpn.is_some()
+    }
+
+    // Shading gain of every sample of the `active` window of the input, combining the
+    // gain map and the flat-field.
+    fn shading(&self, in_info: &InputInfo, active: Rect) -> Result<Option<Vec<f32>>, String> {
+        let pattern = in_info.pattern.offset(active.x, active.y);
+        let mut shading = self
+            .gain_map
+            .as_ref()
+            .map(|gain_map| gain_map.pixel_gains(active.width, active.height, pattern))
+            .transpose()
+            .map_err(|err| format!("Invalid lens shading map: {err}"))?;
+
+        if let Some(ref flat_field) = self.flat_field {
+            let gains = flat_field
+                .pixel_gains(in_info.width, in_info.height, active, pattern)
+                .map_err(|err| format!("Invalid flat-field: {err}"))?;
+            shading = Some(match shading {
+                Some(mut shading) => {
+                    for (gain, flat) in shading.iter_mut().zip(gains) {
+                        *gain *= flat;
+                    }
+                    shading
+                }
+                None => gains,
+            });
+        }
+
+        Ok(shading)
+    }
+
+    fn defects(
+        &self,
+        in_info: &InputInfo,
+        active: Rect,
+    ) -> Result<Option<defects::DefectMap>, String> {
+        self.defects
+            .as_ref()
+            .map(|defects| defects.for_window(in_info.width, in_info.height, active))
+            .transpose()
+            .map_err(|err| format!("Invalid defect list: {err}"))
+    }
+
+    fn fpn(&self, in_info: &InputInfo, active: Rect) -> Result<Option<fpn::Fpn>, String> {
+        self.fpn
+            .as_ref()
+            .map(|fpn| fpn.for_window(in_info.width, in_info.height, active))
+            .transpose()
+            .map_err(|err| format!("Invalid fixed pattern noise offsets: {err}"))
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 struct InputInfo {
+    pattern: Pattern,
+    // Size of the part past the skipped margins, which is all that is converted.
     width: usize,
     height: usize,
-    stride: usize,
+    // Skipped columns on the left and lines at the top.
+    left: usize,
+    top: usize,
+    // How quad bayer input is made a bayer mosaic of `width` x `height`, None for
+    // bayer input.
+    quad: Option<QuadMode>,
+    // The input as negotiated, before skipping or quad bayer conversion.
+    bayer: BayerInfo,
 }
 
-impl RsBayer2Rgb {}
+impl RsBayer2Rgb {
+    fn settings_property(&self, settings: &Settings, name: &str) -> glib::Value {
+        match name {
+            "timing-report-interval" => settings.timing_report_interval.to_value(),
+            "stats-interval" => settings.stats_interval.to_value(),
+            "emit-signals" => settings.emit_signals.to_value(),
+            "enable-last-sample" => settings.enable_last_sample.to_value(),
+            "min-buffers" => settings.min_buffers.to_value(),
+            "max-buffers" => settings.max_buffers.to_value(),
+            "output-alignment" => settings.output_alignment.to_value(),
+            "extra-meta-apis" => gst::Array::new(&settings.extra_meta_apis).to_value(),
+            "method" => settings.method.to_value(),
+            "video-direction" => settings.video_direction.to_value(),
+            "auto-orient" => settings.auto_orient.to_value(),
+            "demosaic-algorithm" => settings.demosaic_algorithm.to_value(),
+            "processing-deadline" => settings.processing_deadline.to_value(),
+            "border-mode" => settings.border_mode.to_value(),
+            "field-mode" => settings.field_mode.to_value(),
+            "red-gain" => settings.gains.red.to_value(),
+            "green-gain" => settings.gains.green.to_value(),
+            "blue-gain" => settings.gains.blue.to_value(),
+            "color-temperature" => settings.color_temperature.to_value(),
+            "tint" => settings.tint.to_value(),
+            "awb-mode" => settings.awb_mode.to_value(),
+            "gr-gb-balance" => settings.gr_gb_balance.to_value(),
+            "gr-gb-ratio" => settings.gr_gb_ratio.to_value(),
+            "exposure-gain" => settings.exposure_gain.to_value(),
+            "highlight-mode" => settings.highlight_mode.to_value(),
+            "exposure-compensation" => settings.exposure_compensation.to_value(),
+            "reference-exposure" => settings.reference_exposure.to_value(),
+            "skip-lines-top" => settings.skip_lines_top.to_value(),
+            "skip-lines-bottom" => settings.skip_lines_bottom.to_value(),
+            "skip-cols-left" => settings.skip_cols_left.to_value(),
+            "skip-cols-right" => settings.skip_cols_right.to_value(),
+            "post-embedded-data" => settings.post_embedded_data.to_value(),
+            "decompanding" => match settings.decompanding {
+                Some((ref curve, _)) => curve.to_value(),
+                None => gst::Array::new(Vec::<i32>::new()).to_value(),
+            },
+            "ob-rows" => settings.ob_rows.to_value(),
+            "ob-cols" => settings.ob_cols.to_value(),
+            "ob-crop" => settings.ob_crop.to_value(),
+            "crop-left" => (settings.crop.x as u32).to_value(),
+            "crop-top" => (settings.crop.y as u32).to_value(),
+            "crop-width" => (settings.crop.width as u32).to_value(),
+            "crop-height" => (settings.crop.height as u32).to_value(),
+            "pattern" => settings.pattern.to_value(),
+            "cfa-layout" => settings.cfa_layout.to_value(),
+            "quad-mode" => settings.quad_mode.to_value(),
+            "x-phase" => settings.x_phase.to_value(),
+            "y-phase" => settings.y_phase.to_value(),
+            "lsc-file" => settings.lsc_file.to_value(),
+            "flat-field-file" => settings.flat_field_file.to_value(),
+            "dark-frame-file" => settings.dark_frame_file.to_value(),
+            "defect-list-file" => settings.defect_list_file.to_value(),
+            "fpn-correction" => settings.fpn_correction.to_value(),
+            "fpn-file" => settings.fpn_file.to_value(),
+            "auto-defect-correction" => settings.auto_defect_correction.to_value(),
+            "defect-threshold" => settings.defect_threshold.to_value(),
+            "defect-detection-interval" => settings.defect_detection_interval.to_value(),
+            "gamma" => settings.gamma.to_value(),
+            "tone-lut" => gst::Array::new(
+                settings
+                    .tone_lut
+                    .iter()
+                    .flat_map(|curve| curve.iter().map(|&level| level as i32)),
+            )
+            .to_value(),
+            "brightness" => settings.brightness.to_value(),
+            "contrast" => settings.contrast.to_value(),
+            "saturation" => settings.saturation.to_value(),
+            "false-color-suppression" => settings.false_color_suppression.to_value(),
+            "high-precision" => settings.high_precision.to_value(),
+            "dither" => settings.dither.to_value(),
+            "tone-mapping" => settings.tone_mapping.to_value(),
+            "black-point" => settings.black_point.to_value(),
+            "white-point" => settings.white_point.to_value(),
+            "auto-levels" => settings.auto_levels.to_value(),
+            "auto-levels-low" => settings.auto_levels_low.to_value(),
+            "auto-levels-high" => settings.auto_levels_high.to_value(),
+            "auto-levels-frames" => settings.auto_levels_frames.to_value(),
+            "levels-lock" => settings.levels_lock.to_value(),
+            "show-zebra" => settings.show_zebra.to_value(),
+            "zebra-threshold" => settings.zebra_threshold.to_value(),
+            "post-stats" => settings.post_stats.to_value(),
+            "stats-subsampling" => settings.stats_subsampling.to_value(),
+            "post-focus-metric" => settings.post_focus_metric.to_value(),
+            "attach-timing-meta" => settings.attach_timing_meta.to_value(),
+            "stats-roi-x" => (settings.stats_roi.x as u32).to_value(),
+            "stats-roi-y" => (settings.stats_roi.y as u32).to_value(),
+            "stats-roi-width" => (settings.stats_roi.width as u32).to_value(),
+            "stats-roi-height" => (settings.stats_roi.height as u32).to_value(),
+            "process-roi-x" => (settings.process_roi.x as u32).to_value(),
+            "process-roi-y" => (settings.process_roi.y as u32).to_value(),
+            "process-roi-width" => (settings.process_roi.width as u32).to_value(),
+            "process-roi-height" => (settings.process_roi.height as u32).to_value(),
+            "process-roi-fill-color" => settings.process_roi_fill_color.to_value(),
+            "process-roi-keep-previous" => settings.process_roi_keep_previous.to_value(),
+            "stack-frames" => settings.stack_frames.to_value(),
+            "max-queue-buffers" => settings.max_queue_buffers.to_value(),
+            "leaky" => settings.leaky.to_value(),
+            "backend" => settings.backend.to_value(),
+            "preset" => settings.preset.to_value(),
+            "tuning-file" => settings.tuning_file.to_value(),
+            "watch-tuning-file" => settings.watch_tuning_file.to_value(),
+            #[cfg(feature = "dump")]
+            "dump-location" => settings.dump_location.to_value(),
+            #[cfg(feature = "dump")]
+            "dump-interval" => settings.dump_interval.to_value(),
+            #[cfg(feature = "dump")]
+            "dump-raw" => settings.dump_raw.to_value(),
+            #[cfg(feature = "opencv")]
+            name => settings.opencv.property(name).unwrap_or_else(|| unimplemented!()),
+            #[cfg(not(feature = "opencv"))]
+            _ => unimplemented!(),
+        }
+    }
 
-#[glib::object_subclass]
-impl ObjectSubclass for RsBayer2Rgb {
-    const NAME: &'static str = "GstRsBayer2Rgb";
-    type Type = super::RsBayer2Rgb;
-    type ParentType = gst_base::BaseTransform; // Changed from VideoFilter
+    // Sets up the conversion of `in_info` input to `out_info`, for new caps and again
+    // once auto-detection locks in a pattern.
+    fn new_state(
+        &self,
+        in_info: InputInfo,
+        out_info: gst_video::VideoInfo,
+        interlaced: bool,
+    ) -> Result<State, gst::LoggableError> {
+        let (width, height) = (in_info.width, in_info.height);
+        let (backend, geometry, stats_roi, process_roi, dark_frame, field_mode, auto_detect) = {
+            let settings = self.settings.lock().unwrap();
+            (
+                settings.backend,
+                Geometry::new(&settings),
+                settings.stats_roi,
+                settings.process_roi,
+                settings.dark_frame.clone(),
+                settings.field_mode,
+                settings.pattern == InputPattern::AutoDetect,
+            )
+        };
+        let method = geometry.method;
+        let detector = match auto_detect && self.detected.lock().unwrap().is_none() {
+            true => {
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Detecting the pattern on {} frames",
+                    detect::FRAMES
+                );
+                Some(detect::Detector::default())
+            }
+            false => None,
+        };
 
-    fn class_init(klass: &mut Self::Class) {
-        unsafe {
-            let base_transform_class = &mut *(klass as *mut _ as *mut ffi::GstBaseTransformClass);
-            base_transform_class.get_unit_size = Some(get_unit_size_trampoline)
+        // The ROI may still change while playing, this only reports what it
+        // amounts to for these caps.
+        if !stats_roi.is_full_frame() {
+            match stats_roi.clamp_to_cfa(width, height) {
+                Some(roi) => gst::info!(CAT, imp = self, "Measuring stats in {:?}", roi),
+                None => gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Stats ROI {:?} is outside the {}x{} input, measuring the whole frame",
+                    stats_roi,
+                    width,
+                    height
+                ),
+            }
+        }
+
+        let converted = geometry.turn(
+            geometry.output_size(width as i32, geometry.cols),
+            geometry.output_size(height as i32, geometry.rows),
+        );
+        let expected = geometry.scale(converted.0, converted.1);
+        let active = match geometry.window(width, height) {
+            Some(active) if (out_info.width() as i32, out_info.height() as i32) == expected => {
+                active
+            }
+            _ => {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Output size {}x{} doesn't match {}x{} input with {:?}",
+                    out_info.width(),
+                    out_info.height(),
+                    width,
+                    height,
+                    geometry
+                ));
+            }
+        };
+        // P010 is converted from 16 bits by the converter of the whole frame, the
+        // scaler, field converters and process ROIs only take 8-bit samples.
+        let p010 = out_info.format() == yuv::FORMAT;
+        if p010 {
+            if in_info.bayer.depth == Depth::Eight {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "{} output needs input deeper than 8 bits",
+                    yuv::FORMAT.to_str()
+                ));
+            }
+            if converted != expected || (interlaced && field_mode == FieldMode::Separate) {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Can't scale {} output or convert its fields separately",
+                    yuv::FORMAT.to_str()
+                ));
+            }
+            if !process_roi.is_full_frame() {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Converting whole frames, no process ROI for {} output",
+                    yuv::FORMAT.to_str()
+                );
+            }
+        }
+        #[cfg(feature = "opencv")]
+        let scaler = match converted == expected {
+            true => None,
+            false => {
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Scaling the {}x{} converted frames to {}x{}",
+                    converted.0,
+                    converted.1,
+                    expected.0,
+                    expected.1
+                );
+                let scaler = resize::Scaler::new(
+                    out_info.format(),
+                    converted.0 as usize,
+                    converted.1 as usize,
+                )
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+                Some(scaler)
+            }
+        };
+
+        // P010 is written from RGB.
+        let format = match p010 {
+            true => gst_video::VideoFormat::Rgb,
+            false => out_info.format(),
+        };
+        let new_converter = |pattern: Pattern, height: usize| {
+            let mut converter = Converter::new(backend, pattern, active.width, height, format)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter
+                .set_method(method)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter
+                .set_border_mode(geometry.border_mode)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter.set_direction(geometry.direction);
+            Ok::<_, gst::LoggableError>(converter)
+        };
+
+        let fields = interlaced.then_some(field_mode);
+        if fields == Some(FieldMode::Separate) {
+            // Every field converter sees every other row of the active window.
+            let rows = match method {
+                Method::Full => 2,
+                Method::Superpixel => 4,
+            };
+            let transpose = orient::Transform::for_direction(geometry.direction).transpose;
+            // Cropping every field would drop twice the rows.
+            let crop = method == Method::Full && geometry.border_mode == BorderMode::Crop;
+            if transpose || crop || active.y % 2 != 0 || active.height % rows != 0 {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Can't convert fields separately with {:?} and the window {:?}",
+                    geometry,
+                    active
+                ));
+            }
+            gst::info!(CAT, imp = self, "Converting fields separately");
+            if !process_roi.is_full_frame() {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Converting whole frames, no process ROI for separately converted fields"
+                );
+            }
+
+            let row = active.y / 2;
+            let converter =
+                new_converter(in_info.pattern.offset(active.x, row), active.height / 2)?;
+            let field_converter =
+                new_converter(in_info.pattern.offset(active.x, row + 1), active.height / 2)?;
+            if self.calibration.lock().unwrap().is_loaded() || dark_frame.is_some() {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Not applying calibration files to separately converted fields"
+                );
+            }
+
+            return Ok(State {
+                in_info,
+                out_info,
+                active,
+                orientation: orient::Transform::for_direction(geometry.direction),
+                converter,
+                field_converter: Some(field_converter),
+                fields,
+                woven: Vec::new(),
+                quad_samples: Vec::new(),
+                narrowed: Vec::new(),
+                wide: WideFrames::default(),
+                detector,
+                dark_frame: None,
+                black_level: None,
+                fpn: fpn::Estimator::default(),
+                process_roi: None,
+                background: Vec::new(),
+                timing: FrameTiming::new(),
+                stats_timing: FrameTiming::new(),
+                #[cfg(feature = "opencv")]
+                overlay: overlay::Overlay::default(),
+                #[cfg(feature = "opencv")]
+                scaler,
+            });
         }
+        if fields.is_some() {
+            gst::info!(CAT, imp = self, "Weaving fields");
+        }
+
+        let mut converter =
+            new_converter(in_info.pattern.offset(active.x, active.y), active.height)?;
+        {
+            let calibration = self.calibration.lock().unwrap();
+            let shading = calibration
+                .shading(&in_info, active)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter.set_shading(shading);
+            let defects = calibration
+                .defects(&in_info, active)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter.set_defects(defects);
+            let fpn = calibration
+                .fpn(&in_info, active)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+            converter.set_fpn(fpn);
+        }
+        converter.set_dark_frame(
+            dark_window(dark_frame.as_deref(), &in_info, active)
+                .map_err(|err| gst::loggable_error!(CAT, "{}", err))?,
+        );
+
+        Ok(State {
+            in_info,
+            out_info,
+            active,
+            orientation: orient::Transform::for_direction(geometry.direction),
+            converter,
+            field_converter: None,
+            fields,
+            woven: Vec::new(),
+            quad_samples: Vec::new(),
+            narrowed: Vec::new(),
+            wide: WideFrames::default(),
+            detector,
+            dark_frame,
+            black_level: None,
+            fpn: fpn::Estimator::default(),
+            process_roi: None,
+            background: Vec::new(),
+            timing: FrameTiming::new(),
+            stats_timing: FrameTiming::new(),
+            #[cfg(feature = "opencv")]
+            overlay: overlay::Overlay::default(),
+            #[cfg(feature = "opencv")]
+            scaler,
+        })
     }
-}
 
-unsafe extern "C" fn get_unit_size_trampoline(
-    _ptr: *mut ffi::GstBaseTransform,
-    caps: *mut gst_sys::GstCaps,
-    size: *mut usize,
-) -> glib::ffi::gboolean {
-    unsafe {
-        let caps = gst::Caps::from_glib_borrow(caps);
+    // Measures one more frame for pattern=auto-detect. Once enough frames are in, locks
+    // in the detected pattern, sets up the conversion for it and returns the
+    // `bayer-pattern-detected` element message.
+    fn detect_pattern(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        state: &mut State,
+    ) -> Result<Option<gst::Message>, gst::FlowError> {
+        let Some(detector) = &mut state.detector else {
+            return Ok(None);
+        };
 
-        let Some(structure) = caps.structure(0) else {
-            gst::warning!(CAT, "get_unit_size: no structure in caps");
-            return glib::ffi::GFALSE;
+        // Separately converted fields are still interleaved here, the top field alone
+        // has the pattern of the frame.
+        let (stride, height) = match state.fields {
+            Some(FieldMode::Separate) => (2 * in_stride, state.in_info.height / 2),
+            _ => (in_stride, state.in_info.height),
         };
+        detector
+            .add(in_data, stride, state.in_info.width, height)
+            .map_err(|err| {
+                gst::error!(CAT, imp = self, "Failed to detect the pattern: {}", err);
+                gst::FlowError::Error
+            })?;
+        if detector.frames() < detect::FRAMES {
+            return Ok(None);
+        }
 
-        let Ok(width) = structure.get::<i32>("width") else {
-            gst::warning!(CAT, "get_unit_size: no width in caps");
-            return glib::ffi::GFALSE;
+        let frames = detector.frames();
+        let detection = detector.detect();
+        if detection.is_ambiguous() {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Guessing pattern {} with confidence {:.3}, the scene may be too gray",
+                detection.pattern,
+                detection.confidence
+            );
+        } else {
+            gst::info!(
+                CAT,
+                imp = self,
+                "Detected pattern {} with confidence {:.3}",
+                detection.pattern,
+                detection.confidence
+            );
+        }
+        *self.detected.lock().unwrap() = Some(detection.pattern);
+
+        if detection.pattern == state.in_info.pattern {
+            state.detector = None;
+        } else {
+            let in_info = InputInfo {
+                pattern: detection.pattern,
+                ..state.in_info
+            };
+            let interlaced = state.fields.is_some();
+            *state = self
+                .new_state(in_info, state.out_info.clone(), interlaced)
+                .map_err(|err| {
+                    err.log_with_imp(self);
+                    gst::FlowError::NotNegotiated
+                })?;
+        }
+
+        let s = gst::Structure::builder("bayer-pattern-detected")
+            .field("pattern", detection.pattern.to_caps_format())
+            .field("confidence", detection.confidence)
+            .field("frames", frames)
+            .build();
+        Ok(Some(
+            gst::message::Element::builder(s).src(&*self.obj()).build(),
+        ))
+    }
+
+    // Loads the saved preset `name` with `load_saved`, or the built-in one of that name
+    // if there is no saved one.
+    fn load_preset(&self, name: &str, load_saved: impl FnOnce() -> bool) -> bool {
+        let _loading = self.tuning_lock.lock().unwrap();
+
+        let loaded = load_saved()
+            || preset::built_in(name).is_some_and(|properties| {
+                let obj = self.obj();
+                for property in preset::PROPERTIES {
+                    if let Some(pspec) = obj.find_property(property) {
+                        obj.set_property_from_value(property, pspec.default_value());
+                    }
+                }
+                for (property, value) in properties {
+                    obj.set_property_from_str(property, value);
+                }
+                true
+            });
+        if loaded {
+            gst::info!(CAT, imp = self, "Loaded preset {}", name);
+            self.settings.lock().unwrap().preset = Some(name.to_string());
+        } else {
+            gst::warning!(CAT, imp = self, "No preset {}", name);
+        }
+
+        loaded
+    }
+
+    // Applies the tuning file at `path` as a whole or not at all, and posts a
+    // `tuning-file-loaded` or `tuning-file-error` message.
+    fn load_tuning_file(&self, path: &str) {
+        // Remembered even if the file is invalid, so watching it doesn't retry until
+        // it changes again.
+        let location = std::path::Path::new(path);
+        *self.tuning_modified.lock().unwrap() = std::fs::metadata(location)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        let obj = self.obj();
+        let dir = location.parent().unwrap_or(std::path::Path::new(""));
+        let values = tuning::load(location).and_then(|entries| {
+            entries
+                .into_iter()
+                .map(|(name, value)| {
+                    let pspec = self
+                        .tuning_pspec(&name)
+                        .map_err(|err| format!("{path}: {err}"))?;
+                    let value = tuning::property_value(&pspec, &value, dir)
+                        .map_err(|err| format!("{path}: {err}"))?;
+                    Ok((name, value))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        });
+
+        let s = match values {
+            Ok(values) => {
+                self.set_tuning(&values);
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Applied {} properties from {}",
+                    values.len(),
+                    path
+                );
+                gst::Structure::builder("tuning-file-loaded")
+                    .field("location", path)
+                    .field("properties", values.len() as u32)
+                    .build()
+            }
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Not applying tuning file: {}", err);
+                gst::Structure::builder("tuning-file-error")
+                    .field("location", path)
+                    .field("error", err)
+                    .build()
+            }
         };
+        let _ = obj.post_message(gst::message::Element::builder(s).src(&*obj).build());
+    }
 
-        let Ok(height) = structure.get::<i32>("height") else {
-            gst::warning!(CAT, "get_unit_size: no height in caps");
-            return glib::ffi::GFALSE;
+    fn tuning_pspec(&self, name: &str) -> Result<glib::ParamSpec, String> {
+        preset::PROPERTIES
+            .contains(&name)
+            .then(|| self.obj().find_property(name))
+            .flatten()
+            .ok_or_else(|| format!("unknown tuning property {name}"))
+    }
+
+    // Sets tuning properties between two frames: transform() takes its snapshot of the
+    // settings with the tuning lock held.
+    fn set_tuning(&self, values: &[(String, glib::Value)]) {
+        let obj = self.obj();
+        let _loading = self.tuning_lock.lock().unwrap();
+        for (name, value) in values {
+            obj.set_property_from_value(name, value);
+        }
+    }
+
+    // Applies every field of an isp-params structure, or nothing if any is invalid.
+    fn set_isp_params(&self, params: &gst::StructureRef) {
+        let values = params
+            .iter()
+            .map(|(name, value)| {
+                let pspec = self.tuning_pspec(name)?;
+                // NULL unsets a calibration file.
+                if pspec.value_type() == glib::Type::STRING
+                    && matches!(value.get::<Option<&str>>(), Ok(None))
+                {
+                    return Ok((name.to_string(), glib::Value::clone(value)));
+                }
+                let value = tuning::Value::from_value(value)
+                    .ok_or_else(|| format!("invalid value type {} for {name}", value.type_()))?;
+                let value = tuning::property_value(&pspec, &value, std::path::Path::new(""))?;
+                Ok((name.to_string(), value))
+            })
+            .collect::<Result<Vec<_>, String>>();
+
+        match values {
+            Ok(values) => {
+                gst::debug!(CAT, imp = self, "Applying ISP parameters {}", params);
+                self.set_tuning(&values);
+            }
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Not applying ISP parameters: {}", err);
+            }
+        }
+    }
+
+    // Every tuning property, unset calibration files as NULL.
+    fn isp_params(&self) -> gst::Structure {
+        let obj = self.obj();
+        let _loading = self.tuning_lock.lock().unwrap();
+        let settings = self.settings.lock().unwrap();
+        let mut params = gst::Structure::new_empty("isp-params");
+        for name in preset::PROPERTIES {
+            if obj.find_property(name).is_some() {
+                params.set_value(name, send_value(self.settings_property(&settings, name)));
+            }
+        }
+        params
+    }
+
+    // Loads the tuning file again if it changed, while watch-tuning-file is set.
+    fn watch_tuning_file(&self) {
+        let path = {
+            let settings = self.settings.lock().unwrap();
+            match (&settings.tuning_file, settings.watch_tuning_file) {
+                (Some(path), true) => path.clone(),
+                _ => return,
+            }
+        };
+
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_some() && modified != *self.tuning_modified.lock().unwrap() {
+            gst::info!(CAT, imp = self, "Tuning file {} changed", path);
+            self.load_tuning_file(&path);
+        }
+    }
+
+    // Makes quad bayer input a bayer mosaic and weaves interleaved fields, into
+    // `quad_samples` and `woven`, before anything else sees the input. Returns the
+    // mosaic and its stride in samples.
+    fn mosaic<'a, S: raw::Sample>(
+        &self,
+        in_data: &'a [S],
+        in_stride: usize,
+        in_info: &InputInfo,
+        fields: Option<FieldMode>,
+        quad_samples: &'a mut Vec<S>,
+        woven: &'a mut Vec<S>,
+    ) -> Result<(&'a [S], usize), gst::FlowError> {
+        let (in_data, in_stride) = match in_info.quad {
+            Some(mode) => {
+                let convert = match mode {
+                    QuadMode::Binning => quad::bin,
+                    QuadMode::Remosaic => quad::remosaic,
+                };
+                convert(
+                    in_data,
+                    in_stride,
+                    in_info.width,
+                    in_info.height,
+                    quad_samples,
+                )
+                .map_err(|err| {
+                    gst::error!(
+                        CAT,
+                        imp = self,
+                        "Failed to convert quad bayer input: {}",
+                        err
+                    );
+                    gst::FlowError::Error
+                })?;
+                (quad_samples.as_slice(), in_info.width)
+            }
+            None => (in_data, in_stride),
+        };
+
+        match fields {
+            Some(FieldMode::Weave) => {
+                fields::weave(in_data, in_stride, in_info.width, in_info.height, woven).map_err(
+                    |err| {
+                        gst::error!(CAT, imp = self, "Failed to weave fields: {}", err);
+                        gst::FlowError::Error
+                    },
+                )?;
+                Ok((woven.as_slice(), in_info.width))
+            }
+            _ => Ok((in_data, in_stride)),
+        }
+    }
+
+    fn convert(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        wide: Option<WideInput>,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Result<(), gst::FlowError> {
+        // Rotations by 90 degrees change the output size, so wait for the renegotiation
+        // set_property() asked for.
+        let orientation = orient::Transform::for_direction(settings.direction());
+        let set_direction = orientation.transpose == state.orientation.transpose;
+        if set_direction {
+            state.orientation = orientation;
+        }
+        // Separately converted fields go without calibration.
+        if state.field_converter.is_none()
+            && !same_dark_frame(&state.dark_frame, &settings.dark_frame)
+        {
+            let window = dark_window(settings.dark_frame.as_deref(), &state.in_info, state.active)
+                .unwrap_or_else(|err| {
+                    gst::warning!(CAT, imp = self, "Not subtracting dark frame: {}", err);
+                    None
+                });
+            state.converter.set_dark_frame(window);
+            state.dark_frame = settings.dark_frame.clone();
+            // Set up again with its part of the new dark frame.
+            state.process_roi = None;
+        }
+        self.update_process_roi(state, settings);
+        let auto_defects = settings.auto_defect_correction.then_some(AutoDefects {
+            threshold: settings.defect_threshold,
+            interval: settings.defect_detection_interval,
+        });
+        state.black_level = self.optical_black(in_data, in_stride, &state.in_info, settings);
+        let black_level = state.black_level.unwrap_or_default();
+        if settings.fpn_correction == FpnCorrection::OpticalBlack && state.field_converter.is_none()
+        {
+            self.estimate_fpn(in_data, in_stride, state, black_level, settings);
+        }
+        let gains = self.white_balance(in_data, in_stride, &state.in_info, black_level, settings);
+
+        let roi_converter = state
+            .process_roi
+            .as_mut()
+            .and_then(|process_roi| process_roi.converter.as_mut())
+            .map(|(_, converter)| converter);
+        let converters = std::iter::once(&mut state.converter)
+            .chain(&mut state.field_converter)
+            .chain(roi_converter);
+        for converter in converters {
+            // The backend property is mutable while playing, so switch at the next
+            // buffer.
+            converter.set_backend(settings.backend).map_err(|err| {
+                gst::error!(CAT, imp = self, "Failed to switch backend: {}", err);
+                gst::FlowError::NotNegotiated
+            })?;
+            converter.set_algorithm(settings.demosaic_algorithm);
+            // Switching to or from cropping waits for the renegotiation like rotations.
+            if (settings.border_mode == BorderMode::Crop)
+                == (converter.border_mode() == BorderMode::Crop)
+            {
+                converter
+                    .set_border_mode(settings.border_mode)
+                    .map_err(|err| {
+                        gst::error!(CAT, imp = self, "Failed to set border mode: {}", err);
+                        gst::FlowError::NotNegotiated
+                    })?;
+            }
+            if set_direction {
+                converter.set_direction(settings.direction());
+            }
+            converter.set_auto_defects(auto_defects);
+            converter.set_black_level(black_level);
+            converter.set_gains(gains);
+            converter.set_exposure_gain(settings.exposure_gain);
+            converter.set_highlight_mode(settings.highlight_mode);
+            converter.set_gamma(settings.gamma);
+            converter.set_tone_curve(settings.tone_lut.as_deref());
+            converter.set_brightness_contrast(settings.brightness, settings.contrast);
+            converter.set_saturation(settings.saturation);
+            converter.set_false_color_suppression(settings.false_color_suppression);
+            converter.set_decompanding(settings.decompanding.as_ref().map(|(_, lut)| &lut[..]));
+            converter.set_high_precision(settings.high_precision, settings.dither);
+            converter.set_tone_mapping(
+                settings.tone_mapping,
+                settings.black_point,
+                settings.white_point,
+            );
+            converter.set_auto_levels(settings.auto_levels.then_some(AutoLevels {
+                low: settings.auto_levels_low,
+                high: settings.auto_levels_high,
+                frames: settings.auto_levels_frames,
+                lock: settings.levels_lock,
+            }));
+            converter.set_zebra(settings.show_zebra.then_some(settings.zebra_threshold));
+            #[cfg(feature = "opencv")]
+            converter.set_opencv_options(settings.opencv.clone());
+        }
+        self.publish(state);
+
+        if let Some(wide) = wide {
+            return self.convert_p010(wide, out_frame, state);
+        }
+
+        let out_stride = out_frame.plane_stride()[0] as usize;
+        let out_data = out_frame
+            .plane_data_mut(0)
+            .map_err(|_| gst::FlowError::Error)?;
+
+        // Scaled output is converted at its full size into a frame of its own first.
+        #[cfg(feature = "opencv")]
+        if let Some(mut scaler) = state.scaler.take() {
+            let stride = scaler.info.stride()[0] as usize;
+            let result = self
+                .convert_frame(
+                    in_data,
+                    in_stride,
+                    &mut scaler.frame,
+                    stride,
+                    Some(&scaler.info),
+                    state,
+                    settings,
+                )
+                .and_then(|()| {
+                    scaler
+                        .resize(
+                            out_data,
+                            out_stride,
+                            state.out_info.width() as usize,
+                            state.out_info.height() as usize,
+                            settings.opencv.scale_method,
+                        )
+                        .map_err(|err| {
+                            gst::error!(CAT, imp = self, "{}", err);
+                            gst::FlowError::Error
+                        })
+                });
+            state.scaler = Some(scaler);
+            return result;
+        }
+
+        self.convert_frame(
+            in_data, in_stride, out_data, out_stride, None, state, settings,
+        )
+    }
+
+    // Converts the widened input into the 16-bit RGB of the converter, then writes
+    // that as the two planes of a P010 frame.
+    fn convert_p010(
+        &self,
+        wide: WideInput,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        state: &mut State,
+    ) -> Result<(), gst::FlowError> {
+        let (width, height) = (
+            state.out_info.width() as usize,
+            state.out_info.height() as usize,
+        );
+        let layout = OutputLayout::for_format(gst_video::VideoFormat::Rgb)
+            .ok_or(gst::FlowError::NotNegotiated)?;
+        let stride = width * layout.pixel_stride;
+        wide.rgb.resize(stride * height, 0);
+        let active = wide
+            .data
+            .get(state.active.y * wide.stride + state.active.x..)
+            .unwrap_or_default();
+        state
+            .converter
+            .convert_wide(active, wide.stride, wide.rgb, stride)?;
+
+        let colorimetry = state.out_info.colorimetry();
+        let (y_stride, uv_stride) = match out_frame.plane_stride() {
+            [y, uv, ..] => (*y as usize, *uv as usize),
+            _ => return Err(gst::FlowError::NotNegotiated),
+        };
+        let [y, uv, ..] = out_frame.planes_data_mut();
+        yuv::write_p010(
+            wide.rgb,
+            stride,
+            width,
+            height,
+            layout,
+            yuv::Matrix::for_colorimetry(colorimetry.matrix()),
+            colorimetry.range() == gst_video::VideoColorRange::Range0_255,
+            y,
+            y_stride,
+            uv,
+            uv_stride,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to write P010 frame: {}", err);
+            gst::FlowError::Error
+        })
+    }
+
+    // Converts a frame into `out_data`, which is laid out like `converted`, or like the
+    // output without it.
+    #[allow(clippy::too_many_arguments)]
+    fn convert_frame(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        out_data: &mut [u8],
+        out_stride: usize,
+        converted: Option<&gst_video::VideoInfo>,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Result<(), gst::FlowError> {
+        let active = &in_data[(state.active.y * in_stride + state.active.x).min(in_data.len())..];
+        if let Some((window, converter)) = state
+            .process_roi
+            .as_mut()
+            .and_then(|process_roi| process_roi.converter.as_mut())
+        {
+            return self.convert_process_roi(
+                active,
+                in_stride,
+                *window,
+                converter,
+                out_data,
+                out_stride,
+                converted.unwrap_or(&state.out_info),
+                state.orientation,
+                &mut state.background,
+                settings,
+            );
+        }
+        let Some(field_converter) = &mut state.field_converter else {
+            return state
+                .converter
+                .convert(active, in_stride, out_data, out_stride);
+        };
+
+        // Each field is every other row of the input and goes to every other row of
+        // the output, the top field to the odd rows if flipped upside down.
+        let (top, bottom) = match state.orientation.flip_y {
+            true => (out_stride, 0),
+            false => (0, out_stride),
         };
+        let bottom_input = active.get(in_stride..).unwrap_or_default();
+        let top_output = out_data.get_mut(top..).ok_or(gst::FlowError::Error)?;
+        state
+            .converter
+            .convert(active, 2 * in_stride, top_output, 2 * out_stride)?;
+        let bottom_output = out_data.get_mut(bottom..).ok_or(gst::FlowError::Error)?;
+        field_converter.convert(bottom_input, 2 * in_stride, bottom_output, 2 * out_stride)
+    }
+
+    // Sets up the conversion of the process ROI whenever the process-roi properties
+    // change.
+    fn update_process_roi(&self, state: &mut State, settings: &Settings) {
+        let roi = settings.process_roi;
+        // Separately converted fields and P010 output always convert the whole frame.
+        if roi.is_full_frame()
+            || state.field_converter.is_some()
+            || state.out_info.format() == yuv::FORMAT
+        {
+            state.process_roi = None;
+            state.background.clear();
+            return;
+        }
+        if state
+            .process_roi
+            .as_ref()
+            .map(|process_roi| process_roi.roi)
+            == Some(roi)
+        {
+            return;
+        }
+
+        let converter = match process_window(roi, &state.in_info, state.active) {
+            Some(window) => match self.new_roi_converter(state, window, settings) {
+                Ok(converter) => {
+                    gst::info!(CAT, imp = self, "Converting {:?} of the input", window);
+                    Some((window, converter))
+                }
+                Err(err) => {
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Converting the whole frame, can't convert ROI {:?}: {}",
+                        roi,
+                        err
+                    );
+                    None
+                }
+            },
+            None => {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Process ROI {:?} is outside the {:?} window of the {}x{} input, \
+                     converting the whole frame",
+                    roi,
+                    state.active,
+                    state.in_info.width,
+                    state.in_info.height
+                );
+                None
+            }
+        };
+        state.process_roi = Some(ProcessRoi { roi, converter });
+    }
 
-        let width = width as usize;
-        let height = height as usize;
-        let result = match structure.name().as_str() {
-            "video/x-bayer" => {
-                *size = 1 * height * width;
-                glib::ffi::GTRUE
+    // Converter of `window` of the active input, with the calibration of that part.
+    fn new_roi_converter(
+        &self,
+        state: &State,
+        window: Rect,
+        settings: &Settings,
+    ) -> Result<Converter, String> {
+        let window_in_input = Rect {
+            x: state.active.x + window.x,
+            y: state.active.y + window.y,
+            ..window
+        };
+        let in_info = &state.in_info;
+        let mut converter = state.converter.for_window(
+            in_info.pattern.offset(window_in_input.x, window_in_input.y),
+            window.width,
+            window.height,
+        )?;
+        {
+            let calibration = self.calibration.lock().unwrap();
+            converter.set_shading(calibration.shading(in_info, window_in_input)?);
+            converter.set_defects(calibration.defects(in_info, window_in_input)?);
+            converter.set_fpn(calibration.fpn(in_info, window_in_input)?);
+        }
+        converter.set_dark_frame(dark_window(
+            settings.dark_frame.as_deref(),
+            in_info,
+            window_in_input,
+        )?);
+
+        Ok(converter)
+    }
+
+    // Converts `window` of the `active` input into its part of the output and fills
+    // the rest of it, or leaves it from the previous frame with
+    // process-roi-keep-previous.
+    #[allow(clippy::too_many_arguments)]
+    fn convert_process_roi(
+        &self,
+        active: &[u8],
+        in_stride: usize,
+        window: Rect,
+        converter: &mut Converter,
+        out_data: &mut [u8],
+        out_stride: usize,
+        out_info: &gst_video::VideoInfo,
+        orientation: orient::Transform,
+        background: &mut Vec<u8>,
+        settings: &Settings,
+    ) -> Result<(), gst::FlowError> {
+        let layout =
+            OutputLayout::for_format(out_info.format()).ok_or(gst::FlowError::NotNegotiated)?;
+        let (width, height) = (out_info.width() as usize, out_info.height() as usize);
+
+        if settings.process_roi_keep_previous && background.len() == out_data.len() {
+            out_data.copy_from_slice(background);
+        } else {
+            fill(
+                out_data,
+                out_stride,
+                width,
+                height,
+                layout,
+                settings.process_roi_fill_color,
+            );
+        }
+
+        // Where the window lands before any flip or rotation, then once turned.
+        let scale = match converter.method() {
+            Method::Full => 1,
+            Method::Superpixel => 2,
+        };
+        let (unturned_width, unturned_height) = orientation.size(width, height);
+        // With border-mode=crop the window loses its border like the whole frame, which
+        // keeps its top-left corner where it is in the output.
+        let cropped = 2 * converter.cropped_border();
+        let region = orientation.rect(
+            Rect {
+                x: window.x / scale,
+                y: window.y / scale,
+                width: (window.width / scale).saturating_sub(cropped),
+                height: (window.height / scale).saturating_sub(cropped),
+            },
+            unturned_width,
+            unturned_height,
+        );
+
+        let input = active
+            .get(window.y * in_stride + window.x..)
+            .unwrap_or_default();
+        let output = out_data
+            .get_mut(region.y * out_stride + region.x * layout.pixel_stride..)
+            .ok_or(gst::FlowError::Error)?;
+        converter.convert(input, in_stride, output, out_stride)?;
+
+        background.clear();
+        if settings.process_roi_keep_previous {
+            background.extend_from_slice(out_data);
+        }
+
+        Ok(())
+    }
+
+    // Sets the fixed pattern noise offsets measured on the optical black margins of
+    // this frame on the converters, keeping the last ones if they can't be measured.
+    fn estimate_fpn(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        state: &mut State,
+        black_level: [u16; 4],
+        settings: &Settings,
+    ) {
+        let in_info = state.in_info;
+        let fpn = match state.fpn.update(
+            in_data,
+            in_stride,
+            in_info.width,
+            in_info.height,
+            in_info.pattern,
+            settings.ob_rows as usize,
+            settings.ob_cols as usize,
+            black_level,
+        ) {
+            Ok(fpn) => fpn,
+            Err(err) => {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Failed to measure fixed pattern noise: {}",
+                    err
+                );
+                return;
+            }
+        };
+
+        state.converter.set_fpn(Some(fpn.window(state.active)));
+        if let Some((window, converter)) = state
+            .process_roi
+            .as_mut()
+            .and_then(|process_roi| process_roi.converter.as_mut())
+        {
+            converter.set_fpn(Some(fpn.window(Rect {
+                x: state.active.x + window.x,
+                y: state.active.y + window.y,
+                ..*window
+            })));
+        }
+    }
+
+    // Black level of the optical black margins, if there are any.
+    fn optical_black(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        in_info: &InputInfo,
+        settings: &Settings,
+    ) -> Option<[u16; 4]> {
+        if settings.ob_rows == 0 && settings.ob_cols == 0 {
+            return None;
+        }
+
+        raw::optical_black(
+            in_data,
+            in_stride,
+            in_info.width,
+            in_info.height,
+            in_info.pattern,
+            settings.ob_rows as usize,
+            settings.ob_cols as usize,
+        )
+        .inspect_err(|err| {
+            gst::warning!(CAT, imp = self, "Failed to measure optical black: {}", err);
+        })
+        .ok()
+    }
+
+    // Gains for this frame according to awb-mode and gr-gb-balance, remembered as the
+    // applied gains.
+    fn white_balance(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        in_info: &InputInfo,
+        black_level: [u16; 4],
+        settings: &Settings,
+    ) -> Gains {
+        // Both automatic modes take the same means.
+        let means = (settings.awb_mode == AwbMode::GrayWorld
+            || settings.gr_gb_balance == GrGbBalance::Auto)
+            .then(|| {
+                let (window, roi) =
+                    measurement_window(in_data, in_stride, in_info, settings.stats_roi);
+                raw::channel_means(window, in_stride, roi.width, roi.height, in_info.pattern)
+                    .inspect_err(|err| {
+                        gst::warning!(CAT, imp = self, "Failed to measure the frame: {}", err);
+                    })
+                    .ok()
+            })
+            .flatten()
+            .map(|means| std::array::from_fn(|i| (means[i] - black_level[i] as f64).max(0.0)));
+
+        let mut applied = self.applied_gains.lock().unwrap();
+        let green_balance = applied.green_balance;
+        match settings.awb_mode {
+            AwbMode::Manual => *applied = settings.manual_gains(),
+            AwbMode::GrayWorld => {
+                if let Some(means) = means {
+                    *applied = awb::gray_world(means, *applied);
+                }
+            }
+            AwbMode::Locked => (),
+        }
+        applied.green_balance = match settings.gr_gb_balance {
+            GrGbBalance::Off => 1.0,
+            GrGbBalance::Manual => settings.gr_gb_ratio,
+            GrGbBalance::Auto => means.map_or(green_balance, |means| {
+                awb::green_balance(means, green_balance)
+            }),
+        };
+
+        *applied
+    }
+
+    // Warns when red/green/blue-gain override a color-temperature that was set too.
+    fn warn_gains_override(&self, settings: &Settings) {
+        if settings.color_temperature != 0 && settings.explicit_gains() {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Explicit gains override the color temperature of {} K",
+                settings.color_temperature
+            );
+        }
+    }
+
+    // White balance gains making the gray card in the window calibrate-wb asked for
+    // neutral, set in this frame's `settings` with AWB switched to manual. None unless
+    // a calibration is pending and succeeds.
+    fn calibrate_white_balance(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        in_info: &InputInfo,
+        settings: &mut Settings,
+    ) -> Option<Gains> {
+        let roi = self.wb_calibration.lock().unwrap().take()?;
+        let black_level = self
+            .optical_black(in_data, in_stride, in_info, settings)
+            .unwrap_or_default();
+        let (window, roi) = measurement_window(in_data, in_stride, in_info, roi);
+        let gains = raw::channel_means(window, in_stride, roi.width, roi.height, in_info.pattern)
+            .map_err(|err| err.to_string())
+            .and_then(|means| {
+                let means = std::array::from_fn(|i| (means[i] - black_level[i] as f64).max(0.0));
+                awb::gray_card(means, settings.gains)
+                    .ok_or_else(|| "no signal in every color".to_string())
+            });
+        let gains = match gains {
+            Ok(gains) => gains,
+            Err(err) => {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Failed to calibrate white balance: {}",
+                    err
+                );
+                return None;
+            }
+        };
+        gst::info!(CAT, imp = self, "Calibrated white balance to {:?}", gains);
+
+        settings.gains = gains;
+        settings.awb_mode = AwbMode::Manual;
+
+        Some(gains)
+    }
+
+    // Sets the gain properties to those of a white balance calibration and announces
+    // them.
+    fn wb_calibrated(&self, gains: Gains) {
+        {
+            let mut settings = self.settings.lock().unwrap();
+            settings.gains = gains;
+            settings.awb_mode = AwbMode::Manual;
+        }
+
+        let obj = self.obj();
+        for name in ["red-gain", "green-gain", "blue-gain", "awb-mode"] {
+            obj.notify(name);
+        }
+        obj.emit_by_name::<()>("wb-calibrated", &[&gains.red, &gains.green, &gains.blue]);
+
+        let s = gst::Structure::builder("rsbayer2rgb-wb-calibrated")
+            .field("red-gain", gains.red)
+            .field("green-gain", gains.green)
+            .field("blue-gain", gains.blue)
+            .build();
+        let _ = obj.post_message(gst::message::Element::builder(s).src(&*obj).build());
+    }
+
+    // Records the last sample and emits handoff for a buffer about to be pushed.
+    fn output_produced(&self, buffer: &gst::Buffer) {
+        let (emit_signals, enable_last_sample) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.emit_signals, settings.enable_last_sample)
+        };
+
+        if enable_last_sample {
+            let caps = self.obj().src_pad().current_caps();
+            let mut builder = gst::Sample::builder().buffer(buffer);
+            if let Some(ref caps) = caps {
+                builder = builder.caps(caps);
+            }
+            *self.last_sample.lock().unwrap() = Some(builder.build());
+        }
+
+        if emit_signals {
+            let info = self
+                .negotiated
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|negotiated| negotiated.out_info.clone());
+            if let Some(info) = info {
+                self.obj()
+                    .emit_by_name::<()>("handoff", &[buffer, &info]);
+            }
+        }
+    }
+
+    // Asynchronous counterpart of generate_output(): allocates the output buffer and
+    // leaves conversion and pushing to the worker.
+    fn queue_output(
+        &self,
+        worker: &Worker,
+        max_queue_buffers: usize,
+        leaky: Leaky,
+    ) -> Result<gst_base::subclass::base_transform::GenerateOutputSuccess, gst::FlowError> {
+        use gst_base::subclass::base_transform::{
+            GenerateOutputSuccess, InputBuffer, PrepareOutputBufferSuccess,
+        };
+
+        let Some(inbuf) = self.take_queued_buffer() else {
+            return Ok(GenerateOutputSuccess::NoOutput);
+        };
+        let outbuf = match self.parent_prepare_output_buffer(InputBuffer::Readable(&inbuf))? {
+            PrepareOutputBufferSuccess::Buffer(outbuf) => outbuf,
+            PrepareOutputBufferSuccess::InputBuffer => return Err(gst::FlowError::Error),
+        };
+
+        match worker.push(inbuf, outbuf, max_queue_buffers, leaky)? {
+            Queued::Fits => (),
+            Queued::DroppedOldest(dropped) => {
+                gst::debug!(CAT, imp = self, "Queue full, dropped {} old buffers", dropped);
+                for _ in 0..dropped {
+                    self.stats.frame_dropped();
+                }
+            }
+            Queued::DroppedNewest => {
+                gst::debug!(CAT, imp = self, "Queue full, dropping buffer");
+                self.stats.frame_dropped();
+            }
+        }
+
+        Ok(GenerateOutputSuccess::NoOutput)
+    }
+
+    // Converts a buffer list as a batch and pushes the converted frames on as one list
+    // in the same order, so downstream sees the bursts upstream produced. Every buffer
+    // goes through submit_input_buffer() and generate_output() as in the base class'
+    // chain function, frames converted before an error are still pushed.
+    fn sink_chain_list(&self, list: gst::BufferList) -> Result<gst::FlowSuccess, gst::FlowError> {
+        use gst_base::subclass::base_transform::GenerateOutputSuccess;
+
+        let len = list.len();
+        let mut outputs = gst::BufferList::new_sized(len);
+        let mut res = Ok(gst::FlowSuccess::Ok);
+        'list: for inbuf in list.iter_owned() {
+            let discont = inbuf.flags().contains(gst::BufferFlags::DISCONT);
+            match self.submit_input_buffer(discont, inbuf) {
+                Ok(gst::FlowSuccess::Ok) => (),
+                Ok(_) => continue,
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+            loop {
+                match self.generate_output() {
+                    Ok(GenerateOutputSuccess::Buffer(outbuf)) => {
+                        outputs.get_mut().unwrap().add(outbuf)
+                    }
+                    Ok(_) => break,
+                    Err(err) => {
+                        res = Err(err);
+                        break 'list;
+                    }
+                }
+            }
+        }
+
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Converted a list of {} buffers into {}",
+            len,
+            outputs.len()
+        );
+        if !outputs.is_empty() {
+            self.obj().src_pad().push_list(outputs)?;
+        }
+        res
+    }
+
+    // Adds `roi` to the output with its rectangle in output pixels, unless none of it
+    // is converted.
+    fn copy_roi(&self, outbuf: &mut gst::BufferRef, roi: &gst_video::VideoRegionOfInterestMeta) {
+        // Called before the frame converts, on the upstream thread while
+        // max-queue-buffers is non-zero.
+        let Some(negotiated) = self.negotiated.lock().unwrap().clone() else {
+            return;
+        };
+
+        // ROIs refer to the whole input, skipped margins included and before
+        // binning.
+        let (x, y, width, height) = roi.rect();
+        let (left, top) = (negotiated.in_info.left, negotiated.in_info.top);
+        let scale = match negotiated.in_info.quad {
+            Some(QuadMode::Binning) => 2,
+            _ => 1,
+        };
+        let (right, bottom) = (x as usize + width as usize, y as usize + height as usize);
+        let x = (x as usize).saturating_sub(left) / scale;
+        let y = (y as usize).saturating_sub(top) / scale;
+        let right = right.saturating_sub(left).div_ceil(scale);
+        let bottom = bottom.saturating_sub(top).div_ceil(scale);
+        let rect = Rect {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        };
+        let Some(rect) = output_region(&negotiated, rect) else {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Dropping {} ROI outside the output",
+                roi.roi_type()
+            );
+            return;
+        };
+
+        let mut out = gst_video::VideoRegionOfInterestMeta::add(
+            outbuf,
+            roi.roi_type(),
+            (
+                rect.x as u32,
+                rect.y as u32,
+                rect.width as u32,
+                rect.height as u32,
+            ),
+        );
+        out.set_id(roi.id());
+        out.set_parent_id(roi.parent_id());
+        for param in roi.params() {
+            out.add_param(param.to_owned());
+        }
+    }
+
+    // Hands the frame to the dump writer if dump-interval selects it.
+    #[cfg(feature = "dump")]
+    fn dump(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        out_frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        state: &State,
+        pts: Option<gst::ClockTime>,
+        settings: &Settings,
+    ) {
+        let index = self.stats.frames_processed.load(Ordering::Relaxed);
+        let Some(location) = &settings.dump_location else {
+            return;
+        };
+        if settings.dump_interval == 0 || index % settings.dump_interval as u64 != 0 {
+            return;
+        }
+
+        let mut images = Vec::with_capacity(2);
+        let output = OutputLayout::for_format(state.out_info.format()).and_then(|layout| {
+            dump::Image::output(
+                out_frame.plane_data(0).ok()?,
+                out_frame.plane_stride()[0] as usize,
+                out_frame.width() as usize,
+                out_frame.height() as usize,
+                layout,
+            )
+        });
+        images.extend(output);
+        if settings.dump_raw {
+            let in_info = &state.in_info;
+            images.extend(dump::Image::raw(
+                in_data,
+                in_stride,
+                in_info.width,
+                in_info.height,
+            ));
+        }
+
+        let mut writer = self.dump_writer.lock().unwrap();
+        if writer.is_none() {
+            let name = format!("{}:dump", self.obj().name());
+            match dump::Writer::new(name) {
+                Ok(new) => *writer = Some(new),
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to start dump thread: {}", err);
+                    return;
+                }
+            }
+        }
+        let writer = writer.as_ref().unwrap();
+
+        let dir = dump::directory(location, &self.obj().name());
+        for image in images {
+            let path = dir.join(dump::file_name(index, pts, &image));
+            if !writer.write(path, image) {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Dump writer is behind, skipping frame {}",
+                    index
+                );
+            }
+        }
+    }
+
+    // Runs on the worker thread for every queued buffer.
+    fn finish_queued(
+        &self,
+        inbuf: gst::Buffer,
+        mut outbuf: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.transform(&inbuf, outbuf.make_mut())?;
+        self.output_produced(&outbuf);
+        self.obj().src_pad().push(outbuf)
+    }
+
+    fn report_timing(&self, timing: &mut FrameTiming, interval: u32) {
+        if interval == 0
+            || timing.since.elapsed() < std::time::Duration::from_secs(interval as u64)
+        {
+            return;
+        }
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Converted {} frames in the last {:?}: min {:?}, avg {:?}, max {:?}",
+            timing.frames,
+            timing.since.elapsed(),
+            timing.min,
+            timing.total / timing.frames.max(1) as u32,
+            timing.max
+        );
+
+        *timing = FrameTiming::new();
+    }
+
+    // Builds the `bayer-stats` element message of post-stats for one input frame.
+    fn bayer_stats_message(
+        &self,
+        in_data: &[u8],
+        in_stride: usize,
+        in_info: &InputInfo,
+        pts: Option<gst::ClockTime>,
+        settings: &Settings,
+    ) -> Option<gst::Message> {
+        let (window, roi) = measurement_window(in_data, in_stride, in_info, settings.stats_roi);
+        let channels = stats::measure(
+            window,
+            in_stride,
+            roi.width,
+            roi.height,
+            in_info.pattern,
+            settings.stats_subsampling as usize,
+        )
+        .inspect_err(|err| {
+            gst::warning!(CAT, imp = self, "Failed to measure the frame: {}", err);
+        })
+        .ok()?;
+
+        let mut builder = gst::Structure::builder("bayer-stats").field_if_some("pts", pts);
+        for color in CfaColor::ALL {
+            let channel = &channels[color as usize];
+            let s = gst::Structure::builder(color.nick())
+                .field("mean", channel.mean)
+                .field("min", channel.min)
+                .field("max", channel.max)
+                .field("histogram", gst::Array::new(channel.histogram))
+                .build();
+            builder = builder.field(color.nick(), s);
+        }
+
+        Some(gst::message::Element::builder(builder.build()).src(&*self.obj()).build())
+    }
+
+    // Publishes what other threads need to know of `state`, if it changed.
+    fn publish(&self, state: &State) {
+        let negotiated = Negotiated::of(state);
+        let mut published = self.negotiated.lock().unwrap();
+        if published.as_deref() != Some(&negotiated) {
+            *published = Some(std::sync::Arc::new(negotiated));
+        }
+    }
+
+    // Draws the text of show-debug-overlay over the converted frame.
+    #[cfg(feature = "opencv")]
+    fn draw_overlay(
+        &self,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        state: &mut State,
+        conversion_time: std::time::Duration,
+        settings: &Settings,
+    ) {
+        let status = overlay::Status {
+            pattern: state.in_info.bayer.pattern,
+            width: state.in_info.bayer.width,
+            height: state.in_info.bayer.height,
+            fps: state.out_info.fps(),
+            conversion_time,
+        };
+        // P010 output goes without.
+        let Some(layout) = OutputLayout::for_format(out_frame.format()) else {
+            return;
+        };
+        let channels = layout.pixel_stride;
+        let (width, height) = (out_frame.width() as usize, out_frame.height() as usize);
+        let stride = out_frame.plane_stride()[0] as usize;
+        let drawn = out_frame
+            .plane_data_mut(0)
+            .map_err(|err| err.to_string())
+            .and_then(|data| {
+                state.overlay.draw(
+                    data,
+                    stride,
+                    width,
+                    height,
+                    channels,
+                    settings.opencv.overlay_corner,
+                    &status,
+                )
+            });
+        if let Err(err) = drawn {
+            gst::warning!(CAT, imp = self, "Not drawing the debug overlay: {}", err);
+        }
+    }
+
+    // The gain bringing `inbuf` to the brightness of reference-exposure, 1.0 without an
+    // exposure meta, or with one of no exposure.
+    fn exposure_compensation(&self, inbuf: &gst::BufferRef, settings: &Settings) -> f64 {
+        let Some(meta) = inbuf.meta::<RsBayerExposureMeta>() else {
+            gst::trace!(CAT, imp = self, "No exposure meta, not compensating");
+            return 1.0;
+        };
+        let total_exposure = meta.total_exposure();
+        if total_exposure.is_nan() || total_exposure <= 0.0 {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Ignoring exposure meta of no exposure, {:?}",
+                meta
+            );
+            return 1.0;
+        }
+
+        let gain = settings.reference_exposure as f64 / total_exposure;
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Compensating {:?} with a gain of {}",
+            meta,
+            gain
+        );
+        gain
+    }
+
+    // Builds the `embedded-data` element message of post-embedded-data from the lines
+    // skipped at the top of a frame, rows `in_stride` bytes apart in `data`.
+    fn embedded_data_message(
+        &self,
+        data: &[u8],
+        in_stride: usize,
+        in_info: &InputInfo,
+        pts: Option<gst::ClockTime>,
+    ) -> Option<gst::Message> {
+        // The full width of the input, skipped columns included.
+        let width = in_info.bayer.width;
+        let mut lines = Vec::with_capacity(width * in_info.top);
+        for y in 0..in_info.top {
+            lines.extend_from_slice(data.get(y * in_stride..y * in_stride + width)?);
+        }
+
+        let s = gst::Structure::builder("embedded-data")
+            .field_if_some("pts", pts)
+            .field("width", width as u32)
+            .field("lines", in_info.top as u32)
+            .field("data", gst::Buffer::from_mut_slice(lines))
+            .build();
+        Some(gst::message::Element::builder(s).src(&*self.obj()).build())
+    }
+
+    // Builds the `focus-metric` element message of post-focus-metric for one output
+    // frame.
+    fn focus_message(
+        &self,
+        out_frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        state: &State,
+        pts: Option<gst::ClockTime>,
+        settings: &Settings,
+    ) -> Option<gst::Message> {
+        let layout = OutputLayout::for_format(state.out_info.format())?;
+        let out_stride = out_frame.plane_stride()[0] as usize;
+        let window = output_window(state, settings.stats_roi);
+        let offset = window.y * out_stride + window.x * layout.pixel_stride;
+        let data = out_frame.plane_data(0).ok()?.get(offset..)?;
+
+        let focus = focus::variance_of_laplacian(
+            data,
+            out_stride,
+            window.width,
+            window.height,
+            layout,
+            settings.stats_subsampling as usize,
+        )
+        .inspect_err(|err| {
+            gst::warning!(CAT, imp = self, "Failed to measure focus: {}", err);
+        })
+        .ok()?;
+
+        let s = gst::Structure::builder("focus-metric")
+            .field_if_some("pts", pts)
+            .field("focus", focus)
+            .build();
+        Some(gst::message::Element::builder(s).src(&*self.obj()).build())
+    }
+
+    // Keeps the direction of the `image-orientation` tag in `event` for auto-orient and
+    // video-direction=auto. While either follows it, the event is returned without the
+    // tag so downstream doesn't turn the frames once more. Other events are returned as
+    // they are.
+    fn orientation_tag(&self, event: gst::Event) -> gst::Event {
+        let gst::EventView::Tag(tag) = event.view() else {
+            return event;
+        };
+        let Some(orientation) = tag.tag().get::<gst::tags::ImageOrientation>() else {
+            return event;
+        };
+        let Some(direction) = orient::direction_from_tag(orientation.get()) else {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Ignoring unknown image orientation {}",
+                orientation.get()
+            );
+            return event;
+        };
+
+        let mut settings = self.settings.lock().unwrap();
+        let previous = settings.direction();
+        settings.tag_direction = direction;
+        let follows = settings.auto_orient
+            || settings.video_direction == gst_video::VideoOrientationMethod::Auto;
+        let renegotiate = transposes(previous) != transposes(settings.direction());
+        drop(settings);
+        if !follows {
+            return event;
+        }
+
+        gst::info!(
+            CAT,
+            imp = self,
+            "Image orientation {} from tags",
+            orientation.get()
+        );
+        // The event is serialized, so the frames after it already see the new size.
+        if renegotiate {
+            self.obj().reconfigure_src();
+        }
+
+        let mut tags = tag.tag_owned();
+        tags.make_mut().remove::<gst::tags::ImageOrientation>();
+        gst::event::Tag::builder(tags)
+            .seqnum(event.seqnum())
+            .build()
+    }
+
+    // Builds the `rsbayer2rgb-stats` element message once `interval` seconds have passed
+    // since the previous one, or right away if `now`. Posting is left to the caller so it
+    // happens unlocked.
+    fn stats_message(&self, state: &mut State, interval: u32, now: bool) -> Option<gst::Message> {
+        if !now
+            && (interval == 0
+                || state.stats_timing.since.elapsed()
+                    < std::time::Duration::from_secs(interval as u64))
+        {
+            return None;
+        }
+
+        let timing = &state.stats_timing;
+        let micros = |d: std::time::Duration| d.as_secs_f64() * 1_000_000.0;
+        let s = gst::Structure::builder("rsbayer2rgb-stats")
+            .field(
+                "frames-processed",
+                self.stats.frames_processed.load(Ordering::Relaxed),
+            )
+            .field(
+                "frames-dropped",
+                self.stats.frames_dropped.load(Ordering::Relaxed),
+            )
+            .field("min-conversion-time", micros(timing.min.min(timing.max)))
+            .field(
+                "avg-conversion-time",
+                micros(timing.total / timing.frames.max(1) as u32),
+            )
+            .field("max-conversion-time", micros(timing.max))
+            .field("input-format", state.in_info.pattern.to_caps_format())
+            .field("output-format", state.out_info.format().to_str())
+            .field("corrected-defects", state.converter.corrected_defects() as u64)
+            .field("quality-level", self.quality.lock().unwrap().level())
+            .field_if_some(
+                "black-level",
+                state.black_level.map(|black_level| {
+                    let mut builder = gst::Structure::builder("black-level");
+                    for color in CfaColor::ALL {
+                        builder = builder.field(color.nick(), black_level[color as usize] as u32);
+                    }
+                    builder.build()
+                }),
+            )
+            .build();
+
+        state.stats_timing = FrameTiming::new();
+
+        Some(gst::message::Element::builder(s).src(&*self.obj()).build())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsBayer2Rgb {
+    const NAME: &'static str = "GstRsBayer2Rgb";
+    type Type = super::RsBayer2Rgb;
+    type ParentType = gst_base::BaseTransform;
+
+    fn type_init(_type: &mut glib::subclass::InitializingType<Self>) {
+        // gstreamer-rs adds GstPreset with its default functions only, which save the
+        // controllable properties and know no built-in presets.
+        let info = glib::gobject_ffi::GInterfaceInfo {
+            interface_init: Some(preset_init),
+            interface_finalize: None,
+            interface_data: std::ptr::null_mut(),
+        };
+        unsafe {
+            glib::gobject_ffi::g_type_add_interface_static(
+                glib::translate::IntoGlib::into_glib(Self::type_()),
+                glib::translate::IntoGlib::into_glib(gst::Preset::static_type()),
+                &info,
+            );
+        }
+    }
+}
+
+// The default GstPreset functions replaced by the element's own.
+struct ParentPreset {
+    get_preset_names: unsafe extern "C" fn(*mut gst_sys::GstPreset) -> *mut *mut std::ffi::c_char,
+    load_preset: unsafe extern "C" fn(
+        *mut gst_sys::GstPreset,
+        *const std::ffi::c_char,
+    ) -> glib::ffi::gboolean,
+}
+
+static PARENT_PRESET: std::sync::OnceLock<ParentPreset> = std::sync::OnceLock::new();
+
+unsafe extern "C" fn preset_init(iface: glib::ffi::gpointer, _data: glib::ffi::gpointer) {
+    unsafe {
+        let iface = &mut *(iface as *mut gst_sys::GstPresetInterface);
+        let (Some(get_preset_names), Some(load_preset)) =
+            (iface.get_preset_names, iface.load_preset)
+        else {
+            return;
+        };
+        let _ = PARENT_PRESET.set(ParentPreset {
+            get_preset_names,
+            load_preset,
+        });
+
+        iface.get_preset_names = Some(get_preset_names_trampoline);
+        iface.get_property_names = Some(get_property_names_trampoline);
+        iface.load_preset = Some(load_preset_trampoline);
+    }
+}
+
+// Saved presets and the built-in ones.
+unsafe extern "C" fn get_preset_names_trampoline(
+    ptr: *mut gst_sys::GstPreset,
+) -> *mut *mut std::ffi::c_char {
+    use glib::translate::FromGlibPtrContainer;
+
+    unsafe {
+        let parent = PARENT_PRESET.get().expect("preset interface initialized");
+        let mut names = Vec::<String>::from_glib_full((parent.get_preset_names)(ptr));
+        names.extend(preset::BUILT_IN.map(|(name, _)| name.to_string()));
+        names.sort();
+        names.dedup();
+
+        glib::StrV::from(names).into_raw()
+    }
+}
+
+unsafe extern "C" fn get_property_names_trampoline(
+    ptr: *mut gst_sys::GstPreset,
+) -> *mut *mut std::ffi::c_char {
+    unsafe {
+        let obj: glib::translate::Borrowed<glib::Object> =
+            glib::translate::from_glib_borrow(ptr as *mut glib::gobject_ffi::GObject);
+        let names = preset::PROPERTIES
+            .into_iter()
+            .filter(|name| obj.find_property(name).is_some())
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        glib::StrV::from(names).into_raw()
+    }
+}
+
+unsafe extern "C" fn load_preset_trampoline(
+    ptr: *mut gst_sys::GstPreset,
+    name: *const std::ffi::c_char,
+) -> glib::ffi::gboolean {
+    unsafe {
+        let obj: glib::translate::Borrowed<glib::Object> =
+            glib::translate::from_glib_borrow(ptr as *mut glib::gobject_ffi::GObject);
+        let Some(element) = obj.downcast_ref::<super::RsBayer2Rgb>() else {
+            return glib::ffi::GFALSE;
+        };
+        let parent = PARENT_PRESET.get().expect("preset interface initialized");
+        let loaded = element.imp().load_preset(glib::GStr::from_ptr(name), || {
+            (parent.load_preset)(ptr, name) != glib::ffi::GFALSE
+        });
+
+        glib::translate::IntoGlib::into_glib(loaded)
+    }
+}
+
+impl ObjectImpl for RsBayer2Rgb {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            #[allow(unused_mut)]
+            let mut properties = vec![
+                glib::ParamSpecUInt::builder("timing-report-interval")
+                    .nick("Timing Report Interval")
+                    .blurb("Seconds between DEBUG reports of conversion timing (0 = disabled)")
+                    .default_value(DEFAULT_TIMING_REPORT_INTERVAL)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stats-interval")
+                    .nick("Stats Interval")
+                    .blurb("Seconds between rsbayer2rgb-stats element messages (0 = disabled)")
+                    .default_value(DEFAULT_STATS_INTERVAL)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("emit-signals")
+                    .nick("Emit Signals")
+                    .blurb("Emit the handoff signal for every converted frame")
+                    .default_value(DEFAULT_EMIT_SIGNALS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("enable-last-sample")
+                    .nick("Enable Last Sample")
+                    .blurb("Keep a reference to the last converted frame in last-sample")
+                    .default_value(DEFAULT_ENABLE_LAST_SAMPLE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Sample>("last-sample")
+                    .nick("Last Sample")
+                    .blurb("The last converted frame with its caps")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt::builder("min-buffers")
+                    .nick("Min Buffers")
+                    .blurb("Minimum number of buffers in the output buffer pool")
+                    .default_value(DEFAULT_MIN_BUFFERS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-buffers")
+                    .nick("Max Buffers")
+                    .blurb("Maximum number of buffers in the output buffer pool (0 = unlimited)")
+                    .default_value(DEFAULT_MAX_BUFFERS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("output-alignment")
+                    .nick("Output Alignment")
+                    .blurb("Byte alignment of output rows, a power of two (needs downstream VideoMeta support)")
+                    .minimum(1)
+                    .maximum(4096)
+                    .default_value(DEFAULT_OUTPUT_ALIGNMENT)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:extra-meta-apis:
+                 *
+                 * Names of meta APIs, e.g. `GstVideoCropMetaAPI`, copied from the
+                 * input to the output buffers as they are. By default only metas
+                 * without tags, reference timestamp and timecode metas are copied,
+                 * and region of interest metas, whose rectangles are mapped to the
+                 * output through crop, superpixel and flip or rotation and dropped
+                 * if nothing of them is left.
+                 */
+                gst::ParamSpecArray::builder("extra-meta-apis")
+                    .nick("Extra Meta APIs")
+                    .blurb("Names of further meta APIs to copy to the output")
+                    .element_spec(
+                        &glib::ParamSpecString::builder("api")
+                            .nick("API")
+                            .blurb("Name of a meta API type")
+                            .build(),
+                    )
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:method:
+                 *
+                 * How output pixels are produced. `superpixel` turns every 2x2 CFA
+                 * quad into one pixel, so the output has half the input width and
+                 * height. Changing it while playing renegotiates the output caps.
+                 */
+                glib::ParamSpecEnum::builder_with_default("method", Method::default())
+                    .nick("Method")
+                    .blurb("Conversion method, superpixel halves the output resolution")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:video-direction:
+                 *
+                 * Flips or rotates the output, for cameras mounted upside down or
+                 * sideways, as one copy of the finished frame instead of a separate
+                 * videoflip pass. Rotations by 90 degrees swap the output width and
+                 * height and renegotiate. `auto` follows the image-orientation tags
+                 * of the stream like #GstRsBayer2Rgb:auto-orient, `custom` isn't
+                 * supported and leaves the output as it is.
+                 */
+                glib::ParamSpecEnum::builder_with_default(
+                    "video-direction",
+                    gst_video::VideoOrientationMethod::Identity,
+                )
+                .nick("Video Direction")
+                .blurb("Flip or rotation of the output")
+                .mutable_playing()
+                .build(),
+                /**
+                 * GstRsBayer2Rgb:auto-orient:
+                 *
+                 * Turn the output by the `image-orientation` tags of the stream,
+                 * which sources knowing how the camera is mounted send, unless
+                 * #GstRsBayer2Rgb:video-direction turns it already. The tag is taken
+                 * out of the tag events passed downstream, so the frames aren't
+                 * turned a second time. A tag rotating by 90 degrees where the last
+                 * one didn't renegotiates like the property.
+                 */
+                glib::ParamSpecBoolean::builder("auto-orient")
+                    .nick("Auto Orient")
+                    .blurb("Turn the output by the image-orientation tags of the stream")
+                    .default_value(DEFAULT_AUTO_ORIENT)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:demosaic-algorithm:
+                 *
+                 * Interpolation used for full resolution conversions. `vng` and
+                 * `edge-aware` avoid most of the zippering bilinear shows along
+                 * edges, at a cost: edge-aware runs a few times slower than bilinear
+                 * and VNG an order of magnitude slower, which rules it out for live
+                 * video at high resolutions. Both are only implemented by the OpenCV
+                 * backend on the CPU; the built-in backend and CUDA fall back to
+                 * bilinear with a warning.
+                 */
+                glib::ParamSpecEnum::builder_with_default(
+                    "demosaic-algorithm",
+                    DemosaicAlgorithm::default(),
+                )
+                .nick("Demosaic Algorithm")
+                .blurb("Interpolation used to reconstruct the missing colors")
+                .mutable_playing()
+                .build(),
+                /**
+                 * GstRsBayer2Rgb:processing-deadline:
+                 *
+                 * Conversion time in nanoseconds frames should stay within, 0 to
+                 * always convert as configured. While the average conversion time is
+                 * over it, the conversion steps down a #GstRsBayer2Rgb:quality-level
+                 * every few frames: `reduced` skips the optional steps,
+                 * #GstRsBayer2Rgb:false-color-suppression and the OpenCV denoise and
+                 * sharpen filters, and `minimal` demosaics bilinearly too. Once the
+                 * average has stayed under half of it for 60 frames it steps up
+                 * again. To keep up with a stream, set it below the frame duration,
+                 * 26666666 leaving a fifth of a 30 fps frame to the rest of the
+                 * pipeline.
+                 */
+                glib::ParamSpecUInt64::builder("processing-deadline")
+                    .nick("Processing Deadline")
+                    .blurb("Conversion time in ns to lower the quality to stay within (0 = off)")
+                    .default_value(DEFAULT_PROCESSING_DEADLINE)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:border-mode:
+                 *
+                 * How full resolution conversions treat the outermost two pixels of
+                 * the frame, which the demosaic interpolates without the neighbors on
+                 * one side and gets visibly wrong colors for. `replicate` pads the raw
+                 * frame by two samples of the same CFA color on every side before
+                 * demosaic and keeps the original window of the result, which costs a
+                 * copy of the frame on either side of the demosaic. `crop` drops the
+                 * border instead, making the output 4 pixels narrower and lower; it
+                 * renegotiates when switched to or from while playing and can't be
+                 * combined with `field-mode=separate`. Superpixel conversions don't
+                 * interpolate and ignore it.
+                 */
+                glib::ParamSpecEnum::builder_with_default("border-mode", BorderMode::default())
+                    .nick("Border Mode")
+                    .blurb("Handling of the frame border the demosaic lacks neighbors for")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:field-mode:
+                 *
+                 * How interlaced input, with `interlace-mode=interleaved` in the
+                 * caps, is made progressive. Each field of such input is a bayer
+                 * mosaic of its own, the bottom field with the pattern shifted by
+                 * one row. `weave` puts the fields together into a single mosaic
+                 * before demosaic, swapping the fields in every other pair of rows
+                 * to keep the CFA phase. `separate` demosaics the two fields on
+                 * their own and interleaves the results; it doesn't support
+                 * rotations by 90 degrees, needs a crop starting and ending on an
+                 * even row (a multiple of four rows with superpixel) and applies
+                 * no calibration files. The output is progressive either way.
+                 */
+                glib::ParamSpecEnum::builder_with_default("field-mode", FieldMode::default())
+                    .nick("Field Mode")
+                    .blurb("Weave interlaced fields before demosaic or demosaic them separately")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:red-gain:
+                 *
+                 * White balance gain of the red CFA sites, applied to the raw
+                 * samples before demosaic with saturation. Together with
+                 * #GstRsBayer2Rgb:green-gain and #GstRsBayer2Rgb:blue-gain it is
+                 * used while #GstRsBayer2Rgb:awb-mode is `manual`, and costs
+                 * nothing while all three are 1.0.
+                 */
+                glib::ParamSpecDouble::builder("red-gain")
+                    .nick("Red Gain")
+                    .blurb("White balance gain of the red samples")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("green-gain")
+                    .nick("Green Gain")
+                    .blurb("White balance gain of both green samples")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("blue-gain")
+                    .nick("Blue Gain")
+                    .blurb("White balance gain of the blue samples")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:color-temperature:
+                 *
+                 * Color temperature of the light in kelvin, from 2500 to 10000, for
+                 * which the red and blue gains of `awb-mode=manual` are computed, or
+                 * 0 to take them from #GstRsBayer2Rgb:red-gain and friends. The
+                 * gains make a black body of that temperature neutral, the raw
+                 * channels taken for sRGB primaries. Gains set explicitly override
+                 * the temperature. Controllable, so the balance can ramp smoothly.
+                 */
+                glib::ParamSpecUInt::builder("color-temperature")
+                    .nick("Color Temperature")
+                    .blurb("Color temperature in kelvin the manual gains are computed for, 0 off")
+                    .maximum(awb::TEMPERATURE_RANGE.1)
+                    .default_value(DEFAULT_COLOR_TEMPERATURE)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:tint:
+                 *
+                 * Green-magenta correction of #GstRsBayer2Rgb:color-temperature,
+                 * negative for a greener and positive for a more magenta output,
+                 * scaling the red and blue gains by up to a quarter.
+                 */
+                glib::ParamSpecDouble::builder("tint")
+                    .nick("Tint")
+                    .blurb("Green-magenta correction of the color temperature gains")
+                    .minimum(-1.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_TINT)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:awb-mode:
+                 *
+                 * Where the white balance gains come from. `gray-world` measures
+                 * the raw channel means of every frame and moves the gains towards
+                 * equalizing them, `locked` freezes the gains applied last, which
+                 * can be read from #GstRsBayer2Rgb:applied-red-gain and friends.
+                 */
+                glib::ParamSpecEnum::builder_with_default("awb-mode", AwbMode::default())
+                    .nick("AWB Mode")
+                    .blurb("Source of the white balance gains")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:gr-gb-balance:
+                 *
+                 * Correction of a sensitivity difference between the green sites
+                 * on red rows and those on blue rows, which demosaics into a fine
+                 * checkerboard maze. The blue row greens are scaled in the raw
+                 * samples before demosaic, by #GstRsBayer2Rgb:gr-gb-ratio with
+                 * `manual`, by the ratio of the means of both greens with `auto`.
+                 * The measured ratio is damped over the frames and, like gray world
+                 * white balance, taken within the stats ROI. The applied ratio can
+                 * be read from #GstRsBayer2Rgb:applied-gr-gb-ratio.
+                 */
+                glib::ParamSpecEnum::builder_with_default("gr-gb-balance", GrGbBalance::default())
+                    .nick("Gr/Gb Balance")
+                    .blurb("Correction of the sensitivity difference between both greens")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:gr-gb-ratio:
+                 *
+                 * Gain of the blue row greens with `gr-gb-balance=manual`, the mean
+                 * of the red row greens over that of the blue row greens on a
+                 * uniform scene.
+                 */
+                glib::ParamSpecDouble::builder("gr-gb-ratio")
+                    .nick("Gr/Gb Ratio")
+                    .blurb("Gain of the blue row greens relative to the red row greens")
+                    .minimum(awb::GREEN_BALANCE_RANGE.0)
+                    .maximum(awb::GREEN_BALANCE_RANGE.1)
+                    .default_value(DEFAULT_GR_GB_RATIO)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("applied-red-gain")
+                    .nick("Applied Red Gain")
+                    .blurb("Red gain used for the last frame")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("applied-green-gain")
+                    .nick("Applied Green Gain")
+                    .blurb("Green gain used for the last frame")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("applied-blue-gain")
+                    .nick("Applied Blue Gain")
+                    .blurb("Blue gain used for the last frame")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(1.0)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("applied-gr-gb-ratio")
+                    .nick("Applied Gr/Gb Ratio")
+                    .blurb("Gain of the blue row greens used for the last frame")
+                    .minimum(awb::GREEN_BALANCE_RANGE.0)
+                    .maximum(awb::GREEN_BALANCE_RANGE.1)
+                    .default_value(1.0)
+                    .read_only()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:exposure-gain:
+                 *
+                 * Digital gain applied to every bayer sample, for when the analog
+                 * gain of the sensor tops out. It is multiplied into the white
+                 * balance gains, so it costs nothing extra, and the product of both
+                 * saturates at 16. Controllable, so exposure loops can ramp it.
+                 */
+                glib::ParamSpecDouble::builder("exposure-gain")
+                    .nick("Exposure Gain")
+                    .blurb("Linear digital gain applied to the raw samples")
+                    .minimum(0.0)
+                    .maximum(raw::MAX_GAIN)
+                    .default_value(DEFAULT_EXPOSURE_GAIN)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:highlight-mode:
+                 *
+                 * Handling of highlights after the black level and the gains.
+                 * Saturated samples of every color end up at a level of their own
+                 * then, so with `clip` a highlight clipping in some colors turns
+                 * pink or cyan. `soft` rolls every color off into the lowest of
+                 * those levels, so they compress together and clip neutral,
+                 * `reconstruct` takes the clipped samples of every CFA tile to
+                 * that level and scales the others towards it, keeping their
+                 * ratio. Both cost a pass over the raw samples.
+                 */
+                glib::ParamSpecEnum::builder_with_default(
+                    "highlight-mode",
+                    HighlightMode::default(),
+                )
+                .nick("Highlight Mode")
+                .blurb("Handling of highlights clipped by the gains")
+                .mutable_playing()
+                .build(),
+                /**
+                 * GstRsBayer2Rgb:exposure-compensation:
+                 *
+                 * Scale every frame carrying an RsBayerExposureMeta to the
+                 * brightness of #GstRsBayer2Rgb:reference-exposure, so the output
+                 * doesn't flicker while the source changes the exposure time or the
+                 * analog gain. The compensating gain is multiplied into
+                 * #GstRsBayer2Rgb:exposure-gain and saturates with it. Frames
+                 * without the meta are converted without compensation.
+                 */
+                glib::ParamSpecBoolean::builder("exposure-compensation")
+                    .nick("Exposure Compensation")
+                    .blurb("Normalize frames to the reference exposure by their exposure meta")
+                    .default_value(DEFAULT_EXPOSURE_COMPENSATION)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:reference-exposure:
+                 *
+                 * Exposure time in nanoseconds at unity analog gain that
+                 * #GstRsBayer2Rgb:exposure-compensation normalizes frames to. A frame
+                 * exposed for half of it at an analog gain of 4 is halved.
+                 */
+                glib::ParamSpecUInt64::builder("reference-exposure")
+                    .nick("Reference Exposure")
+                    .blurb("Exposure time in ns at unity gain output brightness is kept at")
+                    .minimum(1)
+                    .default_value(DEFAULT_REFERENCE_EXPOSURE)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:skip-lines-top:
+                 *
+                 * Lines at the top of every input frame that aren't image, such
+                 * as the embedded register data some sensors prepend. Together
+                 * with #GstRsBayer2Rgb:skip-lines-bottom,
+                 * #GstRsBayer2Rgb:skip-cols-left and #GstRsBayer2Rgb:skip-cols-right
+                 * they are removed before anything else sees the frame: the output
+                 * is smaller by the skipped margins, and the optical black margins,
+                 * the crop, the statistics ROI and calibration files all refer to
+                 * what is left. The caps, the #GstRsBayer2Rgb:pattern and the phase
+                 * properties still describe the whole input, odd margins shift the
+                 * pattern accordingly. With interlaced input only an even number
+                 * of top lines can be skipped.
+                 */
+                glib::ParamSpecUInt::builder("skip-lines-top")
+                    .nick("Skip Lines Top")
+                    .blurb("Non-image lines at the top of the input, such as embedded data")
+                    .default_value(DEFAULT_SKIP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("skip-lines-bottom")
+                    .nick("Skip Lines Bottom")
+                    .blurb("Non-image lines at the bottom of the input")
+                    .default_value(DEFAULT_SKIP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("skip-cols-left")
+                    .nick("Skip Columns Left")
+                    .blurb("Non-image columns on the left of the input")
+                    .default_value(DEFAULT_SKIP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("skip-cols-right")
+                    .nick("Skip Columns Right")
+                    .blurb("Non-image columns on the right of the input")
+                    .default_value(DEFAULT_SKIP)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:post-embedded-data:
+                 *
+                 * Post the lines skipped with #GstRsBayer2Rgb:skip-lines-top as an
+                 * `embedded-data` element message for every frame, with the `pts`
+                 * of the buffer, the `width` of the lines in bytes, the number of
+                 * `lines` and the samples as `data`, lines tightly packed.
+                 */
+                glib::ParamSpecBoolean::builder("post-embedded-data")
+                    .nick("Post Embedded Data")
+                    .blurb("Post the skipped top lines as embedded-data element messages")
+                    .default_value(DEFAULT_POST_EMBEDDED_DATA)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:decompanding:
+                 *
+                 * Inverts the piecewise-linear companding of HDR sensors that squeeze
+                 * a wide linear range into few bits, before the black level and every
+                 * other correction but the dark frame. Either an array of knee points,
+                 * `<code, level>` pairs mapping input codes from 0 to 255 to linear
+                 * levels from 0 to 65535 joined by straight lines, or the name of a
+                 * preset alone: `a-law` expands eight segments of 32 codes to 12 bits
+                 * like G.711 A-law. Codes have to rise and levels must not fall from
+                 * one knee point to the next, other curves are refused. While set the
+                 * conversion runs at 16 bits like with
+                 * #GstRsBayer2Rgb:high-precision; an empty array unsets it.
+                 */
+                gst::ParamSpecArray::builder("decompanding")
+                    .nick("Decompanding")
+                    .blurb("Knee points <code, level> of the companding curve, or a preset")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:ob-rows:
+                 *
+                 * Rows of optical black at the top of the input. Together with
+                 * #GstRsBayer2Rgb:ob-cols, the columns of optical black on the left,
+                 * they make up the margins whose per-color median is subtracted
+                 * from every frame as the black level before white balance. The
+                 * last measurement is part of the rsbayer2rgb-stats message.
+                 */
+                glib::ParamSpecUInt::builder("ob-rows")
+                    .nick("OB Rows")
+                    .blurb("Rows of optical black at the top of the input")
+                    .default_value(DEFAULT_OB_ROWS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("ob-cols")
+                    .nick("OB Columns")
+                    .blurb("Columns of optical black on the left of the input")
+                    .default_value(DEFAULT_OB_COLS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("ob-crop")
+                    .nick("OB Crop")
+                    .blurb("Crop the optical black margins out of the output")
+                    .default_value(DEFAULT_OB_CROP)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:crop-left:
+                 *
+                 * Left edge of the part of the input that is converted, in input
+                 * pixels. Cropping happens on the bayer samples before demosaic, so
+                 * the rest of the frame costs nothing. All crop properties are
+                 * rounded down to even values to keep the CFA phase; a zero
+                 * #GstRsBayer2Rgb:crop-width or #GstRsBayer2Rgb:crop-height extends
+                 * the crop to the frame edge. The optical black margins stay cropped
+                 * with #GstRsBayer2Rgb:ob-crop. Changing the crop renegotiates the
+                 * output size.
+                 */
+                glib::ParamSpecUInt::builder("crop-left")
+                    .nick("Crop Left")
+                    .blurb("Left edge of the converted part of the input")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-top")
+                    .nick("Crop Top")
+                    .blurb("Top edge of the converted part of the input")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-width")
+                    .nick("Crop Width")
+                    .blurb("Width of the converted part of the input (0 = to the right edge)")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-height")
+                    .nick("Crop Height")
+                    .blurb("Height of the converted part of the input (0 = to the bottom edge)")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:pattern:
+                 *
+                 * CFA pattern the input is converted with. `caps` takes the
+                 * format of the caps, the four patterns override it for sources
+                 * that announce the wrong one. `auto-detect` measures the first
+                 * frames, picks the most likely pattern, logs it and keeps it
+                 * until the element stops, also across caps changes; see
+                 * #GstRsBayer2Rgb:detected-pattern and the
+                 * `bayer-pattern-detected` element message. Green sites are
+                 * detected reliably on any colorful scene, red and blue are told
+                 * apart by taking the warmer one for red, which fails on scenes
+                 * dominated by blue. The phase properties apply to every choice
+                 * but `auto-detect`. It takes effect with the next caps.
+                 */
+                glib::ParamSpecEnum::builder_with_default("pattern", InputPattern::default())
+                    .nick("Pattern")
+                    .blurb("CFA pattern of the input, from the caps, fixed or detected")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:cfa-layout:
+                 *
+                 * Layout of the color filter array. `quad` is the quad bayer, or
+                 * tetracell, layout of high resolution sensors: a 4x4 tile in which
+                 * every color of the pattern covers a 2x2 block, the caps format
+                 * naming the arrangement of the blocks. Such input is made an
+                 * ordinary bayer mosaic as selected with #GstRsBayer2Rgb:quad-mode
+                 * before anything else sees it, so the optical black margins, the
+                 * crop, the statistics and calibration files refer to that mosaic.
+                 * Skipped margins must be even, and interlaced input isn't
+                 * supported.
+                 */
+                glib::ParamSpecEnum::builder_with_default("cfa-layout", CfaLayout::default())
+                    .nick("CFA Layout")
+                    .blurb("Layout of the color filter array, bayer or quad bayer")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:quad-mode:
+                 *
+                 * How quad bayer input is made a bayer mosaic. `binning` averages
+                 * every 2x2 block into one sample, halving the output size and the
+                 * noise. `remosaic` keeps the full resolution by swapping samples
+                 * within every tile so each lands on a site of its color at most a
+                 * pixel away; fine detail shows some zippering. With `remosaic`
+                 * the input must be made of whole tiles, a multiple of 4 pixels
+                 * in both directions.
+                 */
+                glib::ParamSpecEnum::builder_with_default("quad-mode", QuadMode::default())
+                    .nick("Quad Mode")
+                    .blurb("Bin quad bayer blocks at half resolution or remosaic them")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:detected-pattern:
+                 *
+                 * Pattern locked in by `pattern=auto-detect`, as named in the
+                 * caps, or %NULL while it is still being detected.
+                 */
+                glib::ParamSpecString::builder("detected-pattern")
+                    .nick("Detected Pattern")
+                    .blurb("CFA pattern found by auto-detection")
+                    .read_only()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:x-phase:
+                 *
+                 * Columns the CFA pattern of the caps is shifted by, for input
+                 * cropped upstream by an odd number of columns while the caps still
+                 * name the pattern of the full sensor: `rggb` with an x-phase of 1
+                 * is converted as `grbg`. The shifted pattern applies to the whole
+                 * input, so the optical black margins, the crop properties and the
+                 * statistics all see it. It takes effect with the next caps.
+                 */
+                glib::ParamSpecUInt::builder("x-phase")
+                    .nick("X Phase")
+                    .blurb("Columns to shift the CFA pattern of the caps by")
+                    .maximum(1)
+                    .default_value(DEFAULT_PHASE)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:y-phase:
+                 *
+                 * Rows the CFA pattern of the caps is shifted by, as
+                 * #GstRsBayer2Rgb:x-phase for columns: `rggb` with a y-phase of 1
+                 * is converted as `gbrg`.
+                 */
+                glib::ParamSpecUInt::builder("y-phase")
+                    .nick("Y Phase")
+                    .blurb("Rows to shift the CFA pattern of the caps by")
+                    .maximum(1)
+                    .default_value(DEFAULT_PHASE)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:lsc-file:
+                 *
+                 * Lens shading gain map multiplied into the bayer samples together
+                 * with the white balance gains. It is a NumPy `.npy` file holding a
+                 * `(rows, cols, 4)` array of `float32` or `float64` gains for red,
+                 * red row green, blue row green and blue, the grid spread evenly
+                 * over the converted frame. Grids coarser than one point per 2x2
+                 * quad are upscaled bilinearly. The file is loaded when the element
+                 * starts and checked against the negotiated size.
+                 */
+                glib::ParamSpecString::builder("lsc-file")
+                    .nick("LSC File")
+                    .blurb("Lens shading gain map (.npy) to correct before demosaic")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:flat-field-file:
+                 *
+                 * Flat-field exposure the bayer samples are divided by, after
+                 * normalizing it to the mean of every CFA color, which removes dust
+                 * shadows and illumination gradients. It is a NumPy `.npy` file
+                 * holding a `(height, width)` array of `uint8` or `uint16` samples
+                 * with the size and pattern of the input. The file is loaded when
+                 * the element starts and checked against the negotiated size.
+                 * Combines with #GstRsBayer2Rgb:lsc-file.
+                 */
+                glib::ParamSpecString::builder("flat-field-file")
+                    .nick("Flat-Field File")
+                    .blurb("Flat-field exposure (.npy) to divide the input by before demosaic")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:dark-frame-file:
+                 *
+                 * Master dark subtracted from every input frame before any other
+                 * correction. It is a NumPy `.npy` file holding a `(height, width)`
+                 * array of `uint8` or `uint16` samples with the size and depth of
+                 * the input. The file is loaded again whenever the property is set
+                 * and checked against the negotiated size. A master dark includes
+                 * the black level, so it is normally used without
+                 * #GstRsBayer2Rgb:ob-rows and #GstRsBayer2Rgb:ob-cols.
+                 */
+                glib::ParamSpecString::builder("dark-frame-file")
+                    .nick("Dark Frame File")
+                    .blurb("Master dark (.npy) to subtract from the input")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:defect-list-file:
+                 *
+                 * Defective pixels replaced with the median of their same color
+                 * neighbors before demosaic. It is a text file with one `x,y` input
+                 * pixel position per line, fields separated by commas or
+                 * whitespace; an optional third field with the defect type is
+                 * ignored, lines starting with `#` are comments and the first line
+                 * may be a CSV header. The file is loaded when the element starts
+                 * and checked against the negotiated size.
+                 */
+                glib::ParamSpecString::builder("defect-list-file")
+                    .nick("Defect List File")
+                    .blurb("List of defective pixels to correct before demosaic")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:fpn-correction:
+                 *
+                 * Fixed pattern noise correction, an offset subtracted from every
+                 * column and row of the input together with the black level, which
+                 * removes the stripes column parallel readouts leave in the
+                 * shadows. The two CFA colors of a column or row each get their own
+                 * offset. With `file` the offsets are those of
+                 * #GstRsBayer2Rgb:fpn-file. With `optical-black` they are measured
+                 * on every frame, relative to the optical black level, and damped
+                 * over the frames: the column offsets on the
+                 * #GstRsBayer2Rgb:ob-rows, which cover every column, and the row
+                 * offsets on the #GstRsBayer2Rgb:ob-cols, which cover every row.
+                 * Separately converted fields are not corrected.
+                 */
+                glib::ParamSpecEnum::builder_with_default(
+                    "fpn-correction",
+                    FpnCorrection::default(),
+                )
+                .nick("FPN Correction")
+                .blurb("Source of the column and row offsets to subtract before demosaic")
+                .mutable_ready()
+                .build(),
+                /**
+                 * GstRsBayer2Rgb:fpn-file:
+                 *
+                 * Column and row offsets for #GstRsBayer2Rgb:fpn-correction `file`.
+                 * It is a NumPy `.npy` file holding a `(2, width)` array of
+                 * `float32` or `float64` offsets in input sample levels, the column
+                 * offsets of the even and the odd rows of the input, or a
+                 * `(2, width + height)` array with the row offsets of the even and
+                 * the odd columns after them. The file is loaded when the element
+                 * starts and checked against the negotiated size.
+                 */
+                glib::ParamSpecString::builder("fpn-file")
+                    .nick("FPN File")
+                    .blurb("Column and row offsets (.npy) to subtract before demosaic")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:auto-defect-correction:
+                 *
+                 * Detects defective pixels on the frames themselves and corrects
+                 * them before demosaic, for sensors without a defect list. A sample
+                 * is defective when it lies beyond all four of its horizontal and
+                 * vertical same color neighbors and further than
+                 * #GstRsBayer2Rgb:defect-threshold from their median. The number
+                 * of sites corrected in the last frame is the `corrected-defects`
+                 * field of the rsbayer2rgb-stats message.
+                 */
+                glib::ParamSpecBoolean::builder("auto-defect-correction")
+                    .nick("Auto Defect Correction")
+                    .blurb("Detect and correct defective pixels on the fly")
+                    .default_value(DEFAULT_AUTO_DEFECT_CORRECTION)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("defect-threshold")
+                    .nick("Defect Threshold")
+                    .blurb("Distance from the neighbor median of a defective pixel, as a fraction of full scale")
+                    .minimum(0.01)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_DEFECT_THRESHOLD)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:defect-detection-interval:
+                 *
+                 * Frames from one detection of defective pixels to the next. The
+                 * sites found are corrected on every frame in between, which keeps
+                 * the cost of #GstRsBayer2Rgb:auto-defect-correction low for
+                 * defects that don't move, like hot pixels.
+                 */
+                glib::ParamSpecUInt::builder("defect-detection-interval")
+                    .nick("Defect Detection Interval")
+                    .blurb("Frames between detections of defective pixels")
+                    .minimum(1)
+                    .default_value(DEFAULT_DEFECT_DETECTION_INTERVAL)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:gamma:
+                 *
+                 * Gamma encoding of the output, `out = in^(1 / gamma)`, so 2.2
+                 * brightens linear sensor data for display. It is the last stage,
+                 * after white balance and demosaic. 1.0 leaves the output linear
+                 * and costs nothing.
+                 */
+                glib::ParamSpecDouble::builder("gamma")
+                    .nick("Gamma")
+                    .blurb("Gamma encoding of the output (1.0 = linear)")
+                    .minimum(0.01)
+                    .maximum(10.0)
+                    .default_value(DEFAULT_GAMMA)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:tone-lut:
+                 *
+                 * Tone curve of 256 or 1024 points applied to every color channel
+                 * of the output, each point the output level for its index, so
+                 * values range from 0 to the length minus one. Curves are
+                 * resampled to the sample depth with linear interpolation. Replaces
+                 * #GstRsBayer2Rgb:gamma while set; an empty array unsets it.
+                 */
+                gst::ParamSpecArray::builder("tone-lut")
+                    .nick("Tone LUT")
+                    .blurb("Tone curve of 256 or 1024 output levels, replaces gamma")
+                    .element_spec(
+                        &glib::ParamSpecInt::builder("level")
+                            .nick("Level")
+                            .blurb("Output level")
+                            .minimum(0)
+                            .maximum(TONE_LUT_SIZES[1] as i32 - 1)
+                            .build(),
+                    )
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:brightness:
+                 *
+                 * Brightness, contrast and saturation work like videobalance's,
+                 * after gamma and tone curve. Brightness and contrast are folded
+                 * into the same lookup table and cost nothing extra, saturation
+                 * takes one more pass over the output unless it is 1.0.
+                 */
+                glib::ParamSpecDouble::builder("brightness")
+                    .nick("Brightness")
+                    .blurb("Brightness offset of the output")
+                    .minimum(-1.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_BRIGHTNESS)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("contrast")
+                    .nick("Contrast")
+                    .blurb("Contrast of the output around mid-gray")
+                    .minimum(0.0)
+                    .maximum(2.0)
+                    .default_value(DEFAULT_CONTRAST)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("saturation")
+                    .nick("Saturation")
+                    .blurb("Color saturation of the output (0 = grayscale)")
+                    .minimum(0.0)
+                    .maximum(2.0)
+                    .default_value(DEFAULT_SATURATION)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:false-color-suppression:
+                 *
+                 * Fine detail near the sampling limit of the mosaic, like fabric or
+                 * resolution charts, demosaics into colored moiré. A non-zero strength
+                 * runs a 3x3 median over the color differences right after demosaic and
+                 * moves the chroma of every pixel that far towards it, leaving the luma
+                 * alone. 1.0 takes the median outright. It costs a pass over the output
+                 * with a median per pixel, 0.0 skips it.
+                 */
+                glib::ParamSpecDouble::builder("false-color-suppression")
+                    .nick("False Color Suppression")
+                    .blurb("Strength of the chroma median after demosaic (0 = off)")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_FALSE_COLOR_SUPPRESSION)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:high-precision:
+                 *
+                 * Processes the samples at 16 bits from the black level to the
+                 * saturation and only quantizes them to the 8-bit output at the end,
+                 * which keeps gains, gamma and contrast from banding smooth gradients
+                 * together. Every stage moves twice the data and the demosaic is a
+                 * scalar bilinear one whatever the backend and demosaic algorithm, so
+                 * a conversion takes several times as long as at 8 bits. The OpenCV
+                 * denoise and sharpen filters run on the quantized output.
+                 */
+                glib::ParamSpecBoolean::builder("high-precision")
+                    .nick("High Precision")
+                    .blurb("Process at 16 bits, quantizing to the output at the end")
+                    .default_value(DEFAULT_HIGH_PRECISION)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:dither:
+                 *
+                 * Quantizes #GstRsBayer2Rgb:high-precision output with a 4x4 ordered
+                 * dither instead of rounding, trading the steps left in smooth
+                 * gradients for fine noise. Has no effect without high precision.
+                 */
+                glib::ParamSpecBoolean::builder("dither")
+                    .nick("Dither")
+                    .blurb("Dither high-precision output instead of rounding it")
+                    .default_value(DEFAULT_DITHER)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:tone-mapping:
+                 *
+                 * How #GstRsBayer2Rgb:high-precision reduces the 16-bit samples to
+                 * the range of the 8-bit output, right after demosaic and before
+                 * gamma, tone curve and the other tone stages. `shift` keeps the top
+                 * 8 bits, `linear`, `gamma` and `log` map the range between
+                 * #GstRsBayer2Rgb:black-point and #GstRsBayer2Rgb:white-point to the
+                 * whole output range, the latter two lifting the shadows.
+                 * `percentile-auto` maps linearly between the darkest and brightest
+                 * 0.5% of the samples of every frame, damped over the frames; black
+                 * and white points set to anything but 0.0 and 1.0 win over the
+                 * measured ones. Without high precision there is nothing to reduce.
+                 */
+                glib::ParamSpecEnum::builder_with_default("tone-mapping", ToneMapping::default())
+                    .nick("Tone Mapping")
+                    .blurb("How high-precision samples are reduced to the output range")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("black-point")
+                    .nick("Black Point")
+                    .blurb("Level mapped to black by tone-mapping, fraction of full scale")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_BLACK_POINT)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("white-point")
+                    .nick("White Point")
+                    .blurb("Level mapped to white by tone-mapping, fraction of full scale")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_WHITE_POINT)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:auto-levels:
+                 *
+                 * Stretches the output linearly to the full range between
+                 * percentiles of the luma of every frame, for quick looks at
+                 * signals that only fill a small part of the sensor's range. The
+                 * darkest #GstRsBayer2Rgb:auto-levels-low of the pixels map to
+                 * black and the brightest above #GstRsBayer2Rgb:auto-levels-high to
+                 * white, the levels damped over #GstRsBayer2Rgb:auto-levels-frames
+                 * frames. Unlike CLAHE the stretch is the same all over the frame.
+                 * It comes right before gamma, with or without high precision.
+                 */
+                glib::ParamSpecBoolean::builder("auto-levels")
+                    .nick("Auto Levels")
+                    .blurb("Stretch the output between percentiles of every frame")
+                    .default_value(DEFAULT_AUTO_LEVELS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("auto-levels-low")
+                    .nick("Auto Levels Low")
+                    .blurb("Fraction of the pixels auto-levels maps to black")
+                    .minimum(0.0)
+                    .maximum(0.5)
+                    .default_value(DEFAULT_AUTO_LEVELS_LOW)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecDouble::builder("auto-levels-high")
+                    .nick("Auto Levels High")
+                    .blurb("Fraction of the pixels auto-levels maps below white")
+                    .minimum(0.5)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_AUTO_LEVELS_HIGH)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+                glib::ParamSpecUInt::builder("auto-levels-frames")
+                    .nick("Auto Levels Frames")
+                    .blurb("Frames the auto-levels are damped over, 1 to follow every frame")
+                    .minimum(1)
+                    .maximum(1000)
+                    .default_value(DEFAULT_AUTO_LEVELS_FRAMES)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:levels-lock:
+                 *
+                 * Holds the levels #GstRsBayer2Rgb:auto-levels measured so far
+                 * instead of following the frames, e.g. once the scene is set up,
+                 * so a passing object doesn't change the stretch. Set before any
+                 * frame, the levels of the first one are held.
+                 */
+                glib::ParamSpecBoolean::builder("levels-lock")
+                    .nick("Levels Lock")
+                    .blurb("Hold the current auto-levels")
+                    .default_value(DEFAULT_LEVELS_LOCK)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:show-zebra:
+                 *
+                 * Debug overlay of black diagonal stripes over the output pixels
+                 * whose raw samples exceed #GstRsBayer2Rgb:zebra-threshold, to see
+                 * clipped highlights while tuning exposure. It is drawn over the
+                 * finished output and costs nothing while off.
+                 */
+                glib::ParamSpecBoolean::builder("show-zebra")
+                    .nick("Show Zebra")
+                    .blurb("Draw stripes over overexposed regions")
+                    .default_value(DEFAULT_SHOW_ZEBRA)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("zebra-threshold")
+                    .nick("Zebra Threshold")
+                    .blurb("Raw level above which show-zebra marks pixels, as a fraction of full scale")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_ZEBRA_THRESHOLD)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:post-stats:
+                 *
+                 * Posts a `bayer-stats` element message for every frame, measured on
+                 * the raw input before any processing. It holds the buffer `pts`
+                 * and one structure per CFA color, `red`, `green-red`, `green-blue`
+                 * and `blue`, each with the `mean`, `min` and `max` sample and a
+                 * 64 bin `histogram` over the full sample range.
+                 */
+                glib::ParamSpecBoolean::builder("post-stats")
+                    .nick("Post Stats")
+                    .blurb("Post per-channel statistics of the raw input for every frame")
+                    .default_value(DEFAULT_POST_STATS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stats-subsampling")
+                    .nick("Stats Subsampling")
+                    .blurb("Measure only every Nth 2x2 quad in each direction for post-stats")
+                    .minimum(1)
+                    .maximum(64)
+                    .default_value(DEFAULT_STATS_SUBSAMPLING)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:post-focus-metric:
+                 *
+                 * Posts a `focus-metric` element message for every frame, holding
+                 * the buffer `pts` and `focus`, the variance of the Laplacian of
+                 * the output luma on a 0 to 1 scale. Higher is sharper. It is
+                 * measured in the stats ROI, on every Nth output pixel in each
+                 * direction for a #GstRsBayer2Rgb:stats-subsampling of N, and
+                 * doesn't depend on the output format.
+                 */
+                glib::ParamSpecBoolean::builder("post-focus-metric")
+                    .nick("Post Focus Metric")
+                    .blurb("Post a sharpness figure of the output for every frame")
+                    .default_value(DEFAULT_POST_FOCUS_METRIC)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:attach-timing-meta:
+                 *
+                 * Attach a `RsBayerTimingMeta` to every output buffer, holding
+                 * the monotonic times (as of `gst_util_get_timestamp()`) the
+                 * conversion of the frame started and ended, the method and the
+                 * demosaic algorithm. Tracers find it by its API name,
+                 * `RsBayerTimingMetaAPI`.
+                 */
+                glib::ParamSpecBoolean::builder("attach-timing-meta")
+                    .nick("Attach Timing Meta")
+                    .blurb("Attach the conversion start and end times to every output buffer")
+                    .default_value(DEFAULT_ATTACH_TIMING_META)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:stats-roi-x:
+                 *
+                 * Left edge of the window #GstRsBayer2Rgb:post-stats, the focus
+                 * metric and the gray-world AWB measure, in input pixels. The
+                 * window is clamped to the input and grown to whole 2x2 quads; a
+                 * zero #GstRsBayer2Rgb:stats-roi-width or
+                 * #GstRsBayer2Rgb:stats-roi-height extends it to the frame edge.
+                 */
+                glib::ParamSpecUInt::builder("stats-roi-x")
+                    .nick("Stats ROI X")
+                    .blurb("Left edge of the statistics and AWB window")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stats-roi-y")
+                    .nick("Stats ROI Y")
+                    .blurb("Top edge of the statistics and AWB window")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stats-roi-width")
+                    .nick("Stats ROI Width")
+                    .blurb("Width of the statistics and AWB window (0 = to the right edge)")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stats-roi-height")
+                    .nick("Stats ROI Height")
+                    .blurb("Height of the statistics and AWB window (0 = to the bottom edge)")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:process-roi-x:
+                 *
+                 * Left edge of the only part of the input that is converted, in input
+                 * pixels. The output keeps its full size, the ROI lands where it would
+                 * in a whole frame conversion and everything around it is filled with
+                 * #GstRsBayer2Rgb:process-roi-fill-color. The ROI is clamped to the
+                 * converted window and shrunk to even coordinates and sizes so it keeps
+                 * the CFA phase; a zero #GstRsBayer2Rgb:process-roi-width or
+                 * #GstRsBayer2Rgb:process-roi-height extends it to the frame edge. A
+                 * ROI outside the input converts the whole frame.
+                 *
+                 * Demosaic doesn't look past the ROI, so the pixels on its edge may
+                 * differ slightly from a whole frame conversion.
+                 */
+                glib::ParamSpecUInt::builder("process-roi-x")
+                    .nick("Process ROI X")
+                    .blurb("Left edge of the converted region of interest")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("process-roi-y")
+                    .nick("Process ROI Y")
+                    .blurb("Top edge of the converted region of interest")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("process-roi-width")
+                    .nick("Process ROI Width")
+                    .blurb("Width of the converted region of interest (0 = to the right edge)")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("process-roi-height")
+                    .nick("Process ROI Height")
+                    .blurb("Height of the converted region of interest (0 = to the bottom edge)")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:process-roi-fill-color:
+                 *
+                 * Color of the output outside the process ROI, as 0xAARRGGBB. The
+                 * alpha only matters for RGBA output.
+                 */
+                glib::ParamSpecUInt::builder("process-roi-fill-color")
+                    .nick("Process ROI Fill Color")
+                    .blurb("Color of the output outside the process ROI (0xAARRGGBB)")
+                    .default_value(DEFAULT_PROCESS_ROI_FILL_COLOR)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:process-roi-keep-previous:
+                 *
+                 * Leaves the output outside the process ROI as it was in the previous
+                 * frame instead of filling it. The first frame is filled with
+                 * #GstRsBayer2Rgb:process-roi-fill-color.
+                 */
+                glib::ParamSpecBoolean::builder("process-roi-keep-previous")
+                    .nick("Process ROI Keep Previous")
+                    .blurb("Keep the previous frame outside the process ROI instead of filling it")
+                    .default_value(DEFAULT_PROCESS_ROI_KEEP_PREVIOUS)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:stack-frames:
+                 *
+                 * Averages this many consecutive raw frames into one before demosaic,
+                 * which cuts the noise of a static scene by the square root of the
+                 * number of frames. The output framerate is the input one divided by
+                 * it. Every output frame has the PTS of the first frame of its stack
+                 * and the durations of all of them summed. The frames of an
+                 * incomplete stack are dropped at a flush, a discontinuity or EOS.
+                 */
+                glib::ParamSpecUInt::builder("stack-frames")
+                    .nick("Stack Frames")
+                    .blurb("Raw frames averaged into every output frame")
+                    .minimum(1)
+                    .maximum(MAX_STACK_FRAMES)
+                    .default_value(DEFAULT_STACK_FRAMES)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:max-queue-buffers:
+                 *
+                 * When non-zero, frames are converted and pushed on a separate thread
+                 * with up to this many waiting, so a slow conversion doesn't block the
+                 * upstream thread. What happens when the queue is full is controlled
+                 * by #GstRsBayer2Rgb:leaky.
+                 */
+                glib::ParamSpecUInt::builder("max-queue-buffers")
+                    .nick("Max Queue Buffers")
+                    .blurb("Convert on a separate thread with up to this many queued buffers (0 = in the streaming thread)")
+                    .default_value(DEFAULT_MAX_QUEUE_BUFFERS)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:leaky:
+                 *
+                 * With `no`, upstream waits for room in a full conversion queue.
+                 * `upstream` drops the incoming buffer instead and `downstream` drops
+                 * the oldest queued ones, the frame being converted is never dropped.
+                 * Drops are counted in #GstRsBayer2Rgb:frames-dropped.
+                 */
+                glib::ParamSpecEnum::builder_with_default("leaky", Leaky::default())
+                    .nick("Leaky")
+                    .blurb("Where to drop buffers when the conversion queue is full")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:preset:
+                 *
+                 * Loads the preset of this name when set, like
+                 * gst_preset_load_preset(): one saved with gst_preset_save_preset()
+                 * or one of the built-in `imx477-daylight` and `imx477-tungsten`.
+                 * Presets hold the demosaic, gain, black level, calibration and tone
+                 * properties, properties set after this one override their values.
+                 * Reads back the name of the last preset loaded.
+                 */
+                glib::ParamSpecString::builder("preset")
+                    .nick("Preset")
+                    .blurb("Load the tuning preset of this name")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:tuning-file:
+                 *
+                 * JSON or TOML file of tuning property values by property name,
+                 * applied when set and, with #GstRsBayer2Rgb:watch-tuning-file,
+                 * whenever the file changes. A file is applied as a whole between two
+                 * frames or, if anything in it is invalid, not at all. Either way a
+                 * `tuning-file-loaded` element message with the `location` and the
+                 * number of `properties` set, or a `tuning-file-error` one with the
+                 * `location` and the `error`, is posted.
+                 */
+                glib::ParamSpecString::builder("tuning-file")
+                    .nick("Tuning File")
+                    .blurb("JSON or TOML file of tuning property values to apply")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:watch-tuning-file:
+                 *
+                 * Applies #GstRsBayer2Rgb:tuning-file again before the next frame
+                 * whenever its modification time changes.
+                 */
+                glib::ParamSpecBoolean::builder("watch-tuning-file")
+                    .nick("Watch Tuning File")
+                    .blurb("Reload the tuning file whenever it changes")
+                    .default_value(DEFAULT_WATCH_TUNING_FILE)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:isp-params:
+                 *
+                 * Tuning properties as the fields of one structure, named like the
+                 * properties. Setting it applies the fields it has, any subset, all
+                 * at once between two frames, so no frame is converted with some of
+                 * them applied; if any field is unknown or invalid, none is. The
+                 * others keep their values, and later settings of the individual
+                 * properties override it as usual. Reading it gives every tuning
+                 * property in use, unset calibration files as %NULL.
+                 *
+                 * |[
+                 * isp-params="isp-params,red-gain=1.9,blue-gain=1.6,gamma=2.2"
+                 * ]|
+                 */
+                glib::ParamSpecBoxed::builder::<gst::Structure>("isp-params")
+                    .nick("ISP Parameters")
+                    .blurb("Structure of tuning property values to apply at once")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt64::builder("frames-processed")
+                    .nick("Frames Processed")
+                    .blurb("Number of frames converted since the element started")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("frames-dropped")
+                    .nick("Frames Dropped")
+                    .blurb("Number of input frames that produced no output")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("avg-conversion-time")
+                    .nick("Average Conversion Time")
+                    .blurb("Exponentially weighted average conversion time in microseconds")
+                    .minimum(0.0)
+                    .read_only()
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb:quality-level:
+                 *
+                 * Quality the frames are converted at, lowered from `full` while
+                 * conversions miss #GstRsBayer2Rgb:processing-deadline. Every change
+                 * posts an `rsbayer2rgb-stats` element message right away, whatever
+                 * #GstRsBayer2Rgb:stats-interval, with the new level in its
+                 * `quality-level` field.
+                 */
+                glib::ParamSpecEnum::builder_with_default("quality-level", QualityLevel::default())
+                    .nick("Quality Level")
+                    .blurb("Quality the frames are converted at under the processing deadline")
+                    .read_only()
+                    .build(),
+            ];
+
+            #[cfg(all(feature = "opencv", feature = "rust-demosaic"))]
+            properties.push(
+                glib::ParamSpecEnum::builder_with_default("backend", Backend::default())
+                    .nick("Backend")
+                    .blurb("Implementation used for the conversion")
+                    .mutable_playing()
+                    .build(),
+            );
+
+            #[cfg(feature = "dump")]
+            properties.extend([
+                /**
+                 * GstRsBayer2Rgb:dump-location:
+                 *
+                 * Directory debug frames are written to while
+                 * #GstRsBayer2Rgb:dump-interval is non-zero, created if needed.
+                 * `{name}` in it is replaced by the element name. Every dumped
+                 * output frame is a PNG file named after the frame index and the
+                 * PTS in nanoseconds, e.g. `frame-000042-1400000000.png`. Files
+                 * are written on a separate thread; frames are skipped while it
+                 * is behind and failures to write only log a warning.
+                 */
+                glib::ParamSpecString::builder("dump-location")
+                    .nick("Dump Location")
+                    .blurb("Directory to write debug frames to")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("dump-interval")
+                    .nick("Dump Interval")
+                    .blurb("Write every Nth converted frame to dump-location (0 = disabled)")
+                    .default_value(DEFAULT_DUMP_INTERVAL)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("dump-raw")
+                    .nick("Dump Raw")
+                    .blurb("Also write the raw input of dumped frames as 16-bit PGM")
+                    .default_value(DEFAULT_DUMP_RAW)
+                    .mutable_playing()
+                    .build(),
+            ]);
+
+            #[cfg(feature = "opencv")]
+            properties.extend(cv::properties());
+
+            properties
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: LazyLock<Vec<glib::subclass::Signal>> = LazyLock::new(|| {
+            vec![
+                /**
+                 * GstRsBayer2Rgb::handoff:
+                 * @buffer: the converted output buffer
+                 * @info: the negotiated output video info
+                 *
+                 * Emitted for every converted frame before it is pushed downstream,
+                 * only while #GstRsBayer2Rgb:emit-signals is enabled.
+                 */
+                glib::subclass::Signal::builder("handoff")
+                    .param_types([
+                        gst::Buffer::static_type(),
+                        gst_video::VideoInfo::static_type(),
+                    ])
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb::calibrate-wb:
+                 * @x: left edge of the gray card, in input pixels
+                 * @y: top edge of the gray card, in input pixels
+                 * @width: width of the gray card, 0 for the rest of the frame
+                 * @height: height of the gray card, 0 for the rest of the frame
+                 *
+                 * Action signal calibrating the white balance on a gray card. The
+                 * next frame measures the mean of every CFA color in the window,
+                 * above the black level, and sets #GstRsBayer2Rgb:red-gain,
+                 * #GstRsBayer2Rgb:green-gain and #GstRsBayer2Rgb:blue-gain to the
+                 * gains that make them neutral, with #GstRsBayer2Rgb:awb-mode
+                 * `manual` so they stay. The window is clamped to the input and
+                 * grown to whole 2x2 quads like #GstRsBayer2Rgb:stats-roi-x. It can
+                 * be emitted from any thread; the gains apply to the frame measured.
+                 */
+                glib::subclass::Signal::builder("calibrate-wb")
+                    .param_types([u32::static_type(); 4])
+                    .action()
+                    .class_handler(|args| {
+                        let element = args[0]
+                            .get::<super::RsBayer2Rgb>()
+                            .expect("signal arg");
+                        let [x, y, width, height] = std::array::from_fn(|i| {
+                            args[i + 1].get::<u32>().expect("signal arg") as usize
+                        });
+                        let roi = Rect {
+                            x,
+                            y,
+                            width,
+                            height,
+                        };
+                        let imp = element.imp();
+                        gst::debug!(CAT, imp = imp, "Calibrating white balance on {:?}", roi);
+                        *imp.wb_calibration.lock().unwrap() = Some(roi);
+                        None
+                    })
+                    .build(),
+                /**
+                 * GstRsBayer2Rgb::wb-calibrated:
+                 * @red_gain: the new #GstRsBayer2Rgb:red-gain
+                 * @green_gain: the new #GstRsBayer2Rgb:green-gain
+                 * @blue_gain: the new #GstRsBayer2Rgb:blue-gain
+                 *
+                 * Emitted from the streaming thread once #GstRsBayer2Rgb::calibrate-wb
+                 * has set the gains, along with an `rsbayer2rgb-wb-calibrated`
+                 * element message holding them in `red-gain`, `green-gain` and
+                 * `blue-gain` fields.
+                 */
+                glib::subclass::Signal::builder("wb-calibrated")
+                    .param_types([f64::static_type(); 3])
+                    .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        // Presets and tuning files set the other properties, so they load without the
+        // settings locked.
+        match pspec.name() {
+            "preset" => {
+                let Some(name) = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                else {
+                    return;
+                };
+                if let Err(err) = self.obj().load_preset(&name) {
+                    gst::error!(CAT, imp = self, "Failed to load preset {}: {}", name, err);
+                }
+                return;
+            }
+            "tuning-file" => {
+                let path = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                self.settings.lock().unwrap().tuning_file = path.clone();
+                match path {
+                    Some(path) => self.load_tuning_file(&path),
+                    None => *self.tuning_modified.lock().unwrap() = None,
+                }
+                return;
+            }
+            "isp-params" => {
+                if let Some(params) = value
+                    .get::<Option<gst::Structure>>()
+                    .expect("type checked upstream")
+                {
+                    self.set_isp_params(&params);
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "timing-report-interval" => {
+                let interval = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing timing-report-interval from {} to {}",
+                    settings.timing_report_interval,
+                    interval
+                );
+                settings.timing_report_interval = interval;
+            }
+            "stats-interval" => {
+                let interval = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing stats-interval from {} to {}",
+                    settings.stats_interval,
+                    interval
+                );
+                settings.stats_interval = interval;
+            }
+            "emit-signals" => {
+                settings.emit_signals = value.get().expect("type checked upstream");
+            }
+            "min-buffers" => {
+                settings.min_buffers = value.get().expect("type checked upstream");
+            }
+            "max-buffers" => {
+                settings.max_buffers = value.get().expect("type checked upstream");
+            }
+            "output-alignment" => {
+                let alignment: u32 = value.get().expect("type checked upstream");
+                if !alignment.is_power_of_two() {
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "output-alignment {} is not a power of two, using {}",
+                        alignment,
+                        alignment.next_power_of_two()
+                    );
+                }
+                settings.output_alignment = alignment.next_power_of_two();
+            }
+            "extra-meta-apis" => {
+                let array = value.get::<gst::Array>().expect("type checked upstream");
+                settings.extra_meta_apis = array
+                    .iter()
+                    .filter_map(|value| value.get::<String>().ok())
+                    .collect();
+                // Meta APIs are registered on first use, this may only be a typo.
+                for name in &settings.extra_meta_apis {
+                    if glib::Type::from_name(name).is_none() {
+                        gst::warning!(CAT, imp = self, "No meta API {} registered yet", name);
+                    }
+                }
+            }
+            "method" => {
+                let method = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing method from {:?} to {:?}",
+                    settings.method,
+                    method
+                );
+                if method != settings.method {
+                    settings.method = method;
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            "video-direction" => {
+                let direction = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing video direction from {:?} to {:?}",
+                    settings.video_direction,
+                    direction
+                );
+                let previous = settings.direction();
+                settings.video_direction = direction;
+                if transposes(previous) != transposes(settings.direction()) {
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            "auto-orient" => {
+                let previous = settings.direction();
+                settings.auto_orient = value.get().expect("type checked upstream");
+                if transposes(previous) != transposes(settings.direction()) {
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            "demosaic-algorithm" => {
+                let algorithm = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing demosaic algorithm from {:?} to {:?}",
+                    settings.demosaic_algorithm,
+                    algorithm
+                );
+                settings.demosaic_algorithm = algorithm;
+            }
+            "processing-deadline" => {
+                settings.processing_deadline = value.get().expect("type checked upstream");
+            }
+            "border-mode" => {
+                let mode = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing border mode from {:?} to {:?}",
+                    settings.border_mode,
+                    mode
+                );
+                // Cropping changes the output size.
+                let renegotiate =
+                    (mode == BorderMode::Crop) != (settings.border_mode == BorderMode::Crop);
+                settings.border_mode = mode;
+                if renegotiate {
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            "field-mode" => {
+                settings.field_mode = value.get().expect("type checked upstream");
+            }
+            "red-gain" => {
+                settings.gains.red = value.get().expect("type checked upstream");
+                self.warn_gains_override(&settings);
+            }
+            "green-gain" => {
+                settings.gains.green = value.get().expect("type checked upstream");
+                self.warn_gains_override(&settings);
+            }
+            "blue-gain" => {
+                settings.gains.blue = value.get().expect("type checked upstream");
+                self.warn_gains_override(&settings);
+            }
+            "color-temperature" => {
+                let temperature: u32 = value.get().expect("type checked upstream");
+                settings.color_temperature = match temperature {
+                    0 => 0,
+                    temperature if temperature < awb::TEMPERATURE_RANGE.0 => {
+                        gst::warning!(
+                            CAT,
+                            imp = self,
+                            "Color temperature {} K below {} K, clamping",
+                            temperature,
+                            awb::TEMPERATURE_RANGE.0
+                        );
+                        awb::TEMPERATURE_RANGE.0
+                    }
+                    temperature => temperature,
+                };
+                self.warn_gains_override(&settings);
+            }
+            "tint" => {
+                settings.tint = value.get().expect("type checked upstream");
+            }
+            "awb-mode" => {
+                let awb_mode = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing AWB mode from {:?} to {:?}",
+                    settings.awb_mode,
+                    awb_mode
+                );
+                settings.awb_mode = awb_mode;
+            }
+            "gr-gb-balance" => {
+                let balance = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing Gr/Gb balance from {:?} to {:?}",
+                    settings.gr_gb_balance,
+                    balance
+                );
+                settings.gr_gb_balance = balance;
+            }
+            "gr-gb-ratio" => {
+                settings.gr_gb_ratio = value.get().expect("type checked upstream");
+            }
+            "exposure-gain" => {
+                settings.exposure_gain = value.get().expect("type checked upstream");
+            }
+            "highlight-mode" => {
+                settings.highlight_mode = value.get().expect("type checked upstream");
+            }
+            "exposure-compensation" => {
+                settings.exposure_compensation = value.get().expect("type checked upstream");
+            }
+            "reference-exposure" => {
+                settings.reference_exposure = value.get().expect("type checked upstream");
+            }
+            "skip-lines-top" => {
+                settings.skip_lines_top = value.get().expect("type checked upstream");
+            }
+            "skip-lines-bottom" => {
+                settings.skip_lines_bottom = value.get().expect("type checked upstream");
+            }
+            "skip-cols-left" => {
+                settings.skip_cols_left = value.get().expect("type checked upstream");
+            }
+            "skip-cols-right" => {
+                settings.skip_cols_right = value.get().expect("type checked upstream");
+            }
+            "post-embedded-data" => {
+                settings.post_embedded_data = value.get().expect("type checked upstream");
+            }
+            "decompanding" => {
+                let curve = value.get::<gst::Array>().expect("type checked upstream");
+                let knees = match curve.is_empty() {
+                    true => Ok(None),
+                    false => decompand::knees(&curve).map(Some),
+                };
+                match knees {
+                    Ok(knees) => {
+                        settings.decompanding =
+                            knees.map(|knees| (curve, decompand::lut(&knees).into()));
+                    }
+                    Err(err) => {
+                        gst::error!(CAT, imp = self, "Invalid decompanding: {}", err);
+                    }
+                }
+            }
+            "ob-rows" => {
+                settings.ob_rows = value.get().expect("type checked upstream");
+            }
+            "ob-cols" => {
+                settings.ob_cols = value.get().expect("type checked upstream");
             }
-            "video/x-raw" => {
-                let Ok(format) = structure.get::<&str>("format") else {
+            "ob-crop" => {
+                settings.ob_crop = value.get().expect("type checked upstream");
+            }
+            "pattern" => {
+                settings.pattern = value.get().expect("type checked upstream");
+            }
+            "cfa-layout" => {
+                settings.cfa_layout = value.get().expect("type checked upstream");
+            }
+            "quad-mode" => {
+                settings.quad_mode = value.get().expect("type checked upstream");
+            }
+            "x-phase" => {
+                settings.x_phase = value.get().expect("type checked upstream");
+            }
+            "y-phase" => {
+                settings.y_phase = value.get().expect("type checked upstream");
+            }
+            "crop-left" | "crop-top" | "crop-width" | "crop-height" => {
+                let requested = value.get::<u32>().expect("type checked upstream");
+                // Odd values would shift the CFA phase of the converted part.
+                let even = (requested & !1) as usize;
+                if even as u32 != requested {
                     gst::warning!(
                         CAT,
-                        "Could not find format in structure {}",
-                        structure.to_string()
+                        imp = self,
+                        "Rounding {} {} down to {}",
+                        pspec.name(),
+                        requested,
+                        even
                     );
-                    return glib::ffi::GFALSE;
-                };
-                match format {
-                    "RGB" | "BGR" => {
-                        *size = 3 * height * width;
-                        glib::ffi::GTRUE
-                    }
-                    "RGBA"  => {
-                        *size = 4 * height * width;
-                        glib::ffi::GTRUE
+                }
+
+                let mut crop = settings.crop;
+                match pspec.name() {
+                    "crop-left" => crop.x = even,
+                    "crop-top" => crop.y = even,
+                    "crop-width" => crop.width = even,
+                    _ => crop.height = even,
+                }
+                if crop != settings.crop {
+                    gst::info!(
+                        CAT,
+                        imp = self,
+                        "Changing crop from {:?} to {:?}",
+                        settings.crop,
+                        crop
+                    );
+                    settings.crop = crop;
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            "lsc-file" => {
+                settings.lsc_file = value.get().expect("type checked upstream");
+            }
+            "flat-field-file" => {
+                settings.flat_field_file = value.get().expect("type checked upstream");
+            }
+            "dark-frame-file" => {
+                let path: Option<String> = value.get().expect("type checked upstream");
+                settings.dark_frame = path.as_deref().and_then(|path| {
+                    dark::DarkFrame::load(std::path::Path::new(path))
+                        .map(std::sync::Arc::new)
+                        .inspect_err(|err| {
+                            gst::error!(CAT, imp = self, "Failed to load dark frame: {}", err);
+                        })
+                        .ok()
+                });
+                settings.dark_frame_file = path;
+            }
+            "defect-list-file" => {
+                settings.defect_list_file = value.get().expect("type checked upstream");
+            }
+            "fpn-correction" => {
+                settings.fpn_correction = value.get().expect("type checked upstream");
+            }
+            "fpn-file" => {
+                settings.fpn_file = value.get().expect("type checked upstream");
+            }
+            "auto-defect-correction" => {
+                settings.auto_defect_correction = value.get().expect("type checked upstream");
+            }
+            "defect-threshold" => {
+                settings.defect_threshold = value.get().expect("type checked upstream");
+            }
+            "defect-detection-interval" => {
+                settings.defect_detection_interval = value.get().expect("type checked upstream");
+            }
+            "gamma" => {
+                settings.gamma = value.get().expect("type checked upstream");
+                if settings.gamma != DEFAULT_GAMMA && settings.tone_lut.is_some() {
+                    gst::warning!(CAT, imp = self, "tone-lut is set, gamma is ignored");
+                }
+            }
+            "tone-lut" => {
+                let array = value.get::<gst::Array>().expect("type checked upstream");
+                let curve = array
+                    .iter()
+                    .map(|value| value.get::<i32>().ok().and_then(|v| u16::try_from(v).ok()))
+                    .collect::<Option<Vec<_>>>();
+
+                match curve {
+                    Some(curve) if curve.is_empty() => settings.tone_lut = None,
+                    Some(curve)
+                        if TONE_LUT_SIZES.contains(&curve.len())
+                            && curve.iter().all(|&level| (level as usize) < curve.len()) =>
+                    {
+                        if settings.gamma != DEFAULT_GAMMA {
+                            gst::warning!(CAT, imp = self, "tone-lut replaces gamma");
+                        }
+                        settings.tone_lut = Some(curve.into());
                     }
                     _ => {
-                        gst::warning!(CAT, "{} matched nothing", format);
-                        glib::ffi::GFALSE
+                        gst::error!(
+                            CAT,
+                            imp = self,
+                            "Invalid tone-lut, needs {:?} integers below its length",
+                            TONE_LUT_SIZES
+                        );
                     }
                 }
             }
-            other => {
-                gst::warning!(CAT, "{} matched nothing", other);
-                glib::ffi::GFALSE
+            "brightness" => {
+                settings.brightness = value.get().expect("type checked upstream");
             }
-        };
+            "contrast" => {
+                settings.contrast = value.get().expect("type checked upstream");
+            }
+            "saturation" => {
+                settings.saturation = value.get().expect("type checked upstream");
+            }
+            "false-color-suppression" => {
+                settings.false_color_suppression = value.get().expect("type checked upstream");
+            }
+            "high-precision" => {
+                settings.high_precision = value.get().expect("type checked upstream");
+            }
+            "dither" => {
+                settings.dither = value.get().expect("type checked upstream");
+            }
+            "tone-mapping" => {
+                settings.tone_mapping = value.get().expect("type checked upstream");
+            }
+            "black-point" => {
+                settings.black_point = value.get().expect("type checked upstream");
+            }
+            "white-point" => {
+                settings.white_point = value.get().expect("type checked upstream");
+            }
+            "auto-levels" => {
+                settings.auto_levels = value.get().expect("type checked upstream");
+            }
+            "auto-levels-low" => {
+                settings.auto_levels_low = value.get().expect("type checked upstream");
+            }
+            "auto-levels-high" => {
+                settings.auto_levels_high = value.get().expect("type checked upstream");
+            }
+            "auto-levels-frames" => {
+                settings.auto_levels_frames = value.get().expect("type checked upstream");
+            }
+            "levels-lock" => {
+                settings.levels_lock = value.get().expect("type checked upstream");
+            }
+            "show-zebra" => {
+                settings.show_zebra = value.get().expect("type checked upstream");
+            }
+            "zebra-threshold" => {
+                settings.zebra_threshold = value.get().expect("type checked upstream");
+            }
+            "post-stats" => {
+                settings.post_stats = value.get().expect("type checked upstream");
+            }
+            "stats-subsampling" => {
+                settings.stats_subsampling = value.get().expect("type checked upstream");
+            }
+            "post-focus-metric" => {
+                settings.post_focus_metric = value.get().expect("type checked upstream");
+            }
+            "attach-timing-meta" => {
+                settings.attach_timing_meta = value.get().expect("type checked upstream");
+            }
+            "stats-roi-x" => {
+                settings.stats_roi.x = value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "stats-roi-y" => {
+                settings.stats_roi.y = value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "stats-roi-width" => {
+                settings.stats_roi.width =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "stats-roi-height" => {
+                settings.stats_roi.height =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "process-roi-x" => {
+                settings.process_roi.x =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "process-roi-y" => {
+                settings.process_roi.y =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "process-roi-width" => {
+                settings.process_roi.width =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "process-roi-height" => {
+                settings.process_roi.height =
+                    value.get::<u32>().expect("type checked upstream") as usize;
+            }
+            "process-roi-fill-color" => {
+                settings.process_roi_fill_color = value.get().expect("type checked upstream");
+            }
+            "process-roi-keep-previous" => {
+                settings.process_roi_keep_previous = value.get().expect("type checked upstream");
+            }
+            "stack-frames" => {
+                settings.stack_frames = value.get().expect("type checked upstream");
+            }
+            "max-queue-buffers" => {
+                settings.max_queue_buffers = value.get().expect("type checked upstream");
+            }
+            "leaky" => {
+                settings.leaky = value.get().expect("type checked upstream");
+            }
+            "watch-tuning-file" => {
+                settings.watch_tuning_file = value.get().expect("type checked upstream");
+            }
+            "backend" => {
+                let backend = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing backend from {:?} to {:?}",
+                    settings.backend,
+                    backend
+                );
+                settings.backend = backend;
+            }
+            "enable-last-sample" => {
+                settings.enable_last_sample = value.get().expect("type checked upstream");
+                if !settings.enable_last_sample {
+                    *self.last_sample.lock().unwrap() = None;
+                }
+            }
+            #[cfg(feature = "dump")]
+            "dump-location" => {
+                settings.dump_location = value.get().expect("type checked upstream");
+            }
+            #[cfg(feature = "dump")]
+            "dump-interval" => {
+                settings.dump_interval = value.get().expect("type checked upstream");
+            }
+            #[cfg(feature = "dump")]
+            "dump-raw" => {
+                settings.dump_raw = value.get().expect("type checked upstream");
+            }
+            #[cfg(feature = "opencv")]
+            name => {
+                let output_size = (settings.opencv.output_width, settings.opencv.output_height);
+                if !settings.opencv.set_property(name, value) {
+                    unimplemented!()
+                }
+                if (settings.opencv.output_width, settings.opencv.output_height) != output_size {
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            #[cfg(not(feature = "opencv"))]
+            _ => unimplemented!(),
+        }
+    }
 
-        return result;
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "frames-processed" => self
+                .stats
+                .frames_processed
+                .load(Ordering::Relaxed)
+                .to_value(),
+            "frames-dropped" => self.stats.frames_dropped.load(Ordering::Relaxed).to_value(),
+            "avg-conversion-time" => self.stats.avg_conversion_time().to_value(),
+            "quality-level" => self.quality.lock().unwrap().level().to_value(),
+            "last-sample" => self.last_sample.lock().unwrap().to_value(),
+            "applied-red-gain" => self.applied_gains.lock().unwrap().red.to_value(),
+            "applied-green-gain" => self.applied_gains.lock().unwrap().green.to_value(),
+            "applied-blue-gain" => self.applied_gains.lock().unwrap().blue.to_value(),
+            "applied-gr-gb-ratio" => self.applied_gains.lock().unwrap().green_balance.to_value(),
+            "detected-pattern" => self
+                .detected
+                .lock()
+                .unwrap()
+                .map(Pattern::to_caps_format)
+                .to_value(),
+            "isp-params" => self.isp_params().to_value(),
+            name => self.settings_property(&self.settings.lock().unwrap(), name),
+        }
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        // The base class only chains single buffers, so lists would be split up by the
+        // pad.
+        let sinkpad = self.obj().sink_pad().clone();
+        // SAFETY: the base class sets no chain list function that could be replaced.
+        unsafe {
+            sinkpad.set_chain_list_function(|_pad, parent, list| {
+                RsBayer2Rgb::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |imp| imp.sink_chain_list(list),
+                )
+            });
+        }
     }
 }
 
-impl ObjectImpl for RsBayer2Rgb {}
 impl GstObjectImpl for RsBayer2Rgb {}
 
 impl ElementImpl for RsBayer2Rgb {
@@ -125,7 +4704,8 @@ impl ElementImpl for RsBayer2Rgb {
             gst::subclass::ElementMetadata::new(
                 "Bayer to RGB Converter",
                 "Filter/Converter/Video",
-                "Uses OpenCV to convert bayer formats to RGB/BGR formats",
+                "Converts bayer formats to RGB/BGR formats, or deep bayer formats to P010, with \
+                 OpenCV or a built-in demosaic",
                 "Eric Bridgeford",
             )
         });
@@ -134,7 +4714,8 @@ impl ElementImpl for RsBayer2Rgb {
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
             let sink_caps = gst::Caps::builder("video/x-bayer")
-                .field("format", "rggb")
+                .features([gst::CAPS_FEATURE_MEMORY_SYSTEM_MEMORY])
+                .field("format", gst::List::new(info::caps_formats()))
                 .field("width", gst::IntRange::new(1, i32::MAX))
                 .field("height", gst::IntRange::new(1, i32::MAX))
                 .field(
@@ -154,34 +4735,500 @@ impl ElementImpl for RsBayer2Rgb {
             )
             .unwrap();
 
-            let src_caps = gst_video::VideoCapsBuilder::new()
-                .format_list([
-                    //gst_video::VideoFormat::Rgb,
-                   // gst_video::VideoFormat::Bgr,
-                    gst_video::VideoFormat::Rgba,
-                ])
-                .build();
+            // P010 only for input deeper than 8 bits, see transform_caps().
+            #[allow(unused_mut)]
+            let mut src_caps = gst_video::VideoCapsBuilder::new()
+                .features([gst::CAPS_FEATURE_MEMORY_SYSTEM_MEMORY])
+                .format_list(Converter::OUTPUT_FORMATS.into_iter().chain([yuv::FORMAT]))
+                .build();
+            #[cfg(feature = "gl")]
+            {
+                let mut caps = gst_video::VideoCapsBuilder::new()
+                    .features([gst_gl::CAPS_FEATURE_MEMORY_GL_MEMORY])
+                    .format(gst_video::VideoFormat::Rgba)
+                    .field("texture-target", gl::TEXTURE_TARGET)
+                    .build();
+                caps.merge(src_caps);
+                src_caps = caps;
+            }
+            #[cfg(feature = "dmabuf")]
+            {
+                let mut caps = gst_video::VideoCapsBuilder::new()
+                    .features([dmabuf::CAPS_FEATURE])
+                    .format_list(Converter::OUTPUT_FORMATS)
+                    .build();
+                caps.merge(src_caps);
+                src_caps = caps;
+            }
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &src_caps,
+            )
+            .unwrap();
+
+            let preview_caps = gst_video::VideoCapsBuilder::new()
+                .format_list(Converter::OUTPUT_FORMATS)
+                .build();
+            let preview_pad_template = gst::PadTemplate::with_gtype(
+                "preview_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &preview_caps,
+                PreviewPad::static_type(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template, preview_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn request_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        name: Option<&str>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let obj = self.obj();
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => (0..)
+                .map(|n| format!("preview_{n}"))
+                .find(|name| obj.static_pad(name).is_none())
+                .unwrap(),
+        };
+        if obj.static_pad(&name).is_some() {
+            gst::error!(CAT, imp = self, "Pad {} already exists", name);
+            return None;
+        }
+
+        // Upstream events from the preview's downstream aren't for our upstream.
+        let pad = gst::PadBuilder::<PreviewPad>::from_template(templ)
+            .name(name.as_str())
+            .event_function(|_, _, event| matches!(event.view(), gst::EventView::Reconfigure(_)))
+            .build();
+        obj.add_pad(&pad).ok()?;
+        self.previews.lock().unwrap().push(pad.clone());
+        gst::debug!(CAT, imp = self, "Requested {}", name);
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, pad: &gst::Pad) {
+        let Some(preview) = pad.downcast_ref::<PreviewPad>() else {
+            return;
+        };
+        gst::debug!(CAT, imp = self, "Releasing {}", pad.name());
+        self.previews
+            .lock()
+            .unwrap()
+            .retain(|other| other != preview);
+        let _ = pad.set_active(false);
+        preview.reset();
+        let _ = self.obj().remove_pad(pad);
+    }
+
+    #[cfg(feature = "gl")]
+    fn set_context(&self, context: &gst::Context) {
+        self.gl.set_context(self.obj().upcast_ref(), context);
+        self.parent_set_context(context)
+    }
+}
+
+impl BaseTransformImpl for RsBayer2Rgb {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::NeverInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn generate_output(
+        &self,
+    ) -> Result<gst_base::subclass::base_transform::GenerateOutputSuccess, gst::FlowError> {
+        let (max_queue_buffers, leaky) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.max_queue_buffers, settings.leaky)
+        };
+        let worker = self.worker.lock().unwrap().clone();
+        if let Some(worker) = worker {
+            return self.queue_output(&worker, max_queue_buffers as usize, leaky);
+        }
+
+        let res = self.parent_generate_output()?;
+
+        if let gst_base::subclass::base_transform::GenerateOutputSuccess::Buffer(ref buffer) =
+            res
+        {
+            self.output_produced(buffer);
+        }
+
+        Ok(res)
+    }
+
+    fn submit_input_buffer(
+        &self,
+        is_discont: bool,
+        inbuf: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let stack_frames = self.settings.lock().unwrap().stack_frames;
+        if stack_frames <= 1 {
+            return self.parent_submit_input_buffer(is_discont, inbuf);
+        }
+
+        // Only complete stacks are handed on, generate_output() has nothing to do for
+        // the other frames.
+        let mut stack_guard = self.stack.lock().unwrap();
+        let discont = is_discont || inbuf.flags().contains(gst::BufferFlags::DISCONT);
+        let res = match stack_guard.as_mut() {
+            Some(stack) if !discont => stack.add(&inbuf),
+            _ => {
+                if let Some(stack) = stack_guard.take() {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Discontinuity, dropping {} stacked frames",
+                        stack.frames()
+                    );
+                }
+                // The sink caps are set before the first buffer arrives.
+                let depth = self
+                    .obj()
+                    .sink_pad()
+                    .current_caps()
+                    .and_then(|caps| BayerInfo::from_caps(&caps).ok())
+                    .map_or(Depth::Eight, |info| info.depth);
+                stack::Stack::new(inbuf, depth).map(|stack| {
+                    *stack_guard = Some(stack);
+                })
+            }
+        };
+        res.map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to stack frame: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        if stack_guard
+            .as_ref()
+            .is_some_and(|stack| stack.frames() < stack_frames)
+        {
+            return Ok(gst::FlowSuccess::Ok);
+        }
+        let stacked = stack_guard.take().unwrap().finish().map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to average stacked frames: {}", err);
+            gst::FlowError::Error
+        })?;
+        drop(stack_guard);
+
+        let discont = stacked.flags().contains(gst::BufferFlags::DISCONT);
+        self.parent_submit_input_buffer(discont, stacked)
+    }
+
+    fn sink_event(&self, event: gst::Event) -> bool {
+        // Keep serialized events behind the buffers still queued for conversion.
+        let worker = self.worker.lock().unwrap().clone();
+        if let Some(worker) = worker {
+            match event.view() {
+                gst::EventView::FlushStart(_) => worker.set_flushing(true),
+                gst::EventView::FlushStop(_) => worker.set_flushing(false),
+                _ if event.is_serialized() => worker.drain(),
+                _ => (),
+            }
+        }
+
+        let event = self.orientation_tag(event);
+
+        let previews = self.previews.lock().unwrap().clone();
+        for preview in previews {
+            preview.sink_event(&event);
+        }
+
+        // Frames from before the flush mustn't bleed into the temporal filter or the
+        // next stack.
+        if let gst::EventView::FlushStop(_) = event.view() {
+            if let Some(state) = self.state.lock().unwrap().as_mut() {
+                state.converter.reset_history();
+            }
+            *self.stack.lock().unwrap() = None;
+        }
+
+        self.parent_sink_event(event)
+    }
+
+    // BaseTransform has no idea of bayer caps, VideoInfo none of bayer formats. The
+    // VideoInfo sizes cover every plane, both of P010 included.
+    fn unit_size(&self, caps: &gst::Caps) -> Option<usize> {
+        if caps.structure(0)?.name() == "video/x-bayer" {
+            BayerInfo::from_caps(caps).ok().map(|info| info.size())
+        } else {
+            gst_video::VideoInfo::from_caps(caps)
+                .ok()
+                .map(|info| info.size())
+        }
+    }
+
+    fn transform_size(
+        &self,
+        direction: gst::PadDirection,
+        caps: &gst::Caps,
+        size: usize,
+        othercaps: &gst::Caps,
+    ) -> Option<usize> {
+        if direction == gst::PadDirection::Sink {
+            // The output size only depends on the output caps, so padded input frames
+            // (see propose_allocation()) needn't be a multiple of the input unit size.
+            gst_video::VideoInfo::from_caps(othercaps)
+                .ok()
+                .map(|info| info.size())
+        } else {
+            self.parent_transform_size(direction, caps, size, othercaps)
+        }
+    }
+
+    fn transform_meta<'a>(
+        &self,
+        outbuf: &mut gst::BufferRef,
+        meta: gst::MetaRef<'a, gst::Meta>,
+        inbuf: &'a gst::BufferRef,
+    ) -> bool {
+        // Capture times and timecodes hold whatever the geometry and whichever tags
+        // they have. The base class has already copied flags, timestamps and offsets.
+        if meta.downcast_ref::<gst::ReferenceTimestampMeta>().is_some()
+            || meta
+                .downcast_ref::<gst_video::VideoTimeCodeMeta>()
+                .is_some()
+        {
+            return true;
+        }
+        if let Some(roi) = meta.downcast_ref::<gst_video::VideoRegionOfInterestMeta>() {
+            self.copy_roi(outbuf, roi);
+            return false;
+        }
+
+        let api = meta.api();
+        if self
+            .settings
+            .lock()
+            .unwrap()
+            .extra_meta_apis
+            .iter()
+            .any(|name| name == api.name())
+        {
+            return true;
+        }
+
+        self.parent_transform_meta(outbuf, meta, inbuf)
+    }
+
+    fn propose_allocation(
+        &self,
+        decide_query: Option<&gst::query::Allocation>,
+        query: &mut gst::query::Allocation,
+    ) -> Result<(), gst::LoggableError> {
+        self.parent_propose_allocation(decide_query, query)?;
+
+        // Let upstream push padded frames directly instead of tight-packing them for us,
+        // transform() picks up the real stride and offset from the meta.
+        query.add_allocation_meta::<gst_video::VideoMeta>(None);
+
+        Ok(())
+    }
+
+    fn decide_allocation(
+        &self,
+        query: &mut gst::query::Allocation,
+    ) -> Result<(), gst::LoggableError> {
+        let (min_buffers, max_buffers, alignment) = {
+            let settings = self.settings.lock().unwrap();
+            (
+                settings.min_buffers,
+                settings.max_buffers,
+                settings.output_alignment,
+            )
+        };
+
+        let (caps, _) = query.get_owned();
+        let caps = caps.ok_or_else(|| gst::loggable_error!(CAT, "No caps in allocation query"))?;
+        let info = gst_video::VideoInfo::from_caps(&caps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse allocation caps"))?;
+        let size = u32::try_from(info.size()).map_err(|_| {
+            gst::loggable_error!(
+                CAT,
+                "Output frames of {} bytes are too large for a buffer pool",
+                info.size()
+            )
+        })?;
+
+        #[cfg(feature = "gl")]
+        let gl_context = if gl::is_gl_caps(&caps) {
+            Some(
+                self.gl
+                    .ensure_context(self.obj().upcast_ref())
+                    .ok_or_else(|| gst::loggable_error!(CAT, "No GL context for GL memory"))?,
+            )
+        } else {
+            None
+        };
+
+        let video_meta = query
+            .find_allocation_meta::<gst_video::VideoMeta>()
+            .is_some();
+
+        // Padded strides are only safe when downstream reads them from the VideoMeta
+        // the pool attaches; the converters already follow the mapped plane stride.
+        // GL pools lay out their textures themselves.
+        #[cfg(feature = "gl")]
+        let alignment = if gl_context.is_some() { 1 } else { alignment };
+        let aligned = video_meta && alignment > 1;
+
+        // Adopt the pool downstream proposed, or fall back to our own video pool. The
+        // base class deactivates and replaces the previous pool on renegotiation.
+        let first = query.allocation_pools().next();
+        let update = first.is_some();
+        let proposed = first.and_then(|(pool, _, _, _)| pool);
+        // A proposed pool that can't pad the rows would hand out unaligned frames.
+        let proposed = proposed.filter(|pool| {
+            !aligned || pool.has_option(gst_video::BUFFER_POOL_OPTION_VIDEO_ALIGNMENT)
+        });
+        #[cfg(feature = "gl")]
+        let proposed = proposed.filter(|pool| {
+            gl_context.is_none() || pool.is::<gst_gl::GLBufferPool>()
+        });
+        // DMABuf output always comes from our own pool, which allocates it from the
+        // DMA heap.
+        #[cfg(feature = "dmabuf")]
+        let dmabuf_output = dmabuf::is_dmabuf_caps(&caps);
+        #[cfg(feature = "dmabuf")]
+        let proposed = proposed.filter(|_| !dmabuf_output);
+        let pool = match proposed {
+            Some(pool) => pool,
+            #[cfg(feature = "gl")]
+            None if gl_context.is_some() => gl::buffer_pool(gl_context.as_ref().unwrap()),
+            None => gst_video::VideoBufferPool::new().upcast(),
+        };
+
+        let mut config = pool.config();
+        config.set_params(Some(&caps), size, min_buffers, max_buffers);
+        #[cfg(feature = "dmabuf")]
+        if dmabuf_output {
+            let allocator = dmabuf::HeapAllocator::default();
+            config.set_allocator(Some(allocator.upcast_ref::<gst::Allocator>()), None);
+        }
+        if video_meta {
+            config.add_option(gst_video::BUFFER_POOL_OPTION_VIDEO_META);
+        }
+        if aligned {
+            let mask = alignment - 1;
+            let video_align = gst_video::VideoAlignment::new(
+                0,
+                0,
+                0,
+                0,
+                &[mask; gst_video::ffi::GST_VIDEO_MAX_PLANES as usize],
+            );
+            config.add_option(gst_video::BUFFER_POOL_OPTION_VIDEO_ALIGNMENT);
+            config.set_video_alignment(&video_align);
+
+            let params =
+                gst::AllocationParams::new(gst::MemoryFlags::empty(), mask as usize, 0, 0);
+            let first = query.allocation_params().next();
+            match first {
+                Some((allocator, _)) => {
+                    query.set_nth_allocation_param(0, allocator.as_ref(), params)
+                }
+                None => query.add_allocation_param(None::<&gst::Allocator>, params),
+            }
+        }
+        pool.set_config(config)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to configure buffer pool"))?;
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Using pool {:?} with size {}, min {}, max {}",
+            pool,
+            size,
+            min_buffers,
+            max_buffers
+        );
+
+        if update {
+            query.set_nth_allocation_pool(0, Some(&pool), size, min_buffers, max_buffers);
+        } else {
+            query.add_allocation_pool(Some(&pool), size, min_buffers, max_buffers);
+        }
+
+        self.parent_decide_allocation(query)
+    }
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        self.stats.reset();
+        self.quality.lock().unwrap().reset();
+
+        let calibration = Calibration::load(&self.settings.lock().unwrap())?;
+        *self.calibration.lock().unwrap() = calibration;
+
+        let max_queue_buffers = self.settings.lock().unwrap().max_queue_buffers;
+        if max_queue_buffers > 0 {
+            let element = self.obj().downgrade();
+            let name = format!("{}:convert", self.obj().name());
+            let worker = Worker::new(name, move |inbuf, outbuf| {
+                let element = element.upgrade().ok_or(gst::FlowError::Flushing)?;
+                element.imp().finish_queued(inbuf, outbuf)
+            })
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Failed,
+                    ["Failed to start conversion thread: {}", err]
+                )
+            })?;
+            *self.worker.lock().unwrap() = Some(std::sync::Arc::new(worker));
+        }
+
+        #[cfg(feature = "opencv")]
+        {
+            let opencv_threads = self.settings.lock().unwrap().opencv.opencv_threads;
+            if opencv_threads >= 0 {
+                cv::apply_opencv_threads(opencv_threads);
+            }
+        }
 
-            let src_pad_template = gst::PadTemplate::new(
-                "src",
-                gst::PadDirection::Src,
-                gst::PadPresence::Always,
-                &src_caps,
-            )
-            .unwrap();
+        Ok(())
+    }
 
-            vec![src_pad_template, sink_pad_template]
-        });
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        // Joins the conversion thread, the pads are already deactivated.
+        let worker = self.worker.lock().unwrap().take();
+        drop(worker);
+        // Writes out the frames still queued for dumping.
+        #[cfg(feature = "dump")]
+        drop(self.dump_writer.lock().unwrap().take());
 
-        PAD_TEMPLATES.as_ref()
+        for preview in self.previews.lock().unwrap().iter() {
+            preview.reset();
+        }
+        *self.last_sample.lock().unwrap() = None;
+        *self.detected.lock().unwrap() = None;
+        self.settings.lock().unwrap().tag_direction = gst_video::VideoOrientationMethod::Identity;
+        *self.stack.lock().unwrap() = None;
+        *self.calibration.lock().unwrap() = Calibration::default();
+        #[cfg(feature = "gl")]
+        self.gl.reset();
+        Ok(())
     }
-}
 
-impl BaseTransformImpl for RsBayer2Rgb {
-    const MODE: gst_base::subclass::BaseTransformMode =
-        gst_base::subclass::BaseTransformMode::NeverInPlace;
-    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
-    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    #[cfg(feature = "gl")]
+    fn query(&self, direction: gst::PadDirection, query: &mut gst::QueryRef) -> bool {
+        if let gst::QueryViewMut::Context(context) = query.view_mut()
+            && self.gl.handle_query(self.obj().upcast_ref(), context)
+        {
+            return true;
+        }
+
+        BaseTransformImplExt::parent_query(self, direction, query)
+    }
 
     fn transform_caps(
         &self,
@@ -189,22 +5236,52 @@ impl BaseTransformImpl for RsBayer2Rgb {
         caps: &gst::Caps,
         filter: Option<&gst::Caps>,
     ) -> Option<gst::Caps> {
+        let geometry = Geometry::new(&self.settings.lock().unwrap());
+
         let other_caps = if direction == gst::PadDirection::Src {
             // Transform src caps to sink caps (RGB -> Bayer)
             let mut result = gst::Caps::new_empty();
 
-            for s in caps.iter() {
-                let width = s.get::<i32>("width").ok();
-                let height = s.get::<i32>("height").ok();
-                let framerate = s.get::<gst::Fraction>("framerate").ok();
+            for (s, features) in caps.iter_with_features() {
+                if foreign_memory(features).is_some_and(|memory| !OUTPUT_MEMORY.contains(&memory)) {
+                    continue;
+                }
+                // Interlaced input is always converted to progressive output.
+                if s.get_optional::<&str>("interlace-mode")
+                    .is_ok_and(|mode| mode.is_some_and(|mode| mode != "progressive"))
+                {
+                    continue;
+                }
+                let Some((width, height)) =
+                    geometry.unscale(s.get::<i32>("width").ok(), s.get::<i32>("height").ok())
+                else {
+                    continue;
+                };
+                let (width, height) = geometry.turn(width, height);
+                let framerate = s
+                    .get::<gst::Fraction>("framerate")
+                    .ok()
+                    .and_then(|fr| geometry.input_framerate(fr));
 
-                let mut new_s = gst::Structure::builder("video/x-bayer").field("format", "rggb");
+                // P010 is only made from deeper input.
+                let formats = match s.get::<&str>("format") {
+                    Ok(format) if format == yuv::FORMAT.to_str() => info::deep_caps_formats(),
+                    _ => info::caps_formats(),
+                };
+                let mut new_s = gst::Structure::builder("video/x-bayer")
+                    .field("format", gst::List::new(formats));
 
                 if let Some(w) = width {
-                    new_s = new_s.field("width", w);
+                    match geometry.input_field(new_s, "width", w, geometry.cols) {
+                        Some(builder) => new_s = builder,
+                        None => continue,
+                    }
                 }
                 if let Some(h) = height {
-                    new_s = new_s.field("height", h);
+                    match geometry.input_field(new_s, "height", h, geometry.rows) {
+                        Some(builder) => new_s = builder,
+                        None => continue,
+                    }
                 }
                 if let Some(fr) = framerate {
                     new_s = new_s.field("framerate", fr);
@@ -217,17 +5294,69 @@ impl BaseTransformImpl for RsBayer2Rgb {
             // Transform sink caps to src caps (Bayer -> RGB)
             let mut result = gst::Caps::new_empty();
 
-            for s in caps.iter() {
+            // GL memory first, but only if a GL display is shared or can be created,
+            // and the output isn't scaled, which GL conversion can't do.
+            #[cfg(feature = "gl")]
+            if !geometry.scales() && self.gl.ensure_display(self.obj().upcast_ref()) {
+                for (s, features) in caps.iter_with_features() {
+                    if foreign_memory(features).is_some() {
+                        continue;
+                    }
+                    let mut new_s = gst::Structure::builder("video/x-raw")
+                        .field("format", gst_video::VideoFormat::Rgba.to_str())
+                        .field("texture-target", gl::TEXTURE_TARGET);
+                    let framerate = s
+                        .get::<gst::Fraction>("framerate")
+                        .ok()
+                        .and_then(|fr| geometry.output_framerate(fr));
+                    new_s = new_s.field_if_some("framerate", framerate);
+                    let (width, height) = geometry.turn(
+                        s.get::<i32>("width")
+                            .ok()
+                            .map(|w| geometry.caps_output_size(w, geometry.cols)),
+                        s.get::<i32>("height")
+                            .ok()
+                            .map(|h| geometry.caps_output_size(h, geometry.rows)),
+                    );
+                    new_s = new_s.field_if_some("width", width);
+                    new_s = new_s.field_if_some("height", height);
+
+                    result.get_mut().unwrap().append_structure_full(
+                        new_s.build(),
+                        Some(gst::CapsFeatures::new([gst_gl::CAPS_FEATURE_MEMORY_GL_MEMORY])),
+                    );
+                }
+            }
+
+            let mut system = gst::Caps::new_empty();
+            let mut p010 = gst::Caps::new_empty();
+            for (s, features) in caps.iter_with_features() {
+                // Frames in other memory can't be mapped, see accept_caps().
+                if foreign_memory(features).is_some() {
+                    continue;
+                }
+                // Interleaved fields become progressive frames, other interlace modes
+                // can't be converted. Lists of modes are left to set_caps().
+                let progressive = match s.get_optional::<&str>("interlace-mode") {
+                    Ok(None) => false,
+                    Ok(Some("progressive" | "interleaved")) | Err(_) => true,
+                    Ok(Some(_)) => continue,
+                };
                 let width = s.get::<i32>("width").ok();
                 let height = s.get::<i32>("height").ok();
-                let framerate = s.get::<gst::Fraction>("framerate").ok();
+                let framerate = s
+                    .get::<gst::Fraction>("framerate")
+                    .ok()
+                    .and_then(|fr| geometry.output_framerate(fr));
+
+                let (width, height) = geometry.turn(
+                    width.map(|w| geometry.caps_output_size(w, geometry.cols)),
+                    height.map(|h| geometry.caps_output_size(h, geometry.rows)),
+                );
+                let (width, height) = geometry.scale(width, height);
 
                 // Create RGB variants
-                for format in [
-                    //gst_video::VideoFormat::Rgb,
-                    //gst_video::VideoFormat::Bgr,
-                    gst_video::VideoFormat::Rgba,
-                ] {
+                for format in Converter::OUTPUT_FORMATS {
                     let mut new_s =
                         gst::Structure::builder("video/x-raw").field("format", format.to_str());
 
@@ -240,14 +5369,45 @@ impl BaseTransformImpl for RsBayer2Rgb {
                     if let Some(fr) = framerate {
                         new_s = new_s.field("framerate", fr);
                     }
+                    if progressive {
+                        new_s = new_s.field("interlace-mode", "progressive");
+                    }
+
+                    system.get_mut().unwrap().append_structure(new_s.build());
+                }
 
-                    result.get_mut().unwrap().append_structure(new_s.build());
+                // P010 after RGB, for input that may be deeper than 8 bits and isn't
+                // scaled, which only 8-bit conversions can.
+                if !geometry.scales() && deep_input(s) {
+                    let mut new_s = gst::Structure::builder("video/x-raw")
+                        .field("format", yuv::FORMAT.to_str());
+                    new_s = new_s.field_if_some("width", width);
+                    new_s = new_s.field_if_some("height", height);
+                    new_s = new_s.field_if_some("framerate", framerate);
+                    if progressive {
+                        new_s = new_s.field("interlace-mode", "progressive");
+                    }
+                    p010.get_mut().unwrap().append_structure(new_s.build());
                 }
             }
+
+            // The same in DMABuf memory before system memory, if there's a heap to
+            // allocate it from.
+            #[cfg(feature = "dmabuf")]
+            if dmabuf::available() {
+                let mut dmabuf_caps = system.clone();
+                dmabuf_caps
+                    .get_mut()
+                    .unwrap()
+                    .set_features_simple(Some(gst::CapsFeatures::new([dmabuf::CAPS_FEATURE])));
+                result.get_mut().unwrap().append(dmabuf_caps);
+            }
+            result.get_mut().unwrap().append(system);
+            result.get_mut().unwrap().append(p010);
             result
         };
 
-        gst::info!(
+        gst::debug!(
             CAT,
             imp = self,
             "Transformed caps from {} to {} in direction {:?}",
@@ -263,53 +5423,215 @@ impl BaseTransformImpl for RsBayer2Rgb {
         }
     }
 
+    fn accept_caps(&self, direction: gst::PadDirection, caps: &gst::Caps) -> bool {
+        // Input in NVMM, GL or other device memory would only fail once mapped, name
+        // the memory so the missing download element is obvious.
+        let foreign = caps
+            .iter_with_features()
+            .find_map(|(_, features)| foreign_memory(features));
+        if let (gst::PadDirection::Sink, Some(feature)) = (direction, foreign) {
+            gst::element_imp_error!(
+                self,
+                gst::CoreError::Negotiation,
+                ("Input in {} isn't supported, only system memory", feature),
+                [
+                    "Download the frames to system memory before {}, with gldownload, \
+                     nvvidconv or the like",
+                    self.obj().name()
+                ]
+            );
+            return false;
+        }
+
+        self.parent_accept_caps(direction, caps)
+    }
+
     fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
-        gst::info!(CAT, imp = self, "Input caps: {}", incaps);
-        gst::info!(CAT, imp = self, "Output caps: {}", outcaps);
-
-        // Parse Bayer input caps manually (VideoInfo doesn't support Bayer)
-        let s = incaps.structure(0).unwrap();
-        let width =
-            s.get::<i32>("width")
-                .map_err(|_| gst::loggable_error!(CAT, "No width in caps"))? as usize;
-        let height =
-            s.get::<i32>("height")
-                .map_err(|_| gst::loggable_error!(CAT, "No height in caps"))? as usize;
-
-        // For Bayer, stride is typically width (1 byte per pixel) but may be padded
-        // Use width as stride - GStreamer will pad if needed
-        let stride = width;
+        gst::debug!(CAT, imp = self, "Input caps: {}", incaps);
+        gst::debug!(CAT, imp = self, "Output caps: {}", outcaps);
+
+        let bayer =
+            BayerInfo::from_caps(incaps).map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+        let (width, height, interlaced) = (bayer.width, bayer.height, bayer.interlaced);
+        let (mode, quad, x_phase, y_phase, [top, bottom, left, right]) = {
+            let settings = self.settings.lock().unwrap();
+            (
+                settings.pattern,
+                (settings.cfa_layout == CfaLayout::Quad).then_some(settings.quad_mode),
+                settings.x_phase as usize,
+                settings.y_phase as usize,
+                [
+                    settings.skip_lines_top,
+                    settings.skip_lines_bottom,
+                    settings.skip_cols_left,
+                    settings.skip_cols_right,
+                ]
+                .map(|skip| skip as usize),
+            )
+        };
+        // Fields are only supported interleaved in one buffer. The output is
+        // progressive, the field order only ends up in the log.
+        if interlaced {
+            let field_order = incaps
+                .structure(0)
+                .unwrap()
+                .get_optional::<&str>("field-order")
+                .ok()
+                .flatten()
+                .unwrap_or("unknown field order");
+            gst::info!(CAT, imp = self, "Interlaced input, {}", field_order);
+        }
+
+        if left.saturating_add(right) >= width || top.saturating_add(bottom) >= height {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Skipping {} lines and {} columns leaves nothing of the {}x{} input",
+                top.saturating_add(bottom),
+                left.saturating_add(right),
+                width,
+                height
+            ));
+        }
+        if quad.is_some() {
+            if interlaced {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Interlaced quad bayer input isn't supported"
+                ));
+            }
+            // Odd margins would split the blocks of a color.
+            if left % 2 != 0 || top % 2 != 0 {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Can't skip {} lines and {} columns of quad bayer input",
+                    top,
+                    left
+                ));
+            }
+        }
+        // Skipping an odd number of lines would swap the fields.
+        if interlaced && top % 2 != 0 {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Can't skip {} lines at the top of interlaced input",
+                top
+            ));
+        }
+        if top + bottom + left + right > 0 {
+            gst::info!(
+                CAT,
+                imp = self,
+                "Skipping {} lines at the top, {} at the bottom, {} columns left, {} right",
+                top,
+                bottom,
+                left,
+                right
+            );
+        }
+
+        let pattern = match mode {
+            InputPattern::Caps | InputPattern::AutoDetect => bayer.pattern,
+            InputPattern::Rggb => Pattern::Rggb,
+            InputPattern::Bggr => Pattern::Bggr,
+            InputPattern::Gbrg => Pattern::Gbrg,
+            InputPattern::Grbg => Pattern::Grbg,
+        };
+        let detected = *self.detected.lock().unwrap();
+        // Detection sees the input without the skipped margins, as does everything
+        // else, so only the pattern of the caps moves with them. Quad bayer
+        // patterns move by a block every two pixels.
+        let (shift_x, shift_y) = match quad {
+            Some(_) => (left / 2, top / 2),
+            None => (left, top),
+        };
+        let pattern = match (mode, detected) {
+            // Until detection locks in, the pattern of the caps is as good as any.
+            (InputPattern::AutoDetect, detected) => {
+                detected.unwrap_or_else(|| pattern.offset(shift_x, shift_y))
+            }
+            _ => pattern.offset(x_phase, y_phase).offset(shift_x, shift_y),
+        };
+        if pattern != bayer.pattern {
+            gst::info!(
+                CAT,
+                imp = self,
+                "Converting {} input as {} (pattern {:?}, phase ({}, {}), skipped ({}, {}))",
+                bayer.pattern,
+                pattern,
+                mode,
+                x_phase,
+                y_phase,
+                left,
+                top
+            );
+        }
+
+        let (visible_width, visible_height) = (width - left - right, height - top - bottom);
+        let (frame_width, frame_height) = match quad {
+            Some(QuadMode::Binning) => (visible_width / 2, visible_height / 2),
+            _ => (visible_width, visible_height),
+        };
+        let whole_tiles = visible_width % 4 == 0 && visible_height % 4 == 0;
+        if frame_width == 0 || frame_height == 0 {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Nothing is left of {}x{} quad bayer input to bin",
+                visible_width,
+                visible_height
+            ));
+        }
+        if quad == Some(QuadMode::Remosaic) && !whole_tiles {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Can't remosaic {}x{} quad bayer input, it isn't made of whole 4x4 tiles",
+                visible_width,
+                visible_height
+            ));
+        }
+        if let Some(quad) = quad {
+            gst::info!(CAT, imp = self, "Converting quad bayer input, {:?}", quad);
+        }
 
         let in_info = InputInfo {
-            width,
-            height,
-            stride,
+            pattern,
+            width: frame_width,
+            height: frame_height,
+            left,
+            top,
+            quad,
+            bayer,
         };
         // Parse RGB output caps using VideoInfo
         let out_info = gst_video::VideoInfo::from_caps(outcaps)
             .map_err(|_| gst::loggable_error!(CAT, "Failed to parse output caps"))?;
+        // The largest frame made from the input is the 16-bit four channel output of
+        // high precision, padded by the border on every side. Saturated sizes fail.
+        let padded = |size: usize| size.saturating_add(2 * border::BORDER);
+        let row_bytes = padded(frame_width).saturating_mul(4 * std::mem::size_of::<u16>());
+        if !frame::addressable(padded(frame_height), row_bytes) {
+            return Err(gst::loggable_error!(
+                CAT,
+                "{}x{} frames are too large to process",
+                frame_width,
+                frame_height
+            ));
+        }
 
         gst::info!(
             CAT,
             imp = self,
-            "Input: {}x{}, stride: {}",
+            "Input: {}x{}, stride: {}, output: {:?}, stride: {}",
             width,
             height,
-            stride
-        );
-        gst::info!(
-            CAT,
-            imp = self,
-            "Output: {:?}, stride: {}",
+            bayer.stride,
             out_info.format(),
             out_info.stride()[0]
         );
 
-        *self.state.lock().unwrap() = Some(State {
-            in_info,
-            out_info,
-            intermediate_rgb: None,
-        });
+        let state = self.new_state(in_info, out_info, interlaced)?;
+        self.publish(&state);
+        *self.state.lock().unwrap() = Some(state);
+        *self.stack.lock().unwrap() = None;
 
         Ok(())
     }
@@ -319,120 +5641,767 @@ impl BaseTransformImpl for RsBayer2Rgb {
         inbuf: &gst::Buffer,
         outbuf: &mut gst::BufferRef,
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.watch_tuning_file();
+
+        // Sample any bound control sources at this buffer's stream time before taking
+        // the settings snapshot, so controlled values apply to the whole frame.
+        if let Some(pts) = inbuf.pts() {
+            let stream_time = self
+                .obj()
+                .segment()
+                .downcast_ref::<gst::ClockTime>()
+                .and_then(|segment| segment.to_stream_time(pts))
+                .unwrap_or(pts);
+            if let Err(err) = self.obj().sync_values(stream_time) {
+                gst::warning!(CAT, imp = self, "Failed to sync controlled values: {}", err);
+            }
+        }
+
+        let mut settings = {
+            let _loading = self.tuning_lock.lock().unwrap();
+            self.settings.lock().unwrap().clone()
+        };
+        if settings.exposure_compensation {
+            settings.exposure_gain *= self.exposure_compensation(inbuf, &settings);
+        }
+        apply_quality(&mut settings, self.quality.lock().unwrap().level());
+
         let mut state_guard = self.state.lock().unwrap();
         let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
 
-        let in_map = inbuf.map_readable().map_err(|_| gst::FlowError::Error)?;
-        let in_data = in_map.as_slice();
+        if inbuf.flags().contains(gst::BufferFlags::DISCONT) {
+            state.converter.reset_history();
+            if let Some(field_converter) = &mut state.field_converter {
+                field_converter.reset_history();
+            }
+            if let Some((_, converter)) = state
+                .process_roi
+                .as_mut()
+                .and_then(|process_roi| process_roi.converter.as_mut())
+            {
+                converter.reset_history();
+            }
+        }
+
+        // Upstream may push padded frames described by a VideoMeta since we advertise
+        // support for it in propose_allocation().
+        let in_frame =
+            BayerFrame::from_buffer_readable(inbuf, &state.in_info.bayer).map_err(|err| {
+                gst::error!(CAT, imp = self, "{}", err);
+                gst::FlowError::Error
+            })?;
+        let bayer = state.in_info.bayer;
+
+        // Deeper samples are narrowed to the 8 bits the conversion works on.
+        let mut narrowed = std::mem::take(&mut state.narrowed);
+        let (in_data, in_stride) = match bayer.depth {
+            Depth::Eight => (in_frame.data(), in_frame.stride()),
+            depth => {
+                unpack::narrow(
+                    in_frame.data(),
+                    in_frame.stride(),
+                    bayer.width,
+                    bayer.height,
+                    depth,
+                    &mut narrowed,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, imp = self, "Failed to narrow input: {}", err);
+                    gst::FlowError::Error
+                })?;
+                (narrowed.as_slice(), bayer.width)
+            }
+        };
+
+        // Everything but the embedded data message sees the input past the skipped
+        // margins.
+        let skipped = state.in_info.top * in_stride + state.in_info.left;
+        let (skipped, in_data) = in_data.split_at_checked(skipped).ok_or_else(|| {
+            gst::error!(CAT, imp = self, "Input too small for the skipped margins");
+            gst::FlowError::Error
+        })?;
+        let embedded_data_message = (settings.post_embedded_data && state.in_info.top > 0)
+            .then(|| self.embedded_data_message(skipped, in_stride, &state.in_info, inbuf.pts()));
+
+        let mut quad_samples = std::mem::take(&mut state.quad_samples);
+        let mut woven = std::mem::take(&mut state.woven);
+        let (in_data, in_stride) = self.mosaic(
+            in_data,
+            in_stride,
+            &state.in_info,
+            state.fields,
+            &mut quad_samples,
+            &mut woven,
+        )?;
+
+        // P010 is converted from the input widened to 16 bits, which goes through the
+        // same steps. Everything else, from the pattern detection to the stats, keeps
+        // to the 8-bit mosaic.
+        let mut wide = std::mem::take(&mut state.wide);
+        let wide_input = match state.out_info.format() == yuv::FORMAT {
+            true => {
+                let WideFrames {
+                    widened,
+                    quad_samples,
+                    woven,
+                    rgb,
+                } = &mut wide;
+                unpack::widen(
+                    in_frame.data(),
+                    in_frame.stride(),
+                    bayer.width,
+                    bayer.height,
+                    bayer.depth,
+                    widened,
+                )
+                .map_err(|err| {
+                    gst::error!(CAT, imp = self, "Failed to widen input: {}", err);
+                    gst::FlowError::Error
+                })?;
+                let skipped = state.in_info.top * bayer.width + state.in_info.left;
+                let widened = widened.get(skipped..).ok_or_else(|| {
+                    gst::error!(CAT, imp = self, "Input too small for the skipped margins");
+                    gst::FlowError::Error
+                })?;
+                let (data, stride) = self.mosaic(
+                    widened,
+                    bayer.width,
+                    &state.in_info,
+                    state.fields,
+                    quad_samples,
+                    woven,
+                )?;
+                Some(WideInput { data, stride, rgb })
+            }
+            false => None,
+        };
+        let detected_message = self.detect_pattern(in_data, in_stride, state)?;
+        let wb_calibrated =
+            self.calibrate_white_balance(in_data, in_stride, &state.in_info, &mut settings);
+        let interlaced = state.fields.is_some();
 
         let mut out_frame =
             gst_video::VideoFrameRef::from_buffer_ref_writable(outbuf, &state.out_info)
                 .map_err(|_| gst::FlowError::Error)?;
 
-        gst::info!(
+        gst::trace!(
             CAT,
             imp = self,
             "Transform: {}x{}, in_stride={}",
             state.in_info.width,
             state.in_info.height,
-            state.in_info.stride,
+            state.in_info.bayer.stride,
+        );
+
+        let start = std::time::Instant::now();
+        let start_timestamp = gst::get_timestamp();
+        if let Err(err) = self.convert(
+            in_data,
+            in_stride,
+            wide_input,
+            &mut out_frame,
+            state,
+            &settings,
+        ) {
+            self.stats.frame_dropped();
+            return Err(err);
+        }
+        let end_timestamp = gst::get_timestamp();
+        let elapsed = start.elapsed();
+        let bayer_stats_message = settings.post_stats.then(|| {
+            self.bayer_stats_message(
+                in_data,
+                in_stride,
+                &state.in_info,
+                inbuf.pts(),
+                &settings,
+            )
+        });
+        let focus_message = settings
+            .post_focus_metric
+            .then(|| self.focus_message(&out_frame, state, inbuf.pts(), &settings));
+        #[cfg(feature = "dump")]
+        self.dump(
+            in_data,
+            in_stride,
+            &out_frame,
+            state,
+            inbuf.pts(),
+            &settings,
+        );
+        #[cfg(feature = "opencv")]
+        if settings.opencv.show_debug_overlay {
+            self.draw_overlay(&mut out_frame, state, elapsed, &settings);
+        }
+        state.timing.add(elapsed);
+        state.stats_timing.add(elapsed);
+        self.stats.frame_processed(elapsed);
+        let quality_changed = self.quality.lock().unwrap().update(
+            elapsed,
+            std::time::Duration::from_nanos(settings.processing_deadline),
         );
+        if let Some(level) = quality_changed {
+            gst::info!(CAT, imp = self, "Changing quality level to {:?}", level);
+        }
+
+        self.report_timing(&mut state.timing, settings.timing_report_interval);
+        let stats_message =
+            self.stats_message(state, settings.stats_interval, quality_changed.is_some());
+        state.woven = woven;
+        state.quad_samples = quad_samples;
+        state.narrowed = narrowed;
+        state.wide = wide;
+        let out_info = state.out_info.clone();
+
+        drop(out_frame);
+        drop(state_guard);
+
+        // Pools that lay out frames their own way describe them with a VideoMeta. Every
+        // other frame was written with the layout of the caps, which downstream not
+        // asking for our pool then learns from ours.
+        if outbuf.meta::<gst_video::VideoMeta>().is_none() {
+            gst_video::VideoMeta::add_full(
+                outbuf,
+                gst_video::VideoFrameFlags::empty(),
+                out_info.format(),
+                out_info.width(),
+                out_info.height(),
+                out_info.offset(),
+                out_info.stride(),
+            )
+            .map_err(|err| {
+                gst::error!(CAT, imp = self, "Failed to add VideoMeta: {}", err);
+                gst::FlowError::Error
+            })?;
+        }
+
+        if interlaced {
+            outbuf.unset_flags(gst::BufferFlags::from_bits_retain(
+                (gst_video::VideoBufferFlags::INTERLACED
+                    | gst_video::VideoBufferFlags::TFF
+                    | gst_video::VideoBufferFlags::RFF
+                    | gst_video::VideoBufferFlags::ONEFIELD)
+                    .bits(),
+            ));
+        }
+
+        if settings.attach_timing_meta {
+            RsBayerTimingMeta::add(
+                outbuf,
+                start_timestamp,
+                end_timestamp,
+                settings.method,
+                settings.demosaic_algorithm,
+            );
+        }
+
+        // Previews are pushed before the frame they're made of, which must be RGB.
+        let previews = match out_info.format() == yuv::FORMAT {
+            true => Vec::new(),
+            false => self.previews.lock().unwrap().clone(),
+        };
+        for preview in previews {
+            match preview.push_frame(outbuf, &out_info) {
+                Ok(_) | Err(gst::FlowError::NotLinked | gst::FlowError::Flushing) => (),
+                Err(err) => gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Failed to push preview on {}: {:?}",
+                    preview.name(),
+                    err
+                ),
+            }
+        }
 
-        match opencv_transform(&in_data, &mut out_frame, state) {
-            Ok(()) => Ok(gst::FlowSuccess::Ok),
-            Err(e) => Err(e),
+        if let Some(msg) = embedded_data_message.flatten() {
+            let _ = self.obj().post_message(msg);
+        }
+        if let Some(msg) = detected_message {
+            let _ = self.obj().post_message(msg);
         }
+        if let Some(msg) = bayer_stats_message.flatten() {
+            let _ = self.obj().post_message(msg);
+        }
+        if let Some(msg) = focus_message.flatten() {
+            let _ = self.obj().post_message(msg);
+        }
+        if let Some(msg) = stats_message {
+            let _ = self.obj().post_message(msg);
+        }
+        if let Some(gains) = wb_calibrated {
+            self.wb_calibrated(gains);
+        }
+
+        Ok(gst::FlowSuccess::Ok)
     }
 }
 
-fn opencv_transform(
-    in_data: &[u8],
-    out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
-    state: &mut State,
-) -> Result<(), gst::FlowError> {
-    let input_mat = unsafe {
-        Mat::new_rows_cols_with_data_unsafe(
-            state.in_info.height as i32,
-            state.in_info.width as i32,
-            opencv::core::CV_8UC1, //bayer will always be this
-            in_data.as_ptr() as *mut std::ffi::c_void,
-            state.in_info.stride,
-        )
-        .unwrap()
-    };
+// How input sizes map to output sizes: the optical black margins are cropped if
+// ob-crop is set, as is everything outside the crop rectangle, then superpixel halves
+// what is left and rotations by 90 degrees swap width and height.
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    method: Method,
+    border_mode: BorderMode,
+    direction: gst_video::VideoOrientationMethod,
+    cols: Span,
+    rows: Span,
+    // Input frames averaged into every output frame.
+    stack: u32,
+    // Size output-width and output-height scale the output to, 0 along an axis that
+    // keeps the converted size.
+    output: (u32, u32),
+}
 
-    match state.out_info.format() {
-        gst_video::VideoFormat::Bgr | gst_video::VideoFormat::Rgb =>
-        //One pass, RGGB -> BGR/RGB
-        {
-            let conversion = match state.out_info.format() {
-                gst_video::VideoFormat::Bgr => opencv::imgproc::COLOR_BayerBG2BGR,
-                gst_video::VideoFormat::Rgb => opencv::imgproc::COLOR_BayerBG2RGB,
-                _ => return Err(gst::FlowError::NotNegotiated),
-            };
-            let mut output_mat = unsafe {
-                Mat::new_rows_cols_with_data_unsafe(
-                    state.out_info.height() as i32,
-                    state.out_info.width() as i32,
-                    opencv::core::CV_8UC3, // Grayscale
-                    out_frame.plane_data_mut(0).unwrap().as_mut_ptr() as *mut std::ffi::c_void,
-                    out_frame.plane_stride()[0] as usize,
-                )
-                .unwrap()
-            };
-            // Process
-            opencv::imgproc::cvt_color_def(&input_mat, &mut output_mat, conversion)
-                .map(|_| ())
-                .map_err(|_| gst::FlowError::Error)
+// The input pixels along an axis that are converted: from `start` to `end`, or to
+// the edge of the input without an end, both counted past the `skipped` pixels.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: u32,
+    end: Option<u32>,
+    // Skipped at both edges of the input together.
+    skipped: u32,
+    // Input pixels binned into one, 2 for binned quad bayer input.
+    scale: u32,
+}
+
+impl Span {
+    // Both the optical black margin, if cropped, and the crop are left out.
+    fn new(ob: u32, crop_start: usize, crop_size: usize, skipped: (u32, u32), scale: u32) -> Self {
+        let crop_start = u32::try_from(crop_start).unwrap_or(u32::MAX);
+        Span {
+            start: ob.max(crop_start),
+            end: (crop_size > 0)
+                .then(|| crop_start.saturating_add(u32::try_from(crop_size).unwrap_or(u32::MAX))),
+            skipped: skipped.0.saturating_add(skipped.1),
+            scale,
         }
-        gst_video::VideoFormat::Rgba => {
-            //Two pass RGGB -> RGB -> RGBA, slow but more compatible
+    }
 
-            //Put this first conversion on it's own bracket to limit the mutable scope of
-            //intermdiate_rgb
-            {
-                let mut intermediate_rgb = match &mut state.intermediate_rgb {
-                    Some(mat) => mat,
-                    None => {
-                        let mat = unsafe {
-                            Mat::new_rows_cols(
-                                state.in_info.height as i32,
-                                state.in_info.width as i32,
-                                opencv::core::CV_8UC3,
-                            )
-                            .unwrap()
-                        };
-                        state.intermediate_rgb = Some(mat);
-                        state.intermediate_rgb.as_mut().unwrap()
-                    }
-                };
+    // Pixels of the frame that is converted out of `size` in the caps, once the
+    // margins are skipped and quad bayer input is binned.
+    fn visible(&self, size: i32) -> i32 {
+        size.saturating_sub_unsigned(self.skipped).max(0) / self.scale as i32
+    }
 
-                opencv::imgproc::cvt_color_def(
-                    &input_mat,
-                    &mut intermediate_rgb,
-                    opencv::imgproc::COLOR_BayerBG2RGB,
-                )
-                .map_err(|_| gst::FlowError::Error)?;
+    // Active input pixels out of `size`, none if the span doesn't fit.
+    fn active(&self, size: i32) -> i32 {
+        let end = match self.end {
+            Some(end) if end > size.max(0) as u32 => return 0,
+            Some(end) => end as i32,
+            None => size,
+        };
+        end.saturating_sub_unsigned(self.start).max(0)
+    }
+}
+
+impl Geometry {
+    fn new(settings: &Settings) -> Self {
+        let (ob_cols, ob_rows) = match settings.ob_crop {
+            true => (settings.ob_cols, settings.ob_rows),
+            false => (0, 0),
+        };
+        let crop = settings.crop;
+        let scale = match (settings.cfa_layout, settings.quad_mode) {
+            (CfaLayout::Quad, QuadMode::Binning) => 2,
+            _ => 1,
+        };
+
+        Geometry {
+            method: settings.method,
+            border_mode: settings.border_mode,
+            direction: settings.direction(),
+            cols: Span::new(
+                ob_cols,
+                crop.x,
+                crop.width,
+                (settings.skip_cols_left, settings.skip_cols_right),
+                scale,
+            ),
+            rows: Span::new(
+                ob_rows,
+                crop.y,
+                crop.height,
+                (settings.skip_lines_top, settings.skip_lines_bottom),
+                scale,
+            ),
+            stack: settings.stack_frames.max(1),
+            #[cfg(feature = "opencv")]
+            output: (settings.opencv.output_width, settings.opencv.output_height),
+            #[cfg(not(feature = "opencv"))]
+            output: (0, 0),
+        }
+    }
+
+    // Whether the converted frames are scaled along either axis.
+    fn scales(&self) -> bool {
+        self.output != (0, 0)
+    }
+
+    // Output size of a converted `width` and `height`, once turned.
+    fn scale<T: From<i32>>(&self, width: T, height: T) -> (T, T) {
+        let scale = |output: u32, size: T| match output {
+            0 => size,
+            output => T::from(output as i32),
+        };
+        (scale(self.output.0, width), scale(self.output.1, height))
+    }
+
+    // Converted size giving an output `width` and `height` in the caps, left open
+    // along a scaled axis. None if the output can't have that size.
+    fn unscale(
+        &self,
+        width: Option<i32>,
+        height: Option<i32>,
+    ) -> Option<(Option<i32>, Option<i32>)> {
+        let unscale = |output: u32, size: Option<i32>| match (output, size) {
+            (0, size) => Some(size),
+            (output, Some(size)) if size != output as i32 => None,
+            _ => Some(None),
+        };
+        Some((
+            unscale(self.output.0, width)?,
+            unscale(self.output.1, height)?,
+        ))
+    }
+
+    // Output framerate of an input `framerate`, None if it doesn't fit a fraction.
+    fn output_framerate(&self, framerate: gst::Fraction) -> Option<gst::Fraction> {
+        let denom = framerate.denom().checked_mul(self.stack as i32)?;
+        Some(gst::Fraction::new(framerate.numer(), denom))
+    }
+
+    // Input framerate giving an output `framerate`, None if it doesn't fit a fraction.
+    fn input_framerate(&self, framerate: gst::Fraction) -> Option<gst::Fraction> {
+        let numer = framerate.numer().checked_mul(self.stack as i32)?;
+        Some(gst::Fraction::new(numer, framerate.denom()))
+    }
+
+    // Swaps a width and height if the direction turns frames by 90 degrees, in either
+    // direction of the conversion.
+    fn turn<T>(&self, width: T, height: T) -> (T, T) {
+        match orient::Transform::for_direction(self.direction).transpose {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    // The active window of a `width` x `height` input, if anything of it is left.
+    fn window(&self, width: usize, height: usize) -> Option<Rect> {
+        let window = Rect {
+            x: self.cols.start as usize,
+            y: self.rows.start as usize,
+            width: self.cols.active(width as i32) as usize,
+            height: self.rows.active(height as i32) as usize,
+        };
+        (window.width > 0 && window.height > 0).then_some(window)
+    }
+
+    // Output pixels along an axis of `size` input pixels past the skipped margins.
+    fn output_size(&self, size: i32, span: Span) -> i32 {
+        let active = span.active(size);
+        match self.method {
+            Method::Full => active.saturating_sub(self.cropped()).max(0),
+            Method::Superpixel => active / 2,
+        }
+    }
+
+    // Pixels border-mode=crop drops along an axis of full resolution output.
+    fn cropped(&self) -> i32 {
+        match self.border_mode {
+            BorderMode::Crop => 2 * border::BORDER as i32,
+            BorderMode::None | BorderMode::Replicate => 0,
+        }
+    }
+
+    // Output pixels along an axis of `size` input pixels in the caps.
+    fn caps_output_size(&self, size: i32, span: Span) -> i32 {
+        self.output_size(span.visible(size), span)
+    }
+
+    // Sets `field` to the input sizes in the caps along an axis that give `size`
+    // output pixels, None if there are none.
+    fn input_field(
+        &self,
+        builder: gst::structure::Builder,
+        field: &str,
+        size: i32,
+        span: Span,
+    ) -> Option<gst::structure::Builder> {
+        let Some(active) = (match self.method {
+            Method::Full => size.checked_add(self.cropped()),
+            Method::Superpixel => size.checked_mul(2),
+        }) else {
+            return Some(builder);
+        };
+
+        let sizes = match span.end {
+            // A fixed crop takes the same pixels out of any input that contains them.
+            Some(end) => {
+                let cropped = end.saturating_sub(span.start) as i32;
+                let fits = match self.method {
+                    Method::Full => cropped == active,
+                    Method::Superpixel => cropped == active || cropped == active + 1,
+                };
+                if !fits {
+                    return None;
+                }
+                (end.min(i32::MAX as u32) as i32, None)
+            }
+            None => {
+                let Some(min) = active.checked_add_unsigned(span.start) else {
+                    return Some(builder);
+                };
+                match self.method {
+                    Method::Full => (min, Some(min)),
+                    // An odd trailing column or row is dropped, so either parity works.
+                    Method::Superpixel => (min, Some(min.saturating_add(1))),
+                }
             }
+        };
 
-            let mut output_mat = unsafe {
-                Mat::new_rows_cols_with_data_unsafe(
-                    state.out_info.height() as i32,
-                    state.out_info.width() as i32,
-                    opencv::core::CV_8UC4,
-                    out_frame.plane_data_mut(0).unwrap().as_mut_ptr() as *mut std::ffi::c_void,
-                    out_frame.plane_stride()[0] as usize,
-                )
-                .unwrap()
-            };
-            opencv::imgproc::cvt_color_def(
-                state.intermediate_rgb.as_ref().unwrap(),
-                &mut output_mat,
-                opencv::imgproc::COLOR_RGB2RGBA,
-            )
-            .map(|_| ())
-            .map_err(|_| gst::FlowError::Error)
+        // Back to the caps: binning takes `scale` input pixels per pixel, dropping
+        // what is left over, and the skipped margins come on top.
+        let scale = span.scale as i32;
+        let to_caps = |size: i32| size.checked_mul(scale)?.checked_add_unsigned(span.skipped);
+        let Some(min) = to_caps(sizes.0) else {
+            return Some(builder);
+        };
+        let max = match sizes.1 {
+            Some(max) => to_caps(max).and_then(|max| max.checked_add(scale - 1)),
+            None => Some(i32::MAX),
+        };
+        Some(match max {
+            Some(max) if max == min => builder.field(field, min),
+            Some(max) => builder.field(field, gst::IntRange::new(min, max)),
+            None => builder.field(field, gst::IntRange::new(min, i32::MAX)),
+        })
+    }
+}
+
+// Whether turning the output in `direction` swaps its width and height.
+fn transposes(direction: gst_video::VideoOrientationMethod) -> bool {
+    orient::Transform::for_direction(direction).transpose
+}
+
+// Leaves out of the frame's settings what quality level `level` skips.
+fn apply_quality(settings: &mut Settings, level: QualityLevel) {
+    if level >= QualityLevel::Reduced {
+        settings.false_color_suppression = 0.0;
+        #[cfg(feature = "opencv")]
+        {
+            settings.opencv.denoise = Denoise::None;
+            settings.opencv.temporal_denoise = 0.0;
+            settings.opencv.sharpen_amount = 0.0;
+        }
+    }
+    if level >= QualityLevel::Minimal {
+        settings.demosaic_algorithm = DemosaicAlgorithm::Bilinear;
+    }
+}
+
+// The part of the input stats and AWB measure: `roi` clamped to the input, or the
+// whole frame if nothing of it is left. Returns the samples starting at its top-left
+// corner, rows still `in_stride` apart, and the clamped rectangle.
+fn measurement_window<'a>(
+    in_data: &'a [u8],
+    in_stride: usize,
+    in_info: &InputInfo,
+    roi: Rect,
+) -> (&'a [u8], Rect) {
+    let roi = roi
+        .clamp_to_cfa(in_info.width, in_info.height)
+        .unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: in_info.width,
+            height: in_info.height,
+        });
+    let offset = (roi.y * in_stride + roi.x).min(in_data.len());
+
+    (&in_data[offset..], roi)
+}
+
+// The `active` window of a dark frame for the input, if there is one.
+fn dark_window(
+    dark_frame: Option<&dark::DarkFrame>,
+    in_info: &InputInfo,
+    active: Rect,
+) -> Result<Option<Vec<u8>>, String> {
+    dark_frame
+        .map(|dark_frame| dark_frame.window_u8(in_info.width, in_info.height, active))
+        .transpose()
+        .map_err(|err| format!("Invalid dark frame: {err}"))
+}
+
+// Memory besides system memory output can be produced in, all written through a CPU
+// mapping as well.
+const OUTPUT_MEMORY: &[&str] = &[
+    #[cfg(feature = "gl")]
+    "memory:GLMemory",
+    #[cfg(feature = "dmabuf")]
+    dmabuf::CAPS_FEATURE,
+];
+
+// Memory caps feature of `features` other than system memory, if any. Features
+// without one, like meta features alone, describe frames in system memory.
+fn foreign_memory(features: &gst::CapsFeaturesRef) -> Option<&str> {
+    features
+        .iter()
+        .find(|feature| {
+            feature.starts_with("memory:") && *feature != gst::CAPS_FEATURE_MEMORY_SYSTEM_MEMORY
+        })
+        .map(glib::GStr::as_str)
+}
+
+// Whether a `video/x-bayer` structure allows samples deeper than 8 bits, any format
+// of a list or none at all.
+fn deep_input(s: &gst::StructureRef) -> bool {
+    let deep = |format: &str| Depth::from_caps_format(format).is_some_and(|d| d != Depth::Eight);
+    match s.value("format") {
+        Ok(value) => match (value.get::<&str>(), value.get::<gst::List>()) {
+            (Ok(format), _) => deep(format),
+            (_, Ok(list)) => list
+                .iter()
+                .any(|format| format.get::<&str>().is_ok_and(deep)),
+            _ => false,
+        },
+        Err(_) => true,
+    }
+}
+
+// Values of tuning properties are numbers, strings, enums and arrays of integers, none
+// tied to a thread.
+fn send_value(value: glib::Value) -> glib::SendValue {
+    // SAFETY: SendValue is a transparent wrapper of Value.
+    unsafe { std::mem::transmute::<glib::Value, glib::SendValue>(value) }
+}
+
+fn same_dark_frame(
+    a: &Option<std::sync::Arc<dark::DarkFrame>>,
+    b: &Option<std::sync::Arc<dark::DarkFrame>>,
+) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => std::sync::Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// The stats ROI in output pixels, or the whole output if nothing of it is left.
+fn output_window(state: &State, roi: Rect) -> Rect {
+    roi.clamp_to_cfa(state.in_info.width, state.in_info.height)
+        .and_then(|roi| output_region(&Negotiated::of(state), roi))
+        .unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: state.out_info.width() as usize,
+            height: state.out_info.height() as usize,
+        })
+}
+
+// Window of the active input the process ROI `roi` converts, clamped to the active
+// window and shrunk to even coordinates and sizes so it keeps the CFA phase and the
+// superpixel quads of the whole frame. None if nothing of it is left.
+fn process_window(roi: Rect, in_info: &InputInfo, active: Rect) -> Option<Rect> {
+    let roi = roi.clamp_to_cfa(in_info.width, in_info.height)?;
+    let extent = |start: usize, len: usize, origin: usize, size: usize| {
+        let first = start.saturating_sub(origin).next_multiple_of(2);
+        let last = (start + len).saturating_sub(origin).min(size) & !1;
+        (last > first).then_some((first, last - first))
+    };
+
+    let (x, width) = extent(roi.x, roi.width, active.x, active.width)?;
+    let (y, height) = extent(roi.y, roi.height, active.y, active.height)?;
+    Some(Rect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+// Fills `width` x `height` pixels of `data` with the ARGB `color`.
+fn fill(
+    data: &mut [u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    color: u32,
+) {
+    let [alpha, red, green, blue] = color.to_be_bytes();
+    let mut pixel = [0u8; 4];
+    pixel[layout.red] = red;
+    pixel[layout.green] = green;
+    pixel[layout.blue] = blue;
+    if let Some(index) = layout.alpha {
+        pixel[index] = alpha;
+    }
+    let pixel = &pixel[..layout.pixel_stride];
+
+    for row in data.chunks_mut(stride).take(height) {
+        for out in row.chunks_exact_mut(pixel.len()).take(width) {
+            out.copy_from_slice(pixel);
         }
-        _ => return Err(gst::FlowError::NotNegotiated),
     }
 }
+
+// The part of the output showing `rect` of the input, None if none of it is converted.
+fn output_region(negotiated: &Negotiated, rect: Rect) -> Option<Rect> {
+    // Before any flip or rotation.
+    let (width, height) = negotiated
+        .orientation
+        .size(negotiated.converted.0, negotiated.converted.1);
+    let scale = negotiated.scale;
+    // The output starts past the border border-mode=crop drops.
+    let border = negotiated.border;
+    let (left, top) = (negotiated.active.x + border, negotiated.active.y + border);
+
+    let x = rect.x.saturating_sub(left) / scale;
+    let y = rect.y.saturating_sub(top) / scale;
+    let right = ((rect.x + rect.width).saturating_sub(left))
+        .div_ceil(scale)
+        .min(width);
+    let bottom = ((rect.y + rect.height).saturating_sub(top))
+        .div_ceil(scale)
+        .min(height);
+    if right <= x || bottom <= y {
+        return None;
+    }
+    let region = negotiated.orientation.rect(
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        },
+        width,
+        height,
+    );
+
+    // Scaled along with the rest of the frame by output-width and output-height.
+    let rescale = |start: usize, len: usize, from: usize, to: usize| {
+        let end = ((start + len) * to).div_ceil(from);
+        let start = start * to / from;
+        (start, end - start)
+    };
+    let (x, width) = rescale(
+        region.x,
+        region.width,
+        negotiated.converted.0,
+        negotiated.out_info.width() as usize,
+    );
+    let (y, height) = rescale(
+        region.y,
+        region.height,
+        negotiated.converted.1,
+        negotiated.out_info.height() as usize,
+    );
+    Some(Rect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
@@ -0,0 +1,214 @@
+// The bayer side of the element's frame handling.
+//
+// gst_video's VideoInfo and VideoFrameRef describe and map the raw video output, but
+// know nothing of `video/x-bayer`. BayerInfo and BayerFrame are their counterparts for
+// the input: the caps are parsed in one place, and every frame is mapped with the
+// layout a VideoMeta describes, or the default one of the caps without.
+//
+// Samples deeper than 8 bits come stored in 16 bits or MIPI CSI-2 packed. The
+// conversion works on 8-bit mosaics, so the element narrows them first, see
+// unpack::narrow(), and widens them to 16 bits for P010 output, see unpack::widen().
+
+use std::fmt;
+
+use super::cfa::Pattern;
+use super::frame;
+use super::unpack::Packing;
+
+/// How the samples of a `video/x-bayer` format are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// A byte per sample, `rggb` and the like.
+    Eight,
+    /// The low `bits` of 16, `rggb10le` to `rggb16be`.
+    Sixteen { bits: u32, big_endian: bool },
+    /// MIPI CSI-2 packed, `rggb10p`, `rggb12p` and `rggb14p`.
+    Packed(Packing),
+}
+
+impl Depth {
+    /// Every depth, in the order of the caps.
+    pub fn all() -> impl Iterator<Item = Depth> {
+        let sixteen = [10, 12, 14, 16]
+            .into_iter()
+            .flat_map(|bits| [false, true].map(|big_endian| Depth::Sixteen { bits, big_endian }));
+        std::iter::once(Depth::Eight)
+            .chain(sixteen)
+            .chain(Packing::ALL.map(Depth::Packed))
+    }
+
+    /// The depth of a format suffix, what follows the pattern.
+    pub fn from_caps_suffix(suffix: &str) -> Option<Self> {
+        Depth::all().find(|depth| depth.to_caps_suffix() == suffix)
+    }
+
+    /// The depth of a `video/x-bayer` format, e.g. `rggb12le`.
+    pub fn from_caps_format(format: &str) -> Option<Self> {
+        let (_, suffix) = format.split_at_checked(4)?;
+        Depth::from_caps_suffix(suffix)
+    }
+
+    pub fn to_caps_suffix(self) -> String {
+        match self {
+            Depth::Eight => String::new(),
+            Depth::Sixteen { bits, big_endian } => {
+                format!("{bits}{}", if big_endian { "be" } else { "le" })
+            }
+            Depth::Packed(packing) => packing.to_caps_suffix().to_string(),
+        }
+    }
+
+    /// Bytes of a row of `width` samples.
+    pub fn row_bytes(self, width: usize) -> Option<usize> {
+        match self {
+            Depth::Eight => Some(width),
+            Depth::Sixteen { .. } => width.checked_mul(2),
+            Depth::Packed(packing) => Some(packing.row_bytes(width)),
+        }
+    }
+}
+
+/// Every format of every pattern and depth, for caps.
+pub fn caps_formats() -> Vec<String> {
+    formats(|_| true)
+}
+
+/// Every format of every pattern deeper than 8 bits, for the caps of P010 output.
+pub fn deep_caps_formats() -> Vec<String> {
+    formats(|depth| depth != Depth::Eight)
+}
+
+fn formats(filter: impl Fn(Depth) -> bool + Copy) -> Vec<String> {
+    Pattern::ALL
+        .into_iter()
+        .flat_map(|pattern| {
+            Depth::all()
+                .filter(move |&depth| filter(depth))
+                .map(move |depth| format!("{}{}", pattern.to_caps_format(), depth.to_caps_suffix()))
+        })
+        .collect()
+}
+
+/// Description of bayer frames parsed from `video/x-bayer` caps, like VideoInfo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BayerInfo {
+    pub pattern: Pattern,
+    pub width: usize,
+    pub height: usize,
+    pub depth: Depth,
+    /// Default distance of rows in bytes, the bytes of a row of the depth.
+    pub stride: usize,
+    /// Fields interleaved in one buffer, the one interlace mode supported.
+    pub interlaced: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NoWidth,
+    NoHeight,
+    NoFormat,
+    InterlaceMode,
+    /// Width or height not positive, or frames too large to address.
+    Size,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::NoWidth => "No width in caps",
+            Error::NoHeight => "No height in caps",
+            Error::NoFormat => "No valid bayer format in caps",
+            Error::InterlaceMode => "Unsupported interlace-mode in caps",
+            Error::Size => "Frame size in caps out of range",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl BayerInfo {
+    pub fn from_caps(caps: &gst::CapsRef) -> Result<Self, Error> {
+        let s = caps.structure(0).ok_or(Error::NoFormat)?;
+        let width = s.get::<i32>("width").map_err(|_| Error::NoWidth)?;
+        let height = s.get::<i32>("height").map_err(|_| Error::NoHeight)?;
+        let (pattern, depth) = s
+            .get::<&str>("format")
+            .ok()
+            .and_then(|format| format.split_at_checked(4))
+            .and_then(|(pattern, suffix)| {
+                Some((
+                    Pattern::from_caps_format(pattern)?,
+                    Depth::from_caps_suffix(suffix)?,
+                ))
+            })
+            .ok_or(Error::NoFormat)?;
+        // Checked here once, so the size computations on it can't overflow.
+        let (width, height, stride) = match (usize::try_from(width), usize::try_from(height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => match depth.row_bytes(width) {
+                Some(stride) if frame::addressable(height, stride) => (width, height, stride),
+                _ => return Err(Error::Size),
+            },
+            _ => return Err(Error::Size),
+        };
+        let interlaced = match s.get_optional::<&str>("interlace-mode") {
+            Ok(None | Some("progressive")) => false,
+            Ok(Some("interleaved")) => true,
+            _ => return Err(Error::InterlaceMode),
+        };
+
+        Ok(BayerInfo {
+            pattern,
+            width,
+            height,
+            depth,
+            stride,
+            interlaced,
+        })
+    }
+
+    /// Size of a frame without padding in bytes.
+    pub fn size(&self) -> usize {
+        self.stride * self.height
+    }
+}
+
+/// A bayer frame mapped for reading, like a readable VideoFrameRef.
+pub struct BayerFrame<'a> {
+    map: gst::BufferMap<'a, gst::buffer::Readable>,
+    offset: usize,
+    stride: usize,
+}
+
+impl<'a> BayerFrame<'a> {
+    /// Maps `buffer` with the layout of its VideoMeta, or the default one of `info`.
+    pub fn from_buffer_readable(
+        buffer: &'a gst::BufferRef,
+        info: &BayerInfo,
+    ) -> Result<Self, String> {
+        let map = buffer
+            .map_readable()
+            .map_err(|_| "Failed to map input buffer".to_string())?;
+        let (offset, stride) = match buffer.meta::<gst_video::VideoMeta>() {
+            Some(meta) => (meta.offset()[0], meta.stride()[0] as usize),
+            None => (0, info.stride),
+        };
+        if offset > map.len() {
+            return Err(format!("VideoMeta offset {offset} out of bounds"));
+        }
+
+        Ok(BayerFrame {
+            map,
+            offset,
+            stride,
+        })
+    }
+
+    /// Samples from the first row on, rows [`BayerFrame::stride`] bytes apart.
+    pub fn data(&self) -> &[u8] {
+        &self.map[self.offset..]
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
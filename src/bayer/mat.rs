@@ -0,0 +1,314 @@
+// Bounds-checked construction of OpenCV `Mat` headers over frame memory.
+//
+// OpenCV never copies the memory handed to `Mat::new_rows_cols_with_data_unsafe`; it
+// trusts `rows`, `cols`, the element type and the row step blindly. A header that is
+// larger than the slice behind it is undefined behavior the first time OpenCV reads or
+// writes the last rows, so every header we build over buffer memory goes through the
+// helpers below.
+//
+// Safety invariants upheld by `wrap` and `wrap_mut`:
+//
+// * `rows` and `cols` are non-zero and fit in an `i32`, as OpenCV requires.
+// * `stride` is at least `cols * elem_size(typ)` so rows never overlap.
+// * `(rows - 1) * stride + cols * elem_size(typ)` is computed without overflow and does
+//   not exceed the slice length, so the last byte OpenCV can touch is in bounds.
+// * The returned header borrows the slice (`BoxedRef` / `BoxedRefMut`), so it can't
+//   outlive the mapping it points into.
+
+use opencv::boxed_ref::{BoxedRef, BoxedRefMut};
+use opencv::core::{self, Mat, Scalar};
+use opencv::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatError {
+    /// Zero rows/columns or dimensions OpenCV can't represent.
+    InvalidDimensions { rows: usize, cols: usize },
+    /// Row stride is smaller than one row of elements.
+    StrideTooSmall { stride: usize, row_bytes: usize },
+    /// The slice can't hold `rows` rows at the given stride.
+    BufferTooSmall { required: usize, len: usize },
+    /// OpenCV itself refused to build the header.
+    OpenCv,
+}
+
+impl fmt::Display for MatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatError::InvalidDimensions { rows, cols } => {
+                write!(f, "invalid Mat dimensions {cols}x{rows}")
+            }
+            MatError::StrideTooSmall { stride, row_bytes } => {
+                write!(f, "stride {stride} is smaller than a row of {row_bytes} bytes")
+            }
+            MatError::BufferTooSmall { required, len } => {
+                write!(f, "buffer of {len} bytes is too small, {required} bytes required")
+            }
+            MatError::OpenCv => write!(f, "OpenCV failed to create the Mat header"),
+        }
+    }
+}
+
+impl std::error::Error for MatError {}
+
+/// Size in bytes of one element (all channels) of the OpenCV type `typ`.
+pub fn elem_size(typ: i32) -> usize {
+    let depth = typ & ((1 << core::CV_CN_SHIFT) - 1);
+    let channels = ((typ >> core::CV_CN_SHIFT) + 1) as usize;
+    let depth_size = match depth {
+        core::CV_8U | core::CV_8S => 1,
+        core::CV_16U | core::CV_16S | core::CV_16F => 2,
+        core::CV_32S | core::CV_32F => 4,
+        _ => 8,
+    };
+    depth_size * channels
+}
+
+/// Checks that a `rows` x `cols` header of `typ` with the given `stride` fits in `len`
+/// bytes, returning the number of bytes it spans.
+pub fn check_layout(
+    len: usize,
+    rows: usize,
+    cols: usize,
+    typ: i32,
+    stride: usize,
+) -> Result<usize, MatError> {
+    if rows == 0 || cols == 0 || rows > i32::MAX as usize || cols > i32::MAX as usize {
+        return Err(MatError::InvalidDimensions { rows, cols });
+    }
+
+    let row_bytes = cols
+        .checked_mul(elem_size(typ))
+        .ok_or(MatError::InvalidDimensions { rows, cols })?;
+    if stride < row_bytes {
+        return Err(MatError::StrideTooSmall { stride, row_bytes });
+    }
+
+    let required = (rows - 1)
+        .checked_mul(stride)
+        .and_then(|n| n.checked_add(row_bytes))
+        .ok_or(MatError::InvalidDimensions { rows, cols })?;
+    if required > len {
+        return Err(MatError::BufferTooSmall { required, len });
+    }
+
+    Ok(required)
+}
+
+/// Wraps `data` in a read-only `Mat` header without copying.
+pub fn wrap(
+    data: &[u8],
+    rows: usize,
+    cols: usize,
+    typ: i32,
+    stride: usize,
+) -> Result<BoxedRef<'_, Mat>, MatError> {
+    check_layout(data.len(), rows, cols, typ, stride)?;
+
+    // SAFETY: the layout was validated against the slice above and the header borrows
+    // `data`. OpenCV only reads through headers passed as input arrays.
+    let mat = unsafe {
+        Mat::new_rows_cols_with_data_unsafe(
+            rows as i32,
+            cols as i32,
+            typ,
+            data.as_ptr() as *mut std::ffi::c_void,
+            stride,
+        )
+    }
+    .map_err(|_| MatError::OpenCv)?;
+
+    Ok(BoxedRef::from(mat))
+}
+
+/// Wraps `data` in a writable `Mat` header without copying.
+pub fn wrap_mut(
+    data: &mut [u8],
+    rows: usize,
+    cols: usize,
+    typ: i32,
+    stride: usize,
+) -> Result<BoxedRefMut<'_, Mat>, MatError> {
+    check_layout(data.len(), rows, cols, typ, stride)?;
+
+    // SAFETY: the layout was validated against the slice above and the header holds
+    // the exclusive borrow of `data` for its whole lifetime.
+    let mat = unsafe {
+        Mat::new_rows_cols_with_data_unsafe(
+            rows as i32,
+            cols as i32,
+            typ,
+            data.as_mut_ptr() as *mut std::ffi::c_void,
+            stride,
+        )
+    }
+    .map_err(|_| MatError::OpenCv)?;
+
+    Ok(BoxedRefMut::from(mat))
+}
+
+/// Allocates an OpenCV-owned, zero-initialized `Mat`.
+pub fn zeroed(rows: usize, cols: usize, typ: i32) -> Result<Mat, MatError> {
+    if rows == 0 || cols == 0 || rows > i32::MAX as usize || cols > i32::MAX as usize {
+        return Err(MatError::InvalidDimensions { rows, cols });
+    }
+
+    Mat::new_rows_cols_with_default(rows as i32, cols as i32, typ, Scalar::all(0.))
+        .map_err(|_| MatError::OpenCv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [i32; 5] = [
+        core::CV_8UC1,
+        core::CV_8UC3,
+        core::CV_8UC4,
+        core::CV_16UC1,
+        core::CV_32FC3,
+    ];
+
+    // xorshift64*, enough to spread the cases around.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        // Mostly small values, sometimes ones at the edges of the integer types.
+        fn size(&mut self) -> usize {
+            match self.next() % 8 {
+                0 => 0,
+                1 => {
+                    [i32::MAX as usize, i32::MAX as usize + 1, usize::MAX][self.next() as usize % 3]
+                }
+                2 => usize::MAX / (1 + self.next() as usize % 16),
+                _ => (self.next() % 40) as usize,
+            }
+        }
+    }
+
+    // What check_layout() must return, computed in 128 bits so nothing overflows.
+    fn expected(
+        len: usize,
+        rows: usize,
+        cols: usize,
+        typ: i32,
+        stride: usize,
+    ) -> Result<usize, MatError> {
+        let invalid = MatError::InvalidDimensions { rows, cols };
+        if rows == 0 || cols == 0 || rows > i32::MAX as usize || cols > i32::MAX as usize {
+            return Err(invalid);
+        }
+        let row_bytes = cols as u128 * elem_size(typ) as u128;
+        let row_bytes = usize::try_from(row_bytes).map_err(|_| invalid)?;
+        if stride < row_bytes {
+            return Err(MatError::StrideTooSmall { stride, row_bytes });
+        }
+        let required = (rows as u128 - 1) * stride as u128 + row_bytes as u128;
+        let required = usize::try_from(required).map_err(|_| invalid)?;
+        if required > len {
+            return Err(MatError::BufferTooSmall { required, len });
+        }
+        Ok(required)
+    }
+
+    #[test]
+    fn check_layout_cases() {
+        let cases = [
+            // Zero sizes.
+            (100, 0, 4, core::CV_8UC1, 4),
+            (100, 4, 0, core::CV_8UC1, 4),
+            (0, 1, 1, core::CV_8UC1, 1),
+            // Stride shorter than a row.
+            (100, 2, 4, core::CV_8UC3, 11),
+            // One byte short of the last row.
+            (3 * 16 + 11, 4, 4, core::CV_8UC3, 16),
+            // The last row needs no padding.
+            (3 * 16 + 12, 4, 4, core::CV_8UC3, 16),
+            // Overflowing row bytes and rows times stride.
+            (usize::MAX, 1, i32::MAX as usize, core::CV_64FC4, usize::MAX),
+            (usize::MAX, i32::MAX as usize, 1, core::CV_8UC1, usize::MAX),
+            // Too many rows or columns for OpenCV.
+            (usize::MAX, i32::MAX as usize + 1, 1, core::CV_8UC1, 1),
+        ];
+        for (len, rows, cols, typ, stride) in cases {
+            assert_eq!(
+                check_layout(len, rows, cols, typ, stride),
+                expected(len, rows, cols, typ, stride),
+                "{len} bytes, {cols}x{rows} of {typ} at stride {stride}"
+            );
+        }
+        assert_eq!(check_layout(3 * 16 + 12, 4, 4, core::CV_8UC3, 16), Ok(60));
+    }
+
+    #[test]
+    fn check_layout_random() {
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        for _ in 0..100_000 {
+            let (rows, cols, stride) = (rng.size(), rng.size(), rng.size());
+            let typ = TYPES[rng.next() as usize % TYPES.len()];
+            // Lengths around the required one, where the off-by-one errors are.
+            let len = match expected(usize::MAX, rows, cols, typ, stride) {
+                Ok(required) if rng.next() % 2 == 0 => {
+                    required.saturating_add_signed(rng.next() as isize % 3 - 1)
+                }
+                _ => rng.size(),
+            };
+            assert_eq!(
+                check_layout(len, rows, cols, typ, stride),
+                expected(len, rows, cols, typ, stride),
+                "{len} bytes, {cols}x{rows} of {typ} at stride {stride}"
+            );
+        }
+    }
+
+    #[test]
+    fn wrap_random() {
+        let mut rng = Rng(0xfedc_ba98_7654_3210);
+        for _ in 0..2_000 {
+            // Small enough to back with a real buffer.
+            let rows = (rng.next() % 12) as usize;
+            let cols = (rng.next() % 12) as usize;
+            let stride = (rng.next() % 64) as usize;
+            let typ = TYPES[rng.next() as usize % TYPES.len()];
+            let mut data = vec![0u8; (rng.next() % 600) as usize];
+            let len = data.len();
+            let expected = expected(len, rows, cols, typ, stride);
+
+            match (wrap(&data, rows, cols, typ, stride), expected) {
+                (Ok(mat), Ok(_)) => {
+                    assert_eq!((mat.rows(), mat.cols()), (rows as i32, cols as i32));
+                    assert_eq!(mat.typ(), typ);
+                    assert_eq!(mat.step1(0).unwrap() * mat.elem_size1(), stride);
+                    assert_eq!(mat.data(), data.as_ptr());
+                }
+                (Err(err), Err(expected)) => assert_eq!(err, expected),
+                (res, expected) => panic!(
+                    "wrap of {len} bytes, {cols}x{rows} of {typ} at stride {stride}: \
+                     {:?}, expected {expected:?}",
+                    res.map(|_| ())
+                ),
+            }
+
+            let ptr = data.as_ptr();
+            match (wrap_mut(&mut data, rows, cols, typ, stride), expected) {
+                (Ok(mat), Ok(_)) => {
+                    assert_eq!((mat.rows(), mat.cols()), (rows as i32, cols as i32));
+                    assert_eq!(mat.data(), ptr);
+                }
+                (Err(err), Err(expected)) => assert_eq!(err, expected),
+                (res, expected) => panic!(
+                    "wrap_mut of {len} bytes, {cols}x{rows} of {typ} at stride {stride}: \
+                     {:?}, expected {expected:?}",
+                    res.map(|_| ())
+                ),
+            }
+        }
+    }
+}
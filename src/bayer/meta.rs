@@ -0,0 +1,204 @@
+// RsBayerTimingMeta, attached to the output buffers with attach-timing-meta so a
+// tracer can tell how long every frame spent in the conversion.
+//
+// The meta holds plain values only, so init copies them in, free has nothing to
+// release and transform adds the same values to the destination buffer, whatever the
+// kind of transformation.
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+use gst::glib;
+use gst::glib::translate::*;
+use gst::prelude::*;
+
+use super::{DemosaicAlgorithm, Method};
+
+/// Conversion timing of one output buffer of `rsbayer2rgb`, in the monotonic time of
+/// `gst::util_get_timestamp()`.
+///
+/// The meta API is registered as `RsBayerTimingMetaAPI`, without tags.
+#[repr(transparent)]
+pub struct RsBayerTimingMeta(imp::RsBayerTimingMeta);
+
+unsafe impl Send for RsBayerTimingMeta {}
+unsafe impl Sync for RsBayerTimingMeta {}
+
+impl RsBayerTimingMeta {
+    /// Adds a timing meta to `buffer`.
+    pub fn add(
+        buffer: &mut gst::BufferRef,
+        start: gst::ClockTime,
+        end: gst::ClockTime,
+        method: Method,
+        algorithm: DemosaicAlgorithm,
+    ) -> gst::MetaRefMut<'_, Self, gst::meta::Standalone> {
+        unsafe {
+            let mut params = imp::Params {
+                start,
+                end,
+                method,
+                algorithm,
+            };
+            let meta = gst_sys::gst_buffer_add_meta(
+                buffer.as_mut_ptr(),
+                imp::meta_get_info(),
+                &mut params as *mut imp::Params as glib::ffi::gpointer,
+            ) as *mut imp::RsBayerTimingMeta;
+
+            Self::from_mut_ptr(buffer, meta)
+        }
+    }
+
+    /// When the conversion of the frame started.
+    pub fn start(&self) -> gst::ClockTime {
+        self.0.start
+    }
+
+    /// When the conversion of the frame ended.
+    pub fn end(&self) -> gst::ClockTime {
+        self.0.end
+    }
+
+    /// Time spent converting the frame.
+    pub fn duration(&self) -> gst::ClockTime {
+        self.0.end.saturating_sub(self.0.start)
+    }
+
+    pub fn method(&self) -> Method {
+        self.0.method
+    }
+
+    /// Demosaic algorithm of a full resolution conversion, not used by superpixel.
+    pub fn algorithm(&self) -> DemosaicAlgorithm {
+        self.0.algorithm
+    }
+}
+
+unsafe impl MetaAPI for RsBayerTimingMeta {
+    type GstType = imp::RsBayerTimingMeta;
+
+    fn meta_api() -> glib::Type {
+        imp::meta_api_get_type()
+    }
+}
+
+impl fmt::Debug for RsBayerTimingMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RsBayerTimingMeta")
+            .field("start", &self.start())
+            .field("end", &self.end())
+            .field("method", &self.method())
+            .field("algorithm", &self.algorithm())
+            .finish()
+    }
+}
+
+/// Registers `RsBayerTimingMetaAPI` at plugin load, so applications can probe for the
+/// timing meta with `gst_meta_api_type_get_type()` before the first converted frame.
+pub fn register() {
+    imp::meta_api_get_type();
+}
+
+mod imp {
+    use super::*;
+
+    pub(super) struct Params {
+        pub start: gst::ClockTime,
+        pub end: gst::ClockTime,
+        pub method: Method,
+        pub algorithm: DemosaicAlgorithm,
+    }
+
+    #[repr(C)]
+    pub struct RsBayerTimingMeta {
+        parent: gst_sys::GstMeta,
+        pub(super) start: gst::ClockTime,
+        pub(super) end: gst::ClockTime,
+        pub(super) method: Method,
+        pub(super) algorithm: DemosaicAlgorithm,
+    }
+
+    pub(super) fn meta_api_get_type() -> glib::Type {
+        static TYPE: std::sync::OnceLock<glib::Type> = std::sync::OnceLock::new();
+
+        *TYPE.get_or_init(|| unsafe {
+            let t = from_glib(gst_sys::gst_meta_api_type_register(
+                c"RsBayerTimingMetaAPI".as_ptr(),
+                [ptr::null::<std::os::raw::c_char>()].as_ptr() as *mut *const _,
+            ));
+            assert_ne!(t, glib::Type::INVALID);
+            t
+        })
+    }
+
+    unsafe extern "C" fn meta_init(
+        meta: *mut gst_sys::GstMeta,
+        params: glib::ffi::gpointer,
+        _buffer: *mut gst_sys::GstBuffer,
+    ) -> glib::ffi::gboolean {
+        unsafe {
+            assert!(!params.is_null());
+            let meta = &mut *(meta as *mut RsBayerTimingMeta);
+            let params = &*(params as *const Params);
+
+            ptr::write(&mut meta.start, params.start);
+            ptr::write(&mut meta.end, params.end);
+            ptr::write(&mut meta.method, params.method);
+            ptr::write(&mut meta.algorithm, params.algorithm);
+        }
+
+        true.into_glib()
+    }
+
+    unsafe extern "C" fn meta_free(_meta: *mut gst_sys::GstMeta, _buffer: *mut gst_sys::GstBuffer) {
+        // Nothing but plain values in the meta.
+    }
+
+    unsafe extern "C" fn meta_transform(
+        dest: *mut gst_sys::GstBuffer,
+        meta: *mut gst_sys::GstMeta,
+        _buffer: *mut gst_sys::GstBuffer,
+        _type_: glib::ffi::GQuark,
+        _data: glib::ffi::gpointer,
+    ) -> glib::ffi::gboolean {
+        unsafe {
+            let meta = &*(meta as *const RsBayerTimingMeta);
+            super::RsBayerTimingMeta::add(
+                gst::BufferRef::from_mut_ptr(dest),
+                meta.start,
+                meta.end,
+                meta.method,
+                meta.algorithm,
+            );
+        }
+
+        true.into_glib()
+    }
+
+    pub(super) fn meta_get_info() -> *const gst_sys::GstMetaInfo {
+        struct MetaInfo(ptr::NonNull<gst_sys::GstMetaInfo>);
+        unsafe impl Send for MetaInfo {}
+        unsafe impl Sync for MetaInfo {}
+
+        static META_INFO: std::sync::OnceLock<MetaInfo> = std::sync::OnceLock::new();
+
+        META_INFO
+            .get_or_init(|| unsafe {
+                MetaInfo(
+                    ptr::NonNull::new(gst_sys::gst_meta_register(
+                        meta_api_get_type().into_glib(),
+                        c"RsBayerTimingMeta".as_ptr(),
+                        mem::size_of::<RsBayerTimingMeta>(),
+                        Some(meta_init),
+                        Some(meta_free),
+                        Some(meta_transform),
+                    ) as *mut gst_sys::GstMetaInfo)
+                    .expect("Failed to register RsBayerTimingMeta"),
+                )
+            })
+            .0
+            .as_ptr()
+    }
+}
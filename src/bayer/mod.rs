@@ -1,14 +1,514 @@
 use gst::glib;
 use gst::prelude::*;
 
+use std::str::FromStr;
+
+#[cfg(not(any(feature = "opencv", feature = "rust-demosaic")))]
+compile_error!("at least one of the `opencv` and `rust-demosaic` features is required");
+#[cfg(all(feature = "dmabuf", not(target_os = "linux")))]
+compile_error!("the `dmabuf` feature needs the DMA heaps of Linux");
+
+mod awb;
+mod border;
+mod chroma;
+pub(crate) mod cfa;
+pub mod convert;
+#[cfg(feature = "cuda")]
+mod cuda;
+#[cfg(feature = "opencv")]
+mod cv;
+mod dark;
+mod decompand;
+mod defects;
+mod detect;
+mod demosaic;
+#[cfg(feature = "dmabuf")]
+mod dmabuf;
+#[cfg(feature = "dump")]
+mod dump;
+mod exposure;
+mod fields;
+#[cfg(feature = "opencv")]
+mod filter;
+mod focus;
+mod fpn;
+pub(crate) mod frame;
+#[cfg(feature = "gl")]
+mod gl;
 mod imp;
+pub(crate) mod info;
+#[cfg(feature = "opencv")]
+mod mat;
+mod meta;
+mod npy;
+mod orient;
+#[cfg(feature = "opencv")]
+mod overlay;
+mod precision;
+mod preset;
+mod preview;
+mod quad;
+mod quality;
+pub(crate) mod raw;
+#[cfg(feature = "opencv")]
+mod resize;
+mod shading;
+mod stack;
+mod stats;
+mod superpixel;
+mod tone;
+mod tuning;
+pub(crate) mod unpack;
+mod worker;
+mod yuv;
+mod zebra;
+
+pub use exposure::RsBayerExposureMeta;
+pub use meta::RsBayerTimingMeta;
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbBackend")]
+pub enum Backend {
+    #[cfg(feature = "opencv")]
+    #[default]
+    #[enum_value(name = "OpenCV cvtColor()", nick = "opencv")]
+    OpenCv = 0,
+    #[cfg(feature = "rust-demosaic")]
+    #[cfg_attr(not(feature = "opencv"), default)]
+    #[enum_value(name = "Built-in bilinear demosaic", nick = "rust")]
+    Rust = 1,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbMethod")]
+pub enum Method {
+    #[default]
+    #[enum_value(name = "Full resolution demosaic", nick = "full")]
+    Full = 0,
+    #[enum_value(name = "Half resolution, one pixel per 2x2 quad", nick = "superpixel")]
+    Superpixel = 1,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbDemosaicAlgorithm")]
+pub enum DemosaicAlgorithm {
+    #[default]
+    #[enum_value(name = "Bilinear interpolation", nick = "bilinear")]
+    Bilinear = 0,
+    #[enum_value(name = "Variable number of gradients", nick = "vng")]
+    Vng = 1,
+    #[enum_value(name = "Edge-aware interpolation", nick = "edge-aware")]
+    EdgeAware = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbBorderMode")]
+pub enum BorderMode {
+    #[default]
+    #[enum_value(name = "Demosaic the border like the rest", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Pad the raw frame by replicating the CFA", nick = "replicate")]
+    Replicate = 1,
+    #[enum_value(
+        name = "Drop the border, making the output 4 pixels smaller",
+        nick = "crop"
+    )]
+    Crop = 2,
+}
+
+#[cfg(feature = "opencv")]
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbDenoise")]
+pub enum Denoise {
+    #[default]
+    #[enum_value(name = "No denoising", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Gaussian blur", nick = "gaussian")]
+    Gaussian = 1,
+    #[enum_value(name = "Edge-preserving bilateral filter", nick = "bilateral")]
+    Bilateral = 2,
+}
+
+#[cfg(feature = "opencv")]
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbOverlayCorner")]
+pub enum OverlayCorner {
+    #[default]
+    #[enum_value(name = "Top left", nick = "top-left")]
+    TopLeft = 0,
+    #[enum_value(name = "Top right", nick = "top-right")]
+    TopRight = 1,
+    #[enum_value(name = "Bottom left", nick = "bottom-left")]
+    BottomLeft = 2,
+    #[enum_value(name = "Bottom right", nick = "bottom-right")]
+    BottomRight = 3,
+}
+
+#[cfg(feature = "opencv")]
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbScaleMethod")]
+pub enum ScaleMethod {
+    #[enum_value(name = "Nearest neighbour", nick = "nearest")]
+    Nearest = 0,
+    #[enum_value(name = "Bilinear interpolation", nick = "linear")]
+    Linear = 1,
+    #[enum_value(name = "Bicubic interpolation", nick = "cubic")]
+    Cubic = 2,
+    #[default]
+    #[enum_value(name = "Pixel area averaging", nick = "area")]
+    Area = 3,
+    #[enum_value(name = "Lanczos interpolation over 8x8 pixels", nick = "lanczos")]
+    Lanczos = 4,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbAwbMode")]
+pub enum AwbMode {
+    #[default]
+    #[enum_value(name = "Gains from the gain properties", nick = "manual")]
+    Manual = 0,
+    #[enum_value(name = "Gray world, measured on every frame", nick = "gray-world")]
+    GrayWorld = 1,
+    #[enum_value(name = "Keep the gains currently applied", nick = "locked")]
+    Locked = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbGrGbBalance")]
+pub enum GrGbBalance {
+    #[default]
+    #[enum_value(name = "Leave both greens as they are", nick = "off")]
+    Off = 0,
+    #[enum_value(name = "Ratio from the gr-gb-ratio property", nick = "manual")]
+    Manual = 1,
+    #[enum_value(name = "Ratio measured on every frame", nick = "auto")]
+    Auto = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbFpnCorrection")]
+pub enum FpnCorrection {
+    #[default]
+    #[enum_value(name = "No fixed pattern noise correction", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Offsets from the fpn-file property", nick = "file")]
+    File = 1,
+    #[enum_value(name = "Offsets estimated on the optical black margins", nick = "optical-black")]
+    OpticalBlack = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbHighlightMode")]
+pub enum HighlightMode {
+    #[default]
+    #[enum_value(name = "Saturate every color on its own", nick = "clip")]
+    Clip = 0,
+    #[enum_value(name = "Roll every color off into a common clip level", nick = "soft")]
+    Soft = 1,
+    #[enum_value(name = "Pull clipped tiles towards their unclipped colors", nick = "reconstruct")]
+    Reconstruct = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbFieldMode")]
+pub enum FieldMode {
+    #[default]
+    #[enum_value(name = "Weave the fields into one frame before demosaic", nick = "weave")]
+    Weave = 0,
+    #[enum_value(name = "Demosaic the fields separately and interleave", nick = "separate")]
+    Separate = 1,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbCfaLayout")]
+pub enum CfaLayout {
+    #[default]
+    #[enum_value(name = "One color per site of the 2x2 tile", nick = "bayer")]
+    Bayer = 0,
+    #[enum_value(name = "One color per 2x2 block of a 4x4 tile", nick = "quad")]
+    Quad = 1,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbQuadMode")]
+pub enum QuadMode {
+    #[default]
+    #[enum_value(name = "Average every 2x2 block, half resolution", nick = "binning")]
+    Binning = 0,
+    #[enum_value(name = "Rearrange into a bayer mosaic, full resolution", nick = "remosaic")]
+    Remosaic = 1,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbInputPattern")]
+pub enum InputPattern {
+    #[default]
+    #[enum_value(name = "Pattern of the caps", nick = "caps")]
+    Caps = 0,
+    #[enum_value(name = "Red, green / green, blue", nick = "rggb")]
+    Rggb = 1,
+    #[enum_value(name = "Blue, green / green, red", nick = "bggr")]
+    Bggr = 2,
+    #[enum_value(name = "Green, blue / red, green", nick = "gbrg")]
+    Gbrg = 3,
+    #[enum_value(name = "Green, red / blue, green", nick = "grbg")]
+    Grbg = 4,
+    #[enum_value(name = "Detected on the first frames", nick = "auto-detect")]
+    AutoDetect = 5,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbToneMapping")]
+pub enum ToneMapping {
+    #[enum_value(name = "Keep the top 8 bits", nick = "shift")]
+    Shift = 0,
+    #[default]
+    #[enum_value(name = "Linear from the black to the white point", nick = "linear")]
+    Linear = 1,
+    #[enum_value(name = "Display gamma from the black to the white point", nick = "gamma")]
+    Gamma = 2,
+    #[enum_value(name = "Logarithmic from the black to the white point", nick = "log")]
+    Log = 3,
+    #[enum_value(name = "Linear between percentiles of every frame", nick = "percentile-auto")]
+    PercentileAuto = 4,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbQualityLevel")]
+pub enum QualityLevel {
+    #[default]
+    #[enum_value(name = "As configured", nick = "full")]
+    Full = 0,
+    #[enum_value(name = "Optional processing steps disabled", nick = "reduced")]
+    Reduced = 1,
+    #[enum_value(name = "Bilinear demosaic, optional steps disabled", nick = "minimal")]
+    Minimal = 2,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayer2RgbLeaky")]
+pub enum Leaky {
+    #[default]
+    #[enum_value(name = "Not Leaky", nick = "no")]
+    No = 0,
+    #[enum_value(name = "Leaky on upstream (new buffers)", nick = "upstream")]
+    Upstream = 1,
+    #[enum_value(name = "Leaky on downstream (old buffers)", nick = "downstream")]
+    Downstream = 2,
+}
+
+// Parses the nick of one of the enums above, as in gst-launch property strings.
+fn from_nick<T>(nick: &str, what: &str) -> Result<T, String>
+where
+    T: StaticType + for<'a> glib::value::FromValue<'a>,
+{
+    glib::EnumClass::with_type(T::static_type())
+        .and_then(|class| class.to_value_by_nick(nick))
+        .and_then(|value| value.get::<T>().ok())
+        .ok_or_else(|| format!("unknown {what} {nick:?}"))
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_nick(s, "backend")
+    }
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_nick(s, "method")
+    }
+}
+
+impl FromStr for DemosaicAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_nick(s, "demosaic algorithm")
+    }
+}
+
+impl FromStr for BorderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_nick(s, "border mode")
+    }
+}
 
 glib::wrapper! {
     pub struct RsBayer2Rgb(ObjectSubclass<imp::RsBayer2Rgb>)
-        @extends gst_video::VideoFilter, gst_base::BaseTransform, gst::Element, gst::Object;
+        @extends gst_video::VideoFilter, gst_base::BaseTransform, gst::Element, gst::Object,
+        @implements gst::Preset;
+}
+
+impl RsBayer2Rgb {
+    /// Creates a converter with every property at its default.
+    pub fn new() -> Self {
+        glib::Object::new()
+    }
+
+    /// Creates a builder for a converter, setting the properties with their types
+    /// checked at compile time:
+    ///
+    /// ```no_run
+    /// use gstrsbayer::RsBayer2Rgb;
+    /// use gstrsbayer::convert::DemosaicAlgorithm;
+    ///
+    /// gst::init().unwrap();
+    /// let convert = RsBayer2Rgb::builder()
+    ///     .name("convert")
+    ///     .demosaic_algorithm(DemosaicAlgorithm::EdgeAware)
+    ///     .gains(1.8, 1.0, 1.5)
+    ///     .gamma(2.2)
+    ///     .build();
+    ///
+    /// let pipeline = gst::Pipeline::new();
+    /// pipeline.add(&convert).unwrap();
+    /// ```
+    ///
+    /// The bayer pattern and the output format aren't properties, they are
+    /// negotiated through the caps of the pads.
+    pub fn builder() -> RsBayer2RgbBuilder {
+        RsBayer2RgbBuilder {
+            builder: glib::Object::builder(),
+        }
+    }
+}
+
+impl Default for RsBayer2Rgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for [`RsBayer2Rgb`], see [`RsBayer2Rgb::builder()`].
+#[must_use = "The builder must be built to be used"]
+pub struct RsBayer2RgbBuilder {
+    builder: glib::object::ObjectBuilder<'static, RsBayer2Rgb>,
+}
+
+impl RsBayer2RgbBuilder {
+    pub fn name(self, name: &str) -> Self {
+        self.property("name", name)
+    }
+
+    pub fn method(self, method: Method) -> Self {
+        self.property("method", method)
+    }
+
+    pub fn video_direction(self, direction: gst_video::VideoOrientationMethod) -> Self {
+        self.property("video-direction", direction)
+    }
+
+    pub fn demosaic_algorithm(self, algorithm: DemosaicAlgorithm) -> Self {
+        self.property("demosaic-algorithm", algorithm)
+    }
+
+    pub fn border_mode(self, mode: BorderMode) -> Self {
+        self.property("border-mode", mode)
+    }
+
+    /// Sets the red, green and blue white balance gains.
+    pub fn gains(self, red: f64, green: f64, blue: f64) -> Self {
+        self.property("red-gain", red)
+            .property("green-gain", green)
+            .property("blue-gain", blue)
+    }
+
+    pub fn awb_mode(self, mode: AwbMode) -> Self {
+        self.property("awb-mode", mode)
+    }
+
+    pub fn gr_gb_balance(self, balance: GrGbBalance) -> Self {
+        self.property("gr-gb-balance", balance)
+    }
+
+    pub fn exposure_gain(self, gain: f64) -> Self {
+        self.property("exposure-gain", gain)
+    }
+
+    pub fn gamma(self, gamma: f64) -> Self {
+        self.property("gamma", gamma)
+    }
+
+    pub fn brightness(self, brightness: f64) -> Self {
+        self.property("brightness", brightness)
+    }
+
+    pub fn contrast(self, contrast: f64) -> Self {
+        self.property("contrast", contrast)
+    }
+
+    pub fn saturation(self, saturation: f64) -> Self {
+        self.property("saturation", saturation)
+    }
+
+    #[cfg(all(feature = "opencv", feature = "rust-demosaic"))]
+    pub fn backend(self, backend: Backend) -> Self {
+        self.property("backend", backend)
+    }
+
+    pub fn build(self) -> RsBayer2Rgb {
+        self.builder.build()
+    }
+
+    fn property(self, name: &'static str, value: impl Into<glib::Value>) -> Self {
+        Self {
+            builder: self.builder.property(name, value),
+        }
+    }
 }
 
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    #[cfg(all(feature = "opencv", feature = "rust-demosaic"))]
+    Backend::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    Method::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    DemosaicAlgorithm::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    BorderMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    AwbMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    GrGbBalance::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    FpnCorrection::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    HighlightMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    #[cfg(feature = "opencv")]
+    Denoise::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    #[cfg(feature = "opencv")]
+    OverlayCorner::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    #[cfg(feature = "opencv")]
+    ScaleMethod::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    Leaky::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    FieldMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    InputPattern::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    CfaLayout::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    QuadMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    ToneMapping::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    QualityLevel::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    preview::PreviewPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    meta::register();
+    exposure::register();
+
     gst::Element::register(
         Some(plugin),
         "rsbayer2rgb",
@@ -0,0 +1,122 @@
+// Reader for the calibration files, stored as NumPy `.npy` arrays so they can be
+// produced with a single `numpy.save()`.
+//
+// Only what the calibration data needs is supported: format versions 1 to 3, C order,
+// and little endian `u1`, `u2`, `f4` or `f8` elements.
+
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Elements of an array, flattened in C order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    pub shape: Vec<usize>,
+    pub data: Data,
+}
+
+impl Array {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Array::parse(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| "Not a NumPy array file".to_string())?;
+        let (header_len, rest) = match rest {
+            [1, _, a, b, rest @ ..] => (u16::from_le_bytes([*a, *b]) as usize, rest),
+            [2 | 3, _, a, b, c, d, rest @ ..] => {
+                (u32::from_le_bytes([*a, *b, *c, *d]) as usize, rest)
+            }
+            _ => return Err("Unsupported NumPy format version".to_string()),
+        };
+        if rest.len() < header_len {
+            return Err("Truncated header".to_string());
+        }
+        let header = std::str::from_utf8(&rest[..header_len])
+            .map_err(|_| "Header is not text".to_string())?;
+        let body = &rest[header_len..];
+
+        if header_value(header, "fortran_order") != Some("False") {
+            return Err("Only C order arrays are supported".to_string());
+        }
+        let shape = header_value(header, "shape")
+            .and_then(parse_shape)
+            .ok_or_else(|| "Invalid shape".to_string())?;
+        let len = shape.iter().product::<usize>();
+
+        let descr = header_value(header, "descr").map(|descr| descr.trim_matches(['\'', '"']));
+        let data = match descr {
+            Some("|u1" | "<u1") => Data::U8(elements(body, len, |[b]| b)?),
+            Some("<u2") => Data::U16(elements(body, len, u16::from_le_bytes)?),
+            Some("<f4") => Data::F32(elements(body, len, f32::from_le_bytes)?),
+            Some("<f8") => Data::F64(elements(body, len, f64::from_le_bytes)?),
+            _ => {
+                return Err(format!(
+                    "Unsupported element type {}, needs u1, u2, f4 or f8",
+                    descr.unwrap_or("?")
+                ));
+            }
+        };
+
+        Ok(Array { shape, data })
+    }
+}
+
+// Value of `key` in the header's Python dict literal, e.g. `(480, 640)` for `shape`.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header
+        .find(&format!("'{key}'"))
+        .or_else(|| header.find(&format!("\"{key}\"")))?;
+    let value = header[start + key.len() + 2..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')')? + 1
+    } else {
+        value.find([',', '}'])?
+    };
+
+    Some(value[..end].trim())
+}
+
+fn parse_shape(shape: &str) -> Option<Vec<usize>> {
+    shape
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().ok())
+        .collect()
+}
+
+fn elements<T, const N: usize>(
+    body: &[u8],
+    len: usize,
+    from_le_bytes: impl Fn([u8; N]) -> T,
+) -> Result<Vec<T>, String> {
+    if body.len() < len * N {
+        return Err(format!(
+            "Truncated data, {} bytes for {} elements",
+            body.len(),
+            len
+        ));
+    }
+
+    Ok(body[..len * N]
+        .chunks_exact(N)
+        .map(|chunk| from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,143 @@
+// Flips and rotations of the output for the video-direction and auto-orient properties, so
+// cameras mounted upside down or sideways need no separate videoflip pass. They are done
+// as one copy of the finished frame into the output.
+//
+// Every direction is a transposition, swapping the axes, followed by a horizontal
+// and a vertical flip of the result, either of them optional.
+
+use super::frame::{Error, Rect, fits};
+
+/// How a frame is turned, as the steps in the order they are taken.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    pub transpose: bool,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+/// The video direction of an `image-orientation` tag, read as videoflip reads it.
+pub fn direction_from_tag(orientation: &str) -> Option<gst_video::VideoOrientationMethod> {
+    use gst_video::VideoOrientationMethod as Direction;
+
+    Some(match orientation {
+        "rotate-0" => Direction::Identity,
+        "rotate-90" => Direction::_90r,
+        "rotate-180" => Direction::_180,
+        "rotate-270" => Direction::_90l,
+        "flip-rotate-0" => Direction::Horiz,
+        "flip-rotate-90" => Direction::UlLr,
+        "flip-rotate-180" => Direction::Vert,
+        "flip-rotate-270" => Direction::UrLl,
+        _ => return None,
+    })
+}
+
+impl Transform {
+    /// The transform of a video direction. Automatic directions are to be resolved
+    /// with [`direction_from_tag()`] first, custom ones, which depend on transformation
+    /// matrices, aren't supported. Both leave frames as they are.
+    pub fn for_direction(direction: gst_video::VideoOrientationMethod) -> Self {
+        use gst_video::VideoOrientationMethod as Direction;
+
+        let (transpose, flip_x, flip_y) = match direction {
+            Direction::_90r => (true, true, false),
+            Direction::_180 => (false, true, true),
+            Direction::_90l => (true, false, true),
+            Direction::Horiz => (false, true, false),
+            Direction::Vert => (false, false, true),
+            Direction::UlLr => (true, false, false),
+            Direction::UrLl => (true, true, true),
+            _ => (false, false, false),
+        };
+
+        Transform {
+            transpose,
+            flip_x,
+            flip_y,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    /// Size of a turned `width` x `height` frame.
+    pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self.transpose {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    /// Position of pixel `x`, `y` of a `width` x `height` frame once turned.
+    fn position(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (width, height) = self.size(width, height);
+        let (x, y) = match self.transpose {
+            true => (y, x),
+            false => (x, y),
+        };
+
+        (
+            if self.flip_x { width - 1 - x } else { x },
+            if self.flip_y { height - 1 - y } else { y },
+        )
+    }
+
+    /// Where `rect` of a `width` x `height` frame ends up once the frame is turned.
+    pub fn rect(&self, rect: Rect, width: usize, height: usize) -> Rect {
+        if rect.width == 0 || rect.height == 0 {
+            return rect;
+        }
+
+        let (x0, y0) = self.position(rect.x, rect.y, width, height);
+        let (x1, y1) = self.position(
+            rect.x + rect.width - 1,
+            rect.y + rect.height - 1,
+            width,
+            height,
+        );
+        Rect {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: x0.abs_diff(x1) + 1,
+            height: y0.abs_diff(y1) + 1,
+        }
+    }
+}
+
+/// Copies a `width` x `height` frame of `pixel_stride` samples per pixel into
+/// `output`, turned by `transform`. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn apply<S: Copy>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pixel_stride: usize,
+    transform: Transform,
+    output: &mut [S],
+    out_stride: usize,
+) -> Result<(), Error> {
+    let (out_width, out_height) = transform.size(width, height);
+    if !fits(input.len(), height, width * pixel_stride, in_stride)
+        || !fits(
+            output.len(),
+            out_height,
+            out_width * pixel_stride,
+            out_stride,
+        )
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        let row = &input[y * in_stride..][..width * pixel_stride];
+        for (x, pixel) in row.chunks_exact(pixel_stride).enumerate() {
+            let (out_x, out_y) = transform.position(x, y, width, height);
+            output[out_y * out_stride + out_x * pixel_stride..][..pixel_stride]
+                .copy_from_slice(pixel);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+// Debug overlay, a block of text burned into a corner of the output with the
+// negotiated pattern and size, the framerate and the conversion time of the frame, so
+// recordings made while investigating an issue carry them. Like the zebra it is drawn
+// over the finished output, after everything measuring it.
+//
+// The lines are formatted into strings kept across frames and drawn with OpenCV's
+// Hershey font without antialiasing, white on a black box so it reads over any image.
+
+use std::fmt::Write;
+
+use opencv::boxed_ref::BoxedRefMut;
+use opencv::core::{Mat, Point, Rect, Scalar};
+use opencv::imgproc;
+
+use super::OverlayCorner;
+use super::cfa::Pattern;
+use super::mat;
+
+const FONT: i32 = imgproc::FONT_HERSHEY_SIMPLEX;
+const FONT_SCALE: f64 = 0.4;
+const THICKNESS: i32 = 1;
+// Distance of the box from the edges of the frame and of the text from the edges of
+// the box, in pixels.
+const MARGIN: i32 = 4;
+const PADDING: i32 = 3;
+
+/// What the overlay shows of a frame.
+pub struct Status {
+    pub pattern: Pattern,
+    pub width: usize,
+    pub height: usize,
+    pub fps: gst::Fraction,
+    pub conversion_time: std::time::Duration,
+}
+
+pub struct Overlay {
+    lines: [String; 3],
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Overlay {
+            lines: std::array::from_fn(|_| String::with_capacity(32)),
+        }
+    }
+}
+
+impl Overlay {
+    /// Draws `status` into the `corner` of a `width` x `height` frame of 3 or 4 channel
+    /// pixels, rows `stride` bytes apart.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        frame: &mut [u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        corner: OverlayCorner,
+        status: &Status,
+    ) -> Result<(), String> {
+        let typ = match channels {
+            3 => opencv::core::CV_8UC3,
+            4 => opencv::core::CV_8UC4,
+            _ => return Err(format!("Unsupported number of channels {channels}")),
+        };
+
+        // Writing into a String can't fail.
+        for line in &mut self.lines {
+            line.clear();
+        }
+        let _ = write!(
+            self.lines[0],
+            "{} {}x{}",
+            status.pattern, status.width, status.height
+        );
+        let _ = match (status.fps.numer(), status.fps.denom()) {
+            (numer, denom) if numer > 0 && denom > 0 => {
+                write!(self.lines[1], "{:.2} fps", numer as f64 / denom as f64)
+            }
+            _ => write!(self.lines[1], "variable fps"),
+        };
+        let _ = write!(
+            self.lines[2],
+            "{:.2} ms",
+            status.conversion_time.as_secs_f64() * 1000.0
+        );
+
+        let mut frame =
+            mat::wrap_mut(frame, height, width, typ, stride).map_err(|err| err.to_string())?;
+        self.draw_lines(&mut frame, width as i32, height as i32, corner)
+            .map_err(|err| format!("Drawing the debug overlay failed: {err}"))
+    }
+
+    fn draw_lines(
+        &self,
+        frame: &mut BoxedRefMut<'_, Mat>,
+        width: i32,
+        height: i32,
+        corner: OverlayCorner,
+    ) -> opencv::Result<()> {
+        let mut baseline = 0;
+        let (mut text_width, mut line_height) = (0, 0);
+        for line in &self.lines {
+            let size = imgproc::get_text_size(line, FONT, FONT_SCALE, THICKNESS, &mut baseline)?;
+            text_width = text_width.max(size.width);
+            line_height = line_height.max(size.height + baseline);
+        }
+        let (box_width, box_height) = (
+            text_width + 2 * PADDING,
+            self.lines.len() as i32 * line_height + 2 * PADDING,
+        );
+
+        // Frames too small for the box get its top left part.
+        let x = match corner {
+            OverlayCorner::TopLeft | OverlayCorner::BottomLeft => MARGIN,
+            OverlayCorner::TopRight | OverlayCorner::BottomRight => width - box_width - MARGIN,
+        }
+        .max(0);
+        let y = match corner {
+            OverlayCorner::TopLeft | OverlayCorner::TopRight => MARGIN,
+            OverlayCorner::BottomLeft | OverlayCorner::BottomRight => height - box_height - MARGIN,
+        }
+        .max(0);
+
+        // The fourth component keeps four channel output opaque.
+        imgproc::rectangle(
+            frame,
+            Rect::new(x, y, box_width, box_height),
+            Scalar::new(0.0, 0.0, 0.0, 255.0),
+            imgproc::FILLED,
+            imgproc::LINE_8,
+            0,
+        )?;
+        for (i, line) in self.lines.iter().enumerate() {
+            let origin = Point::new(
+                x + PADDING,
+                y + PADDING + (i as i32 + 1) * line_height - baseline,
+            );
+            imgproc::put_text(
+                frame,
+                line,
+                origin,
+                FONT,
+                FONT_SCALE,
+                Scalar::all(255.0),
+                THICKNESS,
+                imgproc::LINE_8,
+                false,
+            )?;
+        }
+
+        Ok(())
+    }
+}
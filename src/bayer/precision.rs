@@ -0,0 +1,91 @@
+// High-precision processing for high-precision=true.
+//
+// The 8-bit chain rounds after every stage: the gains leave gaps between the levels
+// they reach, demosaic rounds its averages and the gamma stretches the gaps in the
+// shadows into visible bands. In high-precision mode the samples are promoted to 16
+// bits before the raw corrections, stay at that depth through demosaic, tone mapping
+// and saturation, and are only quantized to the 8-bit output at the end, rounded or
+// with an ordered dither that turns the remaining steps into fine noise.
+//
+// The demosaic is the scalar code of the built-in bilinear one. The backends only take
+// 8-bit frames, so it is used whatever the backend and demosaic algorithm.
+
+use super::frame::{Error, fits};
+
+// 8-bit samples are promoted by repeating the byte, so 255 maps to 65535 and rounding
+// back gives the sample it came from.
+pub const PROMOTE: u16 = 257;
+
+// 4x4 ordered dither, thresholds in sixteenths of an output step.
+const DITHER: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Promotes `width` x `height` 8-bit samples, rows `in_stride` bytes apart, to 16 bits
+/// in `output`, rows tightly packed.
+pub fn promote(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    output: &mut Vec<u16>,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width, in_stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    output.clear();
+    for y in 0..height {
+        let row = &input[y * in_stride..][..width];
+        output.extend(row.iter().map(|&sample| sample as u16 * PROMOTE));
+    }
+
+    Ok(())
+}
+
+/// A black level of 8-bit samples scaled to the promoted ones.
+pub fn promote_black_level(black_level: [u16; 4]) -> [u16; 4] {
+    black_level.map(|black| black.saturating_mul(PROMOTE))
+}
+
+/// Quantizes a `width` x `height` frame of `pixel_stride` 16-bit samples per pixel to
+/// 8 bits, rounding or, with `dither`, adding an ordered dither. Strides are in
+/// samples.
+#[allow(clippy::too_many_arguments)]
+pub fn quantize(
+    input: &[u16],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pixel_stride: usize,
+    output: &mut [u8],
+    out_stride: usize,
+    dither: bool,
+) -> Result<(), Error> {
+    let row_len = width * pixel_stride;
+    if !fits(input.len(), height, row_len, in_stride)
+        || !fits(output.len(), height, row_len, out_stride)
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let max = u16::MAX as u32;
+    for y in 0..height {
+        let src = &input[y * in_stride..][..row_len];
+        let dst = &mut output[y * out_stride..][..row_len];
+        let pixels = dst
+            .chunks_exact_mut(pixel_stride)
+            .zip(src.chunks_exact(pixel_stride));
+
+        for (x, (out, pixel)) in pixels.enumerate() {
+            // Below one step, so full scale stays full scale.
+            let threshold = match dither {
+                true => (2 * DITHER[y % 4][x % 4] + 1) * max / 32,
+                false => max / 2,
+            };
+            for (out, &sample) in out.iter_mut().zip(pixel) {
+                *out = ((sample as u32 * u8::MAX as u32 + threshold) / max) as u8;
+            }
+        }
+    }
+
+    Ok(())
+}
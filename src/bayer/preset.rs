@@ -0,0 +1,88 @@
+// Tuning presets for the GstPreset interface.
+//
+// The default GstPreset implementation saves and loads the controllable properties
+// only, so the element replaces the list with the tuning properties below: the
+// demosaic, the gains, the black level and calibration files and the tone settings.
+// Nothing about the stream or the element's plumbing is part of a preset.
+//
+// The built-in presets are listed along with the saved ones. A saved preset of the
+// same name shadows a built-in one, and a built-in preset sets every tuning property
+// it doesn't list to its default, so it always loads the same way.
+
+/// Properties a preset holds. Those of features the element was built without are
+/// left out at runtime.
+pub const PROPERTIES: [&str; 39] = [
+    "demosaic-algorithm",
+    "red-gain",
+    "green-gain",
+    "blue-gain",
+    "color-temperature",
+    "tint",
+    "awb-mode",
+    "gr-gb-balance",
+    "gr-gb-ratio",
+    "exposure-gain",
+    "highlight-mode",
+    "ob-rows",
+    "ob-cols",
+    "decompanding",
+    "lsc-file",
+    "flat-field-file",
+    "dark-frame-file",
+    "defect-list-file",
+    "fpn-correction",
+    "fpn-file",
+    "auto-defect-correction",
+    "defect-threshold",
+    "gamma",
+    "tone-lut",
+    "brightness",
+    "contrast",
+    "saturation",
+    "false-color-suppression",
+    "tone-mapping",
+    "black-point",
+    "white-point",
+    "auto-levels",
+    "auto-levels-low",
+    "auto-levels-high",
+    "auto-levels-frames",
+    "denoise",
+    "denoise-strength",
+    "sharpen-amount",
+    "sharpen-radius",
+];
+
+/// Built-in presets, properties as strings like in a saved preset.
+pub const BUILT_IN: [(&str, &[(&str, &str)]); 2] = [
+    (
+        "imx477-daylight",
+        &[
+            ("red-gain", "1.92"),
+            ("green-gain", "1.0"),
+            ("blue-gain", "1.58"),
+            ("awb-mode", "manual"),
+            ("gamma", "2.2"),
+            ("saturation", "1.1"),
+        ],
+    ),
+    (
+        "imx477-tungsten",
+        &[
+            ("red-gain", "1.24"),
+            ("green-gain", "1.0"),
+            ("blue-gain", "2.61"),
+            ("awb-mode", "manual"),
+            ("gamma", "2.2"),
+            ("saturation", "1.05"),
+        ],
+    ),
+];
+
+/// The properties of the built-in preset `name`.
+pub fn built_in(name: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    BUILT_IN
+        .iter()
+        .find(|(preset, _)| *preset == name)
+        .map(|(_, properties)| *properties)
+}
@@ -0,0 +1,395 @@
+// Preview pads, requested as `preview_%u`.
+//
+// A preview pad pushes a downscaled copy of every converted frame, so a pipeline
+// needs no tee and videoscale reading the full frame again for a small preview. The
+// copy is averaged from the output buffer right after conversion, before the element
+// hands that on, and takes its timestamps, duration and discontinuity flag.
+//
+// Every preview pad is a stream of its own: it pushes its stream-start, caps and the
+// segment of the main output before its first buffer and sets up its own buffer pool,
+// again whenever its size, the output or downstream changes. EOS and flushes are
+// forwarded to it, everything else it gets from downstream is dropped. Unlinked pads
+// cost nothing.
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_video::VideoFrameExt;
+
+use super::imp::CAT;
+
+const DEFAULT_SCALE: f64 = 0.25;
+const DEFAULT_WIDTH: u32 = 0;
+const DEFAULT_HEIGHT: u32 = 0;
+
+glib::wrapper! {
+    pub struct PreviewPad(ObjectSubclass<imp::PreviewPad>) @extends gst::Pad, gst::Object;
+}
+
+impl PreviewPad {
+    /// Downscales the converted `outbuf` and pushes it, setting up the stream first
+    /// if needed.
+    pub fn push_frame(
+        &self,
+        outbuf: &gst::BufferRef,
+        out_info: &gst_video::VideoInfo,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if !self.is_linked() {
+            return Err(gst::FlowError::NotLinked);
+        }
+        if !self.is_active() {
+            return Err(gst::FlowError::Flushing);
+        }
+
+        let imp = self.imp();
+        let settings = *imp.settings.lock().unwrap();
+        let info = preview_info(&settings, out_info).ok_or(gst::FlowError::NotNegotiated)?;
+        let pool = imp.negotiate(&info)?;
+
+        let mut buffer = pool.acquire_buffer(None)?;
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(outbuf.pts());
+            buffer.set_dts(outbuf.dts());
+            buffer.set_duration(outbuf.duration());
+            buffer.set_offset(outbuf.offset());
+            buffer.set_offset_end(outbuf.offset_end());
+            if outbuf.flags().contains(gst::BufferFlags::DISCONT) {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+
+            let src = gst_video::VideoFrameRef::from_buffer_ref_readable(outbuf, out_info)
+                .map_err(|_| gst::FlowError::Error)?;
+            let mut dst = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &info)
+                .map_err(|_| gst::FlowError::Error)?;
+            downscale(&src, &mut dst);
+        }
+
+        self.push(buffer)
+    }
+
+    /// Takes note of an event the element got on its sink pad, forwarding EOS and
+    /// flushes.
+    pub fn sink_event(&self, event: &gst::Event) {
+        // Unblocks a push holding the state.
+        if let gst::EventView::FlushStart(_) = event.view() {
+            self.push_event(event.clone());
+            return;
+        }
+
+        let mut state = self.imp().state.lock().unwrap();
+        match event.view() {
+            gst::EventView::StreamStart(_) => state.started = false,
+            gst::EventView::Segment(_) => state.segment_pending = true,
+            gst::EventView::FlushStop(_) => {
+                state.segment_pending = true;
+                drop(state);
+                self.push_event(event.clone());
+            }
+            gst::EventView::Eos(_) if state.started => {
+                drop(state);
+                self.push_event(event.clone());
+            }
+            _ => (),
+        }
+    }
+
+    /// Forgets the stream and releases the buffer pool, once the element stops or the
+    /// pad is released.
+    pub fn reset(&self) {
+        let state = std::mem::take(&mut *self.imp().state.lock().unwrap());
+        if let Some(pool) = state.pool {
+            let _ = pool.set_active(false);
+        }
+    }
+}
+
+// The caps of previews of `out_info` output.
+fn preview_info(
+    settings: &imp::Settings,
+    out_info: &gst_video::VideoInfo,
+) -> Option<gst_video::VideoInfo> {
+    let (width, height) = (out_info.width(), out_info.height());
+    let scaled = |size: u32| ((size as f64 * settings.scale).round() as u32).max(1);
+    // The other side of an explicit size keeps the aspect ratio.
+    let fit = |size: u32, to: u32, from: u32| {
+        ((size as u64 * to as u64 + from as u64 / 2) / from as u64).max(1) as u32
+    };
+    let (preview_width, preview_height) = match (settings.width, settings.height) {
+        (0, 0) => (scaled(width), scaled(height)),
+        (0, h) => (fit(width, h, height), h),
+        (w, 0) => (w, fit(height, w, width)),
+        (w, h) => (w, h),
+    };
+
+    gst_video::VideoInfo::builder(
+        out_info.format(),
+        preview_width.min(width),
+        preview_height.min(height),
+    )
+    .fps(out_info.fps())
+    .colorimetry(&out_info.colorimetry())
+    .build()
+    .ok()
+}
+
+// Averages every pixel of `src` into the `dst` pixel it falls in.
+fn downscale(
+    src: &gst_video::VideoFrameRef<&gst::BufferRef>,
+    dst: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+) {
+    let pixel = src.format_info().pixel_stride()[0] as usize;
+    let (src_width, src_height) = (src.width() as usize, src.height() as usize);
+    let (dst_width, dst_height) = (dst.width() as usize, dst.height() as usize);
+    let src_stride = src.plane_stride()[0] as usize;
+    let dst_stride = dst.plane_stride()[0] as usize;
+    let src_data = src.plane_data(0).unwrap();
+    let dst_data = dst.plane_data_mut(0).unwrap();
+
+    // Source pixels from..to make up destination pixel `i` of `n`.
+    let span = |i: usize, n: usize, size: usize| {
+        let from = i * size / n;
+        (from, ((i + 1) * size / n).max(from + 1))
+    };
+    let columns = (0..dst_width)
+        .map(|x| span(x, dst_width, src_width))
+        .collect::<Vec<_>>();
+    let mut sums = vec![0u32; dst_width * pixel];
+
+    for y in 0..dst_height {
+        let (top, bottom) = span(y, dst_height, src_height);
+        sums.fill(0);
+        for row in src_data[top * src_stride..]
+            .chunks(src_stride)
+            .take(bottom - top)
+        {
+            for (sum, &(left, right)) in sums.chunks_exact_mut(pixel).zip(&columns) {
+                for samples in row[left * pixel..right * pixel].chunks_exact(pixel) {
+                    for (sum, &sample) in sum.iter_mut().zip(samples) {
+                        *sum += sample as u32;
+                    }
+                }
+            }
+        }
+
+        let out = &mut dst_data[y * dst_stride..][..dst_width * pixel];
+        for ((out, sum), &(left, right)) in out
+            .chunks_exact_mut(pixel)
+            .zip(sums.chunks_exact(pixel))
+            .zip(&columns)
+        {
+            let count = ((right - left) * (bottom - top)) as u32;
+            for (out, sum) in out.iter_mut().zip(sum) {
+                *out = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+mod imp {
+    use super::*;
+
+    use std::sync::{LazyLock, Mutex};
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Settings {
+        pub scale: f64,
+        pub width: u32,
+        pub height: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                scale: DEFAULT_SCALE,
+                width: DEFAULT_WIDTH,
+                height: DEFAULT_HEIGHT,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct State {
+        // Stream-start pushed for the current stream.
+        pub started: bool,
+        // The segment of the main output is to be pushed before the next buffer.
+        pub segment_pending: bool,
+        // Negotiated caps and the pool for them.
+        pub info: Option<gst_video::VideoInfo>,
+        pub pool: Option<gst::BufferPool>,
+    }
+
+    #[derive(Default)]
+    pub struct PreviewPad {
+        pub settings: Mutex<Settings>,
+        pub state: Mutex<State>,
+    }
+
+    impl PreviewPad {
+        // Pushes whatever events `info` previews need first and returns the pool for
+        // them.
+        pub fn negotiate(
+            &self,
+            info: &gst_video::VideoInfo,
+        ) -> Result<gst::BufferPool, gst::FlowError> {
+            let pad = self.obj();
+            let element = pad.parent_element().ok_or(gst::FlowError::Flushing)?;
+            let src_pad = element.static_pad("src").unwrap();
+            let mut state = self.state.lock().unwrap();
+
+            if !state.started {
+                let stream_id = pad.create_stream_id(&element, Some(pad.name().as_str()));
+                let group_id = src_pad
+                    .sticky_event::<gst::event::StreamStart>(0)
+                    .and_then(|event| event.group_id());
+                let mut builder = gst::event::StreamStart::builder(&stream_id);
+                if let Some(group_id) = group_id {
+                    builder = builder.group_id(group_id);
+                }
+                pad.push_event(builder.build());
+                state.started = true;
+                state.segment_pending = true;
+                state.info = None;
+            }
+
+            if pad.check_reconfigure() || state.info.as_ref() != Some(info) {
+                let caps = info.to_caps().map_err(|_| gst::FlowError::NotNegotiated)?;
+                gst::debug!(CAT, obj = pad, "Negotiating {}", caps);
+                if !pad.push_event(gst::event::Caps::new(&caps)) {
+                    pad.mark_reconfigure();
+                    return Err(gst::FlowError::NotNegotiated);
+                }
+                let pool = self.decide_pool(&caps, info.size() as u32)?;
+                if let Some(pool) = state.pool.replace(pool) {
+                    let _ = pool.set_active(false);
+                }
+                state.info = Some(info.clone());
+            }
+
+            if state.segment_pending {
+                if let Some(segment) = src_pad.sticky_event::<gst::event::Segment>(0) {
+                    pad.push_event(segment);
+                }
+                state.segment_pending = false;
+            }
+
+            Ok(state.pool.clone().unwrap())
+        }
+
+        // Takes the pool downstream proposes, or a video pool of our own.
+        fn decide_pool(
+            &self,
+            caps: &gst::Caps,
+            size: u32,
+        ) -> Result<gst::BufferPool, gst::FlowError> {
+            let pad = self.obj();
+            let mut query = gst::query::Allocation::new(Some(caps), true);
+            let proposed = match pad.peer_query(&mut query) {
+                true => query
+                    .allocation_pools()
+                    .next()
+                    .and_then(|(pool, _, _, _)| pool),
+                false => None,
+            };
+
+            for pool in proposed
+                .into_iter()
+                .chain([gst_video::VideoBufferPool::new().upcast()])
+            {
+                let mut config = pool.config();
+                config.set_params(Some(caps), size, 0, 0);
+                if pool.set_config(config).is_ok() && pool.set_active(true).is_ok() {
+                    gst::debug!(CAT, obj = pad, "Using pool {:?} with size {}", pool, size);
+                    return Ok(pool);
+                }
+            }
+
+            gst::error!(CAT, obj = pad, "Failed to set up a buffer pool");
+            Err(gst::FlowError::Error)
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PreviewPad {
+        const NAME: &'static str = "GstRsBayer2RgbPreviewPad";
+        type Type = super::PreviewPad;
+        type ParentType = gst::Pad;
+    }
+
+    impl ObjectImpl for PreviewPad {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    /**
+                     * GstRsBayer2RgbPreviewPad:scale:
+                     *
+                     * Size of the preview relative to the output, while
+                     * #GstRsBayer2RgbPreviewPad:width and
+                     * #GstRsBayer2RgbPreviewPad:height are both 0.
+                     */
+                    glib::ParamSpecDouble::builder("scale")
+                        .nick("Scale")
+                        .blurb("Size of the preview relative to the output")
+                        .minimum(0.01)
+                        .maximum(1.0)
+                        .default_value(DEFAULT_SCALE)
+                        .mutable_playing()
+                        .build(),
+                    /**
+                     * GstRsBayer2RgbPreviewPad:width:
+                     *
+                     * Width of the preview, or 0 to take it from
+                     * #GstRsBayer2RgbPreviewPad:height keeping the aspect ratio, or
+                     * from #GstRsBayer2RgbPreviewPad:scale if both are 0. Previews
+                     * are never larger than the output.
+                     */
+                    glib::ParamSpecUInt::builder("width")
+                        .nick("Width")
+                        .blurb("Width of the preview, 0 for scaled")
+                        .default_value(DEFAULT_WIDTH)
+                        .mutable_playing()
+                        .build(),
+                    /**
+                     * GstRsBayer2RgbPreviewPad:height:
+                     *
+                     * Height of the preview, or 0 to take it from
+                     * #GstRsBayer2RgbPreviewPad:width keeping the aspect ratio, or
+                     * from #GstRsBayer2RgbPreviewPad:scale if both are 0.
+                     */
+                    glib::ParamSpecUInt::builder("height")
+                        .nick("Height")
+                        .blurb("Height of the preview, 0 for scaled")
+                        .default_value(DEFAULT_HEIGHT)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "scale" => settings.scale = value.get().expect("type checked upstream"),
+                "width" => settings.width = value.get().expect("type checked upstream"),
+                "height" => settings.height = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "scale" => settings.scale.to_value(),
+                "width" => settings.width.to_value(),
+                "height" => settings.height.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for PreviewPad {}
+
+    impl PadImpl for PreviewPad {}
+}
@@ -0,0 +1,85 @@
+// Adaptive quality for processing-deadline. The conversion times of the frames since
+// the last change of level are averaged, and the level drops a step once the average
+// is over the deadline and rises a step once it has stayed well below it for a while.
+// Dropping takes a few frames and rising many, at half the deadline, so a level that
+// barely makes the deadline isn't left and entered again every other frame.
+
+use std::time::Duration;
+
+use super::QualityLevel;
+
+// Frames at a level before it can be dropped, and before it can be raised.
+const DOWNGRADE_FRAMES: u32 = 5;
+const UPGRADE_FRAMES: u32 = 60;
+// Fraction of the deadline the average must stay under to raise the level.
+const UPGRADE_HEADROOM: f64 = 0.5;
+// Weight of the newest frame in the average.
+const WEIGHT: f64 = 0.2;
+
+impl QualityLevel {
+    fn lower(self) -> Option<Self> {
+        match self {
+            QualityLevel::Full => Some(QualityLevel::Reduced),
+            QualityLevel::Reduced => Some(QualityLevel::Minimal),
+            QualityLevel::Minimal => None,
+        }
+    }
+
+    fn higher(self) -> Option<Self> {
+        match self {
+            QualityLevel::Full => None,
+            QualityLevel::Reduced => Some(QualityLevel::Full),
+            QualityLevel::Minimal => Some(QualityLevel::Reduced),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct QualityControl {
+    level: QualityLevel,
+    // Frames converted at the level and their average conversion time in seconds.
+    frames: u32,
+    avg: f64,
+}
+
+impl QualityControl {
+    pub fn level(&self) -> QualityLevel {
+        self.level
+    }
+
+    pub fn reset(&mut self) {
+        *self = QualityControl::default();
+    }
+
+    /// Adds the conversion time of a frame, returning the new level if it changes. A
+    /// zero `deadline` disables adapting and goes back to full quality.
+    pub fn update(&mut self, elapsed: Duration, deadline: Duration) -> Option<QualityLevel> {
+        if deadline.is_zero() {
+            if self.level == QualityLevel::Full {
+                return None;
+            }
+            self.reset();
+            return Some(self.level);
+        }
+
+        let secs = elapsed.as_secs_f64();
+        self.avg = match self.frames {
+            0 => secs,
+            _ => self.avg + WEIGHT * (secs - self.avg),
+        };
+        self.frames += 1;
+
+        let deadline = deadline.as_secs_f64();
+        let next = if self.frames >= DOWNGRADE_FRAMES && self.avg > deadline {
+            self.level.lower()
+        } else if self.frames >= UPGRADE_FRAMES && self.avg < deadline * UPGRADE_HEADROOM {
+            self.level.higher()
+        } else {
+            None
+        }?;
+
+        self.level = next;
+        self.frames = 0;
+        Some(next)
+    }
+}
@@ -0,0 +1,502 @@
+// Processing of the bayer samples before demosaic, where every sample still holds a
+// single color and per-channel corrections are a plain multiplication. Shading gains
+// are folded into the white balance gains, so they stay one multiplication per sample.
+
+use super::HighlightMode;
+use super::cfa::{CfaColor, Pattern};
+use super::frame::{Error, fits};
+
+/// Largest gain accepted by the gain properties.
+pub const MAX_GAIN: f64 = 16.0;
+
+// Gains are applied in 16.16 fixed point.
+const GAIN_SHIFT: u32 = 16;
+// Fraction of the common clip level above which HighlightMode::Soft rolls off.
+const SOFT_KNEE: f64 = 0.8;
+
+/// Per-channel white balance gains. Both greens of the tile get the same gain, but
+/// for the green balance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+    /// Extra gain of the blue row greens, evening out a sensitivity difference
+    /// between the two greens of the tile.
+    pub green_balance: f64,
+}
+
+impl Default for Gains {
+    fn default() -> Self {
+        Gains {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+            green_balance: 1.0,
+        }
+    }
+}
+
+impl Gains {
+    /// The gains of every channel multiplied by `factor`.
+    pub fn scale(self, factor: f64) -> Gains {
+        Gains {
+            red: self.red * factor,
+            green: self.green * factor,
+            blue: self.blue * factor,
+            green_balance: self.green_balance,
+        }
+    }
+
+    /// Whether applying the gains would leave every sample unchanged.
+    pub fn is_unity(&self) -> bool {
+        *self == Gains::default()
+    }
+
+    fn for_color(&self, color: CfaColor) -> f64 {
+        match color {
+            CfaColor::Red => self.red,
+            CfaColor::GreenRed => self.green,
+            CfaColor::GreenBlue => self.green * self.green_balance,
+            CfaColor::Blue => self.blue,
+        }
+    }
+
+    fn fixed(&self, color: CfaColor) -> u64 {
+        (self.for_color(color).clamp(0.0, MAX_GAIN) * (1 << GAIN_SHIFT) as f64).round() as u64
+    }
+}
+
+/// A raw sample type, saturating at `MAX`.
+pub trait Sample: Copy {
+    const MAX: u64;
+
+    fn to_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+impl Sample for u8 {
+    const MAX: u64 = u8::MAX as u64;
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+
+    fn from_u64(value: u64) -> Self {
+        value as u8
+    }
+}
+
+impl Sample for u16 {
+    const MAX: u64 = u16::MAX as u64;
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+
+    fn from_u64(value: u64) -> Self {
+        value as u16
+    }
+}
+
+/// Fixed pattern noise offsets of every column and row of a frame in sample levels,
+/// added to the black level of their samples. `columns[y % 2][x]` is the offset of
+/// column `x` in the rows of `y`'s parity and `rows[x % 2][y]` that of row `y` in the
+/// columns of `x`'s parity, so both CFA colors of a column or row get their own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Offsets {
+    pub columns: [Vec<i32>; 2],
+    pub rows: [Vec<i32>; 2],
+}
+
+impl Offsets {
+    fn fits(&self, width: usize, height: usize) -> bool {
+        self.columns.iter().all(|columns| columns.len() >= width)
+            && self.rows.iter().all(|rows| rows.len() >= height)
+    }
+
+    // Black levels of the two sites of row `y`, offset by the row.
+    fn row_black(&self, y: usize, black: [u64; 2]) -> [i64; 2] {
+        std::array::from_fn(|x| black[x] as i64 + self.rows[x][y] as i64)
+    }
+}
+
+/// Subtracts the sample at the same position of `dark`, a `width` x `height` frame
+/// without padding, from every sample of a frame, clipping at zero. Strides are in
+/// samples.
+pub fn subtract<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    dark: &[S],
+    output: &mut [S],
+    out_stride: usize,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width, in_stride)
+        || !fits(output.len(), height, width, out_stride)
+        || !fits(dark.len(), height, width, width)
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        let src = &input[y * in_stride..][..width];
+        let dark = &dark[y * width..][..width];
+        let dst = &mut output[y * out_stride..][..width];
+
+        for ((out, sample), dark) in dst.iter_mut().zip(src).zip(dark) {
+            *out = S::from_u64(sample.to_u64().saturating_sub(dark.to_u64()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Subtracts the black level of its CFA color from every sample of a `width` x
+/// `height` frame, clipping at zero, multiplies the difference by the color's gain,
+/// rounding and saturating, and writes the result to `output`. `black_level` is
+/// indexed by `CfaColor as usize`, `offsets` are added to it. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn correct<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    black_level: [u16; 4],
+    offsets: Option<&Offsets>,
+    gains: Gains,
+    output: &mut [S],
+    out_stride: usize,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width, in_stride)
+        || !fits(output.len(), height, width, out_stride)
+        || offsets.is_some_and(|offsets| !offsets.fits(width, height))
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let tile = pattern
+        .tile()
+        .map(|row| row.map(|color| (black_level[color as usize] as u64, gains.fixed(color))));
+    let round = 1 << (GAIN_SHIFT - 1);
+
+    for y in 0..height {
+        let sites = tile[y % 2];
+        let src = &input[y * in_stride..][..width];
+        let dst = &mut output[y * out_stride..][..width];
+
+        let Some(offsets) = offsets else {
+            for (x, (out, sample)) in dst.iter_mut().zip(src).enumerate() {
+                let (black, gain) = sites[x % 2];
+                let value = (sample.to_u64().saturating_sub(black) * gain + round) >> GAIN_SHIFT;
+                *out = S::from_u64(value.min(S::MAX));
+            }
+            continue;
+        };
+
+        let row_black = offsets.row_black(y, sites.map(|(black, _)| black));
+        let columns = &offsets.columns[y % 2][..width];
+        for (x, ((out, sample), &column)) in dst.iter_mut().zip(src).zip(columns).enumerate() {
+            let black = (row_black[x % 2] + column as i64).max(0) as u64;
+            let value =
+                (sample.to_u64().saturating_sub(black) * sites[x % 2].1 + round) >> GAIN_SHIFT;
+            *out = S::from_u64(value.min(S::MAX));
+        }
+    }
+
+    Ok(())
+}
+
+/// Per sample gains in the fixed point form `correct_shaded()` takes: the shading gain
+/// of every sample of a `width` x `height` frame times the white balance gain of its
+/// color, saturating at `MAX_GAIN`.
+pub fn shading_gains(
+    shading: &[f32],
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    gains: Gains,
+) -> Vec<u32> {
+    let scale = (1 << GAIN_SHIFT) as f64;
+    shading
+        .iter()
+        .take(width * height)
+        .enumerate()
+        .map(|(i, &shading)| {
+            let gain = gains.for_color(pattern.color_at(i % width, i / width)) * shading as f64;
+            (gain.clamp(0.0, MAX_GAIN) * scale).round() as u32
+        })
+        .collect()
+}
+
+/// Like `correct()`, with a gain per sample from `shading_gains()` instead of one per
+/// color. `gains` holds the `width` x `height` samples without padding.
+#[allow(clippy::too_many_arguments)]
+pub fn correct_shaded<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    black_level: [u16; 4],
+    offsets: Option<&Offsets>,
+    gains: &[u32],
+    output: &mut [S],
+    out_stride: usize,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width, in_stride)
+        || !fits(output.len(), height, width, out_stride)
+        || !fits(gains.len(), height, width, width)
+        || offsets.is_some_and(|offsets| !offsets.fits(width, height))
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let black = pattern
+        .tile()
+        .map(|row| row.map(|color| black_level[color as usize] as u64));
+    let round = 1 << (GAIN_SHIFT - 1);
+
+    for y in 0..height {
+        let black = black[y % 2];
+        let src = &input[y * in_stride..][..width];
+        let gains = &gains[y * width..][..width];
+        let dst = &mut output[y * out_stride..][..width];
+
+        let Some(offsets) = offsets else {
+            for (x, ((out, sample), &gain)) in dst.iter_mut().zip(src).zip(gains).enumerate() {
+                let value = (sample.to_u64().saturating_sub(black[x % 2]) * gain as u64 + round)
+                    >> GAIN_SHIFT;
+                *out = S::from_u64(value.min(S::MAX));
+            }
+            continue;
+        };
+
+        let row_black = offsets.row_black(y, black);
+        let columns = &offsets.columns[y % 2][..width];
+        for (x, (((out, sample), &gain), &column)) in
+            dst.iter_mut().zip(src).zip(gains).zip(columns).enumerate()
+        {
+            let black = (row_black[x % 2] + column as i64).max(0) as u64;
+            let value = (sample.to_u64().saturating_sub(black) * gain as u64 + round) >> GAIN_SHIFT;
+            *out = S::from_u64(value.min(S::MAX));
+        }
+    }
+
+    Ok(())
+}
+
+/// Evens out the highlights of a `width` x `height` frame of samples that went through
+/// `correct()`, without padding. Saturated sensor samples of each color end up at a
+/// level of their own after `black_level` and `gains`, `white` being the saturation
+/// before them, so a highlight clipping in some colors gets their ratio of the clip
+/// levels instead of its own, the pink or cyan of blown skies. With
+/// `HighlightMode::Soft` every color rolls off from a knee into the lowest of the clip
+/// levels, with `HighlightMode::Reconstruct` the clipped samples of every CFA tile go
+/// to that level and the others are scaled towards it, keeping their ratio, by the
+/// share of clipped samples. `HighlightMode::Clip` leaves the samples as they are.
+#[allow(clippy::too_many_arguments)]
+pub fn highlights<S: Sample>(
+    samples: &mut [S],
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    black_level: [u16; 4],
+    gains: Gains,
+    white: u64,
+    mode: HighlightMode,
+) -> Result<(), Error> {
+    if !fits(samples.len(), height, width, width) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let round = 1 << (GAIN_SHIFT - 1);
+    let clip: [u64; 4] = std::array::from_fn(|color| {
+        let color = CfaColor::ALL[color];
+        let black = black_level[color as usize] as u64;
+        ((white.saturating_sub(black) * gains.fixed(color) + round) >> GAIN_SHIFT).min(S::MAX)
+    });
+    let level = clip.iter().copied().min().unwrap_or(S::MAX);
+    if level == 0 {
+        return Ok(());
+    }
+
+    match mode {
+        HighlightMode::Clip => (),
+        HighlightMode::Soft => soft_clip(samples, width, pattern, clip, level),
+        HighlightMode::Reconstruct => reconstruct(samples, width, height, pattern, clip, level),
+    }
+
+    Ok(())
+}
+
+// Rolls every color off from the knee so its clip level lands on `level`, the curve
+// meeting the straight line below the knee with the same slope and flattening out at
+// the clip level. A color clipping at `level` stays linear.
+fn soft_clip<S: Sample>(
+    samples: &mut [S],
+    width: usize,
+    pattern: Pattern,
+    clip: [u64; 4],
+    level: u64,
+) {
+    let level = level as f64;
+    let knee = SOFT_KNEE * level;
+    let curves = clip.map(|clip| {
+        let clip = (clip as f64).max(level);
+        (clip - knee, (clip - knee) / (level - knee))
+    });
+
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let value = sample.to_u64() as f64;
+        if value <= knee {
+            continue;
+        }
+        let (range, exponent) = curves[pattern.color_at(i % width, i / width) as usize];
+        let t = ((value - knee) / range).min(1.0);
+        let value = knee + (level - knee) * (1.0 - (1.0 - t).powf(exponent));
+        *sample = S::from_u64(value.round() as u64);
+    }
+}
+
+fn reconstruct<S: Sample>(
+    samples: &mut [S],
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    clip: [u64; 4],
+    level: u64,
+) {
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            // The sites of the tile within the frame, and which of them clipped. The
+            // rounding of the gains can leave a saturated sample a level below its clip.
+            let sites = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                .map(|(x, y)| (x < width && y < height).then_some(y * width + x));
+            let clipped = sites.map(|site| {
+                site.is_some_and(|i| {
+                    let color = pattern.color_at(i % width, i / width) as usize;
+                    samples[i].to_u64() + 1 >= clip[color]
+                })
+            });
+            let count = sites.iter().flatten().count();
+            let clipped_count = clipped.iter().filter(|&&clipped| clipped).count();
+            if clipped_count == 0 {
+                continue;
+            }
+
+            let brightest = sites
+                .iter()
+                .zip(clipped)
+                .filter_map(|(site, clipped)| site.filter(|_| !clipped))
+                .map(|i| samples[i].to_u64())
+                .max()
+                .unwrap_or(0);
+            let scale = match brightest {
+                0 => 1.0,
+                brightest => {
+                    let share = clipped_count as f64 / count as f64;
+                    1.0 + share * (level as f64 / brightest as f64 - 1.0)
+                }
+            };
+            for (site, clipped) in sites.into_iter().zip(clipped) {
+                let Some(i) = site else {
+                    continue;
+                };
+                let value = match clipped {
+                    true => level,
+                    false => ((samples[i].to_u64() as f64 * scale).round() as u64).min(S::MAX),
+                };
+                samples[i] = S::from_u64(value);
+            }
+        }
+    }
+}
+
+/// Mean of the samples of each CFA color of a `width` x `height` frame, indexed by
+/// `CfaColor as usize`. The stride is in samples.
+pub fn channel_means<S: Sample>(
+    input: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+) -> Result<[f64; 4], Error> {
+    if width < 2 || height < 2 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut sums = [0u64; 4];
+    let mut counts = [0u64; 4];
+    for y in 0..height {
+        let colors = pattern.tile()[y % 2];
+        let row = &input[y * stride..][..width];
+
+        // Both sites of the row at once, they alternate.
+        let mut row_sums = [0u64; 2];
+        for pair in row.chunks(2) {
+            for (sum, sample) in row_sums.iter_mut().zip(pair) {
+                *sum += sample.to_u64();
+            }
+        }
+
+        for (x, color) in colors.into_iter().enumerate() {
+            sums[color as usize] += row_sums[x];
+            counts[color as usize] += (width - x).div_ceil(2) as u64;
+        }
+    }
+
+    Ok(std::array::from_fn(|i| sums[i] as f64 / counts[i] as f64))
+}
+
+/// Black level of every CFA color measured on the optical black margins of a frame,
+/// the top `rows` rows and the left `cols` columns, as the median of the color's
+/// samples there. Indexed by `CfaColor as usize`. Colors without a sample in the
+/// margins, which takes margins of a single row or column, get the median of all
+/// margin samples. The stride is in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn optical_black<S: Sample>(
+    input: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    rows: usize,
+    cols: usize,
+) -> Result<[u16; 4], Error> {
+    if rows >= height || cols >= width {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut samples: [Vec<u64>; 4] = Default::default();
+    for y in 0..height {
+        let row = &input[y * stride..][..width];
+        let margin = if y < rows { row } else { &row[..cols] };
+
+        for (x, sample) in margin.iter().enumerate() {
+            samples[pattern.color_at(x, y) as usize].push(sample.to_u64());
+        }
+    }
+
+    let mut all: Vec<u64> = samples.concat();
+    let fallback = median(&mut all);
+    Ok(samples
+        .map(|mut samples| median(&mut samples).unwrap_or_else(|| fallback.unwrap_or(0)) as u16))
+}
+
+fn median(samples: &mut [u64]) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let mid = samples.len() / 2;
+    Some(*samples.select_nth_unstable(mid).1)
+}
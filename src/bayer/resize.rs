@@ -0,0 +1,84 @@
+// Output scaling of output-width and output-height. Frames are converted at their
+// full size into a frame of their own, which OpenCV's resize() then scales into the
+// output. Both axes scale independently, so an output of another aspect ratio
+// stretches the frame rather than letterboxing it.
+
+use opencv::core::Size;
+use opencv::imgproc;
+
+use super::ScaleMethod;
+use super::mat;
+
+pub struct Scaler {
+    // The converted frame in the output format, at its full size.
+    pub info: gst_video::VideoInfo,
+    pub frame: Vec<u8>,
+}
+
+impl Scaler {
+    pub fn new(
+        format: gst_video::VideoFormat,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, String> {
+        let info = u32::try_from(width)
+            .ok()
+            .zip(u32::try_from(height).ok())
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .and_then(|(width, height)| {
+                gst_video::VideoInfo::builder(format, width, height)
+                    .build()
+                    .ok()
+            })
+            .ok_or_else(|| format!("Can't convert {width}x{height} {format:?} frames to scale"))?;
+
+        Ok(Scaler {
+            frame: vec![0; info.size()],
+            info,
+        })
+    }
+
+    /// Scales the converted frame into `width` x `height` pixels of `output`, rows
+    /// `stride` bytes apart.
+    pub fn resize(
+        &self,
+        output: &mut [u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        method: ScaleMethod,
+    ) -> Result<(), String> {
+        let typ = match self.info.format_info().pixel_stride()[0] {
+            3 => opencv::core::CV_8UC3,
+            4 => opencv::core::CV_8UC4,
+            channels => return Err(format!("Unsupported number of channels {channels}")),
+        };
+        let interpolation = match method {
+            ScaleMethod::Nearest => imgproc::INTER_NEAREST,
+            ScaleMethod::Linear => imgproc::INTER_LINEAR,
+            ScaleMethod::Cubic => imgproc::INTER_CUBIC,
+            ScaleMethod::Area => imgproc::INTER_AREA,
+            ScaleMethod::Lanczos => imgproc::INTER_LANCZOS4,
+        };
+
+        let input = mat::wrap(
+            &self.frame,
+            self.info.height() as usize,
+            self.info.width() as usize,
+            typ,
+            self.info.stride()[0] as usize,
+        )
+        .map_err(|err| err.to_string())?;
+        let mut output =
+            mat::wrap_mut(output, height, width, typ, stride).map_err(|err| err.to_string())?;
+        imgproc::resize(
+            &input,
+            &mut output,
+            Size::new(width as i32, height as i32),
+            0.0,
+            0.0,
+            interpolation,
+        )
+        .map_err(|err| format!("Scaling the output failed: {err}"))
+    }
+}
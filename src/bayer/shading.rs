@@ -0,0 +1,206 @@
+// Lens shading correction, compensating vignetting and color shading with a gain per
+// bayer sample before demosaic.
+//
+// The gain map is a grid of per CFA color gains spread evenly over the converted
+// frame, the outermost grid points on the outermost 2x2 quads. A grid with one point
+// per quad is used as is, coarser grids are upscaled bilinearly.
+//
+// A flat-field is a captured exposure of an evenly lit target instead. Dividing by it,
+// normalized to the mean of every CFA color, removes dust shadows and illumination
+// gradients along with the vignetting. Both end up as a gain per sample.
+
+use std::path::Path;
+
+use super::cfa::Pattern;
+use super::frame::Rect;
+use super::npy::{Array, Data};
+
+// Flat-field samples are floored here, so a dead sample doesn't divide by zero. The
+// resulting gains saturate at `MAX_GAIN` anyway.
+const FLAT_FIELD_EPSILON: f32 = 1.0;
+
+/// Gains of every CFA color on a `rows` x `cols` grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GainMap {
+    rows: usize,
+    cols: usize,
+    // Row major, each point indexed by `CfaColor as usize`.
+    gains: Vec<[f32; 4]>,
+}
+
+impl GainMap {
+    /// Loads a gain map from a `.npy` file holding a `(rows, cols, 4)` array of `f4`
+    /// or `f8` gains, the last axis in red, red row green, blue row green and blue
+    /// order.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let array = Array::load(path)?;
+        let [rows, cols, 4] = array.shape[..] else {
+            return Err(format!(
+                "{}: gain map needs a (rows, cols, 4) shape, not {:?}",
+                path.display(),
+                array.shape
+            ));
+        };
+        let values: Vec<f32> = match array.data {
+            Data::F32(values) => values,
+            Data::F64(values) => values.into_iter().map(|value| value as f32).collect(),
+            _ => {
+                return Err(format!(
+                    "{}: gain map needs floating point gains",
+                    path.display()
+                ));
+            }
+        };
+        if rows == 0 || cols == 0 {
+            return Err(format!("{}: empty gain map", path.display()));
+        }
+        if values.iter().any(|gain| !gain.is_finite() || *gain < 0.0) {
+            return Err(format!(
+                "{}: gain map holds negative or non-finite gains",
+                path.display()
+            ));
+        }
+
+        Ok(GainMap {
+            rows,
+            cols,
+            gains: values
+                .chunks_exact(4)
+                .map(|point| point.try_into().unwrap())
+                .collect(),
+        })
+    }
+
+    /// Gain of every sample of a `width` x `height` frame, row major. Fails if the
+    /// grid has more points than the frame has 2x2 quads.
+    pub fn pixel_gains(
+        &self,
+        width: usize,
+        height: usize,
+        pattern: Pattern,
+    ) -> Result<Vec<f32>, String> {
+        let (quads_x, quads_y) = (width.div_ceil(2), height.div_ceil(2));
+        if self.cols > quads_x || self.rows > quads_y {
+            return Err(format!(
+                "{}x{} gain map is finer than the {}x{} quads of a {}x{} frame",
+                self.cols, self.rows, quads_x, quads_y, width, height
+            ));
+        }
+
+        // Every row of quads interpolates between the same grid columns.
+        let columns: Vec<(usize, usize, f32)> = (0..quads_x)
+            .map(|x| grid_position(x, quads_x, self.cols))
+            .collect();
+        let mut gains = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (r0, r1, fy) = grid_position(y / 2, quads_y, self.rows);
+            for x in 0..width {
+                let (c0, c1, fx) = columns[x / 2];
+                let color = pattern.color_at(x, y) as usize;
+                let at = |row: usize, col: usize| self.gains[row * self.cols + col][color];
+                let top = at(r0, c0) * (1.0 - fx) + at(r0, c1) * fx;
+                let bottom = at(r1, c0) * (1.0 - fx) + at(r1, c1) * fx;
+                gains.push(top * (1.0 - fy) + bottom * fy);
+            }
+        }
+
+        Ok(gains)
+    }
+}
+
+// Grid points surrounding quad `quad` of `quads`, and the weight of the second one.
+fn grid_position(quad: usize, quads: usize, points: usize) -> (usize, usize, f32) {
+    if points == 1 || quads == 1 {
+        return (0, 0, 0.0);
+    }
+
+    let pos = quad as f32 * (points - 1) as f32 / (quads - 1) as f32;
+    let first = (pos as usize).min(points - 2);
+    (first, first + 1, pos - first as f32)
+}
+
+/// A flat-field exposure with the size and pattern of the input frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatField {
+    width: usize,
+    height: usize,
+    samples: Vec<f32>,
+}
+
+impl FlatField {
+    /// Loads a flat-field from a `.npy` file holding a `(height, width)` array of `u1`
+    /// or `u2` bayer samples, e.g. the average of a few raw frames of a gray card.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let array = Array::load(path)?;
+        let [height, width] = array.shape[..] else {
+            return Err(format!(
+                "{}: flat-field needs a (height, width) shape, not {:?}",
+                path.display(),
+                array.shape
+            ));
+        };
+        let samples = match array.data {
+            Data::U8(samples) => samples.into_iter().map(f32::from).collect(),
+            Data::U16(samples) => samples.into_iter().map(f32::from).collect(),
+            _ => {
+                return Err(format!(
+                    "{}: flat-field needs 8 or 16-bit samples",
+                    path.display()
+                ));
+            }
+        };
+
+        Ok(FlatField {
+            width,
+            height,
+            samples,
+        })
+    }
+
+    /// Gain of every sample of the `window` of `width` x `height` input frames, row
+    /// major: the mean of the sample's color over the window divided by the sample.
+    /// `pattern` is the pattern of the window. Fails unless the flat-field has the
+    /// input size.
+    pub fn pixel_gains(
+        &self,
+        width: usize,
+        height: usize,
+        window: Rect,
+        pattern: Pattern,
+    ) -> Result<Vec<f32>, String> {
+        if (self.width, self.height) != (width, height) {
+            return Err(format!(
+                "{}x{} flat-field doesn't match the {}x{} input",
+                self.width, self.height, width, height
+            ));
+        }
+
+        let rows = || {
+            self.samples
+                .chunks_exact(self.width)
+                .skip(window.y)
+                .take(window.height)
+                .map(|row| &row[window.x..][..window.width])
+        };
+
+        let mut sums = [0f64; 4];
+        let mut counts = [0usize; 4];
+        for (y, row) in rows().enumerate() {
+            for (x, &sample) in row.iter().enumerate() {
+                let color = pattern.color_at(x, y) as usize;
+                sums[color] += sample as f64;
+                counts[color] += 1;
+            }
+        }
+        let means: [f32; 4] = std::array::from_fn(|i| (sums[i] / counts[i].max(1) as f64) as f32);
+
+        Ok(rows()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter().enumerate().map(move |(x, &sample)| {
+                    means[pattern.color_at(x, y) as usize] / sample.max(FLAT_FIELD_EPSILON)
+                })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,148 @@
+// Frame stacking for stack-frames > 1.
+//
+// The raw frames of a stack are summed sample by sample into 32-bit sums and the
+// average of every sample goes out as a single raw frame, which cuts the noise of a
+// static scene by the square root of the number of frames. The element converts that
+// frame like any other. It takes the metas, flags and PTS of the first frame of the
+// stack and the durations of all of them summed, so the output stays evenly spaced at
+// the divided framerate.
+//
+// The whole mapped buffer is averaged, padding and skipped margins included, so all
+// frames of a stack must have the same size. Samples stored in 16 bits are summed as
+// the words they are, packed frames can't be averaged without unpacking them first.
+
+use gst::prelude::*;
+
+use super::info::Depth;
+
+/// Raw frames added up so far.
+pub struct Stack {
+    first: gst::Buffer,
+    depth: Depth,
+    sums: Vec<u32>,
+    frames: u32,
+    duration: Option<gst::ClockTime>,
+    offset_end: u64,
+}
+
+impl Stack {
+    /// Starts a stack with `buffer` as its first frame, its samples stored as `depth`.
+    pub fn new(buffer: gst::Buffer, depth: Depth) -> Result<Self, String> {
+        if let Depth::Packed(packing) = depth {
+            return Err(format!(
+                "Can't stack {} bit packed frames, unpack them with rsbayerrepack first",
+                packing.depth()
+            ));
+        }
+        let sums = {
+            let map = buffer
+                .map_readable()
+                .map_err(|_| "Failed to map input buffer".to_string())?;
+            samples(&map, depth).collect()
+        };
+
+        Ok(Stack {
+            duration: buffer.duration(),
+            offset_end: buffer.offset_end(),
+            first: buffer,
+            depth,
+            sums,
+            frames: 1,
+        })
+    }
+
+    /// Adds the samples of `buffer`, which must be as large as the first frame.
+    pub fn add(&mut self, buffer: &gst::Buffer) -> Result<(), String> {
+        let map = buffer
+            .map_readable()
+            .map_err(|_| "Failed to map input buffer".to_string())?;
+        let len = self.sums.len() * bytes(self.depth);
+        if map.len() != len {
+            return Err(format!(
+                "{} bytes frame in a stack of {} bytes frames",
+                map.len(),
+                len
+            ));
+        }
+
+        for (sum, sample) in self.sums.iter_mut().zip(samples(&map, self.depth)) {
+            *sum += sample;
+        }
+        self.frames += 1;
+        self.duration = self.duration.opt_add(buffer.duration());
+        self.offset_end = buffer.offset_end();
+
+        Ok(())
+    }
+
+    /// Frames added so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The stacked frame, every sample the rounded average of the frames.
+    pub fn finish(self) -> Result<gst::Buffer, String> {
+        let Stack {
+            first,
+            depth,
+            sums,
+            frames,
+            duration,
+            offset_end,
+        } = self;
+
+        // A copy of the first frame, so its metas, flags and timestamps come along.
+        let mut stacked = first
+            .copy_deep()
+            .map_err(|_| "Failed to copy input buffer".to_string())?;
+        {
+            let stacked = stacked.get_mut().unwrap();
+            stacked.set_duration(duration);
+            stacked.set_offset_end(offset_end);
+
+            let mut map = stacked
+                .map_writable()
+                .map_err(|_| "Failed to map stacked buffer".to_string())?;
+            let averages = sums.into_iter().map(|sum| (sum + frames / 2) / frames);
+            match depth {
+                Depth::Sixteen { big_endian, .. } => {
+                    for (bytes, average) in map.chunks_exact_mut(2).zip(averages) {
+                        let average = average as u16;
+                        bytes.copy_from_slice(&match big_endian {
+                            true => average.to_be_bytes(),
+                            false => average.to_le_bytes(),
+                        });
+                    }
+                }
+                _ => {
+                    for (sample, average) in map.iter_mut().zip(averages) {
+                        *sample = average as u8;
+                    }
+                }
+            }
+        }
+
+        Ok(stacked)
+    }
+}
+
+// Bytes of the unit the samples of `depth` are summed in.
+fn bytes(depth: Depth) -> usize {
+    match depth {
+        Depth::Sixteen { .. } => 2,
+        _ => 1,
+    }
+}
+
+// The samples of a mapped frame, every byte or every 16-bit word.
+fn samples(data: &[u8], depth: Depth) -> Box<dyn Iterator<Item = u32> + '_> {
+    match depth {
+        Depth::Sixteen { big_endian, .. } => Box::new(data.chunks_exact(2).map(move |b| {
+            u32::from(match big_endian {
+                true => u16::from_be_bytes([b[0], b[1]]),
+                false => u16::from_le_bytes([b[0], b[1]]),
+            })
+        })),
+        _ => Box::new(data.iter().map(|&sample| u32::from(sample))),
+    }
+}
@@ -0,0 +1,81 @@
+// Per-channel statistics of the raw samples, for external AE/AWB loops.
+
+use super::cfa::Pattern;
+use super::frame::{Error, fits};
+use super::raw::Sample;
+
+pub const HISTOGRAM_BINS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStats {
+    pub mean: f64,
+    pub min: u64,
+    pub max: u64,
+    /// Sample counts in `HISTOGRAM_BINS` equal bins over the full sample range.
+    pub histogram: [u32; HISTOGRAM_BINS],
+}
+
+impl Default for ChannelStats {
+    fn default() -> Self {
+        ChannelStats {
+            mean: 0.0,
+            min: 0,
+            max: 0,
+            histogram: [0; HISTOGRAM_BINS],
+        }
+    }
+}
+
+/// Statistics of every CFA color of a `width` x `height` frame, indexed by
+/// `CfaColor as usize`. Only every `step`th 2x2 quad in each direction is measured.
+/// The stride is in samples.
+pub fn measure<S: Sample>(
+    input: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    step: usize,
+) -> Result<[ChannelStats; 4], Error> {
+    if width < 2 || height < 2 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let step = step.max(1);
+    let tile = pattern.tile();
+    let mut stats: [ChannelStats; 4] = Default::default();
+    let mut sums = [0u64; 4];
+    let mut counts = [0u64; 4];
+    for stats in &mut stats {
+        stats.min = S::MAX;
+    }
+
+    for quad_y in (0..height / 2).step_by(step) {
+        for (dy, colors) in tile.iter().enumerate() {
+            let row = &input[(2 * quad_y + dy) * stride..][..width];
+
+            for quad_x in (0..width / 2).step_by(step) {
+                for (dx, color) in colors.iter().enumerate() {
+                    let sample = row[2 * quad_x + dx].to_u64();
+                    let i = *color as usize;
+                    let stats = &mut stats[i];
+
+                    sums[i] += sample;
+                    counts[i] += 1;
+                    stats.min = stats.min.min(sample);
+                    stats.max = stats.max.max(sample);
+                    stats.histogram[(sample * HISTOGRAM_BINS as u64 / (S::MAX + 1)) as usize] += 1;
+                }
+            }
+        }
+    }
+
+    for (i, stats) in stats.iter_mut().enumerate() {
+        stats.mean = sums[i] as f64 / counts[i] as f64;
+    }
+
+    Ok(stats)
+}
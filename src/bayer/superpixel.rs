@@ -0,0 +1,71 @@
+// Half-resolution conversion: every 2x2 CFA quad becomes one output pixel holding its
+// red and blue samples and the rounded average of its two greens. About four times
+// cheaper than a full demosaic and free of interpolation artifacts, which makes it a
+// good fit for previews.
+//
+// A trailing odd column or row of the input is dropped.
+
+use super::cfa::{CfaColor, Pattern};
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+
+/// Collapses the quads of a `width` x `height` frame into an output frame of
+/// `width / 2` x `height / 2` pixels of `layout`. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn convert<S: Sample>(
+    input: &[S],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    pattern: Pattern,
+    output: &mut [S],
+    out_stride: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    let (out_width, out_height) = (width / 2, height / 2);
+    if out_width == 0 || out_height == 0 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(input.len(), height, width, in_stride)
+        || !fits(
+            output.len(),
+            out_height,
+            out_width * layout.pixel_stride,
+            out_stride,
+        )
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    // Offsets of each color within a quad, as (row, column).
+    let mut sites = [(0, 0); 4];
+    for (y, row) in pattern.tile().iter().enumerate() {
+        for (x, color) in row.iter().enumerate() {
+            sites[*color as usize] = (y, x);
+        }
+    }
+    let site = |color: CfaColor| sites[color as usize];
+
+    for y in 0..out_height {
+        let rows = [
+            &input[2 * y * in_stride..][..2 * out_width],
+            &input[(2 * y + 1) * in_stride..][..2 * out_width],
+        ];
+        let sample = |(row, column): (usize, usize), x: usize| rows[row][2 * x + column];
+        let out = &mut output[y * out_stride..][..out_width * layout.pixel_stride];
+
+        for (x, pixel) in out.chunks_exact_mut(layout.pixel_stride).enumerate() {
+            let green_red = sample(site(CfaColor::GreenRed), x).to_u64();
+            let green_blue = sample(site(CfaColor::GreenBlue), x).to_u64();
+
+            pixel[layout.red] = sample(site(CfaColor::Red), x);
+            pixel[layout.green] = S::from_u64((green_red + green_blue).div_ceil(2));
+            pixel[layout.blue] = sample(site(CfaColor::Blue), x);
+            if let Some(alpha) = layout.alpha {
+                pixel[alpha] = S::from_u64(S::MAX);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,297 @@
+// Tone stages on the demosaiced output. The conversion runs
+//
+//     dark frame -> black level -> shading and white balance gains
+//         -> defective pixels -> demosaic -> spatial and temporal denoise
+//         -> sharpen -> gamma or tone curve -> brightness and contrast
+//         -> saturation -> zebra overlay -> flip or rotation
+//
+// Gains and demosaic expect linear samples, so anything reshaping the tone comes
+// last. Gamma, tone curve, brightness and contrast are folded into a single lookup
+// table over the color channels. Alpha is left alone. With high-precision the stages
+// up to the saturation run on 16-bit samples and the OpenCV filters follow the
+// quantization to the output. The tone-mapping operator reducing the 16-bit range
+// comes right after demosaic, as another lookup table.
+//
+// Auto-levels stretches the output linearly between percentiles of its luma, right
+// before the gamma: a global version of what the static black and white points do,
+// measured on every frame.
+
+use super::ToneMapping;
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+
+// Display gamma of ToneMapping::Gamma.
+const DISPLAY_GAMMA: f64 = 2.2;
+// Contrast between the white point and the darkest level ToneMapping::Log tells apart
+// from black, 12 stops.
+const LOG_RANGE: f64 = 4096.0;
+// Histogram bins percentiles() counts the samples in.
+const PERCENTILE_BINS: usize = 1024;
+
+/// Linear stretch of the output between percentiles of its luma.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoLevels {
+    /// Fractions of the pixels mapped to black and below white.
+    pub low: f64,
+    pub high: f64,
+    /// Frames the levels are damped over, 1 to follow every frame.
+    pub frames: u32,
+    /// Holds the levels measured so far.
+    pub lock: bool,
+}
+
+impl AutoLevels {
+    /// The levels of a frame measured at `measured`, fractions of full scale, moved
+    /// from the `last` ones.
+    pub fn damp(&self, last: Option<(f64, f64)>, measured: (f64, f64)) -> (f64, f64) {
+        let Some((black, white)) = last else {
+            return measured;
+        };
+        let weight = 1.0 / self.frames.max(1) as f64;
+        (
+            black + (measured.0 - black) * weight,
+            white + (measured.1 - white) * weight,
+        )
+    }
+}
+
+/// Sample to sample mapping with one entry per sample value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut<S> {
+    entries: Vec<S>,
+}
+
+impl<S: Sample> Lut<S> {
+    pub fn identity() -> Self {
+        Lut {
+            entries: (0..=S::MAX).map(S::from_u64).collect(),
+        }
+    }
+
+    /// Encodes linear samples for display, `out = in^(1 / gamma)` on the normalized
+    /// range. 2.2 maps a linear half to about 73%.
+    pub fn gamma(gamma: f64) -> Self {
+        let max = S::MAX as f64;
+        let entries = (0..=S::MAX)
+            .map(|value| {
+                let encoded = (value as f64 / max).powf(1.0 / gamma) * max;
+                S::from_u64(encoded.round().clamp(0.0, max) as u64)
+            })
+            .collect();
+
+        Lut { entries }
+    }
+
+    /// Resamples a tone curve mapping `curve.len()` input levels to as many output
+    /// levels, interpolating linearly between its points. The curve needs at least
+    /// two points.
+    pub fn from_curve(curve: &[u16]) -> Self {
+        let last = (curve.len() - 1) as f64;
+        let max = S::MAX as f64;
+        let entries = (0..=S::MAX)
+            .map(|value| {
+                let pos = value as f64 * last / max;
+                let i = (pos as usize).min(curve.len() - 2);
+                let frac = pos - i as f64;
+                let level = curve[i] as f64 * (1.0 - frac) + curve[i + 1] as f64 * frac;
+                S::from_u64((level * max / last).round().clamp(0.0, max) as u64)
+            })
+            .collect();
+
+        Lut { entries }
+    }
+
+    /// Follows the mapping by `out = (in - 0.5) * contrast + 0.5 + brightness` on the
+    /// normalized range, so contrast 0 gives flat mid-gray.
+    pub fn adjust(mut self, brightness: f64, contrast: f64) -> Self {
+        let max = S::MAX as f64;
+        for entry in &mut self.entries {
+            let value = (entry.to_u64() as f64 / max - 0.5) * contrast + 0.5 + brightness;
+            *entry = S::from_u64((value * max).round().clamp(0.0, max) as u64);
+        }
+
+        self
+    }
+
+    /// Maps the range from `black` to `white`, fractions of full scale, to the full
+    /// range with `mapping`. [`ToneMapping::Shift`] ignores the points and keeps the
+    /// top 8 bits of every sample, [`ToneMapping::PercentileAuto`] maps linearly like
+    /// [`ToneMapping::Linear`].
+    pub fn reduction(mapping: ToneMapping, black: f64, white: f64) -> Self {
+        let max = S::MAX as f64;
+        let step = (S::MAX + 1) / 256;
+        let range = (white - black).max(1.0 / max);
+        let entries = (0..=S::MAX)
+            .map(|value| {
+                let x = ((value as f64 / max - black) / range).clamp(0.0, 1.0);
+                let y = match mapping {
+                    ToneMapping::Shift => return S::from_u64(value / step * (S::MAX / 255)),
+                    ToneMapping::Linear | ToneMapping::PercentileAuto => x,
+                    ToneMapping::Gamma => x.powf(1.0 / DISPLAY_GAMMA),
+                    ToneMapping::Log => (1.0 + x * (LOG_RANGE - 1.0)).ln() / LOG_RANGE.ln(),
+                };
+                S::from_u64((y * max).round() as u64)
+            })
+            .collect();
+
+        Lut { entries }
+    }
+
+    pub fn map(&self, sample: S) -> S {
+        self.entries[sample.to_u64() as usize]
+    }
+}
+
+/// Maps the color channels of a `width` x `height` frame of `layout` pixels through
+/// `lut`. The stride is in samples.
+pub fn apply<S: Sample>(
+    lut: &Lut<S>,
+    frame: &mut [S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    if !fits(frame.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        let row = &mut frame[y * stride..][..width * layout.pixel_stride];
+        for pixel in row.chunks_exact_mut(layout.pixel_stride) {
+            for channel in [layout.red, layout.green, layout.blue] {
+                pixel[channel] = lut.map(pixel[channel]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Levels below which the fractions `low` and `high` of the color samples of a
+/// `width` x `height` frame of `layout` pixels lie, as fractions of full scale. The
+/// stride is in samples.
+pub fn percentiles<S: Sample>(
+    frame: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    low: f64,
+    high: f64,
+) -> Result<(f64, f64), Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(frame.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut histogram = [0u64; PERCENTILE_BINS];
+    for y in 0..height {
+        let row = &frame[y * stride..][..width * layout.pixel_stride];
+        for pixel in row.chunks_exact(layout.pixel_stride) {
+            for channel in [layout.red, layout.green, layout.blue] {
+                let bin = pixel[channel].to_u64() * PERCENTILE_BINS as u64 / (S::MAX + 1);
+                histogram[bin as usize] += 1;
+            }
+        }
+    }
+
+    Ok(histogram_levels(&histogram, low, high))
+}
+
+/// Like `percentiles()`, over the BT.601 luma of the pixels instead of their color
+/// samples.
+pub fn luma_percentiles<S: Sample>(
+    frame: &[S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    low: f64,
+    high: f64,
+) -> Result<(f64, f64), Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::FrameTooSmall);
+    }
+    if !fits(frame.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut histogram = [0u64; PERCENTILE_BINS];
+    for y in 0..height {
+        let row = &frame[y * stride..][..width * layout.pixel_stride];
+        for pixel in row.chunks_exact(layout.pixel_stride) {
+            let luma = [layout.red, layout.green, layout.blue]
+                .into_iter()
+                .zip(LUMA_WEIGHTS)
+                .map(|(channel, weight)| pixel[channel].to_u64() as i64 * weight)
+                .sum::<i64>()
+                >> 8;
+            let bin = luma as u64 * PERCENTILE_BINS as u64 / (S::MAX + 1);
+            histogram[bin as usize] += 1;
+        }
+    }
+
+    Ok(histogram_levels(&histogram, low, high))
+}
+
+// Levels below which the fractions `low` and `high` of the counts lie.
+fn histogram_levels(histogram: &[u64; PERCENTILE_BINS], low: f64, high: f64) -> (f64, f64) {
+    let total = histogram.iter().sum::<u64>() as f64;
+    let level = |fraction: f64| {
+        let mut count = 0;
+        let bin = histogram
+            .iter()
+            .position(|&n| {
+                count += n;
+                count as f64 >= fraction * total
+            })
+            .unwrap_or(PERCENTILE_BINS - 1);
+        bin as f64 / PERCENTILE_BINS as f64
+    };
+
+    (level(low), level(high) + 1.0 / PERCENTILE_BINS as f64)
+}
+
+// BT.601 luma weights in 8.8 fixed point.
+pub const LUMA_WEIGHTS: [i64; 3] = [77, 150, 29];
+
+/// Scales the distance of every pixel's color channels from its luma by
+/// `saturation`, so 0 gives grayscale. The stride is in samples.
+pub fn saturate<S: Sample>(
+    frame: &mut [S],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    saturation: f64,
+) -> Result<(), Error> {
+    if !fits(frame.len(), height, width * layout.pixel_stride, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let factor = (saturation * 256.0).round() as i64;
+    for y in 0..height {
+        let row = &mut frame[y * stride..][..width * layout.pixel_stride];
+        for pixel in row.chunks_exact_mut(layout.pixel_stride) {
+            let channels = [layout.red, layout.green, layout.blue];
+            let values = channels.map(|channel| pixel[channel].to_u64() as i64);
+            let luma = (values
+                .iter()
+                .zip(LUMA_WEIGHTS)
+                .map(|(v, w)| v * w)
+                .sum::<i64>()
+                + 128)
+                >> 8;
+
+            for (channel, value) in channels.into_iter().zip(values) {
+                let saturated = luma + (((value - luma) * factor + 128) >> 8);
+                pixel[channel] = S::from_u64(saturated.clamp(0, S::MAX as i64) as u64);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,357 @@
+// Reader for the tuning-file property.
+//
+// A tuning file sets tuning properties by name, the same ones a preset holds. Two
+// syntaxes are understood, picked by the file extension: JSON for `.json` and
+// anything else, TOML for `.toml`. Both are flat, one key per property:
+//
+//     {
+//         "red-gain": 1.92,
+//         "blue-gain": 1.58,
+//         "demosaic-algorithm": "edge-aware",
+//         "lsc-file": "unit-0042/lsc.npy",
+//         "tone-lut": [0, 4, 8, 12, ...]
+//     }
+//
+//     red-gain = 1.92
+//     blue-gain = 1.58
+//     demosaic-algorithm = "edge-aware"
+//
+// Numbers go to numeric properties, `true` and `false` to boolean ones, strings to
+// enum properties by nick and to string properties, and arrays to tone-lut and
+// decompanding, whose knee points are arrays of two integers. Relative paths of the `*-file` properties are relative to the directory
+// of the tuning file. Properties a file doesn't name keep their values.
+//
+// Only that subset of the syntaxes is supported: no nested objects or tables, no
+// escapes in strings but `\"`, `\\` and `\n`, and every TOML key and value on a line
+// of its own.
+//
+// The fields of the isp-params structure are taken through the same values, so both
+// ways check them alike.
+
+use gst::glib;
+use gst::prelude::*;
+
+use std::path::Path;
+
+/// A value of a tuning file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The value a field of the isp-params structure stands for, None for types no
+    /// tuning property has.
+    pub fn from_value(value: &glib::Value) -> Option<Value> {
+        if let Ok(value) = value.get::<bool>() {
+            return Some(Value::Bool(value));
+        }
+        if let Ok(string) = value.get::<String>() {
+            return Some(Value::String(string));
+        }
+        if let Some((_, value)) = glib::EnumValue::from_value(value) {
+            return Some(Value::String(value.nick().to_string()));
+        }
+        if let Ok(array) = value.get::<gst::Array>() {
+            return array
+                .iter()
+                .map(|value| Value::from_value(value))
+                .collect::<Option<_>>()
+                .map(Value::Array);
+        }
+        value
+            .transform::<f64>()
+            .ok()
+            .and_then(|value| value.get().ok())
+            .map(Value::Number)
+    }
+}
+
+/// Reads the keys of a tuning file and their values, in file order.
+pub fn load(path: &Path) -> Result<Vec<(String, Value)>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let entries = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => parse_toml(&text),
+        _ => parse_json(&text),
+    };
+    entries.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Reads a JSON object of keys and values.
+pub fn parse_json(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut parser = Parser { text, pos: 0 };
+    parser.skip_whitespace();
+    parser.expect('{')?;
+
+    let mut entries = Vec::new();
+    parser.skip_whitespace();
+    if !parser.eat('}') {
+        loop {
+            parser.skip_whitespace();
+            let key = parser.string()?;
+            parser.skip_whitespace();
+            parser.expect(':')?;
+            parser.skip_whitespace();
+            entries.push((key, parser.value()?));
+            parser.skip_whitespace();
+            if parser.eat('}') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(entries),
+        Some(_) => Err(parser.error("trailing characters")),
+    }
+}
+
+/// Reads TOML `key = value` lines.
+pub fn parse_toml(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let mut entries = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        let key = match parser.peek() {
+            None => return Ok(entries),
+            Some('#') => {
+                parser.skip_line();
+                continue;
+            }
+            Some('[') => return Err(parser.error("tables aren't supported")),
+            Some('"') => parser.string()?,
+            Some(_) => parser.bare_key()?,
+        };
+        parser.skip_blanks();
+        parser.expect('=')?;
+        parser.skip_blanks();
+        entries.push((key, parser.value()?));
+
+        parser.skip_blanks();
+        match parser.peek() {
+            None | Some('\n' | '\r' | '#') => parser.skip_line(),
+            Some(_) => return Err(parser.error("trailing characters")),
+        }
+    }
+}
+
+/// The value of property `pspec` a tuning file `value` stands for, with relative
+/// paths resolved against `dir`.
+pub fn property_value(
+    pspec: &glib::ParamSpec,
+    value: &Value,
+    dir: &Path,
+) -> Result<glib::Value, String> {
+    let name = pspec.name();
+    let value_type = pspec.value_type();
+    let invalid = || format!("invalid value {value:?} for {name}");
+
+    match value {
+        Value::Bool(value) if value_type == glib::Type::BOOL => Ok(value.to_value()),
+        Value::Number(number) if value_type == glib::Type::F64 => {
+            let pspec = pspec.downcast_ref::<glib::ParamSpecDouble>().unwrap();
+            (pspec.minimum()..=pspec.maximum())
+                .contains(number)
+                .then(|| number.to_value())
+                .ok_or_else(invalid)
+        }
+        Value::Number(number) if value_type == glib::Type::U32 => {
+            let pspec = pspec.downcast_ref::<glib::ParamSpecUInt>().unwrap();
+            integer(*number)
+                .and_then(|number| u32::try_from(number).ok())
+                .filter(|number| (pspec.minimum()..=pspec.maximum()).contains(number))
+                .map(|number| number.to_value())
+                .ok_or_else(invalid)
+        }
+        Value::String(nick) if value_type.is_a(glib::Type::ENUM) => {
+            glib::EnumClass::with_type(value_type)
+                .and_then(|class| class.to_value_by_nick(nick))
+                .ok_or_else(invalid)
+        }
+        Value::String(path) if value_type == glib::Type::STRING && name.ends_with("-file") => {
+            let path = dir.join(path);
+            if !path.is_file() {
+                return Err(format!("{name}: no file {}", path.display()));
+            }
+            Ok(path.to_str().ok_or_else(invalid)?.to_value())
+        }
+        Value::String(string) if value_type == glib::Type::STRING => Ok(string.to_value()),
+        Value::Array(values) if value_type == gst::Array::static_type() => array(values)
+            .map(|array| array.to_value())
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+// Arrays of integers, strings and such arrays.
+fn array(values: &[Value]) -> Option<gst::Array> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Number(number) => integer(*number).map(|number| number.to_send_value()),
+            Value::String(string) => Some(string.to_send_value()),
+            Value::Array(values) => array(values).map(|array| array.to_send_value()),
+            Value::Bool(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(gst::Array::from_values)
+}
+
+fn integer(number: f64) -> Option<i32> {
+    (number.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&number))
+        .then_some(number as i32)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    // Byte offset of the next character.
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> String {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        format!("{what} at {line}:{column}")
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += expected.len_utf8();
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.eat(expected) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{expected}'"))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    // Spaces and tabs, within a TOML line.
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while self.next().is_some_and(|c| c != '\n') {}
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => Err(self.error("nested objects aren't supported")),
+            Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '+' => self.word(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.next() {
+                    Some(c @ ('"' | '\\')) => string.push(c),
+                    Some('n') => string.push('\n'),
+                    _ => return Err(self.error("unsupported escape")),
+                },
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            self.skip_whitespace();
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            self.expect(',')?;
+            // TOML allows a trailing comma.
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    // Numbers and the true and false literals.
+    fn word(&mut self) -> Result<Value, String> {
+        let text = self.text;
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '_'))
+        {
+            self.pos += 1;
+        }
+
+        match &text[start..self.pos] {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            word => word
+                .replace('_', "")
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| {
+                    self.pos = start;
+                    self.error(&format!("invalid value '{word}'"))
+                }),
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        {
+            self.pos += 1;
+        }
+
+        match &self.text[start..self.pos] {
+            "" => Err(self.error("expected a key")),
+            key => Ok(key.to_string()),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Unpacking of the MIPI CSI-2 packed raw layouts, where a group of pixels stores the
+// high 8 bits of every pixel in a byte of its own, followed by bytes holding the
+// remaining low bits of the pixels, least significant bits and first pixel first:
+//
+// RAW10: 4 pixels in 5 bytes, the fifth byte holding the low 2 bits of each.
+// RAW12: 2 pixels in 3 bytes, the third byte holding the low 4 bits of each.
+// RAW14: 4 pixels in 7 bytes, the last three holding the low 6 bits of each.
+//
+// Rows are padded to whole groups, V4L2's SRGGB10P and friends and the packed formats
+// of libcamera share the layout.
+//
+// rsbayerrepack unpacks them to 16 bits for other elements. rsbayer2rgb narrows them
+// to the 8-bit mosaic its conversion works on, like the samples stored in 16 bits, and
+// widens both to full 16-bit samples for its P010 output.
+
+use super::frame::{Error, fits};
+use super::info::Depth;
+
+/// A MIPI CSI-2 packing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packing {
+    Raw10,
+    Raw12,
+    Raw14,
+}
+
+impl Packing {
+    pub const ALL: [Packing; 3] = [Packing::Raw10, Packing::Raw12, Packing::Raw14];
+
+    /// The packing of a `video/x-bayer` format suffix, `10p`, `12p` or `14p`.
+    pub fn from_caps_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "10p" => Some(Packing::Raw10),
+            "12p" => Some(Packing::Raw12),
+            "14p" => Some(Packing::Raw14),
+            _ => None,
+        }
+    }
+
+    pub fn to_caps_suffix(self) -> &'static str {
+        match self {
+            Packing::Raw10 => "10p",
+            Packing::Raw12 => "12p",
+            Packing::Raw14 => "14p",
+        }
+    }
+
+    /// Bits of every sample.
+    pub fn depth(self) -> u32 {
+        match self {
+            Packing::Raw10 => 10,
+            Packing::Raw12 => 12,
+            Packing::Raw14 => 14,
+        }
+    }
+
+    // Pixels and bytes of a group.
+    fn group(self) -> (usize, usize) {
+        match self {
+            Packing::Raw10 => (4, 5),
+            Packing::Raw12 => (2, 3),
+            Packing::Raw14 => (4, 7),
+        }
+    }
+
+    /// Bytes of a packed row of `width` pixels, the last group padded.
+    pub fn row_bytes(self, width: usize) -> usize {
+        let (pixels, bytes) = self.group();
+        width.div_ceil(pixels) * bytes
+    }
+
+    /// Unpacks the row in `input` to `output.len()` samples of [`Packing::depth`] bits.
+    /// `input` must hold [`Packing::row_bytes`] of the width.
+    pub fn unpack_row(self, input: &[u8], output: &mut [u16]) {
+        debug_assert!(input.len() >= self.row_bytes(output.len()));
+        match self {
+            Packing::Raw10 => unpack_groups::<4, 5>(input, output, 2),
+            Packing::Raw12 => unpack_groups::<2, 3>(input, output, 4),
+            Packing::Raw14 => unpack_groups::<4, 7>(input, output, 6),
+        }
+    }
+}
+
+// Unpacks groups of PIXELS pixels in BYTES bytes, every pixel `low_bits` below its
+// high byte. Fixed sizes let the compiler unroll the group.
+#[inline]
+fn unpack_groups<const PIXELS: usize, const BYTES: usize>(
+    input: &[u8],
+    output: &mut [u16],
+    low_bits: u32,
+) {
+    let mut samples = output.chunks_exact_mut(PIXELS);
+    let mut groups = input.chunks_exact(BYTES);
+    // Samples first, so the zip stops before taking the group of a partial last chunk.
+    for (samples, group) in (&mut samples).zip(&mut groups) {
+        unpack_group::<PIXELS>(group, samples, low_bits);
+    }
+    // The padded last group of a width that isn't a multiple of the group.
+    let rest = samples.into_remainder();
+    if let Some(group) = groups.next() {
+        unpack_group::<PIXELS>(group, rest, low_bits);
+    }
+}
+
+#[inline(always)]
+fn unpack_group<const PIXELS: usize>(group: &[u8], samples: &mut [u16], low_bits: u32) {
+    // At most 24 low bits in a group, read as a little endian word.
+    let low = group[PIXELS..]
+        .iter()
+        .rev()
+        .fold(0u32, |low, &b| (low << 8) | u32::from(b));
+    let mask = (1u32 << low_bits) - 1;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        *sample =
+            (u16::from(group[i]) << low_bits) | ((low >> (low_bits * i as u32)) & mask) as u16;
+    }
+}
+
+/// Narrows `height` rows of `width` samples stored as `depth`, rows `in_stride` bytes
+/// apart, to their high 8 bits in `output`, rows tightly packed.
+pub fn narrow(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    depth: Depth,
+    output: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let row_bytes = depth.row_bytes(width).ok_or(Error::BufferTooSmall)?;
+    if !fits(input.len(), height, row_bytes, in_stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    output.clear();
+    output.reserve(width * height);
+    let rows = (0..height).map(|y| &input[y * in_stride..][..row_bytes]);
+    match depth {
+        Depth::Eight => rows.for_each(|row| output.extend_from_slice(row)),
+        Depth::Sixteen { bits, big_endian } => {
+            let shift = bits.saturating_sub(8);
+            for row in rows {
+                output.extend(row.chunks_exact(2).map(|b| {
+                    let sample = match big_endian {
+                        true => u16::from_be_bytes([b[0], b[1]]),
+                        false => u16::from_le_bytes([b[0], b[1]]),
+                    };
+                    (sample >> shift).min(u8::MAX as u16) as u8
+                }));
+            }
+        }
+        Depth::Packed(packing) => {
+            let shift = packing.depth() - 8;
+            let mut samples = vec![0; width];
+            for row in rows {
+                packing.unpack_row(row, &mut samples);
+                output.extend(samples.iter().map(|&sample| (sample >> shift) as u8));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Widens `height` rows of `width` samples stored as `depth`, rows `in_stride` bytes
+/// apart, to 16-bit samples in `output`, rows tightly packed. The samples are scaled to
+/// the full range, their high bits repeated below them, so the largest sample of every
+/// depth becomes 65535.
+pub fn widen(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    depth: Depth,
+    output: &mut Vec<u16>,
+) -> Result<(), Error> {
+    let row_bytes = depth.row_bytes(width).ok_or(Error::BufferTooSmall)?;
+    if !fits(input.len(), height, row_bytes, in_stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    output.clear();
+    output.reserve(width * height);
+    let rows = (0..height).map(|y| &input[y * in_stride..][..row_bytes]);
+    match depth {
+        Depth::Eight => {
+            for row in rows {
+                output.extend(row.iter().map(|&sample| scale(sample as u16, 8)));
+            }
+        }
+        Depth::Sixteen { bits, big_endian } => {
+            let bits = bits.min(16);
+            for row in rows {
+                output.extend(row.chunks_exact(2).map(|b| {
+                    let sample = match big_endian {
+                        true => u16::from_be_bytes([b[0], b[1]]),
+                        false => u16::from_le_bytes([b[0], b[1]]),
+                    };
+                    scale(sample.min(u16::MAX >> (16 - bits)), bits)
+                }));
+            }
+        }
+        Depth::Packed(packing) => {
+            let mut samples = vec![0; width];
+            for row in rows {
+                packing.unpack_row(row, &mut samples);
+                output.extend(samples.iter().map(|&sample| scale(sample, packing.depth())));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A sample of `bits` scaled to 16 bits.
+fn scale(sample: u16, bits: u32) -> u16 {
+    match bits {
+        16 => sample,
+        bits => {
+            let high = sample << (16 - bits);
+            high | (high >> bits)
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Conversion thread for max-queue-buffers > 0.
+//
+// The streaming thread only allocates the output buffer and queues it together with
+// the input. The worker converts and pushes downstream in queue order, so upstream is
+// never blocked by a slow conversion unless the queue is full and nothing may be
+// dropped. Serialized events have to wait in `drain()` until every queued buffer has
+// been pushed, which keeps them in order with the buffers.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use super::Leaky;
+
+type Process =
+    Box<dyn FnMut(gst::Buffer, gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> + Send>;
+
+struct Queue {
+    items: VecDeque<(gst::Buffer, gst::Buffer)>,
+    // The worker is converting or pushing a buffer it took off the queue.
+    busy: bool,
+    flushing: bool,
+    shutdown: bool,
+    // Last downstream error, returned to upstream with the next buffer.
+    flow: Result<gst::FlowSuccess, gst::FlowError>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    cond: Condvar,
+}
+
+pub struct Worker {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// What happened to a buffer handed to `Worker::push()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queued {
+    /// Queued, the queue had room for it.
+    Fits,
+    /// Queued, and this many older buffers were dropped to make room.
+    DroppedOldest(usize),
+    /// Not queued because the queue was full.
+    DroppedNewest,
+}
+
+impl Worker {
+    pub fn new(
+        name: String,
+        process: impl FnMut(gst::Buffer, gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError>
+        + Send
+        + 'static,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                items: VecDeque::new(),
+                busy: false,
+                flushing: false,
+                shutdown: false,
+                flow: Ok(gst::FlowSuccess::Ok),
+            }),
+            cond: Condvar::new(),
+        });
+
+        let thread = std::thread::Builder::new().name(name).spawn({
+            let shared = shared.clone();
+            let process: Process = Box::new(process);
+            move || run(&shared, process)
+        })?;
+
+        Ok(Worker {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues a buffer pair, waiting for room if the queue holds `max` buffers and
+    /// `leaky` is `No`. Fails with the last downstream error or when flushing.
+    pub fn push(
+        &self,
+        inbuf: gst::Buffer,
+        outbuf: gst::Buffer,
+        max: usize,
+        leaky: Leaky,
+    ) -> Result<Queued, gst::FlowError> {
+        let mut queue = self.shared.queue.lock().unwrap();
+
+        let mut queued = Queued::Fits;
+        loop {
+            if queue.flushing {
+                return Err(gst::FlowError::Flushing);
+            }
+            queue.flow?;
+
+            if queue.items.len() < max {
+                break;
+            }
+
+            match leaky {
+                Leaky::No => queue = self.shared.cond.wait(queue).unwrap(),
+                Leaky::Upstream => return Ok(Queued::DroppedNewest),
+                Leaky::Downstream => {
+                    let excess = queue.items.len() + 1 - max;
+                    queue.items.drain(..excess);
+                    queued = Queued::DroppedOldest(excess);
+                    break;
+                }
+            }
+        }
+
+        queue.items.push_back((inbuf, outbuf));
+        self.shared.cond.notify_all();
+
+        Ok(queued)
+    }
+
+    /// Waits until every queued buffer has been pushed.
+    pub fn drain(&self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        while !queue.flushing && (queue.busy || !queue.items.is_empty()) {
+            queue = self.shared.cond.wait(queue).unwrap();
+        }
+    }
+
+    /// Drops everything queued and refuses new buffers until unset.
+    pub fn set_flushing(&self, flushing: bool) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.flushing = flushing;
+        if flushing {
+            queue.items.clear();
+        } else {
+            queue.flow = Ok(gst::FlowSuccess::Ok);
+        }
+        self.shared.cond.notify_all();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.shutdown = true;
+            queue.items.clear();
+            self.shared.cond.notify_all();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Shared, mut process: Process) {
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        if queue.shutdown {
+            break;
+        }
+
+        let Some((inbuf, outbuf)) = queue.items.pop_front() else {
+            queue = shared.cond.wait(queue).unwrap();
+            continue;
+        };
+        queue.busy = true;
+        // Room for one more buffer.
+        shared.cond.notify_all();
+        drop(queue);
+
+        let res = process(inbuf, outbuf);
+
+        queue = shared.queue.lock().unwrap();
+        queue.busy = false;
+        if res.is_err() && !queue.flushing {
+            queue.flow = res;
+        }
+        shared.cond.notify_all();
+    }
+}
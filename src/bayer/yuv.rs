@@ -0,0 +1,135 @@
+// P010_10LE output of bayer input deeper than 8 bits.
+//
+// The converter demosaics the widened input into 16-bit RGB, which is turned into
+// Y'CbCr here with the luma coefficients of the matrix of the negotiated colorimetry.
+// Chroma is 4:2:0, every sample made from the average of the RGB of its 2x2 block,
+// or what of it lies within the frame at odd sizes.
+//
+// P010 stores 10-bit samples in the high bits of little endian 16-bit words, in two
+// planes: the luma plane and a plane of interleaved Cb and Cr of half the width and
+// height.
+
+use super::frame::{Error, OutputLayout, fits};
+
+/// The output format written from deep input.
+pub const FORMAT: gst_video::VideoFormat = gst_video::VideoFormat::P01010le;
+
+// Bits of every sample, stored in the high bits of its word.
+const BITS: u32 = 10;
+const MAX: f32 = ((1 << BITS) - 1) as f32;
+
+/// The luma coefficients of red and blue of a Y'CbCr matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix {
+    kr: f32,
+    kb: f32,
+}
+
+impl Matrix {
+    pub const BT601: Matrix = Matrix {
+        kr: 0.299,
+        kb: 0.114,
+    };
+    pub const BT709: Matrix = Matrix {
+        kr: 0.2126,
+        kb: 0.0722,
+    };
+    pub const BT2020: Matrix = Matrix {
+        kr: 0.2627,
+        kb: 0.0593,
+    };
+
+    /// The coefficients of a colorimetry's matrix. Unknown and other matrices get those
+    /// of BT.709.
+    pub fn for_colorimetry(matrix: gst_video::VideoColorMatrix) -> Self {
+        match matrix {
+            gst_video::VideoColorMatrix::Bt601 => Matrix::BT601,
+            gst_video::VideoColorMatrix::Bt2020 => Matrix::BT2020,
+            _ => Matrix::BT709,
+        }
+    }
+
+    // Y', Cb and Cr of normalized R'G'B', Cb and Cr centered on zero.
+    fn ycbcr(&self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let y = self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b;
+        [
+            y,
+            (b - y) / (2.0 * (1.0 - self.kb)),
+            (r - y) / (2.0 * (1.0 - self.kr)),
+        ]
+    }
+}
+
+/// Writes the `width` x `height` frame of 16-bit samples of `layout` in `input`, rows
+/// `in_stride` samples apart, as the P010 luma plane `y`, rows `y_stride` bytes apart,
+/// and chroma plane `uv`, rows `uv_stride` bytes apart. Limited range puts black at
+/// 64 and white at 940, full range uses every code.
+#[allow(clippy::too_many_arguments)]
+pub fn write_p010(
+    input: &[u16],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    matrix: Matrix,
+    full_range: bool,
+    y: &mut [u8],
+    y_stride: usize,
+    uv: &mut [u8],
+    uv_stride: usize,
+) -> Result<(), Error> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    if !fits(input.len(), height, width * layout.pixel_stride, in_stride)
+        || !fits(y.len(), height, 2 * width, y_stride)
+        || !fits(uv.len(), chroma_height, 4 * chroma_width, uv_stride)
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    // Offset and excursion of luma and the excursion of chroma, in codes.
+    let (y_offset, y_range, c_range) = match full_range {
+        true => (0.0, MAX, MAX),
+        false => (64.0, 876.0, 896.0),
+    };
+    let rgb = |x: usize, y: usize| {
+        let pixel = &input[y * in_stride + x * layout.pixel_stride..];
+        [layout.red, layout.green, layout.blue].map(|c| pixel[c] as f32 / u16::MAX as f32)
+    };
+
+    for row in 0..height {
+        let out = &mut y[row * y_stride..][..2 * width];
+        for (x, word) in out.chunks_exact_mut(2).enumerate() {
+            let [luma, _, _] = matrix.ycbcr(rgb(x, row));
+            store(word, y_offset + luma * y_range);
+        }
+    }
+
+    let center = (1 << (BITS - 1)) as f32;
+    for row in 0..chroma_height {
+        let rows = 2 * row..(2 * row + 2).min(height);
+        let out = &mut uv[row * uv_stride..][..4 * chroma_width];
+        for (x, words) in out.chunks_exact_mut(4).enumerate() {
+            let cols = 2 * x..(2 * x + 2).min(width);
+            let mut sum = [0.0; 3];
+            for y in rows.clone() {
+                for x in cols.clone() {
+                    for (sum, value) in sum.iter_mut().zip(rgb(x, y)) {
+                        *sum += value;
+                    }
+                }
+            }
+            let count = (rows.len() * cols.len()) as f32;
+            let [_, cb, cr] = matrix.ycbcr(sum.map(|sum| sum / count));
+            store(&mut words[..2], center + cb * c_range);
+            store(&mut words[2..], center + cr * c_range);
+        }
+    }
+
+    Ok(())
+}
+
+// Stores a code in the high bits of a little endian word.
+fn store(word: &mut [u8], code: f32) {
+    let code = code.round().clamp(0.0, MAX) as u16;
+    word.copy_from_slice(&(code << (16 - BITS)).to_le_bytes());
+}
@@ -0,0 +1,63 @@
+// Overexposure zebra, a debug overlay of diagonal stripes over the output pixels whose
+// raw samples are above a threshold, to see clipped highlights while tuning exposure.
+// It is drawn last, over the finished output.
+
+use super::frame::{Error, OutputLayout, fits};
+use super::raw::Sample;
+
+// Width of the stripes and of the gaps between them, in output pixels.
+const STRIPE_WIDTH: usize = 4;
+
+/// Blackens the stripe pixels of a `width` x `height` output frame of `layout` whose
+/// raw samples exceed `threshold`. `scale` is the number of raw samples per output
+/// pixel in each direction, 2 for superpixel output, where any sample of the 2x2 quad
+/// exceeding it counts. Strides are in samples.
+#[allow(clippy::too_many_arguments)]
+pub fn draw<S: Sample>(
+    raw: &[S],
+    raw_stride: usize,
+    scale: usize,
+    threshold: u64,
+    output: &mut [S],
+    out_stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+) -> Result<(), Error> {
+    if !fits(raw.len(), height * scale, width * scale, raw_stride)
+        || !fits(
+            output.len(),
+            height,
+            width * layout.pixel_stride,
+            out_stride,
+        )
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        let raw_rows: Vec<&[S]> = (0..scale)
+            .map(|dy| &raw[(y * scale + dy) * raw_stride..][..width * scale])
+            .collect();
+        let row = &mut output[y * out_stride..][..width * layout.pixel_stride];
+
+        for (x, pixel) in row.chunks_exact_mut(layout.pixel_stride).enumerate() {
+            if (x + y) / STRIPE_WIDTH % 2 == 1 {
+                continue;
+            }
+
+            let clipped = raw_rows.iter().any(|raw_row| {
+                raw_row[x * scale..][..scale]
+                    .iter()
+                    .any(|sample| sample.to_u64() > threshold)
+            });
+            if clipped {
+                for channel in [layout.red, layout.green, layout.blue] {
+                    pixel[channel] = S::from_u64(0);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// Denoise on the raw bayer samples, before demosaic spreads the noise of every sample
+// over its neighbors and correlates it across the colors.
+//
+// Each sample is pulled towards the median of the 3x3 neighborhood of its own color
+// plane, the samples two sites away horizontally, vertically and diagonally, which
+// share its color in every bayer pattern. The median removes impulse noise without
+// blurring edges the way a mean would.
+
+use crate::bayer::frame::Error;
+use crate::bayer::raw::Sample;
+
+/// Denoises a `width` x `height` frame without padding into `output`, moving every
+/// sample `strength` of the way to the median of its same color neighborhood. Samples
+/// at the frame edges use the neighbors inside the frame. A `strength` of 0.0 copies
+/// the frame, 1.0 replaces every sample with the median.
+pub fn median<S: Sample>(
+    input: &[S],
+    width: usize,
+    height: usize,
+    strength: f64,
+    output: &mut [S],
+) -> Result<(), Error> {
+    let len = width * height;
+    if input.len() < len || output.len() < len {
+        return Err(Error::BufferTooSmall);
+    }
+
+    // Positions of the same color neighbors along an axis, its own included, within
+    // `size`.
+    let neighbors = |pos: usize, size: usize| {
+        [
+            pos.checked_sub(2),
+            Some(pos),
+            Some(pos + 2).filter(|&pos| pos < size),
+        ]
+        .into_iter()
+        .flatten()
+    };
+
+    let mut neighborhood = Vec::with_capacity(9);
+    for y in 0..height {
+        for x in 0..width {
+            neighborhood.clear();
+            for ny in neighbors(y, height) {
+                neighborhood.extend(neighbors(x, width).map(|nx| input[ny * width + nx].to_u64()));
+            }
+
+            let mid = neighborhood.len() / 2;
+            let median = *neighborhood.select_nth_unstable(mid).1 as f64;
+            let value = input[y * width + x].to_u64() as f64;
+            output[y * width + x] =
+                S::from_u64((value + (median - value) * strength).round() as u64);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,342 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::prelude::*;
+
+use std::sync::LazyLock;
+
+use super::denoise;
+use crate::bayer::cfa::Pattern;
+use crate::bayer::frame::{Error, fits};
+use crate::bayer::raw::Sample;
+
+static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "rsbayerdenoise",
+        gst::DebugColorFlags::empty(),
+        Some("Bayer raw denoise"),
+    )
+});
+
+const DEFAULT_STRENGTH: f64 = 0.5;
+
+// Suffixes of the high depth `video/x-bayer` formats, every one stored in 16 bits.
+const DEEP_FORMATS: [&str; 8] = [
+    "10le", "10be", "12le", "12be", "14le", "14be", "16le", "16be",
+];
+
+// How the samples of a format are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Depth {
+    Eight,
+    Sixteen { big_endian: bool },
+}
+
+impl Depth {
+    fn from_caps_format(format: &str) -> Option<Self> {
+        let (pattern, suffix) = format.split_at_checked(4)?;
+        Pattern::from_caps_format(pattern)?;
+        match suffix {
+            "" => Some(Depth::Eight),
+            suffix if DEEP_FORMATS.contains(&suffix) => Some(Depth::Sixteen {
+                big_endian: suffix.ends_with("be"),
+            }),
+            _ => None,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            Depth::Eight => 1,
+            Depth::Sixteen { .. } => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    strength: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            strength: DEFAULT_STRENGTH,
+        }
+    }
+}
+
+struct State {
+    depth: Depth,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+#[derive(Default)]
+pub struct RsBayerDenoise {
+    settings: std::sync::Mutex<Settings>,
+    state: std::sync::Mutex<Option<State>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsBayerDenoise {
+    const NAME: &'static str = "GstRsBayerDenoise";
+    type Type = super::RsBayerDenoise;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ObjectImpl for RsBayerDenoise {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+                /**
+                 * GstRsBayerDenoise:strength:
+                 *
+                 * How far every sample is moved towards the median of the 3x3
+                 * neighborhood of its color plane, from 0.0, which passes the
+                 * buffers through untouched, to 1.0, which replaces the samples with
+                 * the medians.
+                 */
+                glib::ParamSpecDouble::builder("strength")
+                    .nick("Strength")
+                    .blurb("Strength of the denoise (0 = passthrough)")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(DEFAULT_STRENGTH)
+                    .mutable_playing()
+                    .controllable()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+        self.obj().set_passthrough(DEFAULT_STRENGTH == 0.0);
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "strength" => {
+                settings.strength = value.get().expect("type checked upstream");
+                let passthrough = settings.strength == 0.0;
+                drop(settings);
+                self.obj().set_passthrough(passthrough);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "strength" => settings.strength.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RsBayerDenoise {}
+
+impl ElementImpl for RsBayerDenoise {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Bayer Denoise",
+                "Filter/Effect/Video",
+                "Removes noise from bayer frames before demosaic",
+                "Eric Bridgeford",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let formats = Pattern::ALL.into_iter().flat_map(|pattern| {
+                std::iter::once("")
+                    .chain(DEEP_FORMATS)
+                    .map(move |suffix| format!("{}{}", pattern.to_caps_format(), suffix))
+            });
+            let caps = gst::Caps::builder("video/x-bayer")
+                .field("format", gst::List::new(formats))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field(
+                    "framerate",
+                    gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                )
+                .build();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseTransformImpl for RsBayerDenoise {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+    // The caps never change, passthrough is only up to the strength.
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn unit_size(&self, caps: &gst::Caps) -> Option<usize> {
+        let s = caps.structure(0)?;
+        let depth = Depth::from_caps_format(s.get::<&str>("format").ok()?)?;
+        let width = usize::try_from(s.get::<i32>("width").ok()?).ok()?;
+        let height = usize::try_from(s.get::<i32>("height").ok()?).ok()?;
+
+        width.checked_mul(height)?.checked_mul(depth.bytes())
+    }
+
+    fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        gst::debug!(CAT, imp = self, "Input caps: {}", incaps);
+        gst::debug!(CAT, imp = self, "Output caps: {}", outcaps);
+
+        let s = incaps.structure(0).unwrap();
+        let depth = s
+            .get::<&str>("format")
+            .ok()
+            .and_then(Depth::from_caps_format)
+            .ok_or_else(|| gst::loggable_error!(CAT, "No valid bayer format in caps"))?;
+        let width =
+            s.get::<i32>("width")
+                .map_err(|_| gst::loggable_error!(CAT, "No width in caps"))? as usize;
+        let height =
+            s.get::<i32>("height")
+                .map_err(|_| gst::loggable_error!(CAT, "No height in caps"))? as usize;
+
+        *self.state.lock().unwrap() = Some(State {
+            depth,
+            width,
+            height,
+            stride: width * depth.bytes(),
+        });
+
+        Ok(())
+    }
+
+    fn before_transform(&self, inbuf: &gst::BufferRef) {
+        // Runs in passthrough too, so a controlled strength can leave it again.
+        if let Some(pts) = inbuf.pts() {
+            let stream_time = self
+                .obj()
+                .segment()
+                .downcast_ref::<gst::ClockTime>()
+                .and_then(|segment| segment.to_stream_time(pts))
+                .unwrap_or(pts);
+            if let Err(err) = self.obj().sync_values(stream_time) {
+                gst::warning!(CAT, imp = self, "Failed to sync controlled values: {}", err);
+            }
+        }
+    }
+
+    fn transform_ip(&self, buf: &mut gst::BufferRef) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let strength = self.settings.lock().unwrap().strength;
+        let state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_ref().ok_or(gst::FlowError::NotNegotiated)?;
+
+        // Upstream may push padded frames described by a VideoMeta.
+        let (offset, stride) = match buf.meta::<gst_video::VideoMeta>() {
+            Some(meta) => (meta.offset()[0], meta.stride()[0] as usize),
+            None => (0, state.stride),
+        };
+        let mut map = buf.map_writable().map_err(|_| gst::FlowError::Error)?;
+        let data = map.get_mut(offset..).ok_or_else(|| {
+            gst::error!(CAT, imp = self, "VideoMeta offset {} out of bounds", offset);
+            gst::FlowError::Error
+        })?;
+
+        let res = match state.depth {
+            Depth::Eight => denoise_frame(data, stride, state, strength, |b| b[0], |s, b| b[0] = s),
+            Depth::Sixteen { big_endian: false } => denoise_frame(
+                data,
+                stride,
+                state,
+                strength,
+                |b| u16::from_le_bytes([b[0], b[1]]),
+                |s, b| b.copy_from_slice(&s.to_le_bytes()),
+            ),
+            Depth::Sixteen { big_endian: true } => denoise_frame(
+                data,
+                stride,
+                state,
+                strength,
+                |b| u16::from_be_bytes([b[0], b[1]]),
+                |s, b| b.copy_from_slice(&s.to_be_bytes()),
+            ),
+        };
+        res.map_err(|err| {
+            gst::error!(CAT, imp = self, "Denoise failed: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+// Denoises the frame in `data`, rows `stride` bytes apart, reading and writing its
+// samples with `read` and `write`.
+fn denoise_frame<S: Sample + Default>(
+    data: &mut [u8],
+    stride: usize,
+    state: &State,
+    strength: f64,
+    read: impl Fn(&[u8]) -> S,
+    write: impl Fn(S, &mut [u8]),
+) -> Result<(), Error> {
+    let bytes = state.depth.bytes();
+    let row_bytes = state.width * bytes;
+    if !fits(data.len(), state.height, row_bytes, stride) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let input: Vec<S> = data
+        .chunks(stride)
+        .take(state.height)
+        .flat_map(|row| row[..row_bytes].chunks_exact(bytes).map(&read))
+        .collect();
+    let mut output = vec![S::default(); input.len()];
+    denoise::median(&input, state.width, state.height, strength, &mut output)?;
+
+    for (row, samples) in data
+        .chunks_mut(stride)
+        .zip(output.chunks_exact(state.width))
+    {
+        for (bytes, &sample) in row[..row_bytes].chunks_exact_mut(bytes).zip(samples) {
+            write(sample, bytes);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod denoise;
+mod imp;
+
+glib::wrapper! {
+    pub struct RsBayerDenoise(ObjectSubclass<imp::RsBayerDenoise>)
+        @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsbayerdenoise",
+        gst::Rank::NONE,
+        RsBayerDenoise::static_type(),
+    )
+}
@@ -0,0 +1,206 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use std::sync::LazyLock;
+
+use crate::bayer::raw::MAX_GAIN;
+use crate::bayer::{AwbMode, DemosaicAlgorithm, Method, RsBayer2Rgb};
+use crate::bayerdenoise::RsBayerDenoise;
+
+// Properties of the bin forwarded to the converter under the same name.
+const CONVERTER_PROPERTIES: [&str; 7] = [
+    "red-gain",
+    "green-gain",
+    "blue-gain",
+    "awb-mode",
+    "demosaic-algorithm",
+    "method",
+    "gamma",
+];
+
+// The chain, in the order the frames pass through it.
+pub struct RsBayerIsp {
+    denoise: RsBayerDenoise,
+    convert: RsBayer2Rgb,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsBayerIsp {
+    const NAME: &'static str = "GstRsBayerIsp";
+    type Type = super::RsBayerIsp;
+    type ParentType = gst::Bin;
+
+    fn with_class(_klass: &Self::Class) -> Self {
+        RsBayerIsp {
+            denoise: glib::Object::builder().property("name", "denoise").build(),
+            convert: glib::Object::builder().property("name", "convert").build(),
+        }
+    }
+}
+
+impl ObjectImpl for RsBayerIsp {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+                /**
+                 * GstRsBayerIsp:denoise-strength:
+                 *
+                 * Strength of the rsbayerdenoise pass on the raw frames, off by
+                 * default. The remaining properties are those of the rsbayer2rgb
+                 * converter under the same names; anything else of either child is
+                 * reachable through the child proxy as `denoise::<name>` and
+                 * `convert::<name>`.
+                 */
+                glib::ParamSpecDouble::builder("denoise-strength")
+                    .nick("Denoise Strength")
+                    .blurb("Strength of the raw denoise (0 = off)")
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .default_value(0.0)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("red-gain")
+                    .nick("Red Gain")
+                    .blurb("White balance gain of the red samples")
+                    .minimum(0.0)
+                    .maximum(MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("green-gain")
+                    .nick("Green Gain")
+                    .blurb("White balance gain of both green samples")
+                    .minimum(0.0)
+                    .maximum(MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("blue-gain")
+                    .nick("Blue Gain")
+                    .blurb("White balance gain of the blue samples")
+                    .minimum(0.0)
+                    .maximum(MAX_GAIN)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("awb-mode", AwbMode::default())
+                    .nick("AWB Mode")
+                    .blurb("Source of the white balance gains")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default(
+                    "demosaic-algorithm",
+                    DemosaicAlgorithm::default(),
+                )
+                .nick("Demosaic Algorithm")
+                .blurb("Interpolation used to reconstruct the missing colors")
+                .mutable_playing()
+                .build(),
+                glib::ParamSpecEnum::builder_with_default("method", Method::default())
+                    .nick("Method")
+                    .blurb("Conversion method, superpixel halves the output resolution")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecDouble::builder("gamma")
+                    .nick("Gamma")
+                    .blurb("Gamma encoding of the output (1.0 = linear)")
+                    .minimum(0.01)
+                    .maximum(10.0)
+                    .default_value(1.0)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        // Off until asked for, so the bin converts like rsbayer2rgb alone by default.
+        self.denoise.set_property("strength", 0.0);
+        obj.add_many([
+            self.denoise.upcast_ref::<gst::Element>(),
+            self.convert.upcast_ref(),
+        ])
+        .expect("children have unique names");
+        self.denoise
+            .link(&self.convert)
+            .expect("denoise output is converter input");
+
+        for (name, target) in [
+            ("sink", self.denoise.static_pad("sink")),
+            ("src", self.convert.static_pad("src")),
+        ] {
+            let templ = obj.pad_template(name).unwrap();
+            let pad = gst::GhostPad::builder_from_template(&templ)
+                .name(name)
+                .build();
+            pad.set_target(target.as_ref())
+                .expect("ghost pad targets have the template direction");
+            obj.add_pad(&pad).unwrap();
+        }
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "denoise-strength" => self.denoise.set_property_from_value("strength", value),
+            name if CONVERTER_PROPERTIES.contains(&name) => {
+                self.convert.set_property_from_value(name, value)
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "denoise-strength" => self.denoise.property_value("strength"),
+            name if CONVERTER_PROPERTIES.contains(&name) => self.convert.property_value(name),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RsBayerIsp {}
+
+impl ElementImpl for RsBayerIsp {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Bayer ISP",
+                "Filter/Converter/Video",
+                "Denoises bayer frames and converts them to RGB/BGR formats in one element",
+                "Eric Bridgeford",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        // Those of the converter, the chain takes what it takes and outputs what it does.
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let class = glib::Class::<gst::Element>::from_type(RsBayer2Rgb::static_type())
+                .expect("converter is a registered element type");
+            ["src", "sink"]
+                .into_iter()
+                .map(|name| {
+                    let templ = class.pad_template(name).expect("converter has both pads");
+                    gst::PadTemplate::new(
+                        name,
+                        templ.direction(),
+                        gst::PadPresence::Always,
+                        templ.caps(),
+                    )
+                    .unwrap()
+                })
+                .collect()
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BinImpl for RsBayerIsp {}
@@ -0,0 +1,19 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct RsBayerIsp(ObjectSubclass<imp::RsBayerIsp>)
+        @extends gst::Bin, gst::Element, gst::Object,
+        @implements gst::ChildProxy;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsbayerisp",
+        gst::Rank::NONE,
+        RsBayerIsp::static_type(),
+    )
+}
@@ -0,0 +1,289 @@
+use gst::glib;
+use gst::subclass::prelude::*;
+use gst_base::subclass::prelude::*;
+
+use std::sync::LazyLock;
+
+use crate::bayer::cfa::Pattern;
+use crate::bayer::frame::fits;
+use crate::bayer::info::{BayerFrame, BayerInfo, Depth};
+use crate::bayer::unpack::Packing;
+
+static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "rsbayerrepack",
+        gst::DebugColorFlags::empty(),
+        Some("Bayer raw repack"),
+    )
+});
+
+// Suffix of the output format. The samples are shifted to the top of the 16 bits, so
+// full scale of every packing is full scale of the output.
+const OUTPUT_SUFFIX: &str = "16le";
+
+// A `video/x-bayer` format of the sink or the src pad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Packed(Pattern, Packing),
+    Unpacked(Pattern),
+}
+
+impl Format {
+    fn from_caps_format(format: &str) -> Option<Self> {
+        let (pattern, suffix) = format.split_at_checked(4)?;
+        let pattern = Pattern::from_caps_format(pattern)?;
+        match suffix {
+            OUTPUT_SUFFIX => Some(Format::Unpacked(pattern)),
+            suffix => {
+                Packing::from_caps_suffix(suffix).map(|packing| Format::Packed(pattern, packing))
+            }
+        }
+    }
+
+    fn to_caps_format(self) -> String {
+        match self {
+            Format::Packed(pattern, packing) => {
+                format!("{}{}", pattern.to_caps_format(), packing.to_caps_suffix())
+            }
+            Format::Unpacked(pattern) => format!("{}{}", pattern.to_caps_format(), OUTPUT_SUFFIX),
+        }
+    }
+
+    fn pattern(self) -> Pattern {
+        match self {
+            Format::Packed(pattern, _) | Format::Unpacked(pattern) => pattern,
+        }
+    }
+}
+
+// The formats of a structure, every format of its direction if it has none.
+fn formats(s: &gst::StructureRef, direction: gst::PadDirection) -> Vec<Format> {
+    let names: Vec<String> = if let Ok(format) = s.get::<String>("format") {
+        vec![format]
+    } else if let Ok(list) = s.get::<gst::List>("format") {
+        list.iter().filter_map(|v| v.get::<String>().ok()).collect()
+    } else {
+        return all_formats(direction == gst::PadDirection::Sink);
+    };
+
+    names
+        .iter()
+        .filter_map(|name| Format::from_caps_format(name))
+        .collect()
+}
+
+fn all_formats(packed: bool) -> Vec<Format> {
+    Pattern::ALL
+        .into_iter()
+        .flat_map(|pattern| {
+            let formats: Vec<Format> = if packed {
+                Packing::ALL
+                    .into_iter()
+                    .map(|packing| Format::Packed(pattern, packing))
+                    .collect()
+            } else {
+                vec![Format::Unpacked(pattern)]
+            };
+            formats
+        })
+        .collect()
+}
+
+struct State {
+    info: BayerInfo,
+    packing: Packing,
+    // Reused for every row.
+    samples: Vec<u16>,
+}
+
+#[derive(Default)]
+pub struct RsBayerRepack {
+    state: std::sync::Mutex<Option<State>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsBayerRepack {
+    const NAME: &'static str = "GstRsBayerRepack";
+    type Type = super::RsBayerRepack;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ObjectImpl for RsBayerRepack {}
+
+impl GstObjectImpl for RsBayerRepack {}
+
+impl ElementImpl for RsBayerRepack {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Bayer Repack",
+                "Filter/Converter/Video",
+                "Unpacks MIPI CSI-2 packed bayer frames to 16 bit samples",
+                "Eric Bridgeford",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let caps = |packed: bool| {
+                let formats = all_formats(packed).into_iter().map(Format::to_caps_format);
+                gst::Caps::builder("video/x-bayer")
+                    .field("format", gst::List::new(formats))
+                    .field("width", gst::IntRange::new(1, i32::MAX))
+                    .field("height", gst::IntRange::new(1, i32::MAX))
+                    .field(
+                        "framerate",
+                        gst::FractionRange::new(
+                            gst::Fraction::new(0, 1),
+                            gst::Fraction::new(i32::MAX, 1),
+                        ),
+                    )
+                    .build()
+            };
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps(false),
+            )
+            .unwrap();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps(true),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseTransformImpl for RsBayerRepack {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::NeverInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn transform_caps(
+        &self,
+        direction: gst::PadDirection,
+        caps: &gst::Caps,
+        filter: Option<&gst::Caps>,
+    ) -> Option<gst::Caps> {
+        let mut other_caps = gst::Caps::new_empty();
+        for s in caps.iter() {
+            // Same pattern, size and framerate, only the packing changes.
+            let mut patterns: Vec<Pattern> = vec![];
+            for pattern in formats(s, direction).into_iter().map(Format::pattern) {
+                if !patterns.contains(&pattern) {
+                    patterns.push(pattern);
+                }
+            }
+            if patterns.is_empty() {
+                continue;
+            }
+            let other_formats = patterns.into_iter().flat_map(|pattern| {
+                all_formats(direction == gst::PadDirection::Src)
+                    .into_iter()
+                    .filter(move |format| format.pattern() == pattern)
+                    .map(Format::to_caps_format)
+            });
+
+            let mut other = s.to_owned();
+            other.set("format", gst::List::new(other_formats));
+            other_caps.get_mut().unwrap().append_structure(other);
+        }
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn unit_size(&self, caps: &gst::Caps) -> Option<usize> {
+        BayerInfo::from_caps(caps).ok().map(|info| info.size())
+    }
+
+    fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        gst::debug!(CAT, imp = self, "Input caps: {}", incaps);
+        gst::debug!(CAT, imp = self, "Output caps: {}", outcaps);
+
+        let info =
+            BayerInfo::from_caps(incaps).map_err(|err| gst::loggable_error!(CAT, "{}", err))?;
+        let Depth::Packed(packing) = info.depth else {
+            return Err(gst::loggable_error!(CAT, "No packed bayer format in caps"));
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            info,
+            packing,
+            samples: vec![0; info.width],
+        });
+
+        Ok(())
+    }
+
+    fn transform(
+        &self,
+        inbuf: &gst::Buffer,
+        outbuf: &mut gst::BufferRef,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
+
+        let height = state.info.height;
+        let in_row_bytes = state.packing.row_bytes(state.info.width);
+        let out_row_bytes = state.info.width * 2;
+        // Upstream may push padded frames described by a VideoMeta.
+        let in_frame = BayerFrame::from_buffer_readable(inbuf, &state.info).map_err(|err| {
+            gst::error!(CAT, imp = self, "{}", err);
+            gst::FlowError::Error
+        })?;
+        let (input, in_stride) = (in_frame.data(), in_frame.stride());
+        let mut out_map = outbuf.map_writable().map_err(|_| gst::FlowError::Error)?;
+        if !fits(input.len(), height, in_row_bytes, in_stride)
+            || !fits(out_map.len(), height, out_row_bytes, out_row_bytes)
+        {
+            gst::error!(CAT, imp = self, "Buffer too small for frame");
+            return Err(gst::FlowError::Error);
+        }
+
+        let shift = 16 - state.packing.depth();
+        for (in_row, out_row) in input
+            .chunks(in_stride)
+            .zip(out_map.chunks_exact_mut(out_row_bytes))
+            .take(height)
+        {
+            state
+                .packing
+                .unpack_row(&in_row[..in_row_bytes], &mut state.samples);
+            for (bytes, &sample) in out_row.chunks_exact_mut(2).zip(&state.samples) {
+                bytes.copy_from_slice(&(sample << shift).to_le_bytes());
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
@@ -0,0 +1,18 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct RsBayerRepack(ObjectSubclass<imp::RsBayerRepack>)
+        @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsbayerrepack",
+        gst::Rank::NONE,
+        RsBayerRepack::static_type(),
+    )
+}
@@ -0,0 +1,78 @@
+// Test images, rendered straight into bayer samples: every site takes the channel of
+// the image pixel its color filter lets through, which is what a sensor looking at the
+// image would capture.
+
+use super::TestPattern;
+use crate::bayer::cfa::{CfaColor, Pattern};
+
+// Colors of the color bars, left to right.
+const BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+// Size of the checkerboard squares in pixels, even so every square holds whole 2x2
+// quads.
+const CHECKER_SIZE: usize = 8;
+
+/// Parameters of a test image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    pub pattern: TestPattern,
+    /// Color of the solid pattern as red, green and blue.
+    pub color: [u8; 3],
+    /// Seed of the noise pattern, combined with the frame number so every frame
+    /// differs and every run is the same.
+    pub seed: u64,
+}
+
+impl Image {
+    /// Renders frame `frame` into `output`, `height` rows of `width` samples of `cfa`
+    /// bayer frames without padding.
+    pub fn render(&self, frame: u64, cfa: Pattern, width: usize, height: usize, output: &mut [u8]) {
+        if self.pattern == TestPattern::Noise {
+            let mut state = (self.seed ^ frame.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1;
+            for sample in output.iter_mut().take(width * height) {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *sample = (state >> 56) as u8;
+            }
+            return;
+        }
+
+        for (y, row) in output.chunks_exact_mut(width).take(height).enumerate() {
+            for (x, sample) in row.iter_mut().enumerate() {
+                let [red, green, blue] = self.pixel(x, y, width);
+                *sample = match cfa.color_at(x, y) {
+                    CfaColor::Red => red,
+                    CfaColor::GreenRed | CfaColor::GreenBlue => green,
+                    CfaColor::Blue => blue,
+                };
+            }
+        }
+    }
+
+    // Color of pixel `x`, `y` of an image `width` pixels wide.
+    fn pixel(&self, x: usize, y: usize, width: usize) -> [u8; 3] {
+        match self.pattern {
+            TestPattern::Solid => self.color,
+            TestPattern::ColorBars => BARS[x * BARS.len() / width],
+            TestPattern::Gradient => {
+                let level = (x * 255 / (width - 1).max(1)) as u8;
+                [level; 3]
+            }
+            TestPattern::Checker => {
+                let white = (x / CHECKER_SIZE + y / CHECKER_SIZE).is_multiple_of(2);
+                [if white { 255 } else { 0 }; 3]
+            }
+            TestPattern::Noise => unreachable!("noise is rendered per sample"),
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::prelude::*;
+
+use std::sync::LazyLock;
+
+use super::TestPattern;
+use super::image::Image;
+use crate::bayer::cfa::Pattern;
+
+static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "rsbayertestsrc",
+        gst::DebugColorFlags::empty(),
+        Some("Bayer test source"),
+    )
+});
+
+const DEFAULT_FOREGROUND_COLOR: u32 = 0xffff_ffff;
+const DEFAULT_SEED: u64 = 0;
+const DEFAULT_IS_LIVE: bool = false;
+// Caps fixated to when downstream doesn't care.
+const DEFAULT_WIDTH: i32 = 320;
+const DEFAULT_HEIGHT: i32 = 240;
+const DEFAULT_FRAMERATE: (i32, i32) = (30, 1);
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pattern: TestPattern,
+    foreground_color: u32,
+    seed: u64,
+    is_live: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pattern: TestPattern::default(),
+            foreground_color: DEFAULT_FOREGROUND_COLOR,
+            seed: DEFAULT_SEED,
+            is_live: DEFAULT_IS_LIVE,
+        }
+    }
+}
+
+struct State {
+    cfa: Pattern,
+    width: usize,
+    height: usize,
+    framerate: gst::Fraction,
+    // Frames produced since the source started.
+    frame: u64,
+}
+
+impl State {
+    // Timestamp of frame `frame`, None without a framerate.
+    fn pts(&self, frame: u64) -> Option<gst::ClockTime> {
+        let (numer, denom) = (self.framerate.numer() as u64, self.framerate.denom() as u64);
+        (numer > 0).then(|| {
+            gst::ClockTime::SECOND
+                .mul_div_floor(frame * denom, numer)
+                .expect("timestamps fit in 64 bits")
+        })
+    }
+}
+
+// Clock wait of a live source, unscheduled by unlock().
+#[derive(Default)]
+struct ClockWait {
+    clock_id: Option<gst::SingleShotClockId>,
+    flushing: bool,
+}
+
+#[derive(Default)]
+pub struct RsBayerTestSrc {
+    settings: std::sync::Mutex<Settings>,
+    state: std::sync::Mutex<Option<State>>,
+    clock_wait: std::sync::Mutex<ClockWait>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsBayerTestSrc {
+    const NAME: &'static str = "GstRsBayerTestSrc";
+    type Type = super::RsBayerTestSrc;
+    type ParentType = gst_base::PushSrc;
+}
+
+impl ObjectImpl for RsBayerTestSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+                /**
+                 * GstRsBayerTestSrc:pattern:
+                 *
+                 * Test image captured through the color filter array of the
+                 * negotiated `video/x-bayer` format. Size, framerate and bayer
+                 * pattern come from the caps, a capsfilter selects them.
+                 */
+                glib::ParamSpecEnum::builder_with_default("pattern", TestPattern::default())
+                    .nick("Pattern")
+                    .blurb("Test image to produce")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("foreground-color")
+                    .nick("Foreground Color")
+                    .blurb("Color of the solid pattern, as 0xAARRGGBB (alpha is ignored)")
+                    .default_value(DEFAULT_FOREGROUND_COLOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt64::builder("seed")
+                    .nick("Seed")
+                    .blurb("Seed of the noise pattern, equal seeds give equal frames")
+                    .default_value(DEFAULT_SEED)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("is-live")
+                    .nick("Is Live")
+                    .blurb("Produce frames in real time, at the pace of the framerate")
+                    .default_value(DEFAULT_IS_LIVE)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_format(gst::Format::Time);
+        obj.set_live(DEFAULT_IS_LIVE);
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pattern" => {
+                settings.pattern = value.get().expect("type checked upstream");
+            }
+            "foreground-color" => {
+                settings.foreground_color = value.get().expect("type checked upstream");
+            }
+            "seed" => {
+                settings.seed = value.get().expect("type checked upstream");
+            }
+            "is-live" => {
+                settings.is_live = value.get().expect("type checked upstream");
+                let is_live = settings.is_live;
+                drop(settings);
+                self.obj().set_live(is_live);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pattern" => settings.pattern.to_value(),
+            "foreground-color" => settings.foreground_color.to_value(),
+            "seed" => settings.seed.to_value(),
+            "is-live" => settings.is_live.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RsBayerTestSrc {}
+
+impl ElementImpl for RsBayerTestSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Bayer Test Source",
+                "Source/Video",
+                "Produces synthetic bayer frames of test images",
+                "Eric Bridgeford",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let caps = gst::Caps::builder("video/x-bayer")
+                .field(
+                    "format",
+                    gst::List::new(Pattern::ALL.map(Pattern::to_caps_format)),
+                )
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field(
+                    "framerate",
+                    gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                )
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseSrcImpl for RsBayerTestSrc {
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        self.unlock_stop()?;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = None;
+        self.unlock()?;
+        Ok(())
+    }
+
+    fn fixate(&self, mut caps: gst::Caps) -> gst::Caps {
+        caps.truncate();
+        {
+            let caps = caps.make_mut();
+            let s = caps.structure_mut(0).unwrap();
+            s.fixate_field_nearest_int("width", DEFAULT_WIDTH);
+            s.fixate_field_nearest_int("height", DEFAULT_HEIGHT);
+            s.fixate_field_nearest_fraction("framerate", DEFAULT_FRAMERATE);
+            s.fixate_field_str("format", Pattern::default().to_caps_format());
+        }
+
+        self.parent_fixate(caps)
+    }
+
+    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        gst::debug!(CAT, imp = self, "Caps: {}", caps);
+
+        let s = caps
+            .structure(0)
+            .ok_or_else(|| gst::loggable_error!(CAT, "Empty caps"))?;
+        let cfa = s
+            .get::<&str>("format")
+            .ok()
+            .and_then(Pattern::from_caps_format)
+            .ok_or_else(|| gst::loggable_error!(CAT, "No valid bayer format in caps"))?;
+        let width = s
+            .get::<i32>("width")
+            .map_err(|_| gst::loggable_error!(CAT, "No width in caps"))?;
+        let height = s
+            .get::<i32>("height")
+            .map_err(|_| gst::loggable_error!(CAT, "No height in caps"))?;
+        let framerate = s
+            .get::<gst::Fraction>("framerate")
+            .map_err(|_| gst::loggable_error!(CAT, "No framerate in caps"))?;
+
+        // Timestamps continue across renegotiation.
+        let mut state = self.state.lock().unwrap();
+        let frame = state.as_ref().map_or(0, |state| state.frame);
+        *state = Some(State {
+            cfa,
+            width: width as usize,
+            height: height as usize,
+            framerate,
+            frame,
+        });
+
+        Ok(())
+    }
+
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        if let gst::QueryViewMut::Latency(q) = query.view_mut() {
+            let latency = self
+                .state
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|state| state.pts(1));
+            // A live frame is complete one frame duration after its timestamp.
+            if let Some(latency) = latency {
+                let is_live = self.settings.lock().unwrap().is_live;
+                q.set(is_live, latency, None);
+                return true;
+            }
+            return false;
+        }
+
+        BaseSrcImplExt::parent_query(self, query)
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        let mut clock_wait = self.clock_wait.lock().unwrap();
+        if let Some(clock_id) = clock_wait.clock_id.take() {
+            clock_id.unschedule();
+        }
+        clock_wait.flushing = true;
+
+        Ok(())
+    }
+
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.clock_wait.lock().unwrap().flushing = false;
+        Ok(())
+    }
+}
+
+impl PushSrcImpl for RsBayerTestSrc {
+    fn create(
+        &self,
+        _buffer: Option<&mut gst::BufferRef>,
+    ) -> Result<gst_base::subclass::base_src::CreateSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+        let image = Image {
+            pattern: settings.pattern,
+            color: [16, 8, 0].map(|shift| (settings.foreground_color >> shift) as u8),
+            seed: settings.seed,
+        };
+
+        let (buffer, end) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
+
+            let pts = state.pts(state.frame);
+            // Without a framerate there is a single frame.
+            if pts.is_none() && state.frame > 0 {
+                return Err(gst::FlowError::Eos);
+            }
+            let end = state.pts(state.frame + 1);
+
+            let mut buffer = gst::Buffer::with_size(state.width * state.height)
+                .map_err(|_| gst::FlowError::Error)?;
+            {
+                let buffer = buffer.get_mut().unwrap();
+                {
+                    let mut map = buffer.map_writable().map_err(|_| gst::FlowError::Error)?;
+                    image.render(state.frame, state.cfa, state.width, state.height, &mut map);
+                }
+                buffer.set_pts(pts);
+                buffer.set_duration(end.zip(pts).map(|(end, pts)| end - pts));
+                buffer.set_offset(state.frame);
+                buffer.set_offset_end(state.frame + 1);
+            }
+            state.frame += 1;
+
+            (buffer, end)
+        };
+
+        if settings.is_live {
+            self.wait_until(end)?;
+        }
+
+        gst::trace!(CAT, imp = self, "Produced {:?}", buffer);
+        Ok(gst_base::subclass::base_src::CreateSuccess::NewBuffer(
+            buffer,
+        ))
+    }
+}
+
+impl RsBayerTestSrc {
+    // Waits until the running time `running_time` has come on the pipeline clock,
+    // when a live frame ending then would have been captured.
+    fn wait_until(&self, running_time: Option<gst::ClockTime>) -> Result<(), gst::FlowError> {
+        let obj = self.obj();
+        let (Some(clock), Some(base_time), Some(running_time)) =
+            (obj.clock(), obj.base_time(), running_time)
+        else {
+            return Ok(());
+        };
+
+        let clock_id = {
+            let mut clock_wait = self.clock_wait.lock().unwrap();
+            if clock_wait.flushing {
+                return Err(gst::FlowError::Flushing);
+            }
+            let clock_id = clock.new_single_shot_id(base_time + running_time);
+            clock_wait.clock_id = Some(clock_id.clone());
+            clock_id
+        };
+
+        let (res, _) = clock_id.wait();
+        self.clock_wait.lock().unwrap().clock_id = None;
+        match res {
+            Err(gst::ClockError::Unscheduled) => Err(gst::FlowError::Flushing),
+            _ => Ok(()),
+        }
+    }
+}
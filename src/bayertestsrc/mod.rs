@@ -0,0 +1,38 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod image;
+mod imp;
+
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsBayerTestSrcPattern")]
+pub enum TestPattern {
+    #[default]
+    #[enum_value(name = "Solid foreground color", nick = "solid")]
+    Solid = 0,
+    #[enum_value(name = "Eight vertical color bars", nick = "color-bars")]
+    ColorBars = 1,
+    #[enum_value(name = "Horizontal gray ramp", nick = "gradient")]
+    Gradient = 2,
+    #[enum_value(name = "Black and white checkerboard", nick = "checker")]
+    Checker = 3,
+    #[enum_value(name = "Seeded random samples", nick = "noise")]
+    Noise = 4,
+}
+
+glib::wrapper! {
+    pub struct RsBayerTestSrc(ObjectSubclass<imp::RsBayerTestSrc>)
+        @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    TestPattern::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+
+    gst::Element::register(
+        Some(plugin),
+        "rsbayertestsrc",
+        gst::Rank::NONE,
+        RsBayerTestSrc::static_type(),
+    )
+}
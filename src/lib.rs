@@ -7,6 +7,40 @@
 // except according to those terms.
 //
 // SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! GStreamer elements converting bayer frames to RGB/BGR, and back.
+//!
+//! Besides building the `rsbayer` plugin that GStreamer loads from its plugin path,
+//! the crate can be linked into an application directly. After `gst::init()`, a call
+//! to [`plugin_register_static()`] registers the plugin and all its elements with the
+//! default registry, so they can be made by name without any plugin file installed:
+//!
+//! ```no_run
+//! gst::init().unwrap();
+//! gstrsbayer::plugin_register_static().unwrap();
+//!
+//! let pipeline = gst::parse::launch(
+//!     "rsbayertestsrc num-buffers=1 ! rsbayer2rgb ! videoconvert ! fakesink",
+//! )
+//! .unwrap();
+//! ```
+//!
+//! Registration goes through the registry lock and can happen from any thread.
+//! Registering again, from the same or another thread, is harmless: the elements keep
+//! their types and the calls after the first succeed without effect.
+//!
+//! For GStreamer builds with static plugins only, the `static` feature replaces the
+//! entry point of the dynamic plugin with `gst_plugin_rsbayer_register()`, which the
+//! application or `gst_init_static_plugins()` calls to register it, as for the plugins
+//! of gst-plugins-rs:
+//!
+//! ```text
+//! cargo cbuild --release --library-type staticlib --features static
+//! ```
+//!
+//! The converter type is public too, [`RsBayer2Rgb::new()`] and
+//! [`RsBayer2Rgb::builder()`] work even without registering the plugin, which only its
+//! factory by name needs.
 #![allow(clippy::non_send_fields_in_send_ty, unused_doc_comments)]
 
 /**
@@ -17,9 +51,24 @@
 use gst::glib;
 
 mod bayer;
+mod bayerdenoise;
+mod bayerisp;
+mod bayerrepack;
+mod bayertestsrc;
+mod rgb2bayer;
+
+pub use bayer::convert;
+pub use bayer::{
+    AwbMode, RsBayer2Rgb, RsBayer2RgbBuilder, RsBayerExposureMeta, RsBayerTimingMeta,
+};
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     bayer::register(plugin)?;
+    rgb2bayer::register(plugin)?;
+    bayertestsrc::register(plugin)?;
+    bayerdenoise::register(plugin)?;
+    bayerisp::register(plugin)?;
+    bayerrepack::register(plugin)?;
     Ok(())
 }
 
@@ -0,0 +1,346 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::prelude::*;
+use gst_video::VideoFrameExt;
+
+use std::sync::LazyLock;
+
+use super::mosaic;
+use crate::bayer::cfa::Pattern;
+use crate::bayer::frame::OutputLayout;
+
+static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "rsrgb2bayer",
+        gst::DebugColorFlags::empty(),
+        Some("RGB to Bayer converter"),
+    )
+});
+
+// Input formats, in order of preference.
+const INPUT_FORMATS: [gst_video::VideoFormat; 4] = [
+    gst_video::VideoFormat::Rgb,
+    gst_video::VideoFormat::Bgr,
+    gst_video::VideoFormat::Rgba,
+    gst_video::VideoFormat::Gray8,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    pattern: Pattern,
+}
+
+struct State {
+    in_info: gst_video::VideoInfo,
+    layout: OutputLayout,
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+}
+
+#[derive(Default)]
+pub struct RsRgb2Bayer {
+    settings: std::sync::Mutex<Settings>,
+    state: std::sync::Mutex<Option<State>>,
+}
+
+// Channel positions of an input pixel, gray reading all three from its only byte.
+fn input_layout(format: gst_video::VideoFormat) -> Option<OutputLayout> {
+    match format {
+        gst_video::VideoFormat::Gray8 => Some(OutputLayout {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: None,
+            pixel_stride: 1,
+        }),
+        format => OutputLayout::for_format(format),
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RsRgb2Bayer {
+    const NAME: &'static str = "GstRsRgb2Bayer";
+    type Type = super::RsRgb2Bayer;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ObjectImpl for RsRgb2Bayer {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+                /**
+                 * GstRsRgb2Bayer:pattern:
+                 *
+                 * Color filter array the output is sampled through, the format of
+                 * the `video/x-bayer` output caps. Changing it renegotiates.
+                 */
+                glib::ParamSpecEnum::builder_with_default("pattern", Pattern::default())
+                    .nick("Pattern")
+                    .blurb("Bayer pattern of the output")
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pattern" => {
+                let pattern = value.get().expect("type checked upstream");
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Changing pattern from {} to {}",
+                    settings.pattern,
+                    pattern
+                );
+                if pattern != settings.pattern {
+                    settings.pattern = pattern;
+                    drop(settings);
+                    self.obj().reconfigure_src();
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pattern" => settings.pattern.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RsRgb2Bayer {}
+
+impl ElementImpl for RsRgb2Bayer {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RGB to Bayer Converter",
+                "Filter/Converter/Video",
+                "Samples RGB/BGR/gray frames through a bayer color filter array",
+                "Eric Bridgeford",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let sink_caps = gst_video::VideoCapsBuilder::new()
+                .format_list(INPUT_FORMATS)
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &sink_caps,
+            )
+            .unwrap();
+
+            let src_caps = gst::Caps::builder("video/x-bayer")
+                .field(
+                    "format",
+                    gst::List::new(Pattern::ALL.map(Pattern::to_caps_format)),
+                )
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field(
+                    "framerate",
+                    gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                )
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &src_caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseTransformImpl for RsRgb2Bayer {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::NeverInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn transform_caps(
+        &self,
+        direction: gst::PadDirection,
+        caps: &gst::Caps,
+        filter: Option<&gst::Caps>,
+    ) -> Option<gst::Caps> {
+        let pattern = self.settings.lock().unwrap().pattern;
+
+        let mut other_caps = gst::Caps::new_empty();
+        for s in caps.iter() {
+            let width = s.get::<i32>("width").ok();
+            let height = s.get::<i32>("height").ok();
+            let framerate = s.get::<gst::Fraction>("framerate").ok();
+
+            let builders = if direction == gst::PadDirection::Sink {
+                // RGB -> Bayer
+                vec![
+                    gst::Structure::builder("video/x-bayer")
+                        .field("format", pattern.to_caps_format()),
+                ]
+            } else {
+                // Bayer -> RGB
+                INPUT_FORMATS
+                    .iter()
+                    .map(|format| {
+                        gst::Structure::builder("video/x-raw").field("format", format.to_str())
+                    })
+                    .collect()
+            };
+
+            for builder in builders {
+                let s = builder
+                    .field_if_some("width", width)
+                    .field_if_some("height", height)
+                    .field_if_some("framerate", framerate)
+                    .build();
+                other_caps.get_mut().unwrap().append_structure(s);
+            }
+        }
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn unit_size(&self, caps: &gst::Caps) -> Option<usize> {
+        let s = caps.structure(0)?;
+        match s.name().as_str() {
+            "video/x-bayer" => {
+                let width = usize::try_from(s.get::<i32>("width").ok()?).ok()?;
+                let height = usize::try_from(s.get::<i32>("height").ok()?).ok()?;
+                width.checked_mul(height)
+            }
+            _ => gst_video::VideoInfo::from_caps(caps)
+                .ok()
+                .map(|info| info.size()),
+        }
+    }
+
+    fn set_caps(&self, incaps: &gst::Caps, outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        gst::debug!(CAT, imp = self, "Input caps: {}", incaps);
+        gst::debug!(CAT, imp = self, "Output caps: {}", outcaps);
+
+        let in_info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+        let layout = input_layout(in_info.format()).ok_or_else(|| {
+            gst::loggable_error!(CAT, "Unsupported input format {:?}", in_info.format())
+        })?;
+
+        let s = outcaps.structure(0).unwrap();
+        let pattern = s
+            .get::<&str>("format")
+            .ok()
+            .and_then(Pattern::from_caps_format)
+            .ok_or_else(|| gst::loggable_error!(CAT, "No valid bayer format in caps"))?;
+        let width = s
+            .get::<i32>("width")
+            .map_err(|_| gst::loggable_error!(CAT, "No width in caps"))?;
+        let height = s
+            .get::<i32>("height")
+            .map_err(|_| gst::loggable_error!(CAT, "No height in caps"))?;
+        if (width as u32, height as u32) != (in_info.width(), in_info.height()) {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Output size {}x{} doesn't match {}x{} input",
+                width,
+                height,
+                in_info.width(),
+                in_info.height()
+            ));
+        }
+
+        gst::info!(
+            CAT,
+            imp = self,
+            "Input: {:?} {}x{}, output: {}",
+            in_info.format(),
+            width,
+            height,
+            pattern
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            in_info,
+            layout,
+            pattern,
+            width: width as usize,
+            height: height as usize,
+        });
+
+        Ok(())
+    }
+
+    fn transform(
+        &self,
+        inbuf: &gst::Buffer,
+        outbuf: &mut gst::BufferRef,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_ref().ok_or(gst::FlowError::NotNegotiated)?;
+
+        let in_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(inbuf, &state.in_info)
+            .map_err(|_| gst::FlowError::Error)?;
+        let in_stride = in_frame.plane_stride()[0] as usize;
+        let in_data = in_frame.plane_data(0).map_err(|_| gst::FlowError::Error)?;
+        let mut out_map = outbuf.map_writable().map_err(|_| gst::FlowError::Error)?;
+
+        mosaic::mosaic(
+            in_data,
+            in_stride,
+            state.width,
+            state.height,
+            state.layout,
+            state.pattern,
+            &mut out_map,
+            state.width,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, imp = self, "Mosaicing failed: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
@@ -0,0 +1,23 @@
+use gst::glib;
+use gst::prelude::*;
+
+use crate::bayer::cfa::Pattern;
+
+mod imp;
+mod mosaic;
+
+glib::wrapper! {
+    pub struct RsRgb2Bayer(ObjectSubclass<imp::RsRgb2Bayer>)
+        @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    Pattern::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+
+    gst::Element::register(
+        Some(plugin),
+        "rsrgb2bayer",
+        gst::Rank::NONE,
+        RsRgb2Bayer::static_type(),
+    )
+}
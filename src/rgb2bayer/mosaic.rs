@@ -0,0 +1,46 @@
+// Mosaicing, the inverse of demosaic: every site of the bayer frame keeps the one
+// channel of the input pixel its color filter lets through.
+
+use crate::bayer::cfa::{CfaColor, Pattern};
+use crate::bayer::frame::{Error, OutputLayout, fits};
+
+/// Samples a `width` x `height` frame of `layout` pixels through the color filter
+/// array of `pattern` into `output`, one byte per site. Strides are in bytes.
+#[allow(clippy::too_many_arguments)]
+pub fn mosaic(
+    input: &[u8],
+    in_stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+    pattern: Pattern,
+    output: &mut [u8],
+    out_stride: usize,
+) -> Result<(), Error> {
+    if !fits(input.len(), height, width * layout.pixel_stride, in_stride)
+        || !fits(output.len(), height, width, out_stride)
+    {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for y in 0..height {
+        let row = &input[y * in_stride..][..width * layout.pixel_stride];
+        let out_row = &mut output[y * out_stride..][..width];
+        // Even and odd columns of the row.
+        let channels = [0, 1].map(|x| match pattern.color_at(x, y) {
+            CfaColor::Red => layout.red,
+            CfaColor::GreenRed | CfaColor::GreenBlue => layout.green,
+            CfaColor::Blue => layout.blue,
+        });
+
+        for (x, (site, pixel)) in out_row
+            .iter_mut()
+            .zip(row.chunks_exact(layout.pixel_stride))
+            .enumerate()
+        {
+            *site = pixel[channels[x & 1]];
+        }
+    }
+
+    Ok(())
+}
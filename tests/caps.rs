@@ -0,0 +1,334 @@
+// Randomized tests of the caps handling: random but valid caps for the negotiation
+// invariants, random strings for the format parsing that runs on whatever upstream
+// sends. Every case is generated from a fixed seed, so failures reproduce, and the
+// failing caps are part of the assertion messages.
+
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::Pattern;
+
+const CASES: usize = 200;
+const RAW_FORMATS: [&str; 6] = ["RGBA", "RGB", "BGR", "I420", "GRAY8", "xRGB"];
+const SUPPORTED_FORMATS: [&str; 3] = ["RGBA", "RGB", "BGR"];
+const DEEP_SUFFIXES: [&str; 8] = [
+    "10le", "10be", "12le", "12be", "14le", "14be", "16le", "16be",
+];
+// Pieces the random format strings are made of, valid and almost valid ones included.
+const FORMAT_PIECES: [&str; 16] = [
+    "rggb", "bggr", "gbrg", "grbg", "RGGB", "r", "g", "b", "10", "12", "16", "le", "be", " ", "é",
+    "💡",
+];
+
+// xorshift64*, enough to spread the cases around.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+
+    fn even(&mut self, min: usize, max: usize) -> usize {
+        (min / 2 + self.below((max - min) / 2 + 1)) * 2
+    }
+}
+
+// Constraint of a random caps field, and a value satisfying it.
+enum Field {
+    Absent,
+    Fixed(i32),
+    Range(i32, i32),
+}
+
+impl Field {
+    fn random(rng: &mut Rng) -> Self {
+        match rng.below(3) {
+            0 => Field::Absent,
+            1 => Field::Fixed(rng.even(8, 64) as i32),
+            _ => {
+                let min = rng.even(8, 32) as i32;
+                Field::Range(min, min + rng.even(2, 32) as i32)
+            }
+        }
+    }
+
+    fn value(&self, rng: &mut Rng) -> usize {
+        match *self {
+            Field::Absent => rng.even(8, 64),
+            Field::Fixed(value) => value as usize,
+            Field::Range(min, max) => rng.even(min as usize, max as usize),
+        }
+    }
+
+    fn set(&self, s: &mut gst::StructureRef, name: &str) {
+        match *self {
+            Field::Absent => (),
+            Field::Fixed(value) => s.set(name, value),
+            Field::Range(min, max) => s.set(name, gst::IntRange::new(min, max)),
+        }
+    }
+}
+
+// Random downstream caps, and the fixed input caps matching them, with whether the
+// converter can produce any of their formats.
+fn random_raw_caps(rng: &mut Rng) -> (gst::Caps, gst::Caps, bool) {
+    let mut formats = RAW_FORMATS
+        .into_iter()
+        .filter(|_| rng.chance())
+        .collect::<Vec<_>>();
+    let any_format = formats.is_empty() && rng.chance();
+    if formats.is_empty() && !any_format {
+        formats.push(rng.pick(&RAW_FORMATS));
+    }
+    let width = Field::random(rng);
+    let height = Field::random(rng);
+    let framerate = match rng.below(3) {
+        0 => None,
+        1 => Some(gst::Fraction::new(rng.below(60) as i32 + 1, 1)),
+        _ => Some(gst::Fraction::new(30000, 1001)),
+    };
+
+    let mut s = gst::Structure::new_empty("video/x-raw");
+    match formats.as_slice() {
+        [] => (),
+        [format] => s.set("format", *format),
+        formats => s.set("format", gst::List::new(formats.iter().copied())),
+    }
+    width.set(&mut s, "width");
+    height.set(&mut s, "height");
+    if let Some(framerate) = framerate {
+        s.set("framerate", framerate);
+    }
+    let raw = gst::Caps::builder_full().structure(s).build();
+
+    let supported = any_format
+        || formats
+            .iter()
+            .any(|format| SUPPORTED_FORMATS.contains(format));
+    let mut input = bayer_caps(rng.pick(&Pattern::ALL), width.value(rng), height.value(rng));
+    if let Some(framerate) = framerate {
+        input.make_mut().set("framerate", framerate);
+    }
+
+    (raw, input, supported)
+}
+
+// Attaches a bus to the element to catch the error a panic in it would post.
+fn watch(h: &gst_check::Harness) -> gst::Bus {
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    bus
+}
+
+fn assert_no_error(bus: &gst::Bus, context: &dyn std::fmt::Display) {
+    if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+        panic!("{msg:?} for {context}");
+    }
+}
+
+fn push_caps(h: &mut gst_check::Harness, caps: &gst::Caps) -> bool {
+    h.push_event(gst::event::StreamStart::new("caps"));
+    h.push_event(gst::event::Caps::new(caps))
+}
+
+#[test]
+fn test_negotiation_invariants() {
+    init();
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..CASES {
+        let (raw, input, supported) = random_raw_caps(&mut rng);
+        let context = format!("{raw} from {input}");
+
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        let bus = watch(&h);
+        h.set_sink_caps(raw.clone());
+
+        // Downstream caps transformed upstream cover input producing them.
+        let accepted_input = h.srcpad().unwrap().peer_query_caps(None);
+        assert!(accepted_input.can_intersect(&input), "{context}");
+
+        assert_eq!(push_caps(&mut h, &input), supported, "{context}");
+        let s = input.structure(0).unwrap();
+        let size = (s.get::<i32>("width").unwrap() * s.get::<i32>("height").unwrap()) as usize;
+        let res = h.push(gst::Buffer::from_mut_slice(vec![0u8; size]));
+        if !supported {
+            assert_eq!(res, Err(gst::FlowError::NotNegotiated), "{context}");
+            assert_no_error(&bus, &context);
+            continue;
+        }
+        assert_eq!(res, Ok(gst::FlowSuccess::Ok), "{context}");
+
+        // The output is within what downstream allowed, sized as VideoInfo says.
+        let output = h.pull().unwrap();
+        let output_caps = output_caps(&h);
+        assert!(output_caps.is_fixed(), "{context}");
+        assert!(output_caps.is_subset(&raw), "{output_caps} for {context}");
+        let info = gst_video::VideoInfo::from_caps(&output_caps).unwrap();
+        if output.meta::<gst_video::VideoMeta>().is_none() {
+            assert_eq!(output.size(), info.size(), "{context}");
+        }
+        assert_eq!(info.width() as i32, s.get::<i32>("width").unwrap());
+        assert_eq!(info.height() as i32, s.get::<i32>("height").unwrap());
+
+        // And transformed back, the output caps cover the input again.
+        h.set_sink_caps(output_caps.clone());
+        let accepted_input = h.srcpad().unwrap().peer_query_caps(None);
+        assert!(
+            accepted_input.can_intersect(&input),
+            "{accepted_input} for {output_caps} from {input}"
+        );
+
+        assert_no_error(&bus, &context);
+    }
+}
+
+#[test]
+fn test_missing_fields() {
+    init();
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..CASES {
+        let mut caps = bayer_caps(rng.pick(&Pattern::ALL), rng.even(2, 64), rng.even(2, 64));
+        let mut missing = false;
+        {
+            let s = caps.make_mut().structure_mut(0).unwrap();
+            for field in ["format", "width", "height"] {
+                if rng.below(4) == 0 {
+                    s.remove_field(field);
+                    missing = true;
+                }
+            }
+            if rng.chance() {
+                s.remove_field("framerate");
+            }
+        }
+
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        let bus = watch(&h);
+        let accepted = push_caps(&mut h, &caps);
+        if missing {
+            assert!(!accepted, "{caps}");
+        }
+        assert_no_error(&bus, &caps);
+    }
+}
+
+fn random_format(rng: &mut Rng) -> String {
+    (0..rng.below(4))
+        .map(|_| rng.pick(&FORMAT_PIECES))
+        .collect()
+}
+
+#[test]
+fn test_pathological_sizes() {
+    init();
+
+    // Frames whose sizes overflow an i32 row, an OpenCV Mat, a buffer pool or the
+    // address space of 32-bit targets fail negotiation without panicking, either on
+    // the caps or on the first buffer.
+    for (width, height) in [
+        (i32::MAX, 2),
+        (2, i32::MAX),
+        (i32::MAX, i32::MAX),
+        (65536, 65536),
+    ] {
+        for format in SUPPORTED_FORMATS {
+            let caps = gst::Caps::builder("video/x-bayer")
+                .field("format", "rggb")
+                .field("width", width)
+                .field("height", height)
+                .field("framerate", gst::Fraction::new(30, 1))
+                .build();
+            let context = format!("{format} from {caps}");
+
+            let mut h = gst_check::Harness::new("rsbayer2rgb");
+            let bus = watch(&h);
+            h.set_sink_caps_str(&format!("video/x-raw,format={format}"));
+            if push_caps(&mut h, &caps) {
+                let res = h.push(gst::Buffer::from_mut_slice(vec![0u8; 64]));
+                assert_eq!(res, Err(gst::FlowError::NotNegotiated), "{context}");
+            }
+            assert_no_error(&bus, &context);
+        }
+    }
+}
+
+// Whether `format` is a pattern followed by the suffix of a depth stored in 16 bits.
+fn is_deep_format(format: &str) -> bool {
+    Pattern::ALL.iter().any(|pattern| {
+        format
+            .strip_prefix(pattern.to_caps_format())
+            .is_some_and(|suffix| DEEP_SUFFIXES.contains(&suffix))
+    })
+}
+
+#[test]
+fn test_format_strings() {
+    init();
+
+    let mut rng = Rng(0x1234_5678_9abc_def1);
+    for _ in 0..CASES {
+        let format = random_format(&mut rng);
+
+        let parsed = format.parse::<Pattern>();
+        let valid = Pattern::ALL
+            .iter()
+            .any(|pattern| pattern.to_caps_format() == format);
+        assert_eq!(parsed.is_ok(), valid, "{format:?}");
+        if let Ok(pattern) = parsed {
+            assert_eq!(pattern.to_string(), format);
+        }
+        // The element narrows the deep formats.
+        let accepted = valid || is_deep_format(&format);
+
+        let caps = gst::Caps::builder("video/x-bayer")
+            .field("format", format.as_str())
+            .field("width", 16)
+            .field("height", 8)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build();
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        let bus = watch(&h);
+        assert_eq!(push_caps(&mut h, &caps), accepted, "{caps}");
+        assert_no_error(&bus, &caps);
+    }
+}
+
+#[test]
+fn test_denoise_format_strings() {
+    init();
+
+    let mut rng = Rng(0x0fed_cba9_8765_4321);
+    for _ in 0..CASES {
+        let format = random_format(&mut rng);
+        let valid = format.parse::<Pattern>().is_ok() || is_deep_format(&format);
+
+        let caps = gst::Caps::builder("video/x-bayer")
+            .field("format", format.as_str())
+            .field("width", 16)
+            .field("height", 8)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build();
+        let mut h = gst_check::Harness::new("rsbayerdenoise");
+        let bus = watch(&h);
+        assert_eq!(push_caps(&mut h, &caps), valid, "{caps}");
+        assert_no_error(&bus, &caps);
+    }
+}
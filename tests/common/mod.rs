@@ -0,0 +1,216 @@
+// Helpers shared by the integration tests: plugin registration, synthetic bayer
+// frames and reading back converted ones.
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use gst_video::VideoFrameExt;
+use gstrsbayer::convert::{CfaColor, OutputLayout, Pattern};
+
+// Interior pixels are exact for every demosaic algorithm, up to rounding.
+pub const TOLERANCE: u8 = 2;
+// Pixels this close to the frame edges see neighbors the backends make up differently.
+pub const BORDER: usize = 2;
+
+pub fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsbayer::plugin_register_static().expect("rsbayer plugin registers");
+    });
+}
+
+pub fn bayer_caps(pattern: Pattern, width: usize, height: usize) -> gst::Caps {
+    gst::Caps::builder("video/x-bayer")
+        .field("format", pattern.to_caps_format())
+        .field("width", width as i32)
+        .field("height", height as i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .build()
+}
+
+/// Samples of a `width` x `height` frame of `pattern` without padding, every site
+/// taking `value(color, x, y)`.
+pub fn bayer_samples(
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+    value: impl Fn(CfaColor, usize, usize) -> u8,
+) -> Vec<u8> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| value(pattern.color_at(x, y), x, y))
+        .collect()
+}
+
+pub fn bayer_frame(
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+    value: impl Fn(CfaColor, usize, usize) -> u8,
+) -> gst::Buffer {
+    gst::Buffer::from_mut_slice(bayer_samples(pattern, width, height, value))
+}
+
+/// Sample a sensor looking at a uniform `[red, green, blue]` scene would capture.
+pub fn channel(color: CfaColor, [red, green, blue]: [u8; 3]) -> u8 {
+    match color {
+        CfaColor::Red => red,
+        CfaColor::GreenRed | CfaColor::GreenBlue => green,
+        CfaColor::Blue => blue,
+    }
+}
+
+/// Red, green and blue of every pixel of a converted frame, row by row.
+pub fn rgb_pixels(buffer: &gst::BufferRef, caps: &gst::CapsRef) -> Vec<Vec<[u8; 3]>> {
+    let info = gst_video::VideoInfo::from_caps(caps).unwrap();
+    let layout = OutputLayout::for_format(info.format()).unwrap();
+    let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &info).unwrap();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).unwrap();
+
+    rgb_rows(
+        data,
+        stride,
+        info.width() as usize,
+        info.height() as usize,
+        layout,
+    )
+}
+
+/// Red, green and blue of every pixel of `width` x `height` output pixels of `layout`,
+/// rows `stride` bytes apart.
+pub fn rgb_rows(
+    data: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    layout: OutputLayout,
+) -> Vec<Vec<[u8; 3]>> {
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let pixel = &data[y * stride + x * layout.pixel_stride..];
+                    [pixel[layout.red], pixel[layout.green], pixel[layout.blue]]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// How far a converted frame is off from the expected one.
+#[derive(Debug)]
+pub struct Difference {
+    /// Largest difference of any channel.
+    pub max: u8,
+    /// Mean difference over every channel of every pixel.
+    pub mean: f64,
+    /// Position, value and expected value of the first pixel, in row order, off by
+    /// more than the tolerance.
+    pub first: Option<(usize, usize, [u8; 3], [u8; 3])>,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "max error {}, mean error {:.3}", self.max, self.mean)?;
+        if let Some((x, y, actual, expected)) = self.first {
+            write!(f, ", pixel {x},{y} is {actual:?}, expected {expected:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two frames of the same size pixel by pixel, flagging the first pixel
+/// with a channel more than `tolerance` off.
+pub fn compare(actual: &[Vec<[u8; 3]>], expected: &[Vec<[u8; 3]>], tolerance: u8) -> Difference {
+    assert_eq!(
+        (actual.len(), actual.first().map_or(0, Vec::len)),
+        (expected.len(), expected.first().map_or(0, Vec::len)),
+        "frame sizes differ"
+    );
+
+    let mut difference = Difference {
+        max: 0,
+        mean: 0.0,
+        first: None,
+    };
+    let mut total = 0u64;
+    let mut channels = 0u64;
+    for (y, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+        for (x, (&actual, &expected)) in actual.iter().zip(expected).enumerate() {
+            let error = actual
+                .iter()
+                .zip(expected)
+                .map(|(&value, expected)| value.abs_diff(expected))
+                .max()
+                .unwrap();
+            total += actual
+                .iter()
+                .zip(expected)
+                .map(|(&value, expected)| u64::from(value.abs_diff(expected)))
+                .sum::<u64>();
+            channels += 3;
+            difference.max = difference.max.max(error);
+            if error > tolerance && difference.first.is_none() {
+                difference.first = Some((x, y, actual, expected));
+            }
+        }
+    }
+    difference.mean = total as f64 / channels.max(1) as f64;
+
+    difference
+}
+
+/// Peak signal-to-noise ratio in dB of `actual` against `reference`, frames of the
+/// same size, over the pixels at least `border` away from the edges. Infinite for
+/// identical frames.
+pub fn psnr(actual: &[Vec<[u8; 3]>], reference: &[Vec<[u8; 3]>], border: usize) -> f64 {
+    let rows = |frame: &[Vec<[u8; 3]>]| -> Vec<Vec<[u8; 3]>> {
+        let height = frame.len();
+        frame[border..height - border]
+            .iter()
+            .map(|row| row[border..row.len() - border].to_vec())
+            .collect()
+    };
+    let (actual, reference) = (rows(actual), rows(reference));
+
+    let mut squared = 0u64;
+    let mut count = 0u64;
+    for (actual, reference) in actual.iter().flatten().zip(reference.iter().flatten()) {
+        for (&value, &expected) in actual.iter().zip(reference) {
+            squared += u64::from(value.abs_diff(expected)).pow(2);
+            count += 1;
+        }
+    }
+    let mse = squared as f64 / count.max(1) as f64;
+
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Asserts every pixel away from the borders is within [`TOLERANCE`] of
+/// `expected(x, y)`.
+pub fn assert_interior(pixels: &[Vec<[u8; 3]>], expected: impl Fn(usize, usize) -> [u8; 3]) {
+    let height = pixels.len();
+    for (y, row) in pixels.iter().enumerate().take(height - BORDER).skip(BORDER) {
+        let width = row.len();
+        for (x, pixel) in row.iter().enumerate().take(width - BORDER).skip(BORDER) {
+            let expected = expected(x, y);
+            assert!(
+                pixel
+                    .iter()
+                    .zip(expected)
+                    .all(|(&value, expected)| value.abs_diff(expected) <= TOLERANCE),
+                "pixel {x},{y} is {pixel:?}, expected {expected:?}",
+            );
+        }
+    }
+}
+
+/// Caps the harness output pad negotiated.
+pub fn output_caps(h: &gst_check::Harness) -> gst::Caps {
+    h.sinkpad()
+        .and_then(|pad| pad.current_caps())
+        .expect("output is negotiated")
+}
@@ -0,0 +1,229 @@
+// Golden image regression tests of the conversion core, run on plain slices without a
+// pipeline.
+//
+// A deterministic raw frame of every pattern, with gradients, sharp edges and noise so
+// every stage has something to change, is converted by every backend built in, with a
+// few configurations, to every output format. All formats of one conversion have to
+// agree, and the red, green and blue they read back as have to match the golden image
+// in tests/golden/ stored for it, `<backend>-<configuration>-<pattern>.ppm`.
+//
+// A mismatch fails with the largest and mean error and the first pixel off, and leaves
+// the output, the golden image and their difference as PPM images in
+// $TMPDIR/rsbayer-golden/ for inspection. After an intended change of the output the
+// golden images are regenerated, and have to be checked and committed, with
+//
+//     RSBAYER_BLESS_GOLDENS=1 cargo test --test golden
+//
+// which is also the only way to record the golden image of a new conversion; without
+// it a missing one fails like a mismatch.
+
+mod common;
+
+use common::*;
+use gstrsbayer::convert::{Backend, Converter, DemosaicAlgorithm, Gains, OutputLayout, Pattern};
+use std::path::{Path, PathBuf};
+
+const BACKENDS: &[Backend] = &[
+    #[cfg(feature = "opencv")]
+    Backend::OpenCv,
+    #[cfg(feature = "rust-demosaic")]
+    Backend::Rust,
+];
+
+// Conversions of the same frame can differ by a rounding step across platforms and
+// OpenCV builds, never by more.
+const GOLDEN_TOLERANCE: u8 = 1;
+const WIDTH: usize = 48;
+const HEIGHT: usize = 32;
+
+type Configure = fn(&mut Converter);
+
+const CONFIGURATIONS: [(&str, Configure); 4] = [
+    ("bilinear", |_| {}),
+    ("vng", |converter| {
+        converter.set_algorithm(DemosaicAlgorithm::Vng)
+    }),
+    ("edge-aware", |converter| {
+        converter.set_algorithm(DemosaicAlgorithm::EdgeAware)
+    }),
+    // The raw corrections, tone and color stages at 16 bits.
+    ("tuned", |converter| {
+        converter.set_black_level([16; 4]);
+        converter.set_gains(Gains {
+            red: 1.6,
+            green: 1.0,
+            blue: 1.3,
+            green_balance: 1.0,
+        });
+        converter.set_gamma(2.2);
+        converter.set_saturation(1.2);
+        converter.set_high_precision(true, false);
+    }),
+];
+
+fn backend_name(backend: Backend) -> &'static str {
+    match backend {
+        #[cfg(feature = "opencv")]
+        Backend::OpenCv => "opencv",
+        #[cfg(feature = "rust-demosaic")]
+        Backend::Rust => "rust",
+    }
+}
+
+// Horizontal and vertical gradients of different colors, a disc of saturated color
+// with a sharp edge and a little noise, the same for every run.
+fn reference_frame(pattern: Pattern) -> Vec<u8> {
+    bayer_samples(pattern, WIDTH, HEIGHT, |color, x, y| {
+        let (dx, dy) = (x as i64 - 30, y as i64 - 14);
+        let scene = if dx * dx + dy * dy < 100 {
+            [230, 40, 90]
+        } else {
+            [
+                (x * 255 / WIDTH) as u8,
+                (y * 255 / HEIGHT) as u8,
+                ((x + y) * 255 / (WIDTH + HEIGHT)) as u8,
+            ]
+        };
+        let noise = ((x as u64 * 0x9e37_79b9) ^ (y as u64 * 0x85eb_ca6b)).wrapping_mul(0xc2b2_ae35);
+        channel(color, scene).saturating_add(((noise >> 29) % 8) as u8)
+    })
+}
+
+fn convert(
+    backend: Backend,
+    pattern: Pattern,
+    format: gst_video::VideoFormat,
+    configure: Configure,
+) -> Vec<Vec<[u8; 3]>> {
+    let mut converter =
+        Converter::new(backend, pattern, WIDTH, HEIGHT, format).expect("supported conversion");
+    configure(&mut converter);
+
+    let layout = OutputLayout::for_format(format).unwrap();
+    let stride = WIDTH * layout.pixel_stride;
+    let mut output = vec![0u8; stride * HEIGHT];
+    converter
+        .convert(&reference_frame(pattern), WIDTH, &mut output, stride)
+        .expect("conversion succeeds");
+
+    if let Some(alpha) = layout.alpha {
+        assert!(
+            output
+                .chunks_exact(layout.pixel_stride)
+                .all(|pixel| pixel[alpha] == u8::MAX),
+            "{format:?} output isn't opaque"
+        );
+    }
+    rgb_rows(&output, stride, WIDTH, HEIGHT, layout)
+}
+
+fn write_ppm(path: &Path, pixels: &[Vec<[u8; 3]>]) {
+    let mut data = format!("P6\n{} {}\n255\n", pixels[0].len(), pixels.len()).into_bytes();
+    data.extend(pixels.iter().flatten().flatten());
+    std::fs::write(path, data).unwrap_or_else(|err| panic!("writing {}: {err}", path.display()));
+}
+
+fn read_ppm(path: &Path) -> Option<Vec<Vec<[u8; 3]>>> {
+    let data = std::fs::read(path).ok()?;
+    // Magic number, width, height and maximum value, each followed by one whitespace.
+    let mut fields = data.splitn(5, u8::is_ascii_whitespace);
+    let mut header = || std::str::from_utf8(fields.next()?).ok().map(str::to_owned);
+    let (magic, width, height, max) = (header()?, header()?, header()?, header()?);
+    let pixels = fields.next()?;
+    let (width, height) = (width.parse::<usize>().ok()?, height.parse::<usize>().ok()?);
+    assert!(
+        magic == "P6" && max == "255" && pixels.len() == 3 * width * height,
+        "{} isn't an 8-bit PPM image of {width}x{height}",
+        path.display()
+    );
+
+    Some(
+        pixels
+            .chunks_exact(3 * width)
+            .map(|row| {
+                row.chunks_exact(3)
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect()
+            })
+            .collect(),
+    )
+}
+
+// Leaves the output, the golden image and their difference, amplified so a rounding
+// step shows, in the dump directory and returns it.
+fn dump(name: &str, actual: &[Vec<[u8; 3]>], expected: &[Vec<[u8; 3]>]) -> PathBuf {
+    let dir = std::env::temp_dir().join("rsbayer-golden");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let difference = actual
+        .iter()
+        .zip(expected)
+        .map(|(actual, expected)| {
+            actual
+                .iter()
+                .zip(expected)
+                .map(|(actual, expected)| {
+                    [0, 1, 2].map(|i| actual[i].abs_diff(expected[i]).saturating_mul(32))
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    write_ppm(&dir.join(format!("{name}-actual.ppm")), actual);
+    write_ppm(&dir.join(format!("{name}-expected.ppm")), expected);
+    write_ppm(&dir.join(format!("{name}-difference.ppm")), &difference);
+
+    dir
+}
+
+#[test]
+fn test_golden_images() {
+    init();
+
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let bless = std::env::var_os("RSBAYER_BLESS_GOLDENS").is_some();
+    let mut failures = Vec::new();
+
+    for &backend in BACKENDS {
+        for (configuration, configure) in CONFIGURATIONS {
+            for pattern in Pattern::ALL {
+                let name = format!("{}-{configuration}-{pattern}", backend_name(backend));
+                let [first, others @ ..] = Converter::OUTPUT_FORMATS
+                    .map(|format| (format, convert(backend, pattern, format, configure)));
+                for (format, pixels) in others {
+                    let difference = compare(&pixels, &first.1, 0);
+                    assert!(
+                        difference.first.is_none(),
+                        "{name}: {format:?} differs from {:?}, {difference}",
+                        first.0
+                    );
+                }
+                let actual = first.1;
+
+                let path = golden_dir.join(format!("{name}.ppm"));
+                if bless {
+                    std::fs::create_dir_all(&golden_dir).unwrap();
+                    write_ppm(&path, &actual);
+                    eprintln!("{name}: recorded {}", path.display());
+                    continue;
+                }
+                let Some(expected) = read_ppm(&path) else {
+                    failures.push(format!("{name}: no golden image {}", path.display()));
+                    continue;
+                };
+
+                let difference = compare(&actual, &expected, GOLDEN_TOLERANCE);
+                if difference.first.is_some() {
+                    let dir = dump(&name, &actual, &expected);
+                    failures.push(format!("{name}: {difference}, images in {}", dir.display()));
+                }
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} conversions differ from their golden images:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
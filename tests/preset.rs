@@ -0,0 +1,94 @@
+// GstPreset tests. Saved presets go to the user data directory, which is pointed at a
+// fresh one before anything asks GLib for it, so these run in a process of their own.
+
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::{AwbMode, DemosaicAlgorithm};
+
+fn new_element() -> gst::Element {
+    gst::ElementFactory::make("rsbayer2rgb").build().unwrap()
+}
+
+#[test]
+fn test_presets() {
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-presets-{}", std::process::id()));
+    // SAFETY: the only test of this binary, nothing else runs yet.
+    unsafe { std::env::set_var("XDG_DATA_HOME", &dir) };
+    init();
+
+    let element = new_element();
+    let preset = element.dynamic_cast_ref::<gst::Preset>().unwrap();
+    let names = preset.preset_names();
+    assert!(names.iter().any(|name| name == "imx477-daylight"));
+    assert!(names.iter().any(|name| name == "imx477-tungsten"));
+
+    // Tuning properties round-trip, the others aren't part of presets.
+    element.set_property("demosaic-algorithm", DemosaicAlgorithm::EdgeAware);
+    element.set_property("red-gain", 1.75);
+    element.set_property("blue-gain", 2.25);
+    element.set_property("awb-mode", AwbMode::Locked);
+    element.set_property("ob-rows", 8u32);
+    element.set_property("gamma", 2.4);
+    element.set_property("tone-lut", gst::Array::new((0..256).rev()));
+    element.set_property("saturation", 0.5);
+    element.set_property("stats-interval", 7u32);
+    preset.save_preset("test-module").unwrap();
+    assert!(
+        preset
+            .preset_names()
+            .iter()
+            .any(|name| name == "test-module")
+    );
+
+    let fresh = new_element();
+    fresh.set_property("stats-interval", 3u32);
+    fresh.set_property("preset", "test-module");
+    assert_eq!(
+        fresh.property::<DemosaicAlgorithm>("demosaic-algorithm"),
+        DemosaicAlgorithm::EdgeAware
+    );
+    assert_eq!(fresh.property::<f64>("red-gain"), 1.75);
+    assert_eq!(fresh.property::<f64>("green-gain"), 1.0);
+    assert_eq!(fresh.property::<f64>("blue-gain"), 2.25);
+    assert_eq!(fresh.property::<AwbMode>("awb-mode"), AwbMode::Locked);
+    assert_eq!(fresh.property::<u32>("ob-rows"), 8);
+    assert_eq!(fresh.property::<f64>("gamma"), 2.4);
+    assert_eq!(
+        fresh.property::<gst::Array>("tone-lut").as_slice().len(),
+        256
+    );
+    assert_eq!(fresh.property::<f64>("saturation"), 0.5);
+    assert_eq!(fresh.property::<u32>("stats-interval"), 3);
+    assert_eq!(
+        fresh.property::<Option<String>>("preset").as_deref(),
+        Some("test-module")
+    );
+
+    // A built-in preset resets what it doesn't set.
+    let preset = fresh.dynamic_cast_ref::<gst::Preset>().unwrap();
+    preset.load_preset("imx477-tungsten").unwrap();
+    assert_eq!(fresh.property::<f64>("red-gain"), 1.24);
+    assert_eq!(fresh.property::<f64>("blue-gain"), 2.61);
+    assert_eq!(fresh.property::<AwbMode>("awb-mode"), AwbMode::Manual);
+    assert_eq!(
+        fresh.property::<DemosaicAlgorithm>("demosaic-algorithm"),
+        DemosaicAlgorithm::default()
+    );
+    assert_eq!(fresh.property::<u32>("ob-rows"), 0);
+    assert!(fresh.property::<gst::Array>("tone-lut").is_empty());
+
+    assert!(preset.load_preset("no-such-module").is_err());
+    assert_eq!(fresh.property::<f64>("red-gain"), 1.24);
+
+    // From a launch line.
+    let element = gst::parse::bin_from_description("rsbayer2rgb preset=imx477-daylight", false)
+        .unwrap()
+        .children()
+        .remove(0);
+    assert_eq!(element.property::<f64>("red-gain"), 1.92);
+
+    preset.delete_preset("test-module").unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+}
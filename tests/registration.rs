@@ -0,0 +1,129 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::RsBayer2Rgb;
+use gstrsbayer::convert::{DemosaicAlgorithm, Method};
+
+#[cfg(feature = "static")]
+unsafe extern "C" {
+    fn gst_plugin_rsbayer_register();
+}
+
+#[test]
+fn test_static_registration() {
+    init();
+
+    // Registering again is harmless.
+    gstrsbayer::plugin_register_static().unwrap();
+
+    for name in [
+        "rsbayer2rgb",
+        "rsrgb2bayer",
+        "rsbayertestsrc",
+        "rsbayerdenoise",
+        "rsbayerisp",
+        "rsbayerrepack",
+    ] {
+        assert!(
+            gst::ElementFactory::find(name).is_some(),
+            "{name} is registered"
+        );
+    }
+
+    let pipeline = gst::parse::launch("rsbayertestsrc num-buffers=2 ! rsbayer2rgb ! fakesink")
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let bus = pipeline.bus().unwrap();
+    let msg = bus
+        .timed_pop_filtered(
+            gst::ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        )
+        .expect("pipeline finishes");
+    assert_eq!(msg.type_(), gst::MessageType::Eos, "{msg:?}");
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[cfg(feature = "static")]
+#[test]
+fn test_static_entry_point() {
+    gst::init().unwrap();
+    unsafe { gst_plugin_rsbayer_register() };
+
+    assert!(gst::Registry::get().find_plugin("rsbayer").is_some());
+    assert!(gst::ElementFactory::find("rsbayer2rgb").is_some());
+}
+
+#[test]
+fn test_new() {
+    init();
+
+    let convert = RsBayer2Rgb::new();
+    assert_eq!(convert.property::<Method>("method"), Method::Full);
+    assert_eq!(convert.property::<f64>("gamma"), 1.0);
+}
+
+#[test]
+fn test_builder() {
+    init();
+
+    let convert = RsBayer2Rgb::builder()
+        .name("convert")
+        .method(Method::Superpixel)
+        .video_direction(gst_video::VideoOrientationMethod::_180)
+        .demosaic_algorithm(DemosaicAlgorithm::EdgeAware)
+        .gains(1.8, 1.0, 1.5)
+        .awb_mode(gstrsbayer::AwbMode::Locked)
+        .exposure_gain(2.0)
+        .gamma(2.2)
+        .brightness(0.1)
+        .contrast(1.2)
+        .saturation(0.8)
+        .build();
+
+    assert_eq!(convert.name(), "convert");
+    assert_eq!(convert.property::<Method>("method"), Method::Superpixel);
+    assert_eq!(
+        convert.property::<gst_video::VideoOrientationMethod>("video-direction"),
+        gst_video::VideoOrientationMethod::_180
+    );
+    assert_eq!(
+        convert.property::<DemosaicAlgorithm>("demosaic-algorithm"),
+        DemosaicAlgorithm::EdgeAware
+    );
+    assert_eq!(convert.property::<f64>("red-gain"), 1.8);
+    assert_eq!(convert.property::<f64>("green-gain"), 1.0);
+    assert_eq!(convert.property::<f64>("blue-gain"), 1.5);
+    assert_eq!(
+        convert.property::<gstrsbayer::AwbMode>("awb-mode"),
+        gstrsbayer::AwbMode::Locked
+    );
+    assert_eq!(convert.property::<f64>("exposure-gain"), 2.0);
+    assert_eq!(convert.property::<f64>("gamma"), 2.2);
+    assert_eq!(convert.property::<f64>("brightness"), 0.1);
+    assert_eq!(convert.property::<f64>("contrast"), 1.2);
+    assert_eq!(convert.property::<f64>("saturation"), 0.8);
+
+    // The concrete type goes into pipelines as it is.
+    let pipeline = gst::Pipeline::new();
+    pipeline.add(&convert).unwrap();
+}
+
+#[test]
+fn test_enums_from_str() {
+    assert_eq!("superpixel".parse::<Method>(), Ok(Method::Superpixel));
+    assert_eq!(
+        "edge-aware".parse::<DemosaicAlgorithm>(),
+        Ok(DemosaicAlgorithm::EdgeAware)
+    );
+    assert!("sharp".parse::<DemosaicAlgorithm>().is_err());
+    assert_eq!(
+        "gbrg".parse::<gstrsbayer::convert::Pattern>(),
+        Ok(gstrsbayer::convert::Pattern::Gbrg)
+    );
+}
@@ -0,0 +1,3516 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gst_video::VideoFrameExt;
+use gstrsbayer::RsBayerExposureMeta;
+use gstrsbayer::convert::{CfaColor, GrGbBalance, Pattern, temperature_gains};
+use std::sync::mpsc;
+
+const OUTPUT_FORMATS: [&str; 3] = ["RGBA", "RGB", "BGR"];
+const FRAME_DURATION: gst::ClockTime = gst::ClockTime::from_nseconds(33_333_333);
+
+fn harness(pattern: Pattern, width: usize, height: usize, format: &str) -> gst_check::Harness {
+    harness_with(pattern, width, height, format, &[])
+}
+
+// Like harness(), with `properties` set before the caps arrive, for properties that
+// only take effect with new caps.
+fn harness_with(
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+    format: &str,
+    properties: &[(&str, &str)],
+) -> gst_check::Harness {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    let element = h.element().unwrap();
+    for (name, value) in properties {
+        element.set_property_from_str(name, value);
+    }
+    h.set_sink_caps_str(&format!("video/x-raw,format={format}"));
+    h.set_src_caps(bayer_caps(pattern, width, height));
+    h
+}
+
+fn push(h: &mut gst_check::Harness, n: u64, mut buffer: gst::Buffer) -> gst::Buffer {
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(FRAME_DURATION * n);
+        buffer.set_duration(FRAME_DURATION);
+    }
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    h.pull().unwrap()
+}
+
+#[test]
+fn test_solid_colors() {
+    const COLORS: [[u8; 3]; 3] = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+
+    for format in OUTPUT_FORMATS {
+        let mut h = harness(Pattern::Rggb, 16, 12, format);
+
+        for (n, color) in COLORS.into_iter().enumerate() {
+            let n = n as u64;
+            let frame = bayer_frame(Pattern::Rggb, 16, 12, |cfa, _, _| channel(cfa, color));
+            let output = push(&mut h, n, frame);
+
+            let caps = output_caps(&h);
+            let s = caps.structure(0).unwrap();
+            assert_eq!(s.get::<&str>("format").unwrap(), format);
+            assert_eq!(s.get::<i32>("width").unwrap(), 16);
+            assert_eq!(s.get::<i32>("height").unwrap(), 12);
+            assert_eq!(
+                s.get::<gst::Fraction>("framerate").unwrap(),
+                gst::Fraction::new(30, 1)
+            );
+
+            if output.meta::<gst_video::VideoMeta>().is_none() {
+                let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+                assert_eq!(output.size(), info.size());
+            }
+            assert_eq!(output.pts(), Some(FRAME_DURATION * n));
+            assert_eq!(output.duration(), Some(FRAME_DURATION));
+
+            assert_interior(&rgb_pixels(&output, &caps), |_, _| color);
+        }
+    }
+}
+
+#[test]
+fn test_gradient() {
+    for format in OUTPUT_FORMATS {
+        for pattern in Pattern::ALL {
+            let mut h = harness(pattern, 64, 8, format);
+            let frame = bayer_frame(pattern, 64, 8, |_, x, _| (x * 4) as u8);
+            let output = push(&mut h, 0, frame);
+
+            assert_interior(&rgb_pixels(&output, &output_caps(&h)), |x, _| {
+                [(x * 4) as u8; 3]
+            });
+        }
+    }
+}
+
+#[test]
+fn test_padded_input() {
+    // An odd width, so RGB and BGR rows are padded too.
+    const WIDTH: usize = 15;
+    const HEIGHT: usize = 10;
+    const STRIDE: usize = 24;
+    const OFFSET: usize = 40;
+
+    for format in OUTPUT_FORMATS {
+        let mut h = harness(Pattern::Gbrg, WIDTH, HEIGHT, format);
+        let samples = bayer_samples(Pattern::Gbrg, WIDTH, HEIGHT, |_, x, y| (x * 8 + y) as u8);
+        let packed = push(&mut h, 0, gst::Buffer::from_mut_slice(samples.clone()));
+
+        let mut data = vec![0xa5u8; OFFSET + STRIDE * HEIGHT];
+        for (row, samples) in samples.chunks_exact(WIDTH).enumerate() {
+            data[OFFSET + row * STRIDE..][..WIDTH].copy_from_slice(samples);
+        }
+        let mut padded = gst::Buffer::from_mut_slice(data);
+        gst_video::VideoMeta::add_full(
+            padded.get_mut().unwrap(),
+            gst_video::VideoFrameFlags::empty(),
+            gst_video::VideoFormat::Gray8,
+            WIDTH as u32,
+            HEIGHT as u32,
+            &[OFFSET],
+            &[STRIDE as i32],
+        )
+        .unwrap();
+        let padded = push(&mut h, 1, padded);
+
+        let caps = output_caps(&h);
+        let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+        for output in [&packed, &padded] {
+            if output.meta::<gst_video::VideoMeta>().is_none() {
+                assert_eq!(output.size(), info.size());
+            }
+        }
+        assert_eq!(
+            rgb_pixels(&padded, &caps),
+            rgb_pixels(&packed, &caps),
+            "{format}"
+        );
+    }
+}
+
+#[test]
+fn test_video_meta() {
+    // Without downstream VideoMeta support the layout of the caps is described, with it
+    // the pool pads rows to the alignment and describes those.
+    for format in OUTPUT_FORMATS {
+        for (alignment, video_meta) in [(1u32, false), (64, true)] {
+            let mut h = harness_with(
+                Pattern::Rggb,
+                20,
+                8,
+                format,
+                &[("output-alignment", &alignment.to_string())],
+            );
+            if video_meta {
+                h.add_propose_allocation_meta(gst_video::VideoMeta::meta_api(), None);
+            }
+            let frame = bayer_frame(Pattern::Rggb, 20, 8, |cfa, _, _| {
+                channel(cfa, [200, 100, 50])
+            });
+            let output = push(&mut h, 0, frame);
+
+            let caps = output_caps(&h);
+            let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+            let meta = output
+                .meta::<gst_video::VideoMeta>()
+                .unwrap_or_else(|| panic!("{format}, alignment {alignment}: no VideoMeta"));
+            assert_eq!(
+                (meta.format(), meta.width(), meta.height(), meta.n_planes()),
+                (info.format(), info.width(), info.height(), info.n_planes())
+            );
+            let stride = meta.stride()[0] as usize;
+            if alignment == 1 {
+                assert_eq!(meta.stride(), info.stride());
+                assert_eq!(meta.offset(), info.offset());
+            } else {
+                assert_eq!(stride % alignment as usize, 0, "{format}: stride {stride}");
+                assert!(stride >= info.stride()[0] as usize);
+            }
+            assert!(meta.offset()[0] + stride * info.height() as usize <= output.size());
+
+            assert_interior(&rgb_pixels(&output, &caps), |_, _| [200, 100, 50]);
+        }
+    }
+}
+
+#[test]
+fn test_unsupported_input_format() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    h.push_event(gst::event::StreamStart::new("test"));
+    let caps = gst::Caps::builder("video/x-bayer")
+        .field("format", "rggb20le")
+        .field("width", 16)
+        .field("height", 12)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .build();
+    assert!(!h.push_event(gst::event::Caps::new(&caps)));
+
+    let frame = gst::Buffer::from_mut_slice(vec![0u8; 16 * 12 * 2]);
+    assert_eq!(h.push(frame), Err(gst::FlowError::NotNegotiated));
+}
+
+#[test]
+fn test_deep_input() {
+    const COLOR: [u8; 3] = [200, 100, 50];
+    init();
+
+    let (width, height) = (16, 12);
+    let samples = bayer_samples(Pattern::Grbg, width, height, |cfa, _, _| {
+        channel(cfa, COLOR)
+    });
+    for (suffix, bits, big_endian) in [("10le", 10, false), ("12be", 12, true), ("16le", 16, false)]
+    {
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        h.set_sink_caps_str("video/x-raw,format=RGB");
+        h.set_src_caps_str(&format!(
+            "video/x-bayer,format=grbg{suffix},width={width},height={height},framerate=30/1"
+        ));
+
+        // Everything below the high 8 bits is set, and dropped by the narrowing.
+        let low_bits = bits - 8;
+        let frame = samples
+            .iter()
+            .flat_map(|&sample| {
+                let sample = ((sample as u16) << low_bits) | ((1u16 << low_bits) - 1);
+                match big_endian {
+                    true => sample.to_be_bytes(),
+                    false => sample.to_le_bytes(),
+                }
+            })
+            .collect::<Vec<u8>>();
+        let output = push(&mut h, 0, gst::Buffer::from_mut_slice(frame));
+        assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| COLOR);
+    }
+}
+
+#[test]
+fn test_p010_output() {
+    init();
+
+    // A horizontal gray ramp of 12-bit samples, which bilinear demosaic keeps gray.
+    let (width, height) = (64, 8);
+    let level = |x: usize| x * 4095 / (width - 1);
+    let frame = (0..height)
+        .flat_map(|_| (0..width).flat_map(|x| (level(x) as u16).to_le_bytes()))
+        .collect::<Vec<u8>>();
+
+    // Gray has the same luma with every matrix, the range scales it.
+    for (colorimetry, full_range) in [("bt709", false), ("bt2020", false), ("1:3:5:1", true)] {
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        h.set_sink_caps_str(&format!(
+            "video/x-raw,format=P010_10LE,colorimetry={colorimetry}"
+        ));
+        h.set_src_caps_str(&format!(
+            "video/x-bayer,format=rggb12le,width={width},height={height},framerate=30/1"
+        ));
+        let output = push(&mut h, 0, gst::Buffer::from_slice(frame.clone()));
+
+        let info = gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap();
+        assert_eq!(info.format(), gst_video::VideoFormat::P01010le);
+        assert!(output.size() >= info.size());
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(&output, &info).unwrap();
+        let word = |plane: u32, offset: usize| {
+            let data = frame.plane_data(plane).unwrap();
+            let word = u16::from_le_bytes([data[offset], data[offset + 1]]);
+            // The 10 bits are in the high bits of the word.
+            assert_eq!(word & 0x3f, 0);
+            (word >> 6) as f64
+        };
+
+        let (y_stride, uv_stride) = (
+            frame.plane_stride()[0] as usize,
+            frame.plane_stride()[1] as usize,
+        );
+        // The columns next to the edges are mirrored and not on the ramp.
+        for y in 0..height {
+            for x in 2..width - 2 {
+                let gray = level(x) as f64 / 4095.0;
+                let expected = match full_range {
+                    true => gray * 1023.0,
+                    false => 64.0 + gray * 876.0,
+                };
+                let luma = word(0, y * y_stride + 2 * x);
+                assert!(
+                    (luma - expected).abs() <= 2.0,
+                    "{colorimetry}: luma {luma} at ({x}, {y}), expected {expected:.1}"
+                );
+            }
+        }
+        for y in 0..height / 2 {
+            for x in 1..width / 2 - 1 {
+                for chroma in [word(1, y * uv_stride + 4 * x), word(1, y * uv_stride + 4 * x + 2)]
+                {
+                    assert!((chroma - 512.0).abs() <= 2.0, "{colorimetry}: chroma {chroma}");
+                }
+            }
+        }
+    }
+
+    // 8-bit input has no P010.
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    h.set_sink_caps_str("video/x-raw,format=P010_10LE");
+    h.push_event(gst::event::StreamStart::new("test"));
+    assert!(!h.push_event(gst::event::Caps::new(&bayer_caps(Pattern::Rggb, 16, 12))));
+}
+
+#[test]
+fn test_unsupported_output_format() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    h.set_sink_caps_str("video/x-raw,format=I420");
+    h.push_event(gst::event::StreamStart::new("test"));
+    assert!(!h.push_event(gst::event::Caps::new(&bayer_caps(Pattern::Rggb, 16, 12))));
+
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 0);
+    assert_eq!(h.push(frame), Err(gst::FlowError::NotNegotiated));
+}
+
+#[test]
+fn test_device_memory_input() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    let mut caps = bayer_caps(Pattern::Rggb, 16, 12);
+    caps.make_mut()
+        .set_features_simple(Some(gst::CapsFeatures::new(["memory:NVMM"])));
+
+    // Not offered to upstream, and refused with an error naming the memory rather than
+    // a failure to map the first frame.
+    let accepted_input = h.srcpad().unwrap().peer_query_caps(None);
+    assert!(!accepted_input.can_intersect(&caps), "{accepted_input}");
+    h.push_event(gst::event::StreamStart::new("test"));
+    assert!(!h.push_event(gst::event::Caps::new(&caps)));
+
+    let msg = bus
+        .pop_filtered(&[gst::MessageType::Error])
+        .expect("negotiation error is posted");
+    let gst::MessageView::Error(err) = msg.view() else {
+        unreachable!()
+    };
+    assert!(err.error().matches(gst::CoreError::Negotiation), "{msg:?}");
+    assert!(err.error().message().contains("memory:NVMM"), "{msg:?}");
+
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 0);
+    assert_eq!(h.push(frame), Err(gst::FlowError::NotNegotiated));
+    h.element().unwrap().set_bus(None);
+}
+
+#[cfg(feature = "dmabuf")]
+#[test]
+fn test_dmabuf_output() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    let template = h.element().unwrap().pad_template("src").unwrap();
+    let template = template.caps();
+    assert!(
+        template
+            .iter_with_features()
+            .any(|(_, features)| features.contains("memory:DMABuf")),
+        "{template}"
+    );
+
+    // DMABuf output where there's a DMA heap to allocate it from, system memory
+    // otherwise.
+    let heap = ["linux,cma", "reserved", "system"]
+        .iter()
+        .any(|name| std::fs::File::open(format!("/dev/dma_heap/{name}")).is_ok());
+    h.set_sink_caps_str("video/x-raw(memory:DMABuf),format=RGB; video/x-raw,format=RGB");
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 16, 12));
+    let color = [200, 100, 50];
+    let output = push(
+        &mut h,
+        0,
+        bayer_frame(Pattern::Rggb, 16, 12, |cfa, _, _| channel(cfa, color)),
+    );
+
+    let caps = output_caps(&h);
+    let dmabuf = caps.features(0).unwrap().contains("memory:DMABuf");
+    assert_eq!(dmabuf, heap, "{caps}");
+    assert!(
+        !dmabuf
+            || output
+                .iter_memories()
+                .all(|memory| memory.is_memory_type::<gst_allocators::DmaBufMemory>()),
+        "{output:?}"
+    );
+    assert_interior(&rgb_pixels(&output, &caps), |_, _| color);
+}
+
+#[test]
+fn test_buffer_list() {
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [200, 100, 50]];
+
+    let mut h = harness(Pattern::Grbg, 16, 12, "RGB");
+    let lists = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counted = lists.clone();
+    h.element()
+        .unwrap()
+        .static_pad("src")
+        .unwrap()
+        .add_probe(gst::PadProbeType::BUFFER_LIST, move |_, _| {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            gst::PadProbeReturn::Ok
+        })
+        .unwrap();
+
+    let mut list = gst::BufferList::new_sized(COLORS.len());
+    for (n, color) in COLORS.into_iter().enumerate() {
+        let mut buffer = bayer_frame(Pattern::Grbg, 16, 12, |cfa, _, _| channel(cfa, color));
+        let buffer_ref = buffer.get_mut().unwrap();
+        buffer_ref.set_pts(FRAME_DURATION * n as u64);
+        buffer_ref.set_duration(FRAME_DURATION);
+        list.get_mut().unwrap().add(buffer);
+    }
+    assert_eq!(
+        h.srcpad().unwrap().push_list(list),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    // Converted in order, and handed on as a list again.
+    let caps = output_caps(&h);
+    for (n, color) in COLORS.into_iter().enumerate() {
+        let output = h.pull().unwrap();
+        assert_eq!(output.pts(), Some(FRAME_DURATION * n as u64));
+        assert_eq!(output.duration(), Some(FRAME_DURATION));
+        assert_interior(&rgb_pixels(&output, &caps), |_, _| color);
+    }
+    assert_eq!(h.buffers_in_queue(), 0);
+    assert_eq!(lists.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_property_writes_while_streaming() {
+    const WIDTH: usize = 640;
+    const HEIGHT: usize = 480;
+    // Far below the conversion time of a frame this size, which a write waiting for a
+    // conversion would take.
+    const MAX_STALL: std::time::Duration = std::time::Duration::from_millis(5);
+    const PROPERTIES: [&str; 6] = [
+        "red-gain",
+        "blue-gain",
+        "gamma",
+        "saturation",
+        "demosaic-algorithm",
+        "high-precision",
+    ];
+
+    let mut h = harness(Pattern::Rggb, WIDTH, HEIGHT, "RGB");
+    let element = h.element().unwrap();
+    let defaults = PROPERTIES.map(|name| element.property_value(name));
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let writer = std::thread::spawn({
+        let (element, done) = (element.clone(), done.clone());
+        move || {
+            let (mut writes, mut longest) = (0u32, std::time::Duration::ZERO);
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                let start = std::time::Instant::now();
+                let i = f64::from(writes % 8);
+                element.set_property("red-gain", 0.5 + i / 4.0);
+                element.set_property("blue-gain", 2.5 - i / 4.0);
+                element.set_property("gamma", 0.6 + i / 8.0);
+                element.set_property("saturation", i / 4.0);
+                element.set_property_from_str(
+                    "demosaic-algorithm",
+                    ["bilinear", "vng", "edge-aware"][writes as usize % 3],
+                );
+                element.set_property("high-precision", writes % 2 == 0);
+                longest = longest.max(start.elapsed());
+                writes += 1;
+            }
+            (writes, longest)
+        }
+    });
+
+    for n in 0..20 {
+        let frame = bayer_frame(Pattern::Rggb, WIDTH, HEIGHT, |_, x, y| (x ^ y) as u8);
+        let output = push(&mut h, n, frame);
+        assert_eq!(rgb_pixels(&output, &output_caps(&h)).len(), HEIGHT);
+    }
+    done.store(true, std::sync::atomic::Ordering::Relaxed);
+    let (writes, longest) = writer.join().unwrap();
+
+    let conversion_time = element.property::<f64>("avg-conversion-time");
+    assert!(writes > 0);
+    assert!(
+        longest < MAX_STALL,
+        "setting the properties took {longest:?}, converting a frame {conversion_time:.0} µs"
+    );
+
+    // The last values set apply to the next buffer as a whole.
+    for (name, value) in PROPERTIES.iter().zip(&defaults) {
+        element.set_property_from_value(name, value);
+    }
+    let output = push(
+        &mut h,
+        20,
+        bayer_frame(Pattern::Rggb, WIDTH, HEIGHT, |_, _, _| 128),
+    );
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [128; 3]);
+}
+
+#[test]
+fn test_controlled_gain() {
+    use gst_controller::prelude::*;
+
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let element = h.element().unwrap();
+
+    // Ramps the gain from 1 at the first frame to 3 at the fifth.
+    let source = gst_controller::InterpolationControlSource::new();
+    source.set_mode(gst_controller::InterpolationMode::Linear);
+    assert!(source.set(gst::ClockTime::ZERO, 1.0));
+    assert!(source.set(FRAME_DURATION * 4, 3.0));
+    let binding =
+        gst_controller::DirectControlBinding::new_absolute(&element, "exposure-gain", &source);
+    element.add_control_binding(&binding).unwrap();
+
+    for n in 0..5 {
+        let output = push(&mut h, n, bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 50));
+        let level = 50 + 25 * n as u8;
+        assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [level; 3]);
+    }
+    assert_eq!(element.property::<f64>("exposure-gain"), 3.0);
+}
+
+#[test]
+fn test_info_log_per_stream() {
+    init();
+
+    let element = gst::ElementFactory::make("rsbayer2rgb")
+        .name("info-log-per-stream")
+        .build()
+        .unwrap();
+    let messages = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    gst::log::set_active(true);
+    gst::DebugCategory::get("rsbayer2rgb")
+        .unwrap()
+        .set_threshold(gst::DebugLevel::Info);
+    let log_function = gst::log::add_log_function({
+        let messages = messages.clone();
+        move |category, level, _, _, _, object, message| {
+            let ours = object.is_some_and(|object| {
+                let name = object.to_string();
+                name == "info-log-per-stream" || name.starts_with("info-log-per-stream:")
+            });
+            if ours
+                && category.name() == "rsbayer2rgb"
+                && level > gst::DebugLevel::None
+                && level <= gst::DebugLevel::Info
+            {
+                let message = message.get().map(|message| message.to_string());
+                messages.lock().unwrap().push(message.unwrap_or_default());
+            }
+        }
+    });
+
+    let mut h = gst_check::Harness::with_element(&element, Some("sink"), Some("src"));
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 16, 12));
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |_, x, y| (x ^ y) as u8);
+    push(&mut h, 0, frame.copy());
+    let negotiated = messages.lock().unwrap().clone();
+    for n in 1..100 {
+        push(&mut h, n, frame.copy());
+    }
+    gst::log::remove_log_function(log_function);
+
+    // Negotiating logs the caps once, frames don't log at INFO.
+    assert!(!negotiated.is_empty());
+    assert_eq!(*messages.lock().unwrap(), negotiated);
+}
+
+#[test]
+fn test_property_reads_while_streaming() {
+    const WIDTH: usize = 1280;
+    const HEIGHT: usize = 960;
+    // Far below the conversion time of a frame this size, which a read waiting for a
+    // conversion would take.
+    const MAX_STALL: std::time::Duration = std::time::Duration::from_millis(5);
+
+    let mut h = harness_with(
+        Pattern::Rggb,
+        WIDTH,
+        HEIGHT,
+        "RGBA",
+        &[
+            ("demosaic-algorithm", "vng"),
+            ("enable-last-sample", "true"),
+        ],
+    );
+    let element = h.element().unwrap();
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = std::thread::spawn({
+        let (element, done) = (element.clone(), done.clone());
+        move || {
+            let (mut reads, mut longest) = (0, std::time::Duration::ZERO);
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                for name in [
+                    "frames-processed",
+                    "avg-conversion-time",
+                    "last-sample",
+                    "applied-red-gain",
+                    "detected-pattern",
+                    "isp-params",
+                    "gamma",
+                ] {
+                    let start = std::time::Instant::now();
+                    let _ = element.property_value(name);
+                    longest = longest.max(start.elapsed());
+                    reads += 1;
+                }
+            }
+            (reads, longest)
+        }
+    });
+
+    for n in 0..10 {
+        let frame = bayer_frame(Pattern::Rggb, WIDTH, HEIGHT, |_, x, y| (x ^ y) as u8);
+        push(&mut h, n, frame);
+    }
+    done.store(true, std::sync::atomic::Ordering::Relaxed);
+    let (reads, longest) = reader.join().unwrap();
+
+    let conversion_time = element.property::<f64>("avg-conversion-time");
+    assert!(reads > 0);
+    assert!(
+        longest < MAX_STALL,
+        "a property read took {longest:?}, converting a frame {conversion_time:.0} µs"
+    );
+}
+
+#[test]
+fn test_renegotiation() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let output = push(&mut h, 0, bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 128));
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [128; 3]);
+
+    // New size and pattern mid-stream, the output follows.
+    h.set_src_caps(bayer_caps(Pattern::Gbrg, 32, 8));
+    let color = [200, 100, 50];
+    let frame = bayer_frame(Pattern::Gbrg, 32, 8, |cfa, _, _| channel(cfa, color));
+    let output = push(&mut h, 1, frame);
+
+    let caps = output_caps(&h);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<i32>("width").unwrap(), 32);
+    assert_eq!(s.get::<i32>("height").unwrap(), 8);
+    assert_eq!(output.pts(), Some(FRAME_DURATION));
+    assert_interior(&rgb_pixels(&output, &caps), |_, _| color);
+}
+
+#[test]
+fn test_video_direction() {
+    let mut h = harness(Pattern::Rggb, 64, 8, "RGB");
+    let element = h.element().unwrap();
+
+    element.set_property_from_str("video-direction", "horiz");
+    let frame = bayer_frame(Pattern::Rggb, 64, 8, |_, x, _| (x * 4) as u8);
+    let output = push(&mut h, 0, frame);
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |x, _| {
+        [((63 - x) * 4) as u8; 3]
+    });
+
+    // Rotations swap width and height and renegotiate.
+    element.set_property_from_str("video-direction", "90r");
+    let frame = bayer_frame(Pattern::Rggb, 64, 8, |_, x, _| (x * 4) as u8);
+    let output = push(&mut h, 1, frame);
+
+    let caps = output_caps(&h);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<i32>("width").unwrap(), 8);
+    assert_eq!(s.get::<i32>("height").unwrap(), 64);
+    assert_interior(&rgb_pixels(&output, &caps), |_, y| [(y * 4) as u8; 3]);
+}
+
+// Tag event with an `image-orientation` and a title, which is passed on.
+fn orientation_tag(orientation: &str) -> gst::Event {
+    let mut tags = gst::TagList::new();
+    {
+        let tags = tags.get_mut().unwrap();
+        tags.add::<gst::tags::ImageOrientation>(&orientation, gst::TagMergeMode::Replace);
+        tags.add::<gst::tags::Title>(&"camera", gst::TagMergeMode::Replace);
+    }
+    gst::event::Tag::new(tags)
+}
+
+#[test]
+fn test_auto_orient() {
+    let mut h = harness(Pattern::Rggb, 64, 8, "RGB");
+    let element = h.element().unwrap();
+    element.set_property("auto-orient", true);
+    let gradient = || bayer_frame(Pattern::Rggb, 64, 8, |_, x, _| (x * 4) as u8);
+
+    assert!(h.push_event(orientation_tag("rotate-180")));
+    let output = push(&mut h, 0, gradient());
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |x, _| {
+        [((63 - x) * 4) as u8; 3]
+    });
+
+    // Rotations by 90 degrees renegotiate.
+    assert!(h.push_event(orientation_tag("rotate-90")));
+    let output = push(&mut h, 1, gradient());
+    let caps = output_caps(&h);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<i32>("width").unwrap(), 8);
+    assert_eq!(s.get::<i32>("height").unwrap(), 64);
+    assert_interior(&rgb_pixels(&output, &caps), |_, y| [(y * 4) as u8; 3]);
+
+    // The tags reach downstream without the orientation.
+    let mut tag_events = 0;
+    while let Some(event) = h.try_pull_event() {
+        if let gst::EventView::Tag(tag) = event.view() {
+            let tags = tag.tag();
+            assert!(tags.get::<gst::tags::ImageOrientation>().is_none());
+            assert_eq!(tags.get::<gst::tags::Title>().unwrap().get(), "camera");
+            tag_events += 1;
+        }
+    }
+    assert_eq!(tag_events, 2);
+
+    // A video direction takes precedence.
+    element.set_property_from_str("video-direction", "horiz");
+    let output = push(&mut h, 2, gradient());
+    let caps = output_caps(&h);
+    assert_eq!(caps.structure(0).unwrap().get::<i32>("width").unwrap(), 64);
+    assert_interior(&rgb_pixels(&output, &caps), |x, _| {
+        [((63 - x) * 4) as u8; 3]
+    });
+
+    // Without auto-orient the tag is left alone.
+    element.set_property_from_str("video-direction", "identity");
+    element.set_property("auto-orient", false);
+    assert!(h.push_event(orientation_tag("rotate-180")));
+    let output = push(&mut h, 3, gradient());
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |x, _| {
+        [(x * 4) as u8; 3]
+    });
+    let tag = std::iter::from_fn(|| h.try_pull_event())
+        .find(|event| event.type_() == gst::EventType::Tag)
+        .unwrap();
+    let gst::EventView::Tag(tag) = tag.view() else {
+        unreachable!()
+    };
+    assert_eq!(
+        tag.tag()
+            .get::<gst::tags::ImageOrientation>()
+            .unwrap()
+            .get(),
+        "rotate-180"
+    );
+}
+
+fn push_with_roi(
+    h: &mut gst_check::Harness,
+    width: usize,
+    height: usize,
+    rect: (u32, u32, u32, u32),
+) -> gst::Buffer {
+    let mut frame = bayer_frame(Pattern::Rggb, width, height, |_, _, _| 0);
+    {
+        let frame = frame.get_mut().unwrap();
+        let mut roi = gst_video::VideoRegionOfInterestMeta::add(frame, "face", rect);
+        roi.set_id(7);
+        roi.add_param(
+            gst::Structure::builder("detection")
+                .field("confidence", 0.9)
+                .build(),
+        );
+    }
+    push(h, 0, frame)
+}
+
+#[test]
+fn test_roi_meta() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let output = push_with_roi(&mut h, 16, 12, (4, 2, 6, 8));
+
+    let roi = output
+        .meta::<gst_video::VideoRegionOfInterestMeta>()
+        .expect("ROI meta is copied");
+    assert_eq!(roi.roi_type(), "face");
+    assert_eq!(roi.rect(), (4, 2, 6, 8));
+    assert_eq!(roi.id(), 7);
+    let params = roi.param("detection").unwrap();
+    assert_eq!(params.get::<f64>("confidence").unwrap(), 0.9);
+}
+
+#[test]
+fn test_roi_meta_geometry() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    h.element()
+        .unwrap()
+        .set_property_from_str("method", "superpixel");
+
+    // Half the size, rounded outwards.
+    let output = push_with_roi(&mut h, 16, 12, (4, 2, 5, 8));
+    let roi = output
+        .meta::<gst_video::VideoRegionOfInterestMeta>()
+        .unwrap();
+    assert_eq!(roi.rect(), (2, 1, 3, 4));
+
+    // Nothing of it is left after cropping.
+    h.element().unwrap().set_property("crop-left", 10u32);
+    let output = push_with_roi(&mut h, 16, 12, (4, 2, 5, 8));
+    assert!(
+        output
+            .meta::<gst_video::VideoRegionOfInterestMeta>()
+            .is_none()
+    );
+}
+
+#[test]
+fn test_extra_meta_apis() {
+    init();
+
+    let convert = gst::ElementFactory::make("rsbayer2rgb")
+        .property("extra-meta-apis", gst::Array::new(["GstVideoCropMetaAPI"]))
+        .build()
+        .unwrap();
+    let apis = convert.property::<gst::Array>("extra-meta-apis");
+    assert_eq!(apis.len(), 1);
+    assert_eq!(apis[0].get::<&str>().unwrap(), "GstVideoCropMetaAPI");
+}
+
+#[test]
+fn test_timing_metas() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let reference = gst::Caps::new_empty_simple("timestamp/x-ptp");
+    let timecode = gst_video::ValidVideoTimeCode::new(
+        gst::Fraction::new(30, 1),
+        None,
+        gst_video::VideoTimeCodeFlags::empty(),
+        1,
+        2,
+        3,
+        4,
+        0,
+    )
+    .unwrap();
+
+    let mut frame = bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 0);
+    {
+        let frame = frame.get_mut().unwrap();
+        frame.set_offset(42);
+        frame.set_offset_end(43);
+        frame.set_flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER);
+        gst::ReferenceTimestampMeta::add(
+            frame,
+            &reference,
+            gst::ClockTime::from_seconds(1_700_000_000),
+            FRAME_DURATION,
+        );
+        gst_video::VideoTimeCodeMeta::add(frame, &timecode);
+    }
+    let output = push(&mut h, 0, frame);
+
+    assert_eq!(output.offset(), 42);
+    assert_eq!(output.offset_end(), 43);
+    assert!(
+        output
+            .flags()
+            .contains(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+    );
+
+    let meta = output
+        .meta::<gst::ReferenceTimestampMeta>()
+        .expect("reference timestamp is copied");
+    assert_eq!(meta.reference(), reference.as_ref());
+    assert_eq!(
+        meta.timestamp(),
+        gst::ClockTime::from_seconds(1_700_000_000)
+    );
+    assert_eq!(meta.duration(), Some(FRAME_DURATION));
+
+    let meta = output
+        .meta::<gst_video::VideoTimeCodeMeta>()
+        .expect("timecode is copied");
+    assert_eq!(meta.tc().to_string(), "01:02:03:04");
+}
+
+#[cfg(feature = "dump")]
+#[test]
+fn test_dump() {
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-dump-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGBA");
+    let element = h.element().unwrap();
+    element.set_property("dump-location", dir.join("{name}").to_str().unwrap());
+    element.set_property("dump-interval", 2u32);
+    element.set_property("dump-raw", true);
+    let name = element.name();
+
+    for n in 0..3 {
+        push(&mut h, n, bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 128));
+    }
+    // Stopping the element writes out what is still queued.
+    drop(h);
+
+    let dir = dir.join(name.as_str());
+    let mut files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "frame-000000-0-raw.pgm",
+            "frame-000000-0.png",
+            "frame-000002-66666666-raw.pgm",
+            "frame-000002-66666666.png",
+        ]
+    );
+
+    // Width and height from the PNG IHDR chunk.
+    let png = std::fs::read(dir.join("frame-000002-66666666.png")).unwrap();
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 16);
+    assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 12);
+
+    let pgm = std::fs::read(dir.join("frame-000000-0-raw.pgm")).unwrap();
+    let header = String::from_utf8_lossy(&pgm[..64]);
+    let fields = header
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_ascii_whitespace)
+        .take(4)
+        .collect::<Vec<_>>();
+    assert_eq!(fields, ["P5", "16", "12", "65535"]);
+
+    std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_timing_meta() {
+    let mut h = harness(Pattern::Rggb, 64, 48, "RGB");
+    let output = push(&mut h, 0, bayer_frame(Pattern::Rggb, 64, 48, |_, _, _| 0));
+    assert!(output.meta::<gstrsbayer::RsBayerTimingMeta>().is_none());
+
+    let element = h.element().unwrap();
+    element.set_property("attach-timing-meta", true);
+    element.set_property_from_str("demosaic-algorithm", "edge-aware");
+
+    let before = gst::get_timestamp();
+    let output = push(&mut h, 1, bayer_frame(Pattern::Rggb, 64, 48, |_, _, _| 0));
+    let after = gst::get_timestamp();
+
+    let meta = output
+        .meta::<gstrsbayer::RsBayerTimingMeta>()
+        .expect("timing meta is attached");
+    assert!(before <= meta.start(), "{meta:?}");
+    assert!(meta.start() < meta.end(), "{meta:?}");
+    assert!(meta.end() <= after, "{meta:?}");
+    assert!(
+        meta.duration() < gst::ClockTime::from_seconds(1),
+        "{meta:?}"
+    );
+    assert_eq!(meta.method(), gstrsbayer::convert::Method::Full);
+    assert_eq!(
+        meta.algorithm(),
+        gstrsbayer::convert::DemosaicAlgorithm::EdgeAware
+    );
+
+    // Copying the buffer keeps it.
+    let copy = output.copy();
+    let copied = copy.meta::<gstrsbayer::RsBayerTimingMeta>().unwrap();
+    assert_eq!(copied.start(), meta.start());
+    assert_eq!(copied.end(), meta.end());
+}
+
+// Two fields of a uniform scene interleaved line by line, the bottom field one row
+// out of CFA phase.
+fn interlaced_frame(pattern: Pattern, width: usize, height: usize, color: [u8; 3]) -> gst::Buffer {
+    let fields = [pattern, pattern.offset(0, 1)]
+        .map(|pattern| bayer_samples(pattern, width, height / 2, |cfa, _, _| channel(cfa, color)));
+    let samples = (0..height)
+        .flat_map(|y| {
+            let row = y / 2 * width;
+            fields[y % 2][row..row + width].iter().copied()
+        })
+        .collect::<Vec<_>>();
+
+    let mut buffer = gst::Buffer::from_mut_slice(samples);
+    buffer
+        .get_mut()
+        .unwrap()
+        .set_flags(gst::BufferFlags::from_bits_retain(
+            (gst_video::VideoBufferFlags::INTERLACED | gst_video::VideoBufferFlags::TFF).bits(),
+        ));
+    buffer
+}
+
+#[test]
+fn test_interlaced() {
+    let color = [200, 100, 50];
+
+    for mode in ["weave", "separate"] {
+        let mut h = harness(Pattern::Gbrg, 32, 16, "RGB");
+        h.element()
+            .unwrap()
+            .set_property_from_str("field-mode", mode);
+        let mut caps = bayer_caps(Pattern::Gbrg, 32, 16);
+        caps.make_mut().set("interlace-mode", "interleaved");
+        caps.make_mut().set("field-order", "top-field-first");
+        h.set_src_caps(caps);
+
+        let output = push(&mut h, 0, interlaced_frame(Pattern::Gbrg, 32, 16, color));
+        let caps = output_caps(&h);
+        assert_eq!(
+            caps.structure(0)
+                .unwrap()
+                .get::<&str>("interlace-mode")
+                .unwrap(),
+            "progressive"
+        );
+        let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+        assert_eq!(
+            info.interlace_mode(),
+            gst_video::VideoInterlaceMode::Progressive
+        );
+        assert!(
+            !gst_video::VideoBufferFlags::from_bits_truncate(output.flags().bits()).intersects(
+                gst_video::VideoBufferFlags::INTERLACED | gst_video::VideoBufferFlags::TFF
+            )
+        );
+
+        // Fields converted on their own have borders of their own, two rows into
+        // the frame.
+        let pixels = rgb_pixels(&output, &caps);
+        assert_interior(&pixels[2..pixels.len() - 2], |_, _| color);
+    }
+
+    // Taken for a progressive frame, rows pair samples of the wrong colors.
+    let mut h = harness(Pattern::Gbrg, 32, 16, "RGB");
+    let output = push(&mut h, 0, interlaced_frame(Pattern::Gbrg, 32, 16, color));
+    let pixels = rgb_pixels(&output, &output_caps(&h));
+    assert!(pixels[4..12].iter().flatten().any(|&pixel| pixel != color));
+}
+
+#[test]
+fn test_interlace_modes() {
+    init();
+
+    for (mode, accepted) in [
+        ("progressive", true),
+        ("interleaved", true),
+        ("mixed", false),
+        ("alternate", false),
+    ] {
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        h.set_sink_caps_str("video/x-raw,format=RGB");
+        h.push_event(gst::event::StreamStart::new("test"));
+        let mut caps = bayer_caps(Pattern::Rggb, 16, 12);
+        caps.make_mut().set("interlace-mode", mode);
+        assert_eq!(
+            h.push_event(gst::event::Caps::new(&caps)),
+            accepted,
+            "{mode}"
+        );
+    }
+}
+
+#[test]
+fn test_phase() {
+    let convert = |caps_pattern: Pattern, phase: (u32, u32), frame: &gst::Buffer| {
+        let (x_phase, y_phase) = (phase.0.to_string(), phase.1.to_string());
+        let mut h = harness_with(
+            caps_pattern,
+            32,
+            16,
+            "RGB",
+            &[("x-phase", &x_phase), ("y-phase", &y_phase)],
+        );
+        let output = push(&mut h, 0, frame.copy());
+        output.map_readable().unwrap().to_vec()
+    };
+
+    for (phase, pattern) in [
+        ((1, 0), Pattern::Grbg),
+        ((0, 1), Pattern::Gbrg),
+        ((1, 1), Pattern::Bggr),
+    ] {
+        let frame = bayer_frame(pattern, 32, 16, |cfa, x, y| {
+            channel(cfa, [(x * 8) as u8, (y * 16) as u8, 100])
+        });
+        assert_eq!(
+            convert(Pattern::Rggb, phase, &frame),
+            convert(pattern, (0, 0), &frame),
+            "{phase:?}"
+        );
+    }
+}
+
+#[test]
+fn test_pattern_detection() {
+    let color = [180, 110, 60];
+    let bus = gst::Bus::new();
+
+    for pattern in Pattern::ALL {
+        // The caps claim rggb whatever the sensor has.
+        let mut h = harness_with(Pattern::Rggb, 32, 16, "RGB", &[("pattern", "auto-detect")]);
+        let element = h.element().unwrap();
+        element.set_bus(Some(&bus));
+
+        // Warm scene with some texture, so the greens differ a little too.
+        let frame = bayer_frame(pattern, 32, 16, |cfa, x, y| {
+            channel(cfa, color).wrapping_add(((x + y) % 3) as u8)
+        });
+        for n in 0..5 {
+            push(&mut h, n, frame.copy());
+            let detected = element.property::<Option<String>>("detected-pattern");
+            match n {
+                0 | 1 => assert_eq!(detected, None, "{pattern}"),
+                _ => assert_eq!(detected.as_deref(), Some(pattern.to_caps_format())),
+            }
+        }
+
+        let msg = bus
+            .pop_filtered(&[gst::MessageType::Element])
+            .expect("detection posts a message");
+        let s = msg.structure().unwrap();
+        assert_eq!(s.name(), "bayer-pattern-detected");
+        assert_eq!(s.get::<&str>("pattern").unwrap(), pattern.to_caps_format());
+        assert_eq!(s.get::<u32>("frames").unwrap(), 3);
+        assert!(s.get::<f64>("confidence").unwrap() > 0.5);
+        assert!(bus.pop_filtered(&[gst::MessageType::Element]).is_none());
+
+        // Converted with the detected pattern from the frame that locked it in on.
+        let output = push(&mut h, 5, frame.copy());
+        let pixels = rgb_pixels(&output, &output_caps(&h));
+        for row in &pixels[2..pixels.len() - 2] {
+            for pixel in &row[2..row.len() - 2] {
+                for (value, expected) in pixel.iter().zip(color) {
+                    assert!(value.abs_diff(expected) <= 3, "{pattern}: {pixel:?}");
+                }
+            }
+        }
+        element.set_bus(None);
+    }
+}
+
+#[test]
+fn test_skip_margins() {
+    let color = [200, 100, 50];
+    let bus = gst::Bus::new();
+
+    // Top, bottom, left and right, even and odd.
+    for [top, bottom, left, right] in [[2, 0, 0, 0], [2, 2, 2, 2], [1, 0, 3, 0], [1, 3, 1, 1]] {
+        let (width, height) = (32 + left + right, 16 + top + bottom);
+        let skip = [top, bottom, left, right].map(|skip| skip.to_string());
+        let mut h = harness_with(
+            Pattern::Grbg,
+            width,
+            height,
+            "RGB",
+            &[
+                ("skip-lines-top", &skip[0]),
+                ("skip-lines-bottom", &skip[1]),
+                ("skip-cols-left", &skip[2]),
+                ("skip-cols-right", &skip[3]),
+                ("post-embedded-data", "true"),
+            ],
+        );
+        h.element().unwrap().set_bus(Some(&bus));
+
+        // Garbage in the margins, the pattern of the caps in the image.
+        let margin =
+            |x: usize, y: usize| x < left || x >= width - right || y < top || y >= height - bottom;
+        let frame = bayer_frame(Pattern::Grbg, width, height, |cfa, x, y| {
+            match margin(x, y) {
+                true => (x * 37 + y * 101) as u8,
+                false => channel(cfa, color),
+            }
+        });
+        let embedded = frame.map_readable().unwrap()[..top * width].to_vec();
+        let output = push(&mut h, 0, frame);
+
+        let caps = output_caps(&h);
+        let s = caps.structure(0).unwrap();
+        assert_eq!(
+            s.get::<i32>("width").unwrap(),
+            32,
+            "{top} {bottom} {left} {right}"
+        );
+        assert_eq!(
+            s.get::<i32>("height").unwrap(),
+            16,
+            "{top} {bottom} {left} {right}"
+        );
+        assert_interior(&rgb_pixels(&output, &caps), |_, _| color);
+
+        let msg = bus
+            .pop_filtered(&[gst::MessageType::Element])
+            .expect("embedded data is posted");
+        let s = msg.structure().unwrap();
+        assert_eq!(s.name(), "embedded-data");
+        assert_eq!(s.get::<u32>("width").unwrap(), width as u32);
+        assert_eq!(s.get::<u32>("lines").unwrap(), top as u32);
+        let data = s.get::<gst::Buffer>("data").unwrap();
+        assert_eq!(data.map_readable().unwrap().as_slice(), embedded);
+        h.element().unwrap().set_bus(None);
+    }
+}
+
+#[test]
+fn test_skip_margins_caps() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    let element = h.element().unwrap();
+    element.set_property("skip-lines-top", 2u32);
+    element.set_property("skip-lines-bottom", 1u32);
+    element.set_property("skip-cols-left", 3u32);
+
+    // Upstream is asked for the size downstream wants plus the margins.
+    h.set_sink_caps_str("video/x-raw,format=RGB,width=32,height=16");
+    let caps = h.srcpad().unwrap().peer_query_caps(None);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.name(), "video/x-bayer");
+    assert_eq!(s.get::<i32>("width").unwrap(), 35);
+    assert_eq!(s.get::<i32>("height").unwrap(), 19);
+}
+
+// Quad bayer frame of `pattern`, every color covering 2x2 blocks, sites taking
+// `value(color, x, y)`.
+fn quad_samples(
+    pattern: Pattern,
+    width: usize,
+    height: usize,
+    value: impl Fn(CfaColor, usize, usize) -> u8,
+) -> Vec<u8> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| value(pattern.color_at(x / 2, y / 2), x, y))
+        .collect()
+}
+
+#[test]
+fn test_quad_bayer() {
+    let convert = |properties: &[(&str, &str)], pattern, width, height, samples: Vec<u8>| {
+        let mut h = harness_with(pattern, width, height, "RGB", properties);
+        let output = push(&mut h, 0, gst::Buffer::from_mut_slice(samples));
+        let caps = output_caps(&h);
+        let s = caps.structure(0).unwrap();
+        let size = (
+            s.get::<i32>("width").unwrap() as usize,
+            s.get::<i32>("height").unwrap() as usize,
+        );
+        (size, rgb_pixels(&output, &caps))
+    };
+    let binning = [("cfa-layout", "quad"), ("quad-mode", "binning")];
+    let remosaic = [("cfa-layout", "quad"), ("quad-mode", "remosaic")];
+
+    for pattern in Pattern::ALL {
+        // A flat color comes out as it is either way.
+        let color = [200, 100, 50];
+        let flat = quad_samples(pattern, 64, 32, |cfa, _, _| channel(cfa, color));
+        let (size, pixels) = convert(&binning, pattern, 64, 32, flat.clone());
+        assert_eq!(size, (32, 16));
+        assert_interior(&pixels, |_, _| color);
+        let (size, pixels) = convert(&remosaic, pattern, 64, 32, flat);
+        assert_eq!(size, (64, 32));
+        assert_interior(&pixels, |_, _| color);
+
+        // Binning averages every block: blocks varying around a gradient convert
+        // like a bayer frame of the gradient at half the size.
+        let gradient =
+            |cfa, x: usize, y: usize| channel(cfa, [(x * 6 + 10) as u8, (y * 12 + 10) as u8, 80]);
+        let quad = quad_samples(pattern, 64, 32, |cfa, x, y| {
+            let noise = [3, -3, -1, 1][(y % 2) * 2 + x % 2];
+            gradient(cfa, x / 2, y / 2).saturating_add_signed(noise)
+        });
+        let reference = bayer_samples(pattern, 32, 16, gradient);
+        assert_eq!(
+            convert(&binning, pattern, 64, 32, quad),
+            convert(&[], pattern, 32, 16, reference),
+            "{pattern}"
+        );
+
+        // Remosaic moves every sample to the nearest site of its color in the
+        // bayer layout.
+        let quad = quad_samples(pattern, 64, 32, |cfa, x, y| {
+            channel(cfa, [(x * 3) as u8, (y * 6) as u8, (x + y) as u8])
+        });
+        let tile = [0, 2, 1, 3];
+        let reference = (0..32)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .map(|(x, y): (usize, usize)| {
+                quad[(y - y % 4 + tile[y % 4]) * 64 + x - x % 4 + tile[x % 4]]
+            })
+            .collect();
+        assert_eq!(
+            convert(&remosaic, pattern, 64, 32, quad.clone()),
+            convert(&[], pattern, 64, 32, reference),
+            "{pattern}"
+        );
+    }
+}
+
+#[test]
+fn test_quad_bayer_caps() {
+    init();
+
+    // Binning asks upstream for twice the size, an odd column or row to spare, both
+    // add the skipped margins.
+    for (mode, width, height) in [
+        ("binning", (34, 35), (18, 19)),
+        ("remosaic", (18, 18), (10, 10)),
+    ] {
+        let mut h = gst_check::Harness::new("rsbayer2rgb");
+        let element = h.element().unwrap();
+        element.set_property_from_str("cfa-layout", "quad");
+        element.set_property_from_str("quad-mode", mode);
+        element.set_property("skip-lines-top", 2u32);
+        element.set_property("skip-cols-left", 2u32);
+
+        h.set_sink_caps_str("video/x-raw,format=RGB,width=16,height=8");
+        let caps = h.srcpad().unwrap().peer_query_caps(None);
+        let s = caps.structure(0).unwrap();
+        let sizes = |field| match s.get::<gst::IntRange<i32>>(field) {
+            Ok(range) => (range.min(), range.max()),
+            Err(_) => {
+                let size = s.get::<i32>(field).unwrap();
+                (size, size)
+            }
+        };
+        assert_eq!(sizes("width"), width, "{mode}");
+        assert_eq!(sizes("height"), height, "{mode}");
+    }
+
+    // Margins splitting the blocks of a color are refused.
+    let mut h = gst_check::Harness::new("rsbayer2rgb");
+    let element = h.element().unwrap();
+    element.set_property_from_str("cfa-layout", "quad");
+    element.set_property("skip-cols-left", 1u32);
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.push_event(gst::event::StreamStart::new("test"));
+    assert!(!h.push_event(gst::event::Caps::new(&bayer_caps(Pattern::Rggb, 33, 16))));
+}
+
+// Levels between the darkest and the brightest green of `pixels` no pixel has.
+fn empty_green_bins(pixels: &[Vec<[u8; 3]>]) -> usize {
+    let mut histogram = [0u32; 256];
+    for [_, green, _] in pixels.iter().flatten() {
+        histogram[*green as usize] += 1;
+    }
+
+    let first = histogram.iter().position(|&count| count > 0).unwrap();
+    let last = histogram.iter().rposition(|&count| count > 0).unwrap();
+    histogram[first..=last]
+        .iter()
+        .filter(|&&count| count == 0)
+        .count()
+}
+
+#[test]
+fn test_exposure_compensation() {
+    // Exposure time in microseconds, analog gain and the sample value a gray scene
+    // captured with them reads as, all 80 at the reference exposure of 10 ms.
+    const EXPOSURES: [(u64, f64, u8); 5] = [
+        (10_000, 1.0, 80),
+        (5_000, 1.0, 40),
+        (20_000, 1.0, 160),
+        (2_500, 2.0, 40),
+        (5_000, 2.0, 80),
+    ];
+
+    let mut h = harness_with(
+        Pattern::Bggr,
+        16,
+        12,
+        "RGB",
+        &[
+            ("exposure-compensation", "true"),
+            ("reference-exposure", "10000000"),
+        ],
+    );
+    let exposed = |exposure: Option<(u64, f64)>, value| {
+        let mut frame = bayer_frame(Pattern::Bggr, 16, 12, |_, _, _| value);
+        if let Some((exposure_time, analog_gain)) = exposure {
+            RsBayerExposureMeta::add(
+                frame.get_mut().unwrap(),
+                gst::ClockTime::from_useconds(exposure_time),
+                analog_gain,
+            );
+        }
+        frame
+    };
+
+    for (n, (exposure_time, analog_gain, value)) in EXPOSURES.into_iter().enumerate() {
+        let frame = exposed(Some((exposure_time, analog_gain)), value);
+        let output = push(&mut h, n as u64, frame);
+        assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [80; 3]);
+    }
+
+    // Frames without the meta, or with compensation off, are left as they are.
+    let output = push(&mut h, 5, exposed(None, 40));
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [40; 3]);
+    h.element()
+        .unwrap()
+        .set_property("exposure-compensation", false);
+    let output = push(&mut h, 6, exposed(Some((5_000, 1.0)), 40));
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [40; 3]);
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_debug_overlay() {
+    const GRAY: [u8; 3] = [128; 3];
+    const WIDTH: usize = 160;
+    const HEIGHT: usize = 96;
+
+    let frame = || bayer_frame(Pattern::Rggb, WIDTH, HEIGHT, |cfa, _, _| channel(cfa, GRAY));
+    for format in OUTPUT_FORMATS {
+        let mut h = harness(Pattern::Rggb, WIDTH, HEIGHT, format);
+        let output = push(&mut h, 0, frame());
+        assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| GRAY);
+
+        let element = h.element().unwrap();
+        element.set_property("show-debug-overlay", true);
+        for (n, corner, (x, y)) in [
+            (1, "top-left", (5, 5)),
+            (2, "bottom-right", (WIDTH - 6, HEIGHT - 6)),
+        ] {
+            element.set_property_from_str("overlay-corner", corner);
+            let output = push(&mut h, n, frame());
+            let pixels = rgb_pixels(&output, &output_caps(&h));
+
+            // The box is black, the text on it white, the rest of the frame untouched.
+            assert_eq!(pixels[y][x], [0; 3], "{format} {corner}");
+            assert!(
+                pixels.iter().flatten().any(|&pixel| pixel == [255; 3]),
+                "{format} {corner}: no text"
+            );
+            let (far_x, far_y) = (WIDTH - 1 - x, HEIGHT - 1 - y);
+            assert_eq!(pixels[far_y][far_x], GRAY, "{format} {corner}");
+        }
+    }
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_output_scaling() {
+    let mut h = harness_with(
+        Pattern::Rggb,
+        4000,
+        3000,
+        "RGB",
+        &[("output-width", "1920"), ("output-height", "1080")],
+    );
+    let frame = bayer_frame(Pattern::Rggb, 4000, 3000, |_, x, _| (x / 16) as u8);
+    let output = push(&mut h, 0, frame);
+
+    let caps = output_caps(&h);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<i32>("width").unwrap(), 1920);
+    assert_eq!(s.get::<i32>("height").unwrap(), 1080);
+
+    // The gradient is stretched across the whole output, no letterboxing.
+    assert_interior(&rgb_pixels(&output, &caps), |x, _| {
+        let input_x = (x as f64 + 0.5) * 4000.0 / 1920.0 - 0.5;
+        [(input_x / 16.0) as u8; 3]
+    });
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_threads_bit_identical() {
+    const WIDTH: usize = 48;
+    // Room for 3 strips, and fewer rows than the most threads.
+    const HEIGHT: usize = 200;
+
+    for pattern in Pattern::ALL {
+        let frame = || {
+            bayer_frame(pattern, WIDTH, HEIGHT, |_, x, y| {
+                ((x * 7919 + y * 104_729).wrapping_mul(2_654_435_761) >> 16) as u8
+            })
+        };
+        for format in OUTPUT_FORMATS {
+            let convert = |n_threads: u32| {
+                let mut h = harness_with(
+                    pattern,
+                    WIDTH,
+                    HEIGHT,
+                    format,
+                    &[("n-threads", &n_threads.to_string())],
+                );
+                let output = push(&mut h, 0, frame());
+                rgb_pixels(&output, &output_caps(&h))
+            };
+
+            let single = convert(1);
+            for n_threads in [2, 3, 4, 7, 256] {
+                assert!(
+                    convert(n_threads) == single,
+                    "{pattern:?} {format}: {n_threads} threads differ from 1"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_high_precision() {
+    // Without gains or tone mapping the output is the same as at 8 bits.
+    let mut h = harness_with(Pattern::Rggb, 64, 8, "RGB", &[("high-precision", "true")]);
+    let frame = bayer_frame(Pattern::Rggb, 64, 8, |_, x, _| (x * 4) as u8);
+    let output = push(&mut h, 0, frame);
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |x, _| {
+        [(x * 4) as u8; 3]
+    });
+
+    // A dark gradient brought up by the gain and gamma leaves gaps between the
+    // levels it reaches, fewer of them in high precision with dither.
+    let frame = bayer_frame(Pattern::Rggb, 256, 16, |_, x, _| (x / 4) as u8);
+    let empty_bins = |properties: &[(&str, &str)]| {
+        let tone = [("exposure-gain", "4.0"), ("gamma", "2.2")];
+        let properties = [&tone[..], properties].concat();
+        let mut h = harness_with(Pattern::Rggb, 256, 16, "RGB", &properties);
+        let output = push(&mut h, 0, frame.copy());
+        empty_green_bins(&rgb_pixels(&output, &output_caps(&h)))
+    };
+
+    let low = empty_bins(&[]);
+    let high = empty_bins(&[("high-precision", "true"), ("dither", "true")]);
+    assert!(
+        high < low,
+        "{high} empty bins in high precision, {low} at 8 bits"
+    );
+}
+
+#[test]
+fn test_tone_mapping() {
+    const WIDTH: usize = 128;
+
+    // Green of every pixel along the middle row of an exponential gradient, eight
+    // stops from black to white.
+    let greens = |properties: &[(&str, &str)]| {
+        let properties = [&[("high-precision", "true")][..], properties].concat();
+        let mut h = harness_with(Pattern::Rggb, WIDTH, 8, "RGB", &properties);
+        let frame = bayer_frame(Pattern::Rggb, WIDTH, 8, |_, x, _| {
+            ((x as f64 / (WIDTH - 1) as f64 * 8.0).exp2() - 1.0).round() as u8
+        });
+        let output = push(&mut h, 0, frame);
+        let pixels = rgb_pixels(&output, &output_caps(&h));
+        pixels[4]
+            .iter()
+            .map(|&[_, green, _]| green)
+            .collect::<Vec<_>>()
+    };
+
+    let mut dark = Vec::new();
+    for mapping in ["shift", "linear", "gamma", "log", "percentile-auto"] {
+        let greens = greens(&[("tone-mapping", mapping)]);
+        assert!(
+            greens.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{mapping} isn't monotonic: {greens:?}"
+        );
+        // Where the gradient is at about 1% of full scale.
+        dark.push(greens[WIDTH / 4]);
+    }
+    let [shift, linear, gamma, log, _] = dark[..] else {
+        unreachable!()
+    };
+    assert!(shift <= linear && linear < gamma && gamma < log, "{dark:?}");
+
+    // The automatic points stretch a gradient over a fraction of the range to all of
+    // it, unless a point is set explicitly.
+    let narrow = |properties: &[(&str, &str)]| {
+        let properties = [&[("high-precision", "true")][..], properties].concat();
+        let mut h = harness_with(Pattern::Rggb, WIDTH, 8, "RGB", &properties);
+        let frame = bayer_frame(Pattern::Rggb, WIDTH, 8, |_, x, _| (64 + x / 2) as u8);
+        let output = push(&mut h, 0, frame);
+        let pixels = rgb_pixels(&output, &output_caps(&h));
+        let greens = pixels.iter().flatten().map(|&[_, green, _]| green);
+        (greens.clone().min().unwrap(), greens.max().unwrap())
+    };
+
+    let (min, max) = narrow(&[("tone-mapping", "percentile-auto")]);
+    assert!(min <= 5 && max >= 250, "auto range {min}..{max}");
+    let (min, max) = narrow(&[("tone-mapping", "percentile-auto"), ("white-point", "1.0")]);
+    assert!(min <= 5 && max >= 250, "auto range {min}..{max}");
+    let (min, max) = narrow(&[("tone-mapping", "percentile-auto"), ("white-point", "0.8")]);
+    assert!(
+        min <= 5 && max < 200,
+        "range {min}..{max} with an explicit white point"
+    );
+}
+
+#[test]
+fn test_stack_frames() {
+    const STACK: u64 = 4;
+
+    // Gray frame with noise from a xorshift generator, different for every `seed`.
+    let noisy_frame = |seed: u64| {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let samples = (0..32 * 16)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (100 + (state % 41) as i64 - 20) as u8
+            })
+            .collect::<Vec<_>>();
+        gst::Buffer::from_mut_slice(samples)
+    };
+    let timed = |n: u64, mut buffer: gst::Buffer| {
+        let buffer_mut = buffer.get_mut().unwrap();
+        buffer_mut.set_pts(FRAME_DURATION * n);
+        buffer_mut.set_duration(FRAME_DURATION);
+        buffer
+    };
+    let std_dev = |output: &gst::Buffer, caps: &gst::Caps| {
+        let pixels = rgb_pixels(output, caps);
+        let greens = pixels[BORDER..pixels.len() - BORDER]
+            .iter()
+            .flat_map(|row| &row[BORDER..row.len() - BORDER])
+            .map(|&[_, green, _]| green as f64)
+            .collect::<Vec<_>>();
+        let mean = greens.iter().sum::<f64>() / greens.len() as f64;
+        let variance = greens
+            .iter()
+            .map(|green| (green - mean).powi(2))
+            .sum::<f64>()
+            / greens.len() as f64;
+        variance.sqrt()
+    };
+
+    let mut h = harness(Pattern::Rggb, 32, 16, "RGB");
+    let single = push(&mut h, 0, noisy_frame(0));
+    let single = std_dev(&single, &output_caps(&h));
+
+    // One output for every four inputs, with the PTS of the first and all durations.
+    let mut h = harness_with(Pattern::Rggb, 32, 16, "RGB", &[("stack-frames", "4")]);
+    for n in 0..2 * STACK {
+        assert_eq!(h.push(timed(n, noisy_frame(n))), Ok(gst::FlowSuccess::Ok));
+        let expected = (n + 1) / STACK;
+        assert_eq!(h.buffers_received() as u64, expected, "after frame {n}");
+    }
+    let caps = output_caps(&h);
+    let framerate = caps.structure(0).unwrap().get::<gst::Fraction>("framerate");
+    assert_eq!(framerate.unwrap(), gst::Fraction::new(15, 2));
+    for n in 0..2 {
+        let stacked = h.pull().unwrap();
+        assert_eq!(stacked.pts(), Some(FRAME_DURATION * n * STACK));
+        assert_eq!(stacked.duration(), Some(FRAME_DURATION * STACK));
+
+        // Averaging four frames halves the noise.
+        let stacked = std_dev(&stacked, &caps);
+        assert!(stacked < 0.7 * single, "{stacked} stacked, {single} single");
+    }
+
+    // A flush drops the frames stacked so far.
+    for n in 0..2 {
+        assert_eq!(h.push(timed(n, noisy_frame(n))), Ok(gst::FlowSuccess::Ok));
+    }
+    assert!(h.push_event(gst::event::FlushStart::new()));
+    assert!(h.push_event(gst::event::FlushStop::new(true)));
+    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    assert!(h.push_event(gst::event::Segment::new(&segment)));
+    for n in 10..10 + STACK {
+        assert_eq!(h.push(timed(n, noisy_frame(n))), Ok(gst::FlowSuccess::Ok));
+    }
+    assert_eq!(h.buffers_in_queue(), 1);
+    assert_eq!(h.pull().unwrap().pts(), Some(FRAME_DURATION * 10));
+}
+
+// A harness around rsbayer2rgb converting on the queue thread. The worker is created
+// when the element starts, which the harness does as soon as it has the element.
+fn queued_harness(max_queue_buffers: u32, leaky: &str) -> gst_check::Harness {
+    init();
+
+    let element = gst::ElementFactory::make("rsbayer2rgb")
+        .property("max-queue-buffers", max_queue_buffers)
+        .property_from_str("leaky", leaky)
+        .build()
+        .unwrap();
+    let mut h = gst_check::Harness::with_element(&element, Some("sink"), Some("src"));
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 16, 12));
+    h
+}
+
+// Frame `n` of a stream, gray at level `n`.
+fn timed_frame(n: u64) -> gst::Buffer {
+    let mut buffer = bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| n as u8);
+    let buffer_mut = buffer.get_mut().unwrap();
+    buffer_mut.set_pts(FRAME_DURATION * n);
+    buffer_mut.set_duration(FRAME_DURATION);
+    buffer
+}
+
+// Holds every converted frame at the src pad until the returned sender lets it
+// through, or is dropped. The receiver gets a message for every frame arriving there.
+fn hold_output(h: &gst_check::Harness) -> (mpsc::Receiver<()>, mpsc::Sender<()>) {
+    let (arrived_tx, arrived) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel::<()>();
+    let release_rx = std::sync::Mutex::new(release_rx);
+    let srcpad = h.element().unwrap().static_pad("src").unwrap();
+    srcpad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        let _ = arrived_tx.send(());
+        let _ = release_rx.lock().unwrap().recv();
+        gst::PadProbeReturn::Ok
+    });
+    (arrived, release)
+}
+
+#[test]
+fn test_queue_order() {
+    let mut h = queued_harness(4, "no");
+    let (arrived, release) = hold_output(&h);
+
+    // Let the frames through one at a time, while upstream keeps the queue full.
+    let pusher = std::thread::spawn(move || {
+        for _ in 0..20 {
+            arrived.recv().unwrap();
+            release.send(()).unwrap();
+        }
+    });
+    for n in 0..20 {
+        assert_eq!(h.push(timed_frame(n)), Ok(gst::FlowSuccess::Ok));
+    }
+    for n in 0..20 {
+        let output = h.pull().unwrap();
+        assert_eq!(output.pts(), Some(FRAME_DURATION * n));
+        assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [n as u8; 3]);
+    }
+    pusher.join().unwrap();
+}
+
+#[test]
+fn test_queue_eos_drains() {
+    let mut h = queued_harness(8, "no");
+    let (arrived, release) = hold_output(&h);
+
+    // The worker holds the first frame, the others wait in the queue.
+    for n in 0..6 {
+        assert_eq!(h.push(timed_frame(n)), Ok(gst::FlowSuccess::Ok));
+    }
+    arrived.recv().unwrap();
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    // EOS waits for every queued frame to be pushed before going downstream.
+    drop(release);
+    assert!(h.push_event(gst::event::Eos::new()));
+    assert_eq!(h.buffers_in_queue(), 6);
+    for n in 0..6 {
+        assert_eq!(h.pull().unwrap().pts(), Some(FRAME_DURATION * n));
+    }
+    let eos =
+        std::iter::from_fn(|| h.try_pull_event()).any(|event| event.type_() == gst::EventType::Eos);
+    assert!(eos);
+}
+
+#[test]
+fn test_queue_leaky() {
+    // upstream drops the new frames, downstream the old ones, the first frame is
+    // already out of the queue.
+    for (leaky, kept) in [("upstream", [0, 1, 2]), ("downstream", [0, 3, 4])] {
+        let mut h = queued_harness(2, leaky);
+        let (arrived, release) = hold_output(&h);
+
+        assert_eq!(h.push(timed_frame(0)), Ok(gst::FlowSuccess::Ok));
+        arrived.recv().unwrap();
+        // Upstream isn't blocked by the full queue.
+        for n in 1..5 {
+            assert_eq!(h.push(timed_frame(n)), Ok(gst::FlowSuccess::Ok));
+        }
+
+        drop(release);
+        assert!(h.push_event(gst::event::Eos::new()));
+        assert_eq!(h.buffers_in_queue(), kept.len() as u32, "{leaky}");
+        for n in kept {
+            assert_eq!(h.pull().unwrap().pts(), Some(FRAME_DURATION * n), "{leaky}");
+        }
+        let dropped = h.element().unwrap().property::<u64>("frames-dropped");
+        assert_eq!(dropped, 2, "{leaky}");
+    }
+}
+
+#[test]
+fn test_process_roi() {
+    const FILL: [u8; 3] = [0x10, 0x20, 0x30];
+    let frame = || bayer_frame(Pattern::Bggr, 64, 32, |_, x, y| (x * 3 + y * 2) as u8);
+    let inside = |x: usize, y: usize, [left, top, right, bottom]: [usize; 4]| {
+        (left..right).contains(&x) && (top..bottom).contains(&y)
+    };
+
+    for format in OUTPUT_FORMATS {
+        let mut h = harness(Pattern::Bggr, 64, 32, format);
+        let element = h.element().unwrap();
+        let whole = rgb_pixels(&push(&mut h, 0, frame()), &output_caps(&h));
+
+        // Odd coordinates grow to whole quads, 17..47 x 9..23 to 16..48 x 8..24.
+        element.set_property("process-roi-x", 17u32);
+        element.set_property("process-roi-y", 9u32);
+        element.set_property("process-roi-width", 30u32);
+        element.set_property("process-roi-height", 14u32);
+        element.set_property("process-roi-fill-color", 0xff102030u32);
+        let pixels = rgb_pixels(&push(&mut h, 1, frame()), &output_caps(&h));
+        let roi = [16, 8, 48, 24];
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                if !inside(x, y, roi) {
+                    assert_eq!(*pixel, FILL, "{format} pixel {x},{y}");
+                } else if inside(x, y, [18, 10, 46, 22]) {
+                    // Away from the ROI edge it matches the whole frame conversion.
+                    assert!(
+                        pixel
+                            .iter()
+                            .zip(whole[y][x])
+                            .all(|(&value, expected)| value.abs_diff(expected) <= TOLERANCE),
+                        "{format} pixel {x},{y} is {pixel:?}, expected {:?}",
+                        whole[y][x]
+                    );
+                }
+            }
+        }
+
+        // Moving the ROI while keeping the previous frame leaves the old ROI around
+        // it.
+        element.set_property("process-roi-keep-previous", true);
+        push(&mut h, 2, frame());
+        element.set_property("process-roi-x", 0u32);
+        element.set_property("process-roi-width", 8u32);
+        let previous = rgb_pixels(&push(&mut h, 3, frame()), &output_caps(&h));
+        assert_eq!(previous[12][30], pixels[12][30], "{format}");
+        assert_eq!(previous[2][30], FILL, "{format}");
+        assert_ne!(previous[12][4], FILL, "{format}");
+
+        // A ROI outside the input converts the whole frame.
+        element.set_property("process-roi-x", 64u32);
+        let pixels = rgb_pixels(&push(&mut h, 4, frame()), &output_caps(&h));
+        assert_interior(&pixels, |x, y| whole[y][x]);
+    }
+}
+
+#[test]
+fn test_tuning_file() {
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-tuning-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = gst::Bus::new();
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let element = h.element().unwrap();
+    element.set_bus(Some(&bus));
+    let message = || {
+        bus.pop_filtered(&[gst::MessageType::Element])
+            .map(|msg| msg.structure().unwrap().to_owned())
+    };
+    let frame = || bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 128);
+    // Later than the last write, whatever the file system's time resolution.
+    let touch = |path: &std::path::Path, secs: u64| {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(secs))
+            .unwrap();
+    };
+
+    // A partial file sets what it names only, paths relative to the file.
+    std::fs::write(dir.join("defects.txt"), "3,4\n").unwrap();
+    let json = dir.join("unit.json");
+    std::fs::write(
+        &json,
+        r#"{
+            "red-gain": 1.5,
+            "demosaic-algorithm": "edge-aware",
+            "defect-list-file": "defects.txt",
+            "tone-lut": []
+        }"#,
+    )
+    .unwrap();
+    element.set_property("blue-gain", 1.25);
+    element.set_property("tuning-file", json.to_str().unwrap());
+    let s = message().expect("tuning file message");
+    assert_eq!(s.name(), "tuning-file-loaded");
+    assert_eq!(s.get::<&str>("location").unwrap(), json.to_str().unwrap());
+    assert_eq!(s.get::<u32>("properties").unwrap(), 4);
+    assert_eq!(element.property::<f64>("red-gain"), 1.5);
+    assert_eq!(element.property::<f64>("blue-gain"), 1.25);
+    assert_eq!(
+        element.property::<Option<String>>("defect-list-file"),
+        dir.join("defects.txt").to_str().map(String::from)
+    );
+
+    // Anything invalid leaves every property as it was.
+    for (name, text, error) in [
+        (
+            "broken.json",
+            r#"{"red-gain": 2.0 "blue-gain": 1.0}"#,
+            "expected ',' at 1:18",
+        ),
+        (
+            "unknown.toml",
+            "red-gain = 2.0\nno-such-gain = 1.0\n",
+            "unknown tuning property no-such-gain",
+        ),
+        (
+            "range.json",
+            r#"{"red-gain": 2.0, "gamma": -1}"#,
+            "invalid value Number(-1.0) for gamma",
+        ),
+        (
+            "missing.json",
+            r#"{"red-gain": 2.0, "lsc-file": "lsc.npy"}"#,
+            "no file",
+        ),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        element.set_property("tuning-file", path.to_str().unwrap());
+        let s = message().expect("tuning file message");
+        assert_eq!(s.name(), "tuning-file-error", "{name}");
+        let message = s.get::<&str>("error").unwrap();
+        assert!(message.contains(error), "{name}: {message}");
+        assert_eq!(element.property::<f64>("red-gain"), 1.5, "{name}");
+    }
+
+    // TOML, loaded again once it changes while watched.
+    let toml = dir.join("unit.toml");
+    std::fs::write(
+        &toml,
+        "# unit 42\nred-gain = 1.75\nawb-mode = \"manual\" # fixed\n",
+    )
+    .unwrap();
+    element.set_property("watch-tuning-file", true);
+    element.set_property("tuning-file", toml.to_str().unwrap());
+    assert_eq!(message().unwrap().name(), "tuning-file-loaded");
+    assert_eq!(element.property::<f64>("red-gain"), 1.75);
+    push(&mut h, 0, frame());
+    assert!(message().is_none(), "unchanged file isn't loaded again");
+
+    std::fs::write(&toml, "red-gain = 2.5\n").unwrap();
+    touch(&toml, 10);
+    push(&mut h, 1, frame());
+    assert_eq!(message().unwrap().name(), "tuning-file-loaded");
+    assert_eq!(element.property::<f64>("red-gain"), 2.5);
+
+    std::fs::write(&toml, "red-gain =\n").unwrap();
+    touch(&toml, 20);
+    push(&mut h, 2, frame());
+    assert_eq!(message().unwrap().name(), "tuning-file-error");
+    assert_eq!(element.property::<f64>("red-gain"), 2.5);
+    push(&mut h, 3, frame());
+    assert!(
+        message().is_none(),
+        "broken file isn't retried until it changes"
+    );
+
+    element.set_bus(None);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_isp_params() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let element = h.element().unwrap();
+    let params = |text: &str| text.parse::<gst::Structure>().unwrap();
+
+    // Whatever was set last wins, field by field.
+    element.set_property("red-gain", 1.2);
+    element.set_property("gamma", 1.8);
+    element.set_property(
+        "isp-params",
+        params("isp-params, red-gain=1.8, demosaic-algorithm=edge-aware, ob-rows=4"),
+    );
+    assert_eq!(element.property::<f64>("red-gain"), 1.8);
+    assert_eq!(element.property::<f64>("gamma"), 1.8);
+    assert_eq!(element.property::<u32>("ob-rows"), 4);
+    element.set_property("red-gain", 1.4);
+    let current = element.property::<gst::Structure>("isp-params");
+    assert_eq!(current.get::<f64>("red-gain").unwrap(), 1.4);
+    assert_eq!(current.get::<f64>("gamma").unwrap(), 1.8);
+    assert_eq!(current.get::<u32>("ob-rows").unwrap(), 4);
+    assert_eq!(current.get::<Option<String>>("lsc-file").unwrap(), None);
+
+    // It reads back every tuning property by its name, and sets them back as is.
+    for (name, value) in current.iter() {
+        assert_eq!(
+            element.property_value(name).type_(),
+            value.type_(),
+            "{name}"
+        );
+    }
+    element.set_property("red-gain", 1.0);
+    element.set_property("isp-params", &current);
+    assert_eq!(element.property::<f64>("red-gain"), 1.4);
+
+    // An invalid field applies nothing.
+    for text in [
+        "isp-params, red-gain=2.0, gamma=-1.0",
+        "isp-params, red-gain=2.0, no-such-gain=1.0",
+        "isp-params, red-gain=2.0, stats-interval=1",
+        "isp-params, red-gain=2.0, demosaic-algorithm=no-such-algorithm",
+    ] {
+        element.set_property("isp-params", params(text));
+        assert_eq!(element.property::<f64>("red-gain"), 1.4, "{text}");
+    }
+
+    // Red and blue gains changed together never meet in one frame.
+    element.set_property("isp-params", current);
+    element.set_property("emit-signals", true);
+    let mismatches = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let mismatched = mismatches.clone();
+    element.connect("handoff", false, move |args| {
+        let buffer = args[1].get::<gst::Buffer>().unwrap();
+        let info = args[2].get::<gst_video::VideoInfo>().unwrap();
+        let [red, _, blue] = rgb_pixels(&buffer, &info.to_caps().unwrap())[6][8];
+        if red.abs_diff(blue) > TOLERANCE {
+            mismatched.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        None
+    });
+    for (name, value) in [("red-gain", 1.0), ("blue-gain", 1.0), ("gamma", 1.0)] {
+        element.set_property(name, value);
+    }
+    element.set_property("ob-rows", 0u32);
+
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let setter = {
+        let element = element.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut gain = 1.0;
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                gain = if gain == 1.0 { 3.0 } else { 1.0 };
+                let params = gst::Structure::builder("isp-params")
+                    .field("red-gain", gain)
+                    .field("blue-gain", gain)
+                    .build();
+                element.set_property("isp-params", params);
+            }
+        })
+    };
+    for n in 0..200 {
+        push(&mut h, n, bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 60));
+    }
+    done.store(true, std::sync::atomic::Ordering::Relaxed);
+    setter.join().unwrap();
+    assert_eq!(mismatches.load(std::sync::atomic::Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_preview_pad() {
+    const COLOR: [u8; 3] = [200, 100, 50];
+    let frame = || bayer_frame(Pattern::Rggb, 128, 64, |cfa, _, _| channel(cfa, COLOR));
+    let mut h = harness(Pattern::Rggb, 128, 64, "RGB");
+    let element = h.element().unwrap();
+
+    let preview = element.request_pad_simple("preview_%u").unwrap();
+    assert_eq!(preview.name(), "preview_0");
+    let buffers = std::sync::Arc::new(std::sync::Mutex::new(Vec::<gst::Buffer>::new()));
+    let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::<gst::Event>::new()));
+    let sink = gst::Pad::builder(gst::PadDirection::Sink)
+        .chain_function({
+            let buffers = buffers.clone();
+            move |_, _, buffer| {
+                buffers.lock().unwrap().push(buffer);
+                Ok(gst::FlowSuccess::Ok)
+            }
+        })
+        .event_function({
+            let events = events.clone();
+            move |_, _, event| {
+                events.lock().unwrap().push(event);
+                true
+            }
+        })
+        .build();
+    sink.set_active(true).unwrap();
+    preview.link(&sink).unwrap();
+    let preview_caps = || {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find_map(|event| match event.view() {
+                gst::EventView::Caps(caps) => Some(caps.caps_owned()),
+                _ => None,
+            })
+            .expect("preview is negotiated")
+    };
+
+    // A quarter of the size by default, with the timestamps of the output.
+    let mut outputs = Vec::new();
+    for n in 0..3 {
+        outputs.push(push(&mut h, n, frame()));
+    }
+    {
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0].view(), gst::EventView::StreamStart(_)));
+        assert!(matches!(events[1].view(), gst::EventView::Caps(_)));
+        assert!(matches!(events[2].view(), gst::EventView::Segment(_)));
+    }
+    let caps = preview_caps();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<&str>("format").unwrap(), "RGB");
+    assert_eq!(s.get::<i32>("width").unwrap(), 32);
+    assert_eq!(s.get::<i32>("height").unwrap(), 16);
+    {
+        let buffers = buffers.lock().unwrap();
+        assert_eq!(buffers.len(), 3);
+        for (buffer, output) in buffers.iter().zip(&outputs) {
+            assert_eq!(buffer.pts(), output.pts());
+            assert_eq!(buffer.duration(), output.duration());
+            assert_interior(&rgb_pixels(buffer, &caps), |_, _| COLOR);
+        }
+    }
+
+    // An explicit width, the height keeping the aspect ratio.
+    preview.set_property("width", 40u32);
+    push(&mut h, 3, frame());
+    let s = preview_caps().structure(0).unwrap().to_owned();
+    assert_eq!(s.get::<i32>("width").unwrap(), 40);
+    assert_eq!(s.get::<i32>("height").unwrap(), 20);
+    assert_eq!(buffers.lock().unwrap().len(), 4);
+
+    // Released mid-stream, the output goes on alone.
+    element.release_request_pad(&preview);
+    assert!(element.static_pad("preview_0").is_none());
+    for n in 4..6 {
+        let output = push(&mut h, n, frame());
+        assert_eq!(output.pts(), Some(FRAME_DURATION * n));
+    }
+    assert_eq!(buffers.lock().unwrap().len(), 4);
+
+    let preview = element.request_pad_simple("preview_%u").unwrap();
+    assert_eq!(preview.name(), "preview_0");
+    element.release_request_pad(&preview);
+}
+
+#[test]
+fn test_gr_gb_balance() {
+    // A gray scene with the red row greens 3% more sensitive.
+    let frame = || {
+        bayer_frame(Pattern::Rggb, 64, 48, |cfa, _, _| match cfa {
+            CfaColor::GreenRed => 206,
+            _ => 200,
+        })
+    };
+    // Differences between neighboring greens, where the imbalance makes a maze.
+    let maze_energy = |h: &mut gst_check::Harness, n: u64| {
+        let pixels = rgb_pixels(&push(h, n, frame()), &output_caps(h));
+        let width = pixels[0].len();
+        let mut energy = 0;
+        for rows in pixels[BORDER..pixels.len() - BORDER].windows(2) {
+            let row = &rows[0][BORDER..width - BORDER];
+            let below = &rows[1][BORDER..width - BORDER];
+            for ((pixel, right), below) in row.iter().zip(&row[1..]).zip(below) {
+                energy += pixel[1].abs_diff(right[1]) as u32 + pixel[1].abs_diff(below[1]) as u32;
+            }
+        }
+        energy
+    };
+
+    let mut h = harness(Pattern::Rggb, 64, 48, "RGB");
+    let element = h.element().unwrap();
+    let uncorrected = maze_energy(&mut h, 0);
+    assert!(uncorrected > 0);
+
+    element.set_property("gr-gb-balance", GrGbBalance::Manual);
+    element.set_property("gr-gb-ratio", 1.03);
+    let manual = maze_energy(&mut h, 1);
+    assert!(manual * 4 < uncorrected, "{manual} vs {uncorrected}");
+    assert_eq!(element.property::<f64>("applied-gr-gb-ratio"), 1.03);
+
+    // Measured, settling over a few frames.
+    element.set_property("gr-gb-balance", GrGbBalance::Auto);
+    element.set_property("gr-gb-ratio", 1.0);
+    for n in 2..30 {
+        maze_energy(&mut h, n);
+    }
+    let applied = element.property::<f64>("applied-gr-gb-ratio");
+    assert!((applied - 1.03).abs() < 0.005, "{applied}");
+    let auto = maze_energy(&mut h, 30);
+    assert!(auto * 4 < uncorrected, "{auto} vs {uncorrected}");
+
+    element.set_property("gr-gb-balance", GrGbBalance::Off);
+    assert_eq!(maze_energy(&mut h, 31), uncorrected);
+    assert_eq!(element.property::<f64>("applied-gr-gb-ratio"), 1.0);
+}
+
+#[test]
+fn test_false_color_suppression() {
+    // Fine gray texture, which bilinear demosaic turns into speckles of false color.
+    let frame = || {
+        bayer_frame(Pattern::Rggb, 64, 48, |_, x, y| {
+            let hash = (x * 7919 + y * 104_729).wrapping_mul(2_654_435_761) >> 16;
+            80 + (hash % 97) as u8
+        })
+    };
+    let interior = |pixels: &[Vec<[u8; 3]>]| {
+        let width = pixels[0].len();
+        pixels[BORDER..pixels.len() - BORDER]
+            .iter()
+            .flat_map(|row| row[BORDER..width - BORDER].to_vec())
+            .collect::<Vec<_>>()
+    };
+    let chroma_energy = |pixels: &[[u8; 3]]| {
+        pixels
+            .iter()
+            .map(|&[r, g, b]| {
+                let (r, g, b) = (r as f64, g as f64, b as f64);
+                (r - g).powi(2) + (b - g).powi(2)
+            })
+            .sum::<f64>()
+            / pixels.len() as f64
+    };
+    let luma = |[r, g, b]: [u8; 3]| 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+
+    let mut h = harness(Pattern::Rggb, 64, 48, "RGB");
+    let element = h.element().unwrap();
+    let plain = interior(&rgb_pixels(&push(&mut h, 0, frame()), &output_caps(&h)));
+    assert!(chroma_energy(&plain) > 0.0);
+
+    element.set_property("false-color-suppression", 1.0);
+    let suppressed = interior(&rgb_pixels(&push(&mut h, 1, frame()), &output_caps(&h)));
+    assert!(
+        chroma_energy(&suppressed) * 2.0 < chroma_energy(&plain),
+        "{} vs {}",
+        chroma_energy(&suppressed),
+        chroma_energy(&plain)
+    );
+    let luma_error = plain
+        .iter()
+        .zip(&suppressed)
+        .map(|(&plain, &suppressed)| (luma(plain) - luma(suppressed)).abs())
+        .sum::<f64>()
+        / plain.len() as f64;
+    assert!(luma_error < 1.0, "{luma_error}");
+
+    element.set_property("false-color-suppression", 0.0);
+    let off = interior(&rgb_pixels(&push(&mut h, 2, frame()), &output_caps(&h)));
+    assert_eq!(off, plain);
+}
+
+#[test]
+fn test_border_mode() {
+    const COLOR: [u8; 3] = [180, 120, 60];
+    // Largest difference to the scene color of the pixels within BORDER of an edge.
+    let border_error = |pixels: &[Vec<[u8; 3]>]| {
+        let (width, height) = (pixels[0].len(), pixels.len());
+        let mut error = 0;
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let interior =
+                    (BORDER..width - BORDER).contains(&x) && (BORDER..height - BORDER).contains(&y);
+                if !interior {
+                    let diff = pixel
+                        .iter()
+                        .zip(COLOR)
+                        .map(|(&value, color)| value.abs_diff(color));
+                    error = error.max(diff.max().unwrap());
+                }
+            }
+        }
+        error
+    };
+    let convert = |mode: &str| {
+        let mut h = harness_with(Pattern::Grbg, 32, 24, "RGB", &[("border-mode", mode)]);
+        let frame = bayer_frame(Pattern::Grbg, 32, 24, |cfa, _, _| channel(cfa, COLOR));
+        let output = push(&mut h, 0, frame);
+        let pixels = rgb_pixels(&output, &output_caps(&h));
+        assert_interior(&pixels, |_, _| COLOR);
+        pixels
+    };
+
+    let none = convert("none");
+    assert_eq!((none[0].len(), none.len()), (32, 24));
+
+    // Padding by the nearest samples of the same color makes the border as good as
+    // the interior.
+    let replicate = convert("replicate");
+    assert_eq!((replicate[0].len(), replicate.len()), (32, 24));
+    let replicate_error = border_error(&replicate);
+    assert!(replicate_error <= TOLERANCE, "{replicate_error}");
+    assert!(border_error(&none) >= replicate_error);
+
+    // Cropping keeps only pixels that were interior ones.
+    let crop = convert("crop");
+    assert_eq!((crop[0].len(), crop.len()), (28, 20));
+    let crop_error = border_error(&crop);
+    assert!(crop_error <= TOLERANCE, "{crop_error}");
+}
+
+#[test]
+fn test_decompanding() {
+    const KNEES: [(u32, u32); 4] = [(0, 0), (64, 4096), (128, 16384), (255, 65535)];
+    // The linear level of a code along the curve, reduced to the 8-bit output.
+    let expanded = |code: u32| {
+        let segment = KNEES.windows(2).find(|pair| code <= pair[1].0).unwrap();
+        let ((start, from), (end, to)) = (segment[0], segment[1]);
+        let level = from as f64 + (to - from) as f64 * (code - start) as f64 / (end - start) as f64;
+        (level * 255.0 / 65535.0).round() as u8
+    };
+    let gray = |h: &mut gst_check::Harness, n: u64, code: u8| {
+        let pixels = rgb_pixels(
+            &push(h, n, bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| code)),
+            &output_caps(h),
+        );
+        pixels[6][8]
+    };
+
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let element = h.element().unwrap();
+    let curve =
+        gst::Array::new(KNEES.map(|(code, level)| gst::Array::new([code as i32, level as i32])));
+    element.set_property("decompanding", curve);
+    assert_eq!(
+        element
+            .property::<gst::Array>("decompanding")
+            .as_slice()
+            .len(),
+        4
+    );
+
+    // A ramp of companded codes, across the knees.
+    for (n, code) in (0..=255).step_by(15).chain([64, 128, 255]).enumerate() {
+        let pixel = gray(&mut h, n as u64, code as u8);
+        let expected = expanded(code);
+        assert!(
+            pixel.iter().all(|value| value.abs_diff(expected) <= 1),
+            "code {code} gave {pixel:?}, expected {expected}"
+        );
+    }
+
+    // Curves that aren't monotonic are refused, keeping the one set.
+    for knees in [
+        [(0, 0), (128, 40000), (96, 50000)],
+        [(0, 0), (128, 40000), (192, 30000)],
+        [(0, 0), (128, 40000), (300, 50000)],
+    ] {
+        element.set_property(
+            "decompanding",
+            gst::Array::new(knees.map(|(code, level)| gst::Array::new([code, level]))),
+        );
+        assert_eq!(
+            element
+                .property::<gst::Array>("decompanding")
+                .as_slice()
+                .len(),
+            4
+        );
+    }
+
+    // A-law doubles the slope every 32 codes from the third segment on.
+    element.set_property("decompanding", gst::Array::new(["a-law"]));
+    assert_eq!(gray(&mut h, 100, 96)[1], 8);
+    assert_eq!(gray(&mut h, 101, 160)[1], 32);
+    assert_eq!(gray(&mut h, 102, 255)[1], 251);
+
+    // Unset, codes are linear again.
+    element.set_property("decompanding", gst::Array::new(Vec::<i32>::new()));
+    assert_eq!(gray(&mut h, 103, 160)[1], 160);
+}
+
+fn enum_nick(value: &gst::glib::Value) -> String {
+    let (_, value) = gst::glib::EnumValue::from_value(value).unwrap();
+    value.nick().to_owned()
+}
+
+#[test]
+fn test_processing_deadline() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGBA");
+    let element = h.element().unwrap();
+    let bus = gst::Bus::new();
+    element.set_bus(Some(&bus));
+    let quality_level = || enum_nick(&element.property_value("quality-level"));
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |_, _, _| 100);
+    let mut n = 0;
+    let mut push_frames = |h: &mut gst_check::Harness, count: u64| {
+        for _ in 0..count {
+            push(h, n, frame.clone());
+            n += 1;
+        }
+    };
+
+    element.set_property(
+        "demosaic-algorithm",
+        gstrsbayer::convert::DemosaicAlgorithm::Vng,
+    );
+    push_frames(&mut h, 10);
+    assert_eq!(quality_level(), "full");
+    assert!(bus.pop_filtered(&[gst::MessageType::Element]).is_none());
+
+    // Every frame misses a deadline of a nanosecond, so the level drops a step every
+    // five frames and stays at the bottom.
+    element.set_property("processing-deadline", 1u64);
+    push_frames(&mut h, 4);
+    assert_eq!(quality_level(), "full");
+    push_frames(&mut h, 1);
+    assert_eq!(quality_level(), "reduced");
+    push_frames(&mut h, 5);
+    assert_eq!(quality_level(), "minimal");
+    push_frames(&mut h, 20);
+    assert_eq!(quality_level(), "minimal");
+
+    let levels: Vec<String> = std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+        .map(|msg| {
+            let s = msg.structure().unwrap();
+            assert_eq!(s.name(), "rsbayer2rgb-stats");
+            enum_nick(s.value("quality-level").unwrap())
+        })
+        .collect();
+    assert_eq!(levels, ["reduced", "minimal"]);
+
+    // Plenty of headroom under ten seconds. The level rises once it has been held for
+    // 60 frames, counting the 20 above, and another step 60 frames later.
+    element.set_property("processing-deadline", 10_000_000_000u64);
+    push_frames(&mut h, 39);
+    assert_eq!(quality_level(), "minimal");
+    push_frames(&mut h, 1);
+    assert_eq!(quality_level(), "reduced");
+    push_frames(&mut h, 59);
+    assert_eq!(quality_level(), "reduced");
+    push_frames(&mut h, 1);
+    assert_eq!(quality_level(), "full");
+    push_frames(&mut h, 60);
+    assert_eq!(quality_level(), "full");
+
+    // Switching it off restores full quality at once.
+    element.set_property("processing-deadline", 1u64);
+    push_frames(&mut h, 1);
+    assert_eq!(quality_level(), "reduced");
+    element.set_property("processing-deadline", 0u64);
+    push_frames(&mut h, 1);
+    assert_eq!(quality_level(), "full");
+
+    element.set_bus(None);
+}
+
+// Column offsets of a sensor with a column parallel readout, different for the two
+// colors of every column.
+fn column_offset(x: usize, y: usize) -> u8 {
+    ((x * 7 + y % 2 * 3) % 5) as u8 * 3
+}
+
+// Largest difference between the means of two interior columns of any channel.
+fn striping(pixels: &[Vec<[u8; 3]>]) -> f64 {
+    let rows = &pixels[BORDER..pixels.len() - BORDER];
+    (0..3)
+        .map(|channel| {
+            let means: Vec<f64> = (BORDER..pixels[0].len() - BORDER)
+                .map(|x| {
+                    rows.iter().map(|row| row[x][channel] as f64).sum::<f64>() / rows.len() as f64
+                })
+                .collect();
+            let max = means.iter().copied().fold(f64::MIN, f64::max);
+            let min = means.iter().copied().fold(f64::MAX, f64::min);
+            max - min
+        })
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn test_fpn_correction_optical_black() {
+    // Four optical black rows at a black level of 16 above a flat gray, both with the
+    // column offsets.
+    let frame = bayer_frame(Pattern::Rggb, 32, 20, |_, x, y| {
+        let level = if y < 4 { 16 } else { 64 };
+        level + column_offset(x, y)
+    });
+    let convert = |fpn_correction: &str| {
+        let mut h = harness_with(
+            Pattern::Rggb,
+            32,
+            20,
+            "RGB",
+            &[
+                ("ob-rows", "4"),
+                ("ob-crop", "true"),
+                ("fpn-correction", fpn_correction),
+            ],
+        );
+        let mut pixels = Vec::new();
+        for n in 0..5 {
+            pixels = rgb_pixels(&push(&mut h, n, frame.clone()), &output_caps(&h));
+        }
+        assert_eq!((pixels[0].len(), pixels.len()), (32, 16));
+        pixels
+    };
+
+    let uncorrected = striping(&convert("none"));
+    assert!(uncorrected >= 6.0, "{uncorrected}");
+    let corrected = convert("optical-black");
+    let residual = striping(&corrected);
+    assert!(residual <= 1.0, "{residual}");
+    assert_interior(&corrected, |_, _| [48; 3]);
+}
+
+// Writes a `.npy` file of a `shape` array of `descr` elements, `data` in row-major
+// order.
+fn write_npy(path: &std::path::Path, descr: &str, shape: (usize, usize), data: &[u8]) {
+    let mut header = format!(
+        "{{'descr': '{descr}', 'fortran_order': False, 'shape': ({}, {}), }}",
+        shape.0, shape.1
+    );
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+    npy.extend((header.len() as u16).to_le_bytes());
+    npy.extend(header.as_bytes());
+    npy.extend(data);
+    std::fs::write(path, npy).unwrap();
+}
+
+#[test]
+fn test_fpn_correction_file() {
+    init();
+
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-fpn-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fpn.npy");
+
+    // A (2, 32) array of little endian float32 offsets, even rows first.
+    let offsets = (0..2)
+        .flat_map(|y| (0..32).map(move |x| column_offset(x, y) as f32))
+        .flat_map(f32::to_le_bytes)
+        .collect::<Vec<_>>();
+    write_npy(&path, "<f4", (2, 32), &offsets);
+
+    // Calibration files load when the element starts, which the harness does as soon
+    // as it has the element.
+    let element = gst::ElementFactory::make("rsbayer2rgb")
+        .property_from_str("fpn-correction", "file")
+        .property("fpn-file", path.to_str().unwrap())
+        .build()
+        .unwrap();
+    let mut h = gst_check::Harness::with_element(&element, Some("sink"), Some("src"));
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 32, 16));
+    let frame = bayer_frame(Pattern::Rggb, 32, 16, |_, x, y| 40 + column_offset(x, y));
+    let pixels = rgb_pixels(&push(&mut h, 0, frame), &output_caps(&h));
+    let residual = striping(&pixels);
+    assert!(residual <= 1.0, "{residual}");
+    assert_interior(&pixels, |_, _| [40; 3]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_auto_levels() {
+    // A low contrast gray gradient from 100 to 140 across the frame.
+    let frame = bayer_frame(Pattern::Rggb, 64, 12, |_, x, _| 100 + (x * 40 / 63) as u8);
+    let mut h = harness(Pattern::Rggb, 64, 12, "RGB");
+    let element = h.element().unwrap();
+    let row = |h: &mut gst_check::Harness, n: u64| {
+        let pixels = rgb_pixels(&push(h, n, frame.clone()), &output_caps(h));
+        pixels[6][BORDER..64 - BORDER]
+            .iter()
+            .map(|pixel| pixel[1])
+            .collect::<Vec<u8>>()
+    };
+
+    let plain = row(&mut h, 0);
+    assert!(plain[0] >= 99 && *plain.last().unwrap() <= 141, "{plain:?}");
+
+    // With levels following every frame the gradient spans close to the full range,
+    // still rising from left to right.
+    element.set_property("auto-levels", true);
+    element.set_property("auto-levels-frames", 1u32);
+    let stretched = row(&mut h, 1);
+    assert!(stretched[0] <= 16, "{stretched:?}");
+    assert!(*stretched.last().unwrap() >= 240, "{stretched:?}");
+    assert!(
+        stretched.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{stretched:?}"
+    );
+
+    // Locked, a flat frame keeps the stretch of the last one, which had the level of
+    // the frame in column 32.
+    element.set_property("levels-lock", true);
+    let flat = bayer_frame(Pattern::Rggb, 64, 12, |_, _, _| 120);
+    let pixels = rgb_pixels(&push(&mut h, 2, flat), &output_caps(&h));
+    let expected = stretched[32 - BORDER];
+    assert!(
+        pixels[6][32][1].abs_diff(expected) <= 8,
+        "{:?} {expected}",
+        pixels[6][32]
+    );
+
+    // Damped, the levels move part of the way towards those of a new frame.
+    element.set_property("levels-lock", false);
+    element.set_property("auto-levels-frames", 4u32);
+    let shifted = bayer_frame(Pattern::Rggb, 64, 12, |_, x, _| 60 + (x * 40 / 63) as u8);
+    let pixels = rgb_pixels(&push(&mut h, 3, shifted), &output_caps(&h));
+    assert_eq!(pixels[6][BORDER][1], 0);
+    let right = pixels[6][64 - BORDER - 1][1];
+    assert!(right < 200, "{right}");
+}
+
+#[test]
+fn test_calibrate_wb() {
+    // A gray card under a warm light on the left, something blue on the right.
+    let frame = bayer_frame(Pattern::Rggb, 32, 12, |cfa, x, _| match x < 16 {
+        true => channel(cfa, [200, 100, 50]),
+        false => channel(cfa, [40, 60, 180]),
+    });
+    let mut h = harness(Pattern::Rggb, 32, 12, "RGB");
+    let element = h.element().unwrap();
+    let bus = gst::Bus::new();
+    element.set_bus(Some(&bus));
+    let calibrated = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    element.connect("wb-calibrated", false, {
+        let calibrated = calibrated.clone();
+        move |args| {
+            let gains: Vec<f64> = args[1..].iter().map(|arg| arg.get().unwrap()).collect();
+            calibrated.lock().unwrap().push(gains);
+            None
+        }
+    });
+    element.set_property_from_str("awb-mode", "gray-world");
+
+    // Asked for from another thread, the calibration happens on the next frame.
+    std::thread::spawn({
+        let element = element.clone();
+        move || element.emit_by_name::<()>("calibrate-wb", &[&0u32, &0u32, &12u32, &12u32])
+    })
+    .join()
+    .unwrap();
+    assert!(calibrated.lock().unwrap().is_empty());
+    for n in 0..3 {
+        let pixels = rgb_pixels(&push(&mut h, n, frame.clone()), &output_caps(&h));
+        for (x, pixel) in pixels[6][..12].iter().enumerate().skip(BORDER) {
+            assert!(
+                pixel.iter().all(|value| value.abs_diff(100) <= TOLERANCE),
+                "frame {n}: {pixel:?} at {x}"
+            );
+        }
+    }
+
+    assert_eq!(*calibrated.lock().unwrap(), [vec![0.5, 1.0, 2.0]]);
+    assert_eq!(element.property::<f64>("red-gain"), 0.5);
+    assert_eq!(element.property::<f64>("green-gain"), 1.0);
+    assert_eq!(element.property::<f64>("blue-gain"), 2.0);
+    assert_eq!(enum_nick(&element.property_value("awb-mode")), "manual");
+
+    let msg = std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+        .find(|msg| msg.structure().unwrap().name() == "rsbayer2rgb-wb-calibrated")
+        .unwrap();
+    let s = msg.structure().unwrap();
+    assert_eq!(s.get::<f64>("red-gain").unwrap(), 0.5);
+    assert_eq!(s.get::<f64>("blue-gain").unwrap(), 2.0);
+
+    element.set_bus(None);
+}
+
+#[test]
+fn test_temperature_gains() {
+    // Reference gains of the Kim et al. locus in sRGB primaries: tungsten needs a
+    // strong blue gain, daylight little, and 6500 K is close to the D65 white point.
+    for (temperature, red, blue) in [
+        (3200.0, 0.517, 2.660),
+        (5500.0, 0.850, 1.126),
+        (6500.0, 0.944, 0.951),
+    ] {
+        let gains = temperature_gains(temperature, 0.0);
+        assert!(
+            (gains.red - red).abs() < 0.005,
+            "{temperature} K: {gains:?}"
+        );
+        assert_eq!(gains.green, 1.0);
+        assert!(
+            (gains.blue - blue).abs() < 0.005,
+            "{temperature} K: {gains:?}"
+        );
+    }
+
+    // Warmer light needs less red and more blue gain all the way through the range.
+    let reds_blues: Vec<_> = (2500..=10000)
+        .step_by(100)
+        .map(|temperature| temperature_gains(temperature as f64, 0.0))
+        .collect();
+    for pair in reds_blues.windows(2) {
+        assert!(
+            pair[0].red < pair[1].red && pair[0].blue > pair[1].blue,
+            "{pair:?}"
+        );
+    }
+
+    // A magenta tint raises red and blue against green, a green one lowers them.
+    let neutral = temperature_gains(5500.0, 0.0);
+    let magenta = temperature_gains(5500.0, 1.0);
+    let green = temperature_gains(5500.0, -1.0);
+    assert!((magenta.red / neutral.red - 1.25).abs() < 1e-9);
+    assert!((magenta.blue / neutral.blue - 1.25).abs() < 1e-9);
+    assert!((green.red / neutral.red - 0.75).abs() < 1e-9);
+}
+
+#[test]
+fn test_color_temperature() {
+    let mut h = harness(Pattern::Rggb, 16, 12, "RGB");
+    let element = h.element().unwrap();
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |cfa, _, _| channel(cfa, [100; 3]));
+    element.set_property("color-temperature", 3200u32);
+
+    let expected = temperature_gains(3200.0, 0.0);
+    let pixels = rgb_pixels(&push(&mut h, 0, frame.clone()), &output_caps(&h));
+    assert_eq!(element.property::<f64>("applied-red-gain"), expected.red);
+    assert_eq!(element.property::<f64>("applied-blue-gain"), expected.blue);
+    assert_interior(&pixels, |_, _| {
+        [
+            (100.0 * expected.red).round() as u8,
+            100,
+            (100.0 * expected.blue).round().min(255.0) as u8,
+        ]
+    });
+
+    // Below the range it clamps, and explicit gains override the temperature.
+    element.set_property("color-temperature", 1000u32);
+    assert_eq!(element.property::<u32>("color-temperature"), 2500);
+    element.set_property("red-gain", 1.5);
+    push(&mut h, 1, frame.clone());
+    assert_eq!(element.property::<f64>("applied-red-gain"), 1.5);
+    assert_eq!(element.property::<f64>("applied-blue-gain"), 1.0);
+
+    // Back to the temperature once the gains are at unity again.
+    element.set_property("red-gain", 1.0);
+    element.set_property("color-temperature", 6500u32);
+    push(&mut h, 2, frame);
+    assert_eq!(
+        element.property::<f64>("applied-red-gain"),
+        temperature_gains(6500.0, 0.0).red
+    );
+}
+
+#[test]
+fn test_highlight_mode() {
+    // A sky balanced by the red gain and almost blown, over a black level of 32: green
+    // and blue are a few levels below clipping, red is clipped by the gain.
+    let frame = bayer_frame(Pattern::Rggb, 16, 16, |cfa, _, y| match y < 4 {
+        true => 32,
+        false => channel(cfa, [181, 252, 252]),
+    });
+    let convert = |highlight_mode| {
+        let mut h = harness_with(
+            Pattern::Rggb,
+            16,
+            16,
+            "RGB",
+            &[
+                ("ob-rows", "4"),
+                ("ob-crop", "true"),
+                ("red-gain", "1.8"),
+                ("highlight-mode", highlight_mode),
+            ],
+        );
+        rgb_pixels(&push(&mut h, 0, frame.clone()), &output_caps(&h))
+    };
+    let cast = |pixel: [u8; 3]| {
+        let (min, max) = (pixel.iter().min().unwrap(), pixel.iter().max().unwrap());
+        max - min
+    };
+
+    // Red saturates at full scale while the greens can't get above 255 - 32.
+    let clipped = convert("clip");
+    assert!(cast(clipped[6][8]) >= 30, "{:?}", clipped[6][8]);
+
+    for highlight_mode in ["soft", "reconstruct"] {
+        let pixels = convert(highlight_mode);
+        for row in &pixels[BORDER..pixels.len() - BORDER] {
+            for &pixel in &row[BORDER..row.len() - BORDER] {
+                assert!(cast(pixel) <= 4, "{highlight_mode}: {pixel:?}");
+                assert!(pixel[1] >= 218, "{highlight_mode}: {pixel:?}");
+            }
+        }
+    }
+}
+
+// Attaches a bus to the element for the messages it posts.
+fn watch(h: &gst_check::Harness) -> gst::Bus {
+    let bus = gst::Bus::new();
+    h.element().unwrap().set_bus(Some(&bus));
+    bus
+}
+
+// The first element message named `name` posted on `bus`, dropping the ones before it.
+fn element_message(bus: &gst::Bus, name: &str) -> gst::Structure {
+    std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+        .find_map(|msg| {
+            msg.structure()
+                .filter(|s| s.name() == name)
+                .map(|s| s.to_owned())
+        })
+        .unwrap_or_else(|| panic!("no {name} message"))
+}
+
+// Mean, min, max and histogram of one CFA color of a bayer-stats message.
+fn channel_stats(stats: &gst::Structure, color: &str) -> (f64, u64, u64, Vec<u32>) {
+    let channel = stats.get::<gst::Structure>(color).unwrap();
+    let histogram = channel.get::<gst::Array>("histogram").unwrap();
+    (
+        channel.get("mean").unwrap(),
+        channel.get("min").unwrap(),
+        channel.get("max").unwrap(),
+        histogram.iter().map(|bin| bin.get().unwrap()).collect(),
+    )
+}
+
+#[test]
+fn test_post_stats() {
+    let mut h = harness_with(
+        Pattern::Rggb,
+        32,
+        24,
+        "RGB",
+        &[("post-stats", "true"), ("stats-subsampling", "1")],
+    );
+    let bus = watch(&h);
+
+    // The blue greens are two levels, one in each half of the frame.
+    let frame = bayer_frame(Pattern::Rggb, 32, 24, |cfa, x, _| match cfa {
+        CfaColor::Red => 200,
+        CfaColor::GreenRed => 100,
+        CfaColor::GreenBlue if x < 16 => 90,
+        CfaColor::GreenBlue => 110,
+        CfaColor::Blue => 12,
+    });
+    push(&mut h, 3, frame);
+    let stats = element_message(&bus, "bayer-stats");
+    assert_eq!(
+        stats.get::<gst::ClockTime>("pts").unwrap(),
+        FRAME_DURATION * 3
+    );
+
+    // 16x12 samples of every color, in bins of 4 levels.
+    let single_bin =
+        |bin: usize| -> Vec<u32> { (0..64).map(|i| if i == bin { 192 } else { 0 }).collect() };
+    assert_eq!(
+        channel_stats(&stats, "red"),
+        (200.0, 200, 200, single_bin(50))
+    );
+    assert_eq!(
+        channel_stats(&stats, "green-red"),
+        (100.0, 100, 100, single_bin(25))
+    );
+    assert_eq!(channel_stats(&stats, "blue"), (12.0, 12, 12, single_bin(3)));
+    let (mean, min, max, histogram) = channel_stats(&stats, "green-blue");
+    assert_eq!((mean, min, max), (100.0, 90, 110));
+    assert_eq!((histogram[22], histogram[27]), (96, 96));
+    assert_eq!(histogram.iter().sum::<u32>(), 192);
+
+    // Subsampled, only every other quad is measured.
+    h.element().unwrap().set_property("stats-subsampling", 2u32);
+    push(&mut h, 4, bayer_frame(Pattern::Rggb, 32, 24, |_, _, _| 64));
+    let stats = element_message(&bus, "bayer-stats");
+    let (_, _, _, histogram) = channel_stats(&stats, "red");
+    assert_eq!(histogram[16], 48);
+
+    // Without post-stats, nothing is posted.
+    h.element().unwrap().set_property("post-stats", false);
+    push(&mut h, 5, bayer_frame(Pattern::Rggb, 32, 24, |_, _, _| 64));
+    assert!(bus.pop_filtered(&[gst::MessageType::Element]).is_none());
+}
+
+#[test]
+fn test_stats_roi() {
+    // Red on the left half, blue on the right.
+    let frame = bayer_frame(Pattern::Rggb, 32, 24, |cfa, x, _| match x < 16 {
+        true => channel(cfa, [200, 0, 0]),
+        false => channel(cfa, [0, 0, 200]),
+    });
+    let mut h = harness_with(
+        Pattern::Rggb,
+        32,
+        24,
+        "RGB",
+        &[("post-stats", "true"), ("stats-roi-width", "16")],
+    );
+    let element = h.element().unwrap();
+    let bus = watch(&h);
+    let means = |stats: &gst::Structure| {
+        (
+            channel_stats(stats, "red").0,
+            channel_stats(stats, "blue").0,
+        )
+    };
+
+    push(&mut h, 0, frame.copy());
+    assert_eq!(means(&element_message(&bus, "bayer-stats")), (200.0, 0.0));
+
+    // A new window applies from the next frame on.
+    element.set_property("stats-roi-x", 16u32);
+    push(&mut h, 1, frame.copy());
+    assert_eq!(means(&element_message(&bus, "bayer-stats")), (0.0, 200.0));
+
+    // Past the frame edge, the window is clamped to the input.
+    element.set_property("stats-roi-x", 24u32);
+    element.set_property("stats-roi-width", 100u32);
+    push(&mut h, 2, frame.copy());
+    assert_eq!(means(&element_message(&bus, "bayer-stats")), (0.0, 200.0));
+
+    // Gray world balances the window: the gray left half needs no gains, the orange
+    // right half twice the red and half the blue.
+    let frame = bayer_frame(Pattern::Rggb, 32, 24, |cfa, x, _| match x < 16 {
+        true => channel(cfa, [100, 100, 100]),
+        false => channel(cfa, [50, 100, 200]),
+    });
+    for (x, red, blue) in [(0u32, 1.0, 1.0), (16, 2.0, 0.5)] {
+        let mut h = harness_with(
+            Pattern::Rggb,
+            32,
+            24,
+            "RGB",
+            &[("awb-mode", "gray-world"), ("stats-roi-width", "16")],
+        );
+        let element = h.element().unwrap();
+        element.set_property("stats-roi-x", x);
+        // The gains settle over a few frames.
+        for n in 0..16 {
+            push(&mut h, n, frame.copy());
+        }
+        let gains = (
+            element.property::<f64>("applied-red-gain"),
+            element.property::<f64>("applied-blue-gain"),
+        );
+        assert!(
+            (gains.0 - red).abs() < 0.01 && (gains.1 - blue).abs() < 0.01,
+            "window at {x}: gains {gains:?}"
+        );
+    }
+}
+
+// Noise of up to `amplitude` levels around `level` at sample `x`, `y` of frame `n`, the
+// same on every run.
+#[cfg(feature = "opencv")]
+fn noisy(level: u8, amplitude: u8, x: usize, y: usize, n: u64) -> u8 {
+    let hash = (x * 7919 + y * 104_729 + n as usize * 15_485_863).wrapping_mul(2_654_435_761);
+    let offset = ((hash >> 16) % (2 * amplitude as usize + 1)) as i32 - i32::from(amplitude);
+    (i32::from(level) + offset).clamp(0, 255) as u8
+}
+
+// Variance of the green of the pixels at least 8 away from the edges, where no filter
+// sees past them.
+#[cfg(feature = "opencv")]
+fn green_variance(pixels: &[Vec<[u8; 3]>]) -> f64 {
+    let greens = pixels[8..pixels.len() - 8]
+        .iter()
+        .flat_map(|row| &row[8..row.len() - 8])
+        .map(|pixel| f64::from(pixel[1]))
+        .collect::<Vec<_>>();
+    let mean = greens.iter().sum::<f64>() / greens.len() as f64;
+    greens
+        .iter()
+        .map(|green| (green - mean).powi(2))
+        .sum::<f64>()
+        / greens.len() as f64
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_denoise() {
+    let frame = bayer_frame(Pattern::Rggb, 64, 48, |_, x, y| noisy(128, 40, x, y, 0));
+    let convert = |format: &str, properties: &[(&str, &str)]| {
+        let mut h = harness_with(Pattern::Rggb, 64, 48, format, properties);
+        rgb_pixels(&push(&mut h, 0, frame.copy()), &output_caps(&h))
+    };
+
+    for format in OUTPUT_FORMATS {
+        let plain = convert(format, &[]);
+        assert!(
+            convert(format, &[("denoise", "none"), ("denoise-strength", "3.0")]) == plain,
+            "{format}: denoise=none changed the output"
+        );
+
+        let noise = green_variance(&plain);
+        for denoise in ["gaussian", "bilateral"] {
+            let filtered = convert(format, &[("denoise", denoise), ("denoise-strength", "3.0")]);
+            let residual = green_variance(&filtered);
+            assert!(
+                residual < noise / 2.0,
+                "{format} {denoise}: variance {residual} of {noise}"
+            );
+        }
+    }
+
+    // Switching it off mid-stream brings back the unfiltered output.
+    let mut h = harness_with(Pattern::Rggb, 64, 48, "RGB", &[("denoise", "bilateral")]);
+    push(&mut h, 0, frame.copy());
+    h.element()
+        .unwrap()
+        .set_property_from_str("denoise", "none");
+    let pixels = rgb_pixels(&push(&mut h, 1, frame.copy()), &output_caps(&h));
+    assert!(pixels == convert("RGB", &[]));
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_temporal_denoise() {
+    let noisy_frame = |n| {
+        bayer_frame(Pattern::Rggb, 64, 48, move |_, x, y| {
+            noisy(128, 40, x, y, n)
+        })
+    };
+    let flat_frame = |level, discont| {
+        let mut buffer = bayer_frame(Pattern::Rggb, 64, 48, move |_, _, _| level);
+        if discont {
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_flags(gst::BufferFlags::DISCONT);
+        }
+        buffer
+    };
+
+    let mut h = harness(Pattern::Rggb, 64, 48, "RGB");
+    let noise = green_variance(&rgb_pixels(
+        &push(&mut h, 0, noisy_frame(0)),
+        &output_caps(&h),
+    ));
+
+    // A static scene converges to its mean as the noise of the frames averages out.
+    let mut h = harness_with(Pattern::Rggb, 64, 48, "RGB", &[("temporal-denoise", "0.8")]);
+    let mut pixels = Vec::new();
+    for n in 0..30 {
+        pixels = rgb_pixels(&push(&mut h, n, noisy_frame(n)), &output_caps(&h));
+    }
+    let residual = green_variance(&pixels);
+    assert!(residual < noise / 3.0, "variance {residual} of {noise}");
+
+    // Without a discontinuity the next frame is blended with the ones before it...
+    let pixels = rgb_pixels(&push(&mut h, 30, flat_frame(40, false)), &output_caps(&h));
+    assert!(pixels[24][32][1] > 80, "{:?}", pixels[24][32]);
+    // ... a discontinuity starts over from the frame.
+    let pixels = rgb_pixels(&push(&mut h, 31, flat_frame(200, true)), &output_caps(&h));
+    assert_interior(&pixels, |_, _| [200; 3]);
+
+    // So does a flush.
+    push(&mut h, 32, flat_frame(40, false));
+    assert!(h.push_event(gst::event::FlushStart::new()));
+    assert!(h.push_event(gst::event::FlushStop::new(true)));
+    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    assert!(h.push_event(gst::event::Segment::new(&segment)));
+    let pixels = rgb_pixels(&push(&mut h, 0, flat_frame(200, false)), &output_caps(&h));
+    assert_interior(&pixels, |_, _| [200; 3]);
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_sharpen() {
+    // A vertical step edge from 64 to 192 in the middle of the frame.
+    let frame = bayer_frame(
+        Pattern::Rggb,
+        64,
+        16,
+        |_, x, _| if x < 32 { 64 } else { 192 },
+    );
+    let convert = |format: &str, properties: &[(&str, &str)]| {
+        let mut h = harness_with(Pattern::Rggb, 64, 16, format, properties);
+        let output = push(&mut h, 0, frame.copy());
+        (output, output_caps(&h))
+    };
+    let middle_row = |(output, caps): &(gst::Buffer, gst::Caps)| {
+        rgb_pixels(output, caps)[8]
+            .iter()
+            .map(|pixel| pixel[1])
+            .collect::<Vec<_>>()
+    };
+
+    let plain = middle_row(&convert("RGB", &[]));
+    assert_eq!(
+        middle_row(&convert("RGB", &[("sharpen-amount", "0")])),
+        plain
+    );
+
+    // The unsharp mask overshoots on both sides of the edge, far from it the levels
+    // stay.
+    let sharpened = middle_row(&convert("RGB", &[("sharpen-amount", "1.0")]));
+    let (min, max) = (
+        sharpened.iter().min().unwrap(),
+        sharpened.iter().max().unwrap(),
+    );
+    assert!(*min < 64 && *max > 192, "{sharpened:?}");
+    assert_eq!((sharpened[8], sharpened[56]), (64, 192));
+
+    // At the largest amount the overshoot saturates instead of wrapping around, off the
+    // few columns the demosaic blends across the edge.
+    let extreme = middle_row(&convert("RGB", &[("sharpen-amount", "10.0")]));
+    assert!(
+        extreme[..30].iter().all(|&green| green <= 64),
+        "{extreme:?}"
+    );
+    assert!(
+        extreme[34..].iter().all(|&green| green >= 192),
+        "{extreme:?}"
+    );
+    assert!(extreme.iter().min() < Some(min) && extreme.iter().max() > Some(max));
+
+    // The alpha of four channel output isn't sharpened.
+    let (output, caps) = convert("RGBA", &[("sharpen-amount", "10.0")]);
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+    let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(&output, &info).unwrap();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).unwrap();
+    for y in 0..16 {
+        let row = &data[y * stride..][..64 * 4];
+        assert!(row.chunks_exact(4).all(|pixel| pixel[3] == 255), "row {y}");
+    }
+}
+
+#[test]
+fn test_flat_field() {
+    init();
+
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-flat-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // An evenly lit target captured through a lens with a dust speck on it, which
+    // halves the light in a 4x4 spot.
+    let dust = |x: usize, y: usize| (14..18).contains(&x) && (10..14).contains(&y);
+    let flat = bayer_samples(
+        Pattern::Rggb,
+        32,
+        24,
+        |_, x, y| if dust(x, y) { 100 } else { 200 },
+    );
+    let path = dir.join("flat.npy");
+    write_npy(&path, "|u1", (24, 32), &flat);
+    let small_path = dir.join("small.npy");
+    write_npy(&small_path, "|u1", (12, 16), &flat[..16 * 12]);
+
+    let frame = bayer_frame(
+        Pattern::Rggb,
+        32,
+        24,
+        |_, x, y| if dust(x, y) { 64 } else { 128 },
+    );
+    let convert = |flat_field_file: Option<&std::path::Path>| {
+        // Calibration files load when the element starts, which the harness does as
+        // soon as it has the element.
+        let element = gst::ElementFactory::make("rsbayer2rgb")
+            .property(
+                "flat-field-file",
+                flat_field_file.map(|path| path.to_str().unwrap()),
+            )
+            .build()
+            .unwrap();
+        let mut h = gst_check::Harness::with_element(&element, Some("sink"), Some("src"));
+        h.set_sink_caps_str("video/x-raw,format=RGB");
+        h.set_src_caps(bayer_caps(Pattern::Rggb, 32, 24));
+        let mut buffer = frame.copy();
+        buffer.get_mut().unwrap().set_pts(gst::ClockTime::ZERO);
+        h.push(buffer)
+            .map(|_| rgb_pixels(&h.pull().unwrap(), &output_caps(&h)))
+    };
+
+    // The speck shows in the plain conversion...
+    let pixels = convert(None).unwrap();
+    assert!(pixels[12][16][1] < 80, "{:?}", pixels[12][16]);
+    // ... and disappears dividing by the flat-field, normalized to the mean of every
+    // color: 4 of its 192 samples are dimmed.
+    let level = (128.0 * (200.0 - 100.0 * 4.0 / 192.0) / 200.0_f64).round() as u8;
+    assert_interior(&convert(Some(&path)).unwrap(), |_, _| [level; 3]);
+
+    // A flat-field of another size fails negotiation.
+    assert_eq!(
+        convert(Some(&small_path)),
+        Err(gst::FlowError::NotNegotiated)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_dark_frame() {
+    init();
+
+    let dir = std::env::temp_dir().join(format!("rsbayer2rgb-dark-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // A master dark with a black level of 16 and three hot pixels.
+    let hot = |x: usize, y: usize| [(9, 7), (20, 14), (4, 16)].contains(&(x, y));
+    let dark = |offset: u8| {
+        bayer_samples(Pattern::Rggb, 32, 24, move |_, x, y| match hot(x, y) {
+            true => 150 + offset,
+            false => 16 + offset,
+        })
+    };
+    let path = dir.join("dark.npy");
+    let master = dark(0);
+    write_npy(&path, "|u1", (24, 32), &master);
+    let frame = bayer_frame(Pattern::Rggb, 32, 24, |_, x, y| 100 + master[y * 32 + x]);
+
+    // The hot pixels show in the plain conversion...
+    let mut h = harness(Pattern::Rggb, 32, 24, "RGB");
+    let pixels = rgb_pixels(&push(&mut h, 0, frame.copy()), &output_caps(&h));
+    assert!(pixels[7][9][2] > 200, "{:?}", pixels[7][9]);
+
+    // ... and are gone with the black level after subtracting the dark.
+    let mut h = harness_with(
+        Pattern::Rggb,
+        32,
+        24,
+        "RGB",
+        &[("dark-frame-file", path.to_str().unwrap())],
+    );
+    let pixels = rgb_pixels(&push(&mut h, 0, frame.copy()), &output_caps(&h));
+    assert_interior(&pixels, |_, _| [100; 3]);
+
+    // Setting the property loads the file again, a brighter dark applies from the next
+    // frame on.
+    let brighter = dir.join("brighter.npy");
+    write_npy(&brighter, "|u1", (24, 32), &dark(10));
+    let element = h.element().unwrap();
+    element.set_property("dark-frame-file", brighter.to_str().unwrap());
+    let pixels = rgb_pixels(&push(&mut h, 1, frame.copy()), &output_caps(&h));
+    assert_interior(&pixels, |_, _| [90; 3]);
+
+    // A dark of another size fails negotiation.
+    let small = dir.join("small.npy");
+    write_npy(&small, "|u1", (12, 16), &master[..16 * 12]);
+    let mut h = harness_with(
+        Pattern::Rggb,
+        32,
+        24,
+        "RGB",
+        &[("dark-frame-file", small.to_str().unwrap())],
+    );
+    assert_eq!(h.push(frame.copy()), Err(gst::FlowError::NotNegotiated));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_auto_defect_correction() {
+    // A clean gradient, and the same with two hot and a dead pixel.
+    let gradient = |x: usize, y: usize| (40 + x * 4 + y * 2) as u8;
+    let defects = [(9, 7, 255), (20, 14, 0), (5, 16, 255)];
+    let clean = bayer_frame(Pattern::Rggb, 32, 24, |_, x, y| gradient(x, y));
+    let defective = bayer_frame(Pattern::Rggb, 32, 24, |_, x, y| {
+        defects
+            .iter()
+            .find(|&&(dx, dy, _)| (dx, dy) == (x, y))
+            .map_or(gradient(x, y), |&(_, _, value)| value)
+    });
+
+    let mut h = harness(Pattern::Rggb, 32, 24, "RGB");
+    let expected = rgb_pixels(&push(&mut h, 0, clean.copy()), &output_caps(&h));
+
+    let mut h = harness_with(
+        Pattern::Rggb,
+        32,
+        24,
+        "RGB",
+        &[("auto-defect-correction", "true"), ("stats-interval", "1")],
+    );
+    let bus = watch(&h);
+    // The number of sites corrected in the last frame is in the next stats message,
+    // posted a second after the previous one.
+    let mut convert = |n: u64, frame: &gst::Buffer| {
+        push(&mut h, 2 * n, frame.copy());
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let pixels = rgb_pixels(&push(&mut h, 2 * n + 1, frame.copy()), &output_caps(&h));
+        let stats = element_message(&bus, "rsbayer2rgb-stats");
+        (pixels, stats.get::<u64>("corrected-defects").unwrap())
+    };
+
+    // Every defect is replaced from its neighbors, which leaves the gradient.
+    let (pixels, corrected) = convert(0, &defective);
+    let difference = compare(&pixels, &expected, TOLERANCE);
+    assert!(difference.first.is_none(), "{difference}");
+    assert_eq!(corrected, defects.len() as u64);
+
+    // Nothing is taken for a defect in the clean gradient.
+    let (pixels, corrected) = convert(1, &clean);
+    assert!(pixels == expected);
+    assert_eq!(corrected, 0);
+}
+
+#[test]
+fn test_zebra() {
+    // Clipped on the left half, mid gray on the right.
+    let frame = bayer_frame(
+        Pattern::Rggb,
+        32,
+        16,
+        |_, x, _| if x < 16 { 255 } else { 128 },
+    );
+    let convert = |show_zebra: &str| {
+        let mut h = harness_with(Pattern::Rggb, 32, 16, "RGB", &[("show-zebra", show_zebra)]);
+        rgb_pixels(&push(&mut h, 0, frame.copy()), &output_caps(&h))
+    };
+
+    let plain = convert("false");
+    assert!(plain.iter().flatten().all(|&pixel| pixel != [0; 3]));
+
+    // Diagonal stripes four pixels wide cover the clipped half only.
+    let pixels = convert("true");
+    for (y, row) in pixels.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            let stripe = x < 16 && (x + y) / 4 % 2 == 0;
+            match stripe {
+                true => assert_eq!(pixel, [0; 3], "pixel {x},{y}"),
+                false => assert_eq!(pixel, plain[y][x], "pixel {x},{y}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_focus_metric() {
+    // A target of 4x4 squares, and the same out of focus, averaged over 5x5 pixels.
+    let sharp = |x: usize, y: usize| {
+        if (x / 4 + y / 4).is_multiple_of(2) {
+            32u32
+        } else {
+            224
+        }
+    };
+    let blurred = |x: usize, y: usize| {
+        let sum = (0..5)
+            .flat_map(|dy| (0..5).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| sharp(x + dx, y + dy))
+            .sum::<u32>();
+        sum / 25
+    };
+    let focus = |format: &str, target: &dyn Fn(usize, usize) -> u32| {
+        let mut h = harness_with(
+            Pattern::Rggb,
+            64,
+            48,
+            format,
+            &[("post-focus-metric", "true")],
+        );
+        let bus = watch(&h);
+        let frame = bayer_frame(Pattern::Rggb, 64, 48, |_, x, y| target(x, y) as u8);
+        push(&mut h, 2, frame);
+        let metric = element_message(&bus, "focus-metric");
+        assert_eq!(
+            metric.get::<gst::ClockTime>("pts").unwrap(),
+            FRAME_DURATION * 2
+        );
+        metric.get::<f64>("focus").unwrap()
+    };
+
+    let in_focus = focus("RGB", &sharp);
+    let out_of_focus = focus("RGB", &blurred);
+    assert!(
+        out_of_focus < in_focus / 2.0,
+        "{out_of_focus} blurred, {in_focus} sharp"
+    );
+
+    // It's measured on the luma, whatever the channel order.
+    for format in ["BGR", "RGBA"] {
+        assert_eq!(focus(format, &sharp), in_focus, "{format}");
+    }
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_opencv_threads() {
+    init();
+
+    // Applied when the element starts, which the harness does as soon as it has the
+    // element.
+    let start = |opencv_threads: i32| {
+        let element = gst::ElementFactory::make("rsbayer2rgb")
+            .property("opencv-threads", opencv_threads)
+            .build()
+            .unwrap();
+        gst_check::Harness::with_element(&element, Some("sink"), Some("src"))
+    };
+
+    let _first = start(3);
+    assert_eq!(opencv::core::get_num_threads().unwrap(), 3);
+
+    // The count is process-global, the first element to start keeps it.
+    let _second = start(5);
+    assert_eq!(opencv::core::get_num_threads().unwrap(), 3);
+}
+
+#[cfg(feature = "opencv")]
+#[test]
+fn test_opencl() {
+    // Without an OpenCL device the element falls back to the CPU, which has to match
+    // all the same.
+    let frame = bayer_frame(Pattern::Rggb, 64, 48, |_, x, y| {
+        ((x * 7919 + y * 104_729).wrapping_mul(2_654_435_761) >> 16) as u8
+    });
+    for pattern in Pattern::ALL {
+        for format in OUTPUT_FORMATS {
+            let convert = |use_opencl: &str| {
+                let mut h = harness_with(pattern, 64, 48, format, &[("use-opencl", use_opencl)]);
+                let mut pixels = Vec::new();
+                // The second frame goes through the cached device buffers.
+                for n in 0..2 {
+                    pixels = rgb_pixels(&push(&mut h, n, frame.copy()), &output_caps(&h));
+                }
+                // The backends make up the neighbors past the edges differently.
+                pixels[BORDER..48 - BORDER]
+                    .iter()
+                    .map(|row| row[BORDER..64 - BORDER].to_vec())
+                    .collect::<Vec<_>>()
+            };
+
+            let difference = compare(&convert("true"), &convert("false"), TOLERANCE);
+            assert!(
+                difference.first.is_none(),
+                "{pattern:?} {format}: {difference}"
+            );
+        }
+    }
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn test_cuda_fallback() {
+    init();
+
+    // No machine has a device with that index, so the frames are converted on the CPU.
+    let frame = bayer_frame(Pattern::Rggb, 64, 48, |_, x, y| {
+        ((x * 7919 + y * 104_729).wrapping_mul(2_654_435_761) >> 16) as u8
+    });
+    for format in OUTPUT_FORMATS {
+        let element = gst::ElementFactory::make("rsbayer2rgb")
+            .property("use-cuda", true)
+            .property("device", 1000i32)
+            .build()
+            .unwrap();
+        let mut h = gst_check::Harness::with_element(&element, Some("sink"), Some("src"));
+        h.set_sink_caps_str(&format!("video/x-raw,format={format}"));
+        h.set_src_caps(bayer_caps(Pattern::Rggb, 64, 48));
+        let mut fallback = Vec::new();
+        for n in 0..2 {
+            fallback = rgb_pixels(&push(&mut h, n, frame.copy()), &output_caps(&h));
+        }
+
+        let mut h = harness(Pattern::Rggb, 64, 48, format);
+        let cpu = rgb_pixels(&push(&mut h, 0, frame.copy()), &output_caps(&h));
+        assert!(fallback == cpu, "{format}");
+    }
+}
+
+// The pool `buffer` was acquired from, if any.
+fn buffer_pool(buffer: &gst::BufferRef) -> Option<gst::BufferPool> {
+    unsafe { gst::glib::translate::from_glib_none((*buffer.as_ptr()).pool) }
+}
+
+#[test]
+fn test_output_pool() {
+    use gst_base::prelude::*;
+
+    let mut h = harness(Pattern::Rggb, 32, 24, "RGB");
+    let element = h.element().unwrap();
+    let transform = element.downcast_ref::<gst_base::BaseTransform>().unwrap();
+    let frame = |width, height| bayer_frame(Pattern::Rggb, width, height, |_, _, _| 128);
+
+    // Without a pool from downstream the frames come from our own, and an output
+    // released downstream is used again for a later frame.
+    let mut outputs = Vec::new();
+    for n in 0..8 {
+        let output = push(&mut h, n, frame(32, 24));
+        assert_eq!(buffer_pool(&output), transform.buffer_pool());
+        outputs.push(output.as_ptr() as usize);
+    }
+    outputs.sort_unstable();
+    outputs.dedup();
+    assert!(outputs.len() < 8, "{} buffers for 8 frames", outputs.len());
+
+    // New caps bring a new pool, the old one is released.
+    let old_pool = transform.buffer_pool().unwrap().downgrade();
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 48, 32));
+    let output = push(&mut h, 8, frame(48, 32));
+    assert_eq!(buffer_pool(&output), transform.buffer_pool());
+    drop(output);
+    assert!(old_pool.upgrade().is_none());
+
+    // A pool downstream proposes is adopted.
+    let mut h = harness(Pattern::Rggb, 32, 24, "RGB");
+    let proposed = gst_video::VideoBufferPool::new().upcast::<gst::BufferPool>();
+    propose_pool(&h, &proposed);
+    for n in 0..4 {
+        let output = push(&mut h, n, frame(32, 24));
+        assert_eq!(buffer_pool(&output).as_ref(), Some(&proposed));
+    }
+    assert!(proposed.is_active());
+
+    // Unless it can't align the rows, then our own is used instead.
+    let mut h = harness(Pattern::Rggb, 32, 24, "RGB");
+    let proposed = gst::BufferPool::new();
+    propose_pool(&h, &proposed);
+    let output = push(&mut h, 0, frame(32, 24));
+    let pool = buffer_pool(&output).unwrap();
+    assert_ne!(pool, proposed);
+    assert!(pool.has_option(gst_video::BUFFER_POOL_OPTION_VIDEO_ALIGNMENT));
+    assert!(!proposed.is_active());
+}
+
+// Answers the allocation queries of `h` with `pool` and the VideoMeta.
+fn propose_pool(h: &gst_check::Harness, pool: &gst::BufferPool) {
+    let pool = pool.clone();
+    h.element().unwrap().static_pad("src").unwrap().add_probe(
+        gst::PadProbeType::QUERY_DOWNSTREAM,
+        move |_, info| {
+            let Some(query) = info.query_mut() else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let gst::QueryViewMut::Allocation(allocation) = query.view_mut() else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let caps = allocation.get().0.unwrap().to_owned();
+            let size = gst_video::VideoInfo::from_caps(&caps).unwrap().size();
+            allocation.add_allocation_pool(Some(&pool), size as u32, 0, 0);
+            allocation.add_allocation_meta::<gst_video::VideoMeta>(None);
+            gst::PadProbeReturn::Handled
+        },
+    );
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::{CfaColor, Pattern};
+
+// Flat field of 100 with one hot red sample in the middle.
+const HOT: (usize, usize) = (6, 4);
+
+fn hot_pixel(_: CfaColor, x: usize, y: usize) -> u8 {
+    if (x, y) == HOT { 255 } else { 100 }
+}
+
+#[test]
+fn test_removes_hot_pixel() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerdenoise");
+    h.element().unwrap().set_property("strength", 1.0);
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 12, 8));
+
+    let frame = bayer_frame(Pattern::Rggb, 12, 8, hot_pixel);
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+    let output = h.pull().unwrap();
+    assert!(
+        output
+            .map_readable()
+            .unwrap()
+            .iter()
+            .all(|&sample| sample == 100)
+    );
+}
+
+#[test]
+fn test_partial_strength() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerdenoise");
+    h.element().unwrap().set_property("strength", 0.5);
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 12, 8));
+
+    let frame = bayer_frame(Pattern::Rggb, 12, 8, hot_pixel);
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+    let output = h.pull().unwrap();
+    let map = output.map_readable().unwrap();
+    // Halfway from 255 to the median of 100, rounded.
+    assert_eq!(map[HOT.1 * 12 + HOT.0], 178);
+}
+
+#[test]
+fn test_zero_strength_passthrough() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerdenoise");
+    h.element().unwrap().set_property("strength", 0.0);
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 12, 8));
+
+    let frame = bayer_frame(Pattern::Rggb, 12, 8, hot_pixel);
+    let expected = frame.map_readable().unwrap().to_vec();
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+    let output = h.pull().unwrap();
+    assert_eq!(output.map_readable().unwrap().as_slice(), expected);
+}
+
+#[test]
+fn test_16_bit() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerdenoise");
+    h.element().unwrap().set_property("strength", 1.0);
+    h.set_src_caps_str("video/x-bayer,format=rggb12be,width=12,height=8,framerate=30/1");
+
+    let samples = bayer_samples(Pattern::Rggb, 12, 8, hot_pixel)
+        .into_iter()
+        .flat_map(|sample| (u16::from(sample) * 16).to_be_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        h.push(gst::Buffer::from_mut_slice(samples)),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    let output = h.pull().unwrap();
+    let map = output.map_readable().unwrap();
+    assert!(
+        map.chunks_exact(2)
+            .all(|sample| u16::from_be_bytes([sample[0], sample[1]]) == 1600)
+    );
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::Pattern;
+
+#[test]
+fn test_corrected_rgb() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerisp");
+    let isp = h.element().unwrap();
+    isp.set_property("red-gain", 2.0);
+    isp.set_property("blue-gain", 0.5);
+    isp.set_property("denoise-strength", 1.0);
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps(bayer_caps(Pattern::Rggb, 16, 12));
+
+    // Gray scene under a light too blue for the sensor, with one hot sample the
+    // denoise removes before demosaic spreads it.
+    let frame = bayer_frame(Pattern::Rggb, 16, 12, |cfa, x, y| {
+        if (x, y) == (8, 6) {
+            255
+        } else {
+            channel(cfa, [50, 100, 200])
+        }
+    });
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+
+    let output = h.pull().unwrap();
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| [100; 3]);
+}
+
+#[test]
+fn test_child_properties() {
+    init();
+
+    let isp = gst::ElementFactory::make("rsbayerisp")
+        .property("gamma", 2.2)
+        .property("denoise-strength", 0.25)
+        .build()
+        .unwrap();
+    let proxy = isp.dynamic_cast_ref::<gst::ChildProxy>().unwrap();
+
+    assert_eq!(proxy.child_property::<f64>("convert::gamma"), 2.2);
+    assert_eq!(proxy.child_property::<f64>("denoise::strength"), 0.25);
+
+    proxy.set_child_property("convert::saturation", 0.0);
+    assert_eq!(
+        proxy
+            .child_by_name("convert")
+            .unwrap()
+            .property::<f64>("saturation"),
+        0.0
+    );
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::Pattern;
+
+// Samples of every bit of the depth, varying along the row and down the frame.
+fn samples(width: usize, height: usize, depth: u32) -> Vec<u16> {
+    let max = (1u32 << depth) - 1;
+    (0..width * height)
+        .map(|i| (((i as u32).wrapping_mul(2654435761) >> 7) & max) as u16)
+        .collect()
+}
+
+// Packs rows of samples as in the MIPI CSI-2 spec, the last group padded with zeros.
+fn pack(samples: &[u16], width: usize, depth: u32) -> Vec<u8> {
+    let (pixels, _) = group(depth);
+    let mut packed = vec![];
+    for row in samples.chunks(width) {
+        for group in row.chunks(pixels) {
+            let mut p = [0u16; 4];
+            p[..group.len()].copy_from_slice(group);
+            let low = depth - 8;
+            packed.extend(p[..pixels].iter().map(|&p| (p >> low) as u8));
+            match depth {
+                10 => packed.push(
+                    (p[0] & 3) as u8
+                        | ((p[1] & 3) << 2) as u8
+                        | ((p[2] & 3) << 4) as u8
+                        | ((p[3] & 3) << 6) as u8,
+                ),
+                12 => packed.push((p[0] & 0xf) as u8 | ((p[1] & 0xf) << 4) as u8),
+                14 => packed.extend([
+                    (p[0] & 0x3f) as u8 | ((p[1] & 0x3) << 6) as u8,
+                    ((p[1] >> 2) & 0xf) as u8 | ((p[2] & 0xf) << 4) as u8,
+                    ((p[2] >> 4) & 0x3) as u8 | ((p[3] & 0x3f) << 2) as u8,
+                ]),
+                _ => unreachable!(),
+            }
+        }
+    }
+    packed
+}
+
+fn group(depth: u32) -> (usize, usize) {
+    match depth {
+        10 => (4, 5),
+        12 => (2, 3),
+        14 => (4, 7),
+        _ => unreachable!(),
+    }
+}
+
+fn repack(depth: u32, width: usize, height: usize) {
+    let mut h = gst_check::Harness::new("rsbayerrepack");
+    h.set_src_caps_str(&format!(
+        "video/x-bayer,format=bggr{depth}p,width={width},height={height},framerate=30/1"
+    ));
+
+    let samples = samples(width, height, depth);
+    let packed = pack(&samples, width, depth);
+    assert_eq!(
+        packed.len(),
+        width.div_ceil(group(depth).0) * group(depth).1 * height
+    );
+    assert_eq!(
+        h.push(gst::Buffer::from_mut_slice(packed)),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    let output = h.pull().unwrap();
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<&str>("format").unwrap(), "bggr16le");
+    assert_eq!(s.get::<i32>("width").unwrap(), width as i32);
+    assert_eq!(s.get::<i32>("height").unwrap(), height as i32);
+
+    let unpacked = output
+        .map_readable()
+        .unwrap()
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect::<Vec<_>>();
+    let expected = samples
+        .iter()
+        .map(|&sample| sample << (16 - depth))
+        .collect::<Vec<_>>();
+    assert_eq!(unpacked, expected);
+}
+
+#[test]
+fn test_raw10() {
+    init();
+    repack(10, 16, 6);
+}
+
+#[test]
+fn test_raw12() {
+    init();
+    repack(12, 16, 6);
+}
+
+#[test]
+fn test_raw14() {
+    init();
+    repack(14, 16, 6);
+}
+
+#[test]
+fn test_padded_last_group() {
+    init();
+    for depth in [10, 12, 14] {
+        repack(depth, 14, 4);
+        repack(depth, 13, 3);
+    }
+}
+
+#[test]
+fn test_full_scale() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayerrepack");
+    h.set_src_caps_str("video/x-bayer,format=rggb10p,width=4,height=2,framerate=30/1");
+    assert_eq!(
+        h.push(gst::Buffer::from_mut_slice(vec![0xff; 10])),
+        Ok(gst::FlowSuccess::Ok)
+    );
+    let output = h.pull().unwrap();
+    assert!(
+        output
+            .map_readable()
+            .unwrap()
+            .chunks_exact(2)
+            .all(|b| u16::from_le_bytes([b[0], b[1]]) == 1023 << 6)
+    );
+}
+
+#[test]
+fn test_padded_input() {
+    init();
+
+    let (width, height) = (8, 4);
+    let samples = samples(width, height, 12);
+    let packed = pack(&samples, width, 12);
+    // 12 bytes rows at a stride of 16, after 32 bytes of header.
+    let mut padded = vec![0xaa; 32 + 16 * height];
+    for (row, src) in padded[32..].chunks_mut(16).zip(packed.chunks(12)) {
+        row[..12].copy_from_slice(src);
+    }
+    let mut buffer = gst::Buffer::from_mut_slice(padded);
+    gst_video::VideoMeta::add_full(
+        buffer.get_mut().unwrap(),
+        gst_video::VideoFrameFlags::empty(),
+        gst_video::VideoFormat::Gray8,
+        width as u32,
+        height as u32,
+        &[32],
+        &[16],
+    )
+    .unwrap();
+
+    let mut h = gst_check::Harness::new("rsbayerrepack");
+    h.set_src_caps_str("video/x-bayer,format=gbrg12p,width=8,height=4,framerate=30/1");
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    let output = h.pull().unwrap();
+    let unpacked = output
+        .map_readable()
+        .unwrap()
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]) >> 4)
+        .collect::<Vec<_>>();
+    assert_eq!(unpacked, samples);
+}
+
+#[test]
+fn test_into_rsbayer2rgb() {
+    const COLOR: [u8; 3] = [200, 100, 50];
+    init();
+
+    let (width, height) = (16, 12);
+    let mut h = gst_check::Harness::new_parse("rsbayerrepack ! rsbayer2rgb");
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps_str(&format!(
+        "video/x-bayer,format=rggb10p,width={width},height={height},framerate=30/1"
+    ));
+
+    // rsbayer2rgb converts the high 8 bits, the low ones only add noise here.
+    let samples = bayer_samples(Pattern::Rggb, width, height, |cfa, _, _| {
+        channel(cfa, COLOR)
+    })
+    .into_iter()
+    .enumerate()
+    .map(|(i, sample)| ((sample as u16) << 2) | (i % 4) as u16)
+    .collect::<Vec<_>>();
+    assert_eq!(
+        h.push(gst::Buffer::from_mut_slice(pack(&samples, width, 10))),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    let output = h.pull().unwrap();
+    let caps = output_caps(&h);
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<i32>("width").unwrap(), width as i32);
+    assert_eq!(s.get::<i32>("height").unwrap(), height as i32);
+    assert_interior(&rgb_pixels(&output, &caps), |_, _| COLOR);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::Pattern;
+
+#[test]
+fn test_solid() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayertestsrc");
+    let element = h.element().unwrap();
+    element.set_property_from_str("pattern", "solid");
+    element.set_property("foreground-color", 0xff_c8_64_32u32);
+    h.set_sink_caps_str("video/x-bayer,format=bggr,width=32,height=16,framerate=10/1");
+    h.play();
+
+    let expected = bayer_samples(Pattern::Bggr, 32, 16, |cfa, _, _| {
+        channel(cfa, [0xc8, 0x64, 0x32])
+    });
+    for n in 0..3u64 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_mseconds(100 * n)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::from_mseconds(100)));
+        assert_eq!(buffer.offset(), n);
+        assert_eq!(buffer.map_readable().unwrap().as_slice(), expected);
+    }
+
+    assert_eq!(output_caps(&h), bayer_caps_at(Pattern::Bggr, 32, 16, 10));
+}
+
+#[test]
+fn test_default_caps() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsbayertestsrc");
+    h.play();
+
+    let buffer = h.pull().unwrap();
+    assert_eq!(output_caps(&h), bayer_caps(Pattern::Rggb, 320, 240));
+    assert_eq!(buffer.size(), 320 * 240);
+}
+
+#[test]
+fn test_noise_seed() {
+    init();
+
+    let frames = |seed: u64| {
+        let mut h = gst_check::Harness::new("rsbayertestsrc");
+        let element = h.element().unwrap();
+        element.set_property_from_str("pattern", "noise");
+        element.set_property("seed", seed);
+        h.set_sink_caps_str("video/x-bayer,format=rggb,width=16,height=8,framerate=30/1");
+        h.play();
+
+        (0..2)
+            .map(|_| h.pull().unwrap().map_readable().unwrap().to_vec())
+            .collect::<Vec<_>>()
+    };
+
+    let first = frames(1);
+    assert_eq!(first, frames(1));
+    assert_ne!(first, frames(2));
+    assert_ne!(first[0], first[1]);
+}
+
+fn bayer_caps_at(pattern: Pattern, width: usize, height: usize, fps: i32) -> gst::Caps {
+    let mut caps = bayer_caps(pattern, width, height);
+    caps.make_mut().set("framerate", gst::Fraction::new(fps, 1));
+    caps
+}
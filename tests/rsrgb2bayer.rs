@@ -0,0 +1,101 @@
+mod common;
+
+use common::*;
+use gst::prelude::*;
+use gstrsbayer::convert::{OutputLayout, Pattern};
+
+// A frame of `format` with every pixel `color`, rows padded as VideoInfo lays them out.
+fn rgb_frame(
+    format: gst_video::VideoFormat,
+    width: u32,
+    height: u32,
+    color: [u8; 3],
+) -> gst::Buffer {
+    let info = gst_video::VideoInfo::builder(format, width, height)
+        .build()
+        .unwrap();
+    let stride = info.stride()[0] as usize;
+    let mut data = vec![0u8; info.size()];
+    match OutputLayout::for_format(format) {
+        Some(layout) => {
+            for row in data.chunks_exact_mut(stride) {
+                for pixel in row
+                    .chunks_exact_mut(layout.pixel_stride)
+                    .take(width as usize)
+                {
+                    pixel[layout.red] = color[0];
+                    pixel[layout.green] = color[1];
+                    pixel[layout.blue] = color[2];
+                    if let Some(alpha) = layout.alpha {
+                        pixel[alpha] = 255;
+                    }
+                }
+            }
+        }
+        // GRAY8, the one input without color channels.
+        None => {
+            for row in data.chunks_exact_mut(stride) {
+                row[..width as usize].fill(color[0]);
+            }
+        }
+    }
+
+    gst::Buffer::from_mut_slice(data)
+}
+
+#[test]
+fn test_mosaic() {
+    init();
+
+    let color = [200, 100, 50];
+    for format in ["RGB", "BGR", "RGBA"] {
+        for pattern in Pattern::ALL {
+            let mut h = gst_check::Harness::new("rsrgb2bayer");
+            h.element().unwrap().set_property("pattern", pattern);
+            h.set_src_caps_str(&format!(
+                "video/x-raw,format={format},width=10,height=6,framerate=30/1"
+            ));
+
+            let frame = rgb_frame(format.parse().unwrap(), 10, 6, color);
+            assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+            let output = h.pull().unwrap();
+
+            let caps = output_caps(&h);
+            assert_eq!(caps, bayer_caps(pattern, 10, 6));
+            let map = output.map_readable().unwrap();
+            assert_eq!(
+                map.as_slice(),
+                bayer_samples(pattern, 10, 6, |cfa, _, _| channel(cfa, color))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_gray() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsrgb2bayer");
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=10,height=6,framerate=30/1");
+    let frame = rgb_frame(gst_video::VideoFormat::Gray8, 10, 6, [77; 3]);
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+
+    let output = h.pull().unwrap();
+    let map = output.map_readable().unwrap();
+    assert!(map.iter().all(|&sample| sample == 77));
+}
+
+#[test]
+fn test_round_trip() {
+    init();
+
+    let color = [40, 160, 220];
+    let mut h = gst_check::Harness::new_parse("rsrgb2bayer pattern=grbg ! rsbayer2rgb");
+    h.set_sink_caps_str("video/x-raw,format=RGB");
+    h.set_src_caps_str("video/x-raw,format=RGB,width=16,height=12,framerate=30/1");
+    let frame = rgb_frame(gst_video::VideoFormat::Rgb, 16, 12, color);
+    assert_eq!(h.push(frame), Ok(gst::FlowSuccess::Ok));
+
+    let output = h.pull().unwrap();
+    assert_interior(&rgb_pixels(&output, &output_caps(&h)), |_, _| color);
+}